@@ -0,0 +1,162 @@
+// Character-level language model: a single-block transformer trained on a text file, then
+// sampled from.
+//
+//     cargo run --release --example char_lm -- input.txt [steps]
+//
+// Without a file it trains on a short built-in text. The text is cut into windows of BLOCK
+// characters; the last window is padded with targets of IGNORE so cross-entropy skips them.
+
+use rust_ml::generate::Generation;
+use rust_ml::loss::{self, CrossEntropyOptions};
+use rust_ml::nn::{CausalSelfAttention, Embedding, ForwardHooks, Linear, Module, ReLU, Sequential};
+use rust_ml::optim::{Adam, Optimizer};
+use rust_ml::random::manual_seed;
+use rust_ml::tensor::Tensor;
+use rust_ml::text::{CharTokenizer, Tokenizer};
+use std::fs;
+
+const BLOCK: usize = 32;
+const DIM: usize = 48;
+const IGNORE: i64 = -100;
+
+const DEFAULT_TEXT: &str = "\
+the quick brown fox jumps over the lazy dog. the lazy dog sleeps in the sun.
+the brown fox runs through the green field and the dog watches the fox.
+a quick fox and a lazy dog, the sun and the field, the fox and the dog again.
+";
+
+// Token and position embeddings, a residual attention block and MLP, and a linear head over
+// the vocabulary. Takes [T] ids, returns [T, vocab] logits.
+struct CharTransformer {
+    tokens: Embedding,
+    positions: Embedding,
+    attention: CausalSelfAttention,
+    mlp: Sequential,
+    head: Linear,
+    // So register_forward_hook works on the whole model, as on the built-in layers
+    hooks: ForwardHooks,
+}
+
+impl CharTransformer {
+    fn new(vocab_size: usize) -> CharTransformer {
+        CharTransformer {
+            tokens: Embedding::new(vocab_size, DIM),
+            positions: Embedding::new(BLOCK, DIM),
+            attention: CausalSelfAttention::new(DIM),
+            mlp: Sequential::new()
+                .add(Linear::new(DIM, 4 * DIM))
+                .add(ReLU)
+                .add(Linear::new(4 * DIM, DIM)),
+            head: Linear::new(DIM, vocab_size),
+            hooks: ForwardHooks::new(),
+        }
+    }
+}
+
+impl Module for CharTransformer {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let len = input.shape()[0];
+        let positions =
+            Tensor::from(ndarray::Array1::from_iter((0..len).map(|p| p as f32)).into_dyn());
+        let x = &self.tokens.forward(input) + &self.positions.forward(&positions);
+        let x = &x + &self.attention.forward(&x);
+        let x = &x + &self.mlp.forward(&x);
+        self.hooks.run(input, self.head.forward(&x))
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        let layers: [(&str, &dyn Module); 5] = [
+            ("tokens", &self.tokens),
+            ("positions", &self.positions),
+            ("attention", &self.attention),
+            ("mlp", &self.mlp),
+            ("head", &self.head),
+        ];
+        layers
+            .into_iter()
+            .flat_map(|(prefix, layer)| {
+                layer
+                    .named_parameters()
+                    .into_iter()
+                    .map(move |(name, param)| (format!("{}.{}", prefix, name), param))
+            })
+            .collect()
+    }
+}
+
+// (input ids, target ids) per window, targets shifted by one character
+fn windows(ids: &[usize]) -> Vec<(Tensor, Tensor)> {
+    ids.chunks(BLOCK)
+        .filter(|chunk| chunk.len() > 1)
+        .map(|chunk| {
+            let inputs = &chunk[..chunk.len() - 1];
+            let mut targets: Vec<f32> = chunk[1..].iter().map(|&id| id as f32).collect();
+            // Pad short windows to the full block, padded positions don't count
+            let mut padded: Vec<f32> = inputs.iter().map(|&id| id as f32).collect();
+            padded.resize(BLOCK - 1, 0.0);
+            targets.resize(BLOCK - 1, IGNORE as f32);
+            (
+                Tensor::from(ndarray::Array1::from(padded).into_dyn()),
+                Tensor::from(ndarray::Array1::from(targets).into_dyn()),
+            )
+        })
+        .collect()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let text = match args.next() {
+        Some(path) => {
+            fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e))
+        }
+        None => DEFAULT_TEXT.to_string(),
+    };
+    let steps: usize = args
+        .next()
+        .map_or(300, |s| s.parse().expect("steps must be a number"));
+    manual_seed(0);
+
+    let tokenizer = CharTokenizer::fit(&text);
+    let ids = tokenizer.encode(&text);
+    let data = windows(&ids);
+    let model = CharTransformer::new(tokenizer.vocab_size());
+    println!(
+        "{} characters, vocabulary of {}, {} windows, {} parameters",
+        ids.len(),
+        tokenizer.vocab_size(),
+        data.len(),
+        model.parameters().iter().map(|p| p.numel()).sum::<usize>()
+    );
+
+    let mut optimizer = Adam::new(model.parameters(), 3e-3);
+    let options = CrossEntropyOptions {
+        ignore_index: Some(IGNORE),
+        ..Default::default()
+    };
+    for step in 0..steps {
+        let (inputs, targets) = &data[step % data.len()];
+        optimizer.zero_grad();
+        let loss = loss::cross_entropy_with(&model.forward(inputs), targets, options);
+        loss.backward();
+        optimizer.step();
+        if step % 50 == 0 || step + 1 == steps {
+            println!("step {:>4}  loss {:.4}", step, loss.item());
+        }
+    }
+
+    let prompt = tokenizer.encode(&text[..1]);
+    let generated = Generation::new(200)
+        .temperature(0.8)
+        .top_k(10)
+        .context(BLOCK)
+        .run(&model, &prompt);
+    println!(
+        "---\n{}{}",
+        tokenizer.decode(&prompt),
+        tokenizer.decode(&generated)
+    );
+}
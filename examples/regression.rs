@@ -0,0 +1,61 @@
+// Linear and polynomial regression end to end: generate noisy data from known coefficients,
+// fit them back by gradient descent and check they were recovered.
+//
+//     cargo run --example regression
+
+use rust_ml::data::synthetic::make_regression;
+use rust_ml::random::manual_seed;
+use rust_ml::tensor::Tensor;
+use rust_ml::train::{fit_linear_regression, polynomial_features};
+
+fn main() {
+    manual_seed(0);
+
+    // y = 2 x0 - 3 x1 + 0.5 x2 + 1
+    let true_weights = [2.0, -3.0, 0.5];
+    let (x, y) = make_regression(256, &true_weights, 1.0, 0.05);
+    let fit = fit_linear_regression(&x, &y, 500, 0.1);
+    println!(
+        "linear: loss {:.4} -> {:.4}",
+        fit.losses[0],
+        fit.losses.last().unwrap()
+    );
+    println!("  weights {:?} (true {:?})", fit.weights(), true_weights);
+    println!("  bias {:.3} (true 1.0)", fit.bias());
+    for (w, true_w) in fit.weights().iter().zip(true_weights) {
+        assert!(
+            (w - true_w).abs() < 0.05,
+            "weight {} should be {}",
+            w,
+            true_w
+        );
+    }
+    assert!((fit.bias() - 1.0).abs() < 0.05);
+
+    // y = x^3 - 2 x on [-1.5, 1.5], a cubic through polynomial features
+    let n = 200;
+    let xs: Vec<f32> = (0..n)
+        .map(|i| 3.0 * i as f32 / (n - 1) as f32 - 1.5)
+        .collect();
+    let ys: Vec<f32> = xs.iter().map(|x| x.powi(3) - 2.0 * x).collect();
+    let x = Tensor::from(ndarray::Array1::from(xs).into_dyn());
+    let y = Tensor::from(ndarray::Array1::from(ys).into_dyn());
+    let fit = fit_linear_regression(&polynomial_features(&x, 3), &y, 5000, 0.05);
+    println!(
+        "cubic: loss {:.4} -> {:.6}",
+        fit.losses[0],
+        fit.losses.last().unwrap()
+    );
+    println!(
+        "  coefficients of x, x^2, x^3 {:?} (true [-2, 0, 1])",
+        fit.weights()
+    );
+    for (w, true_w) in fit.weights().iter().zip([-2.0, 0.0, 1.0]) {
+        assert!(
+            (w - true_w).abs() < 0.05,
+            "coefficient {} should be {}",
+            w,
+            true_w
+        );
+    }
+}
@@ -0,0 +1,83 @@
+#ifndef RUST_ML_H
+#define RUST_ML_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A model loaded from an ONNX file.
+typedef struct RmlModel RmlModel;
+
+// A tensor owned by the caller.
+typedef struct RmlTensor RmlTensor;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, or NULL if nothing failed yet. The
+// pointer stays valid until the next failing call on the same thread.
+const char *rml_last_error(void);
+
+// Copy `numel(shape)` floats from `data` into a new tensor of the given shape.
+//
+// # Safety
+// `shape` must point to `ndim` values and `data` to as many floats as their product (one for
+// `ndim == 0`). NULL pointers and shapes that overflow are reported as errors.
+struct RmlTensor *rml_tensor_new(const float *data, const size_t *shape, size_t ndim);
+
+// Number of dimensions.
+//
+// # Safety
+// `tensor` must be a live handle from this library.
+size_t rml_tensor_ndim(const struct RmlTensor *tensor);
+
+// Size of dimension `axis`, 0 if the tensor has fewer dimensions.
+//
+// # Safety
+// `tensor` must be a live handle from this library.
+size_t rml_tensor_dim(const struct RmlTensor *tensor, size_t axis);
+
+// Total number of values.
+//
+// # Safety
+// `tensor` must be a live handle from this library.
+size_t rml_tensor_numel(const struct RmlTensor *tensor);
+
+// Copy up to `capacity` values in row-major order into `out`, returns the tensor's total
+// number of values so a short buffer can be detected.
+//
+// # Safety
+// `tensor` must be a live handle and `out` must have room for `capacity` floats.
+size_t rml_tensor_read(const struct RmlTensor *tensor, float *out, size_t capacity);
+
+// # Safety
+// `tensor` must be NULL or a handle that hasn't been freed yet.
+void rml_tensor_free(struct RmlTensor *tensor);
+
+// Load an ONNX model (see `io::onnx` for the supported ops), NULL on failure.
+//
+// # Safety
+// `path` must be a NUL-terminated string.
+struct RmlModel *rml_model_load_onnx(const char *path);
+
+// Run the model on `input`, returns a new tensor the caller frees, NULL on failure
+// (e.g. an input shape the model can't take).
+//
+// # Safety
+// `model` and `input` must be live handles from this library.
+struct RmlTensor *rml_model_forward(const struct RmlModel *model, const struct RmlTensor *input);
+
+// # Safety
+// `model` must be NULL or a handle that hasn't been freed yet.
+void rml_model_free(struct RmlModel *model);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUST_ML_H */
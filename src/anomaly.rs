@@ -0,0 +1,79 @@
+// Anomaly detection: backward checks every gradient an op hands to its inputs and panics at
+// the first op that produces a NaN or infinity, instead of letting it spread through the
+// whole model and show up as a NaN loss a few steps later.
+//
+//     anomaly::detect_anomaly(|| loss.backward());
+//
+// The report names the op and the input, with where both were created in debug builds (see
+// Tensor::created_at), so it points at the expression to fix. Checking costs a pass over every
+// gradient, leave it off outside of debugging. Anomaly detection is per thread.
+
+use crate::tensor::TensorData;
+use std::cell::Cell;
+use std::fmt::Write;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+// Run `f` with anomaly detection on, the previous mode is restored afterwards, also on panic
+pub fn detect_anomaly<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ENABLED.with(|enabled| enabled.set(self.0));
+        }
+    }
+
+    let _restore = Restore(ENABLED.with(|enabled| enabled.replace(true)));
+    f()
+}
+
+// After the backward function of `out` ran. Children that already had a non-finite gradient
+// before would have been reported at the op that produced it.
+pub(crate) fn check_backward(out: &TensorData) {
+    for (i, child) in out._children.iter().enumerate() {
+        let child = child.borrow();
+        let dense = child.grad.iter().flat_map(|grad| grad.iter());
+        let sparse = child.sparse_grad.iter().flat_map(|grad| grad.values.iter());
+        let (nan, inf) = dense.chain(sparse).fold((0, 0), |(nan, inf), g| {
+            (nan + g.is_nan() as usize, inf + g.is_infinite() as usize)
+        });
+        if nan + inf > 0 {
+            panic!("{}", report(out, i, &child, nan, inf));
+        }
+    }
+}
+
+fn report(out: &TensorData, input: usize, child: &TensorData, nan: usize, inf: usize) -> String {
+    let op = out._op.map_or("leaf", |op| op.name());
+    let mut report = format!(
+        "anomaly detected: backward of `{}` produced {} NaN and {} infinite gradient values for \
+         its input {}",
+        op, nan, inf, input
+    );
+    let _ = write!(report, "\n    `{}` {:?}", op, out.data.shape());
+    describe(&mut report, out);
+    let _ = write!(
+        report,
+        "\n    input {} {:?}",
+        child._op.map_or("leaf", |op| op.name()),
+        child.data.shape()
+    );
+    describe(&mut report, child);
+    report
+}
+
+fn describe(report: &mut String, node: &TensorData) {
+    if let Some(label) = &node.label {
+        let _ = write!(report, " named {}", label);
+    }
+    if let Some(location) = node._location {
+        let _ = write!(report, ", created at {}", location);
+    }
+}
@@ -0,0 +1,228 @@
+// Models described as data instead of code, so architecture sweeps only change a file. A spec
+// is a list of layers tagged by `type`, with the arguments of the layer's constructor and
+// builder methods as fields:
+//
+//     {"layers": [{"type": "conv2d", "in_channels": 1, "out_channels": 8, "kernel_size": 3,
+//                  "padding": 1},
+//                 {"type": "relu"},
+//                 {"type": "global_avg_pool2d"},
+//                 {"type": "linear", "in_features": 8, "out_features": 10}]}
+//
+//     let model = ModelSpec::load("model.json")?.build();
+//
+// Fields that a builder method sets are optional and default like the method would. Any serde
+// format works, the CLI reads specs from TOML. Specs describe the structure only, load
+// trained parameters from a checkpoint into the built model.
+
+use crate::nn::{
+    AdaptiveAvgPool2d, AvgPool1d, CausalSelfAttention, Conv1d, Conv2d, Embedding, Flatten,
+    ForwardHooks, GlobalAvgPool2d, Linear, MaxPool1d, Module, ReLU, Reshape, Sequential, Tanh,
+    Upsample,
+};
+use crate::spatial::Interpolation;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSpec {
+    pub layers: Vec<LayerSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LayerSpec {
+    Linear {
+        in_features: usize,
+        out_features: usize,
+        #[serde(default = "yes")]
+        bias: bool,
+    },
+    Embedding {
+        num_embeddings: usize,
+        dim: usize,
+    },
+    CausalSelfAttention {
+        dim: usize,
+    },
+    Conv1d {
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        #[serde(flatten)]
+        options: ConvSpec,
+    },
+    Conv2d {
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        #[serde(flatten)]
+        options: ConvSpec,
+    },
+    MaxPool1d {
+        kernel_size: usize,
+        // The kernel size by default
+        stride: Option<usize>,
+    },
+    AvgPool1d {
+        kernel_size: usize,
+        stride: Option<usize>,
+    },
+    AdaptiveAvgPool2d {
+        output_size: [usize; 2],
+    },
+    GlobalAvgPool2d,
+    Upsample {
+        scale_factor: f32,
+        mode: Interpolation,
+    },
+    Relu,
+    Tanh,
+    Flatten,
+    Reshape {
+        shape: Vec<usize>,
+    },
+    // A nested stack, its parameters are prefixed with its index like any other layer
+    Sequential {
+        layers: Vec<LayerSpec>,
+    },
+}
+
+// Optional convolution settings, defaults as in ConvOptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConvSpec {
+    pub stride: usize,
+    pub padding: usize,
+    pub dilation: usize,
+    pub groups: usize,
+    pub bias: bool,
+}
+
+impl Default for ConvSpec {
+    fn default() -> ConvSpec {
+        ConvSpec {
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            groups: 1,
+            bias: true,
+        }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl ModelSpec {
+    pub fn new(layers: Vec<LayerSpec>) -> ModelSpec {
+        ModelSpec { layers }
+    }
+
+    // A freshly initialized model, parameters named like a hand-written Sequential of the same
+    // layers so checkpoints carry over between the two
+    pub fn build(&self) -> Sequential {
+        build_sequential(&self.layers)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> io::Result<ModelSpec> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ModelSpec> {
+        ModelSpec::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+impl LayerSpec {
+    pub fn build(&self) -> Box<dyn Module> {
+        match *self {
+            LayerSpec::Linear {
+                in_features,
+                out_features,
+                bias,
+            } => Box::new(if bias {
+                Linear::new(in_features, out_features)
+            } else {
+                Linear::without_bias(in_features, out_features)
+            }),
+            LayerSpec::Embedding {
+                num_embeddings,
+                dim,
+            } => Box::new(Embedding::new(num_embeddings, dim)),
+            LayerSpec::CausalSelfAttention { dim } => Box::new(CausalSelfAttention::new(dim)),
+            LayerSpec::Conv1d {
+                in_channels,
+                out_channels,
+                kernel_size,
+                options,
+            } => {
+                let conv = Conv1d::new(in_channels, out_channels, kernel_size)
+                    .stride(options.stride)
+                    .padding(options.padding)
+                    .dilation(options.dilation)
+                    .groups(options.groups);
+                Box::new(if options.bias {
+                    conv
+                } else {
+                    conv.without_bias()
+                })
+            }
+            LayerSpec::Conv2d {
+                in_channels,
+                out_channels,
+                kernel_size,
+                options,
+            } => {
+                let conv = Conv2d::new(in_channels, out_channels, kernel_size)
+                    .stride(options.stride)
+                    .padding(options.padding)
+                    .dilation(options.dilation)
+                    .groups(options.groups);
+                Box::new(if options.bias {
+                    conv
+                } else {
+                    conv.without_bias()
+                })
+            }
+            LayerSpec::MaxPool1d {
+                kernel_size,
+                stride,
+            } => Box::new(MaxPool1d::new(kernel_size).stride(stride.unwrap_or(kernel_size))),
+            LayerSpec::AvgPool1d {
+                kernel_size,
+                stride,
+            } => Box::new(AvgPool1d::new(kernel_size).stride(stride.unwrap_or(kernel_size))),
+            LayerSpec::AdaptiveAvgPool2d { output_size } => {
+                Box::new(AdaptiveAvgPool2d::new(output_size))
+            }
+            LayerSpec::GlobalAvgPool2d => Box::new(GlobalAvgPool2d),
+            LayerSpec::Upsample { scale_factor, mode } => {
+                Box::new(Upsample::new(scale_factor, mode))
+            }
+            LayerSpec::Relu => Box::new(ReLU),
+            LayerSpec::Tanh => Box::new(Tanh),
+            LayerSpec::Flatten => Box::new(Flatten),
+            LayerSpec::Reshape { ref shape } => Box::new(Reshape::new(shape)),
+            LayerSpec::Sequential { ref layers } => Box::new(build_sequential(layers)),
+        }
+    }
+}
+
+fn build_sequential(layers: &[LayerSpec]) -> Sequential {
+    Sequential {
+        layers: layers.iter().map(LayerSpec::build).collect(),
+        hooks: ForwardHooks::default(),
+    }
+}
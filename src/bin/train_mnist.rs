@@ -0,0 +1,112 @@
+// Train a classifier on MNIST and report its test accuracy, a template for wiring the data
+// loader, a model, an optimizer and the Trainer together:
+//
+//     cargo run --release --bin train_mnist -- --model cnn --epochs 3
+//
+// Options (all optional): --data DIR (default data/mnist, holding the MNIST .gz files, see
+// data::mnist for where to get them), --model mlp|cnn, --epochs N, --batch-size N, --lr RATE.
+
+use rust_ml::data::{mnist, DataLoader};
+use rust_ml::loss;
+use rust_ml::metrics::{Accuracy, Metric};
+use rust_ml::nn::{Conv2d, Flatten, Linear, Module, ReLU, Reshape, Sequential};
+use rust_ml::optim::Adam;
+use rust_ml::random::manual_seed;
+use rust_ml::train::{ProgressBar, Trainer};
+use std::process;
+
+struct Args {
+    data: String,
+    model: String,
+    epochs: usize,
+    batch_size: usize,
+    lr: f32,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        data: String::from("data/mnist"),
+        model: String::from("mlp"),
+        epochs: 2,
+        batch_size: 64,
+        lr: 1e-3,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(flag) = argv.next() {
+        let mut value = || argv.next().ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--data" => args.data = value()?,
+            "--model" => args.model = value()?,
+            "--epochs" => args.epochs = value()?.parse().map_err(|e| format!("--epochs: {}", e))?,
+            "--batch-size" => {
+                args.batch_size = value()?
+                    .parse()
+                    .map_err(|e| format!("--batch-size: {}", e))?
+            }
+            "--lr" => args.lr = value()?.parse().map_err(|e| format!("--lr: {}", e))?,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    Ok(args)
+}
+
+// Images come in as [N, 28, 28]
+fn build_model(kind: &str) -> Result<Sequential, String> {
+    match kind {
+        "mlp" => Ok(Sequential::new()
+            .add(Flatten)
+            .add(Linear::new(28 * 28, 128))
+            .add(ReLU)
+            .add(Linear::new(128, 10))),
+        // Two stride-2 convolutions take 28x28 down to 7x7
+        "cnn" => Ok(Sequential::new()
+            .add(Reshape::new(&[1, 28, 28]))
+            .add(Conv2d::new(1, 16, 3).stride(2).padding(1))
+            .add(ReLU)
+            .add(Conv2d::new(16, 32, 3).stride(2).padding(1))
+            .add(ReLU)
+            .add(Flatten)
+            .add(Linear::new(32 * 7 * 7, 10))),
+        _ => Err(format!("unknown model {}, expected mlp or cnn", kind)),
+    }
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("train_mnist: {}", e);
+        process::exit(2);
+    });
+    let model = build_model(&args.model).unwrap_or_else(|e| {
+        eprintln!("train_mnist: {}", e);
+        process::exit(2);
+    });
+    manual_seed(0);
+
+    let data = mnist::load(&args.data).unwrap_or_else(|e| {
+        eprintln!("train_mnist: can't load MNIST from {}: {}", args.data, e);
+        process::exit(1);
+    });
+    let train = DataLoader::new(data.train, args.batch_size).shuffle(true);
+    let test = DataLoader::new(data.test, 1000);
+
+    println!("{}", model.summary(&[1, 28, 28]));
+    let mut optimizer = Adam::new(model.parameters(), args.lr);
+    let history = Trainer::new(&model, &mut optimizer, loss::cross_entropy)
+        .epochs(args.epochs)
+        .callback(ProgressBar::new())
+        .fit_with_validation(&train, &test);
+    for metrics in &history {
+        println!(
+            "epoch {}: train loss {:.4}, test loss {:.4}",
+            metrics.epoch + 1,
+            metrics.train_loss,
+            metrics.val_loss.unwrap()
+        );
+    }
+
+    let mut accuracy = Accuracy::default();
+    for (images, labels) in &test {
+        accuracy.update(&model.predict(&images), &labels);
+    }
+    println!("test accuracy {:.2}%", 100.0 * accuracy.value());
+}
@@ -0,0 +1,137 @@
+// Native training checkpoints: model and optimizer state plus epoch and RNG position, so a
+// run can be resumed exactly. Little-endian binary layout:
+//   b"RMLCKPT\0", version u32, epoch u64,
+//   rng seed [u8; 32], rng stream u64, rng word position u128,
+//   model state dict, optimizer state dict
+// where a state dict is an entry count u32 followed by, per entry, name length u32, name
+// bytes, ndim u32, ndim dims as u64 and the f32 values in row-major order.
+
+use crate::error::TensorError;
+use crate::nn::{Module, StateDict};
+use crate::optim::Optimizer;
+use crate::random::{set_rng_state, RngState};
+use ndarray::{ArrayD, IxDyn};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RMLCKPT\0";
+// Bump when the layout changes, older versions stay readable
+pub const FORMAT_VERSION: u32 = 1;
+
+pub struct Checkpoint {
+    pub version: u32,
+    pub epoch: u64,
+    pub rng_state: RngState,
+    pub model: StateDict,
+    pub optimizer: StateDict,
+}
+
+impl Checkpoint {
+    // Load the saved state into `model` and `optimizer` and put the global RNG back where it was
+    pub fn restore(
+        &self,
+        model: &dyn Module,
+        optimizer: &mut dyn Optimizer,
+    ) -> Result<(), TensorError> {
+        model.load_state_dict(&self.model)?;
+        optimizer.load_state_dict(&self.optimizer);
+        set_rng_state(&self.rng_state);
+        Ok(())
+    }
+}
+
+pub fn save(
+    path: impl AsRef<Path>,
+    model: &dyn Module,
+    optimizer: &dyn Optimizer,
+    epoch: u64,
+    rng_state: &RngState,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&epoch.to_le_bytes())?;
+    writer.write_all(&rng_state.seed)?;
+    writer.write_all(&rng_state.stream.to_le_bytes())?;
+    writer.write_all(&rng_state.word_pos.to_le_bytes())?;
+    write_state_dict(&mut writer, &model.state_dict())?;
+    write_state_dict(&mut writer, &optimizer.state_dict())?;
+    writer.flush()
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<Checkpoint> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a rust-ml checkpoint"));
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader)?);
+    if version > FORMAT_VERSION {
+        return Err(invalid(&format!(
+            "checkpoint version {} is newer than the supported version {}",
+            version, FORMAT_VERSION
+        )));
+    }
+    let epoch = u64::from_le_bytes(read_array(&mut reader)?);
+    let rng_state = RngState {
+        seed: read_array(&mut reader)?,
+        stream: u64::from_le_bytes(read_array(&mut reader)?),
+        word_pos: u128::from_le_bytes(read_array(&mut reader)?),
+    };
+    Ok(Checkpoint {
+        version,
+        epoch,
+        rng_state,
+        model: read_state_dict(&mut reader)?,
+        optimizer: read_state_dict(&mut reader)?,
+    })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_state_dict(writer: &mut impl Write, state: &StateDict) -> io::Result<()> {
+    writer.write_all(&(state.len() as u32).to_le_bytes())?;
+    for (name, array) in state {
+        writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&(array.ndim() as u32).to_le_bytes())?;
+        for &dim in array.shape() {
+            writer.write_all(&(dim as u64).to_le_bytes())?;
+        }
+        for value in array.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_state_dict(reader: &mut impl Read) -> io::Result<StateDict> {
+    let count = u32::from_le_bytes(read_array(reader)?);
+    let mut state = StateDict::new();
+    for _ in 0..count {
+        let name_len = u32::from_le_bytes(read_array(reader)?) as usize;
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("entry name isn't UTF-8"))?;
+
+        let ndim = u32::from_le_bytes(read_array(reader)?) as usize;
+        let shape = (0..ndim)
+            .map(|_| Ok(u64::from_le_bytes(read_array(reader)?) as usize))
+            .collect::<io::Result<Vec<usize>>>()?;
+        let values = (0..shape.iter().product::<usize>())
+            .map(|_| Ok(f32::from_le_bytes(read_array(reader)?)))
+            .collect::<io::Result<Vec<f32>>>()?;
+        state.insert(name, ArrayD::from_shape_vec(IxDyn(&shape), values).unwrap());
+    }
+    Ok(state)
+}
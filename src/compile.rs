@@ -0,0 +1,473 @@
+// Static compilation for inference. `compile` runs the model once on an example input, records
+// the ops of the resulting graph in execution order with their output shapes, and allocates an
+// output buffer per op. Calls to the compiled model replay that list into the same buffers:
+// no graph, no per-op allocations and no shape checks beyond the input's.
+//
+//     let compiled = compile(&model, &example)?;
+//     let out = compiled.forward(&x); // x must have the example's shape
+//
+// Shapes are fixed by the example, batch size included. Parameters are read at every call, so
+// the compiled model follows weight updates, but control flow is frozen: whatever path forward
+// took on the example is the one replayed. Values a layer reads from its input outside of ops
+// (e.g. Embedding's ids) can't be traced and are rejected.
+//
+// Before allocating, passes rewrite the recorded steps, see Pass. `compile` runs all of them,
+// `compile_with` a chosen list, e.g. none to replay the trace as recorded.
+
+use crate::error::TensorError;
+use crate::inference;
+use crate::nn::{ForwardHooks, Module};
+use crate::op::Op;
+use crate::shape;
+use crate::tensor::{gathered_indices, Tensor};
+use ndarray::linalg::general_mat_mul;
+use ndarray::{ArrayD, Axis, Ix2};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Slot {
+    Input,
+    Constant(usize),
+    // Output of step i
+    Step(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Kernel {
+    Add,
+    Mul,
+    MatMul,
+    Transpose,
+    Reshape,
+    Tanh,
+    Exp,
+    Relu,
+    Softmax(usize),
+    Sum(usize),
+    Row(usize),
+    GatherRows(Vec<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Step {
+    kernel: Kernel,
+    inputs: Vec<Slot>,
+}
+
+pub struct CompiledModule {
+    input_shape: Vec<usize>,
+    steps: Vec<Step>,
+    // Parameters and other leaves the graph reads, by reference
+    constants: Vec<Tensor>,
+    params: Vec<(String, Tensor)>,
+    output: Slot,
+    // One per step, shaped like the traced output of that step
+    buffers: RefCell<Vec<ArrayD<f32>>>,
+    hooks: ForwardHooks,
+}
+
+// Rewrites of the recorded steps, run in the order given to compile_with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    // Compute once the steps that only read constants created during the trace. Parameters
+    // aren't constant, they're read at every call.
+    FoldConstants,
+    // Replay steps with the same kernel and inputs once, e.g. an activation computed twice
+    EliminateCommonSubexpressions,
+    // Drop the steps and constants the output doesn't depend on, which the other passes leave
+    // behind. Run it last.
+    EliminateDeadSteps,
+}
+
+impl Pass {
+    // What `compile` runs
+    pub const ALL: [Pass; 3] = [
+        Pass::FoldConstants,
+        Pass::EliminateCommonSubexpressions,
+        Pass::EliminateDeadSteps,
+    ];
+}
+
+// Trace `module` on `example_input` into a CompiledModule, with every pass
+pub fn compile(module: &dyn Module, example_input: &Tensor) -> Result<CompiledModule, TensorError> {
+    compile_with(module, example_input, &Pass::ALL)
+}
+
+// `compile` running `passes` on the trace
+pub fn compile_with(
+    module: &dyn Module,
+    example_input: &Tensor,
+    passes: &[Pass],
+) -> Result<CompiledModule, TensorError> {
+    if inference::is_enabled() {
+        return Err(untraceable("ops aren't recorded in inference mode"));
+    }
+    // A fresh leaf, so the trace stops here even if the example came out of another graph
+    let input = Tensor::from(example_input.borrow().data.clone());
+    let output = module.forward(&input);
+    let input_uuid = input.borrow()._uuid;
+
+    let mut order = Vec::new();
+    topo_sort(&output, &mut HashSet::new(), &mut order);
+    if !order.iter().any(|node| node.borrow()._uuid == input_uuid) {
+        return Err(untraceable(
+            "the output doesn't depend on the input through ops",
+        ));
+    }
+
+    let names: HashMap<Uuid, String> = module
+        .named_parameters()
+        .into_iter()
+        .map(|(name, param)| (param.borrow()._uuid, name))
+        .collect();
+    let mut slots: HashMap<Uuid, Slot> = HashMap::new();
+    let mut steps = Vec::new();
+    let mut buffers = Vec::new();
+    let mut constants = Vec::new();
+    let mut frozen = Vec::new();
+    let mut params = Vec::new();
+    for node in &order {
+        let inner = node.borrow();
+        let Some(op) = inner._op else {
+            let slot = if inner._uuid == input_uuid {
+                Slot::Input
+            } else {
+                let name = names.get(&inner._uuid);
+                if let Some(name) = name {
+                    params.push((name.clone(), node.clone()));
+                }
+                constants.push(node.clone());
+                frozen.push(name.is_none());
+                Slot::Constant(constants.len() - 1)
+            };
+            slots.insert(inner._uuid, slot);
+            continue;
+        };
+
+        let kernel = match op {
+            Op::Add => Kernel::Add,
+            Op::Mul => Kernel::Mul,
+            Op::Matmul => Kernel::MatMul,
+            Op::Transpose => Kernel::Transpose,
+            Op::Reshape => Kernel::Reshape,
+            Op::Tanh => Kernel::Tanh,
+            Op::Exp => Kernel::Exp,
+            Op::Relu => Kernel::Relu,
+            Op::Softmax { axis } => Kernel::Softmax(axis),
+            Op::Sum { axis } => Kernel::Sum(axis),
+            Op::Row { index } => Kernel::Row(index),
+            Op::GatherRows => Kernel::GatherRows(gathered_indices(&inner)),
+            op => return Err(untraceable(&format!("op `{}` has no kernel", op))),
+        };
+        let inputs = inner
+            ._children
+            .iter()
+            .map(|child| slots[&child.borrow()._uuid])
+            .collect();
+        steps.push(Step { kernel, inputs });
+        buffers.push(ArrayD::zeros(inner.data.raw_dim()));
+        slots.insert(inner._uuid, Slot::Step(steps.len() - 1));
+    }
+
+    let mut program = Program {
+        steps,
+        buffers,
+        constants,
+        frozen,
+        output: slots[&output.borrow()._uuid],
+    };
+    for pass in passes {
+        match pass {
+            Pass::FoldConstants => fold_constants(&mut program),
+            Pass::EliminateCommonSubexpressions => eliminate_common_subexpressions(&mut program),
+            Pass::EliminateDeadSteps => eliminate_dead_steps(&mut program),
+        }
+    }
+    Ok(CompiledModule {
+        input_shape: input.shape(),
+        steps: program.steps,
+        constants: program.constants,
+        params,
+        output: program.output,
+        buffers: RefCell::new(program.buffers),
+        hooks: ForwardHooks::default(),
+    })
+}
+
+impl CompiledModule {
+    pub fn input_shape(&self) -> &[usize] {
+        &self.input_shape
+    }
+
+    // Number of recorded ops
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    // Replay on `input`, which must have the traced shape
+    pub fn run(&self, input: &ArrayD<f32>) -> Result<ArrayD<f32>, TensorError> {
+        if input.shape() != self.input_shape.as_slice() {
+            return Err(TensorError::ShapeMismatch {
+                op: "compiled forward",
+                lhs: self.input_shape.clone(),
+                rhs: input.shape().to_vec(),
+                operands: None,
+                location: None,
+            });
+        }
+        let constants: Vec<_> = self.constants.iter().map(|c| c.borrow()).collect();
+        let mut buffers = self.buffers.borrow_mut();
+        for (i, step) in self.steps.iter().enumerate() {
+            // Steps only read earlier steps, so the output can be borrowed apart from them
+            let (done, rest) = buffers.split_at_mut(i);
+            let out = &mut rest[0];
+            let value = |slot: Slot| match slot {
+                Slot::Input => input,
+                Slot::Constant(c) => &constants[c].data,
+                Slot::Step(s) => &done[s],
+            };
+            execute(&step.kernel, |k| value(step.inputs[k]), out);
+        }
+        Ok(match self.output {
+            Slot::Input => input.clone(),
+            Slot::Constant(c) => constants[c].data.clone(),
+            Slot::Step(s) => buffers[s].clone(),
+        })
+    }
+}
+
+// Compute `kernel` of the arguments `arg(0)`, `arg(1)`, .. into `out`
+fn execute<'a>(kernel: &Kernel, arg: impl Fn(usize) -> &'a ArrayD<f32>, out: &mut ArrayD<f32>) {
+    let a = arg(0);
+    match kernel {
+        Kernel::Add => {
+            out.assign(a);
+            out.zip_mut_with(arg(1), |o, &b| *o += b);
+        }
+        Kernel::Mul => {
+            out.assign(a);
+            out.zip_mut_with(arg(1), |o, &b| *o *= b);
+        }
+        Kernel::MatMul => {
+            let a = a.view().into_dimensionality::<Ix2>().unwrap();
+            let b = arg(1).view().into_dimensionality::<Ix2>().unwrap();
+            let mut out = out.view_mut().into_dimensionality::<Ix2>().unwrap();
+            general_mat_mul(1.0, &a, &b, 0.0, &mut out);
+        }
+        Kernel::Transpose => out.assign(&a.t()),
+        Kernel::Reshape => out.iter_mut().zip(a.iter()).for_each(|(o, &x)| *o = x),
+        Kernel::Tanh => out.zip_mut_with(a, |o, &x| *o = x.tanh()),
+        Kernel::Exp => out.zip_mut_with(a, |o, &x| *o = x.exp()),
+        Kernel::Relu => out.zip_mut_with(a, |o, &x| *o = x.max(0.0)),
+        Kernel::Softmax(axis) => {
+            out.assign(a);
+            for mut lane in out.lanes_mut(Axis(*axis)) {
+                let max = lane.fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+                lane.mapv_inplace(|x| (x - max).exp());
+                let sum = lane.sum();
+                lane /= sum;
+            }
+        }
+        Kernel::Sum(axis) => {
+            out.fill(0.0);
+            for slice in a.axis_iter(Axis(*axis)) {
+                out.zip_mut_with(&slice, |o, &x| *o += x);
+            }
+        }
+        Kernel::Row(i) => out.assign(&a.index_axis(Axis(0), *i)),
+        Kernel::GatherRows(indices) => {
+            for (k, &i) in indices.iter().enumerate() {
+                out.index_axis_mut(Axis(0), k)
+                    .assign(&a.index_axis(Axis(0), i));
+            }
+        }
+    }
+}
+
+// The output is a leaf, compiled models don't record a graph
+impl Module for CompiledModule {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let output = self
+            .run(&input.borrow().data)
+            .unwrap_or_else(|e| panic!("{}", e));
+        self.hooks.run(input, Tensor::from(output))
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    // Fixed by the trace
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        if input_shape != self.input_shape.as_slice() {
+            return Err(shape::mismatch(
+                "compiled forward",
+                &self.input_shape,
+                input_shape,
+            ));
+        }
+        Ok(match self.output {
+            Slot::Input => self.input_shape.clone(),
+            Slot::Constant(c) => self.constants[c].shape(),
+            Slot::Step(s) => self.buffers.borrow()[s].shape().to_vec(),
+        })
+    }
+
+    // The traced model's parameters the graph reads
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.params.clone()
+    }
+}
+
+// The trace while passes rewrite it
+struct Program {
+    steps: Vec<Step>,
+    // One per step
+    buffers: Vec<ArrayD<f32>>,
+    constants: Vec<Tensor>,
+    // Per constant, whether it keeps its traced value: leaves created in forward do,
+    // parameters don't
+    frozen: Vec<bool>,
+    output: Slot,
+}
+
+impl Program {
+    // Rebuild the steps in order. `rewrite` gets each step with its inputs pointing into the
+    // new list and returns where its value is now, None to keep the step as is.
+    fn rewrite(
+        &mut self,
+        mut rewrite: impl FnMut(&mut Program, &Step, &mut ArrayD<f32>) -> Option<Slot>,
+    ) {
+        let steps = mem::take(&mut self.steps);
+        let buffers = mem::take(&mut self.buffers);
+        let mut moved = Vec::with_capacity(steps.len());
+        for (mut step, mut buffer) in steps.into_iter().zip(buffers) {
+            for input in &mut step.inputs {
+                *input = moved_to(*input, &moved);
+            }
+            let slot = match rewrite(self, &step, &mut buffer) {
+                Some(slot) => slot,
+                None => {
+                    self.steps.push(step);
+                    self.buffers.push(buffer);
+                    Slot::Step(self.steps.len() - 1)
+                }
+            };
+            moved.push(slot);
+        }
+        self.output = moved_to(self.output, &moved);
+    }
+}
+
+fn moved_to(slot: Slot, moved: &[Slot]) -> Slot {
+    match slot {
+        Slot::Step(i) => moved[i],
+        slot => slot,
+    }
+}
+
+fn fold_constants(program: &mut Program) {
+    program.rewrite(|program, step, buffer| {
+        let args = step
+            .inputs
+            .iter()
+            .map(|&input| match input {
+                Slot::Constant(c) if program.frozen[c] => Some(program.constants[c].borrow()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        execute(&step.kernel, |k| &args[k].data, buffer);
+        drop(args);
+        program.constants.push(Tensor::from(mem::take(buffer)));
+        program.frozen.push(true);
+        Some(Slot::Constant(program.constants.len() - 1))
+    });
+}
+
+fn eliminate_common_subexpressions(program: &mut Program) {
+    let mut seen: HashMap<Step, usize> = HashMap::new();
+    program.rewrite(|program, step, _| {
+        let mut key = step.clone();
+        // Broadcasting is symmetric, so a + b is b + a
+        if matches!(key.kernel, Kernel::Add | Kernel::Mul) {
+            key.inputs.sort();
+        }
+        match seen.get(&key) {
+            Some(&i) => Some(Slot::Step(i)),
+            None => {
+                seen.insert(key, program.steps.len());
+                None
+            }
+        }
+    });
+}
+
+fn eliminate_dead_steps(program: &mut Program) {
+    let mut live_steps = vec![false; program.steps.len()];
+    let mut live_constants = vec![false; program.constants.len()];
+    let mut mark = |slot: Slot, live_steps: &mut Vec<bool>| match slot {
+        Slot::Input => {}
+        Slot::Constant(c) => live_constants[c] = true,
+        Slot::Step(s) => live_steps[s] = true,
+    };
+    mark(program.output, &mut live_steps);
+    for i in (0..program.steps.len()).rev() {
+        if live_steps[i] {
+            for &input in &program.steps[i].inputs {
+                mark(input, &mut live_steps);
+            }
+        }
+    }
+
+    let mut constant_moved = Vec::with_capacity(live_constants.len());
+    let constants = mem::take(&mut program.constants);
+    let frozen = mem::take(&mut program.frozen);
+    for ((constant, frozen), live) in constants.into_iter().zip(frozen).zip(live_constants) {
+        constant_moved.push(program.constants.len());
+        if live {
+            program.constants.push(constant);
+            program.frozen.push(frozen);
+        }
+    }
+    let keep_constant = |slot: Slot| match slot {
+        Slot::Constant(c) => Slot::Constant(constant_moved[c]),
+        slot => slot,
+    };
+
+    let mut i = 0;
+    program.rewrite(|_, _, _| {
+        let live = live_steps[i];
+        i += 1;
+        // Dead steps have no live readers, so nothing points at this slot
+        (!live).then_some(Slot::Input)
+    });
+    for step in &mut program.steps {
+        for input in &mut step.inputs {
+            *input = keep_constant(*input);
+        }
+    }
+    program.output = keep_constant(program.output);
+}
+
+// Children before parents
+fn topo_sort(tensor: &Tensor, visited: &mut HashSet<Uuid>, order: &mut Vec<Tensor>) {
+    if visited.insert(tensor.borrow()._uuid) {
+        for child in tensor.borrow()._children.iter() {
+            topo_sort(child, visited, order);
+        }
+        order.push(tensor.clone());
+    }
+}
+
+fn untraceable(reason: &str) -> TensorError {
+    TensorError::Untraceable {
+        reason: reason.to_string(),
+    }
+}
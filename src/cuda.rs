@@ -0,0 +1,261 @@
+// Experimental CUDA backend, behind the "cuda" feature: ops on tensors on Device::Gpu run on
+// NVIDIA GPUs, matmul through cuBLAS and the element-wise ops and sums through kernels compiled
+// with NVRTC on init.
+//
+//     rust_ml::cuda::init()?; // registers the GPUs with the device module
+//     let x = x.to(Device::Gpu(0));
+//     let y = model.forward(&x);
+//
+// The CUDA libraries are loaded at runtime, so a build with the feature still runs on machines
+// without them and init fails with NotFound there.
+//
+// Not faster than the CPU yet. Tensor data stays in host memory: each op copies its operands to
+// the GPU and the result back, and those copies cost more than the kernels save except for
+// large matmuls. Ops without a kernel here, broadcasting `+` and `*`, and every backward but
+// matmul's run on the CPU whatever the device. Keeping the data on the device is what's missing.
+
+use crate::device::{self, Binary, Unary};
+use cudarc::cublas::sys::cublasOperation_t;
+use cudarc::cublas::{CudaBlas, Gemm, GemmConfig};
+use cudarc::driver::{CudaContext, CudaFunction, CudaStream, LaunchConfig, PushKernelArg};
+use cudarc::nvrtc::compile_ptx;
+use ndarray::{Array2, ArrayD, ArrayView2, IxDyn};
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, OnceLock};
+
+// One thread per output element
+const KERNELS: &str = r#"
+extern "C" __global__ void tanh_f32(const float* x, float* out, int n) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < n) out[i] = tanhf(x[i]);
+}
+
+extern "C" __global__ void exp_f32(const float* x, float* out, int n) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < n) out[i] = expf(x[i]);
+}
+
+extern "C" __global__ void relu_f32(const float* x, float* out, int n) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < n) out[i] = x[i] > 0.0f ? x[i] : 0.0f;
+}
+
+extern "C" __global__ void add_f32(const float* a, const float* b, float* out, int n) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < n) out[i] = a[i] + b[i];
+}
+
+extern "C" __global__ void mul_f32(const float* a, const float* b, float* out, int n) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < n) out[i] = a[i] * b[i];
+}
+
+// x viewed as [outer, len, inner], summed over len
+extern "C" __global__ void sum_axis_f32(const float* x, float* out, int outer, int len, int inner) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= outer * inner) return;
+    int o = i / inner, j = i % inner;
+    float sum = 0.0f;
+    for (int k = 0; k < len; k++) sum += x[(o * len + k) * inner + j];
+    out[i] = sum;
+}
+"#;
+
+struct Gpu {
+    stream: Arc<CudaStream>,
+    blas: CudaBlas,
+    tanh: CudaFunction,
+    exp: CudaFunction,
+    relu: CudaFunction,
+    add: CudaFunction,
+    mul: CudaFunction,
+    sum_axis: CudaFunction,
+}
+
+// Indexed like Device::Gpu
+static GPUS: OnceLock<Vec<Gpu>> = OnceLock::new();
+
+// Set up every GPU the driver lists and register them with the device module, returns how
+// many. Calls after the first return the same count.
+pub fn init() -> io::Result<usize> {
+    if let Some(gpus) = GPUS.get() {
+        return Ok(gpus.len());
+    }
+    // cudarc panics when it can't load libcuda
+    let count = panic::catch_unwind(CudaContext::device_count)
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "the CUDA driver isn't installed"))?
+        .map_err(io::Error::other)?;
+    if count <= 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no CUDA devices found",
+        ));
+    }
+    let ptx = panic::catch_unwind(AssertUnwindSafe(|| compile_ptx(KERNELS)))
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "NVRTC isn't installed"))?
+        .map_err(io::Error::other)?;
+    let mut gpus = Vec::with_capacity(count as usize);
+    for ordinal in 0..count as usize {
+        let context = CudaContext::new(ordinal).map_err(io::Error::other)?;
+        let stream = context.default_stream();
+        let blas = panic::catch_unwind(AssertUnwindSafe(|| CudaBlas::new(stream.clone())))
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "cuBLAS isn't installed"))?
+            .map_err(io::Error::other)?;
+        let module = context.load_module(ptx.clone()).map_err(io::Error::other)?;
+        let function = |name| module.load_function(name).map_err(io::Error::other);
+        gpus.push(Gpu {
+            tanh: function("tanh_f32")?,
+            exp: function("exp_f32")?,
+            relu: function("relu_f32")?,
+            add: function("add_f32")?,
+            mul: function("mul_f32")?,
+            sum_axis: function("sum_axis_f32")?,
+            stream,
+            blas,
+        });
+    }
+    let count = GPUS.get_or_init(|| gpus).len();
+    device::register_gpus(count);
+    Ok(count)
+}
+
+// The GPU at `index`, None before init (e.g. when register_gpus was called by hand) so the op
+// falls back to the CPU
+fn gpu(index: usize) -> Option<&'static Gpu> {
+    GPUS.get()?.get(index)
+}
+
+// Failures past init mean a broken driver or device, nothing an op could recover from
+fn check<T, E: fmt::Display>(op: &str, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| panic!("CUDA {} failed: {}", op, e))
+}
+
+// Kernels take element counts as int
+fn fits_int(len: usize) -> bool {
+    len > 0 && len <= i32::MAX as usize
+}
+
+pub(crate) fn matmul(
+    index: usize,
+    lhs: ArrayView2<f32>,
+    rhs: ArrayView2<f32>,
+) -> Option<Array2<f32>> {
+    let gpu = gpu(index)?;
+    let ((n, k), m) = (lhs.dim(), rhs.dim().1);
+    if ![n * k, k * m, n * m].into_iter().all(fits_int) {
+        return None;
+    }
+    let (lhs, rhs) = (lhs.as_standard_layout(), rhs.as_standard_layout());
+    let stream = &gpu.stream;
+    let a = check("matmul", stream.memcpy_stod(lhs.as_slice().unwrap()));
+    let b = check("matmul", stream.memcpy_stod(rhs.as_slice().unwrap()));
+    let mut c = check("matmul", stream.alloc_zeros::<f32>(n * m));
+    // cuBLAS is column-major, where row-major A B is (B^T A^T)^T, so C^T = B^T A^T is computed
+    // by passing B and A as they are
+    let config = GemmConfig {
+        transa: cublasOperation_t::CUBLAS_OP_N,
+        transb: cublasOperation_t::CUBLAS_OP_N,
+        m: m as i32,
+        n: n as i32,
+        k: k as i32,
+        alpha: 1.0,
+        lda: m as i32,
+        ldb: k as i32,
+        beta: 0.0,
+        ldc: m as i32,
+    };
+    // Safety: the buffers hold k x m, n x k and n x m floats, as the config says
+    check("matmul", unsafe { gpu.blas.gemm(config, &b, &a, &mut c) });
+    let product = check("matmul", stream.memcpy_dtov(&c));
+    Some(Array2::from_shape_vec((n, m), product).unwrap())
+}
+
+pub(crate) fn map(index: usize, op: Unary, x: &ArrayD<f32>) -> Option<ArrayD<f32>> {
+    let gpu = gpu(index)?;
+    let (name, function) = match op {
+        Unary::Tanh => ("tanh", &gpu.tanh),
+        Unary::Exp => ("exp", &gpu.exp),
+        Unary::Relu => ("relu", &gpu.relu),
+    };
+    let len = x.len();
+    if !fits_int(len) {
+        return None;
+    }
+    let x_host = x.as_standard_layout();
+    let stream = &gpu.stream;
+    let x_device = check(name, stream.memcpy_stod(x_host.as_slice().unwrap()));
+    let mut out = check(name, stream.alloc_zeros::<f32>(len));
+    let mut launch = stream.launch_builder(function);
+    let n = len as i32;
+    launch.arg(&x_device).arg(&mut out).arg(&n);
+    // Safety: both buffers hold n floats
+    check(name, unsafe {
+        launch.launch(LaunchConfig::for_num_elems(len as u32))
+    });
+    let out = check(name, stream.memcpy_dtov(&out));
+    Some(ArrayD::from_shape_vec(x.raw_dim(), out).unwrap())
+}
+
+// Operands of the same shape only, broadcasting stays on the CPU
+pub(crate) fn zip(
+    index: usize,
+    op: Binary,
+    lhs: &ArrayD<f32>,
+    rhs: &ArrayD<f32>,
+) -> Option<ArrayD<f32>> {
+    let gpu = gpu(index)?;
+    let (name, function) = match op {
+        Binary::Add => ("+", &gpu.add),
+        Binary::Mul => ("*", &gpu.mul),
+    };
+    let len = lhs.len();
+    if lhs.shape() != rhs.shape() || !fits_int(len) {
+        return None;
+    }
+    let (lhs_host, rhs_host) = (lhs.as_standard_layout(), rhs.as_standard_layout());
+    let stream = &gpu.stream;
+    let a = check(name, stream.memcpy_stod(lhs_host.as_slice().unwrap()));
+    let b = check(name, stream.memcpy_stod(rhs_host.as_slice().unwrap()));
+    let mut out = check(name, stream.alloc_zeros::<f32>(len));
+    let mut launch = stream.launch_builder(function);
+    let n = len as i32;
+    launch.arg(&a).arg(&b).arg(&mut out).arg(&n);
+    // Safety: all three buffers hold n floats
+    check(name, unsafe {
+        launch.launch(LaunchConfig::for_num_elems(len as u32))
+    });
+    let out = check(name, stream.memcpy_dtov(&out));
+    Some(ArrayD::from_shape_vec(lhs.raw_dim(), out).unwrap())
+}
+
+pub(crate) fn sum_axis(index: usize, x: &ArrayD<f32>, axis: usize) -> Option<ArrayD<f32>> {
+    let gpu = gpu(index)?;
+    let shape = x.shape();
+    let outer = shape[..axis].iter().product::<usize>();
+    let inner = shape[axis + 1..].iter().product::<usize>();
+    if !fits_int(x.len()) || !fits_int(outer * inner) {
+        return None;
+    }
+    let x_host = x.as_standard_layout();
+    let stream = &gpu.stream;
+    let x_device = check("sum", stream.memcpy_stod(x_host.as_slice().unwrap()));
+    let mut out = check("sum", stream.alloc_zeros::<f32>(outer * inner));
+    let mut launch = stream.launch_builder(&gpu.sum_axis);
+    let (outer_int, len, inner_int) = (outer as i32, shape[axis] as i32, inner as i32);
+    launch
+        .arg(&x_device)
+        .arg(&mut out)
+        .arg(&outer_int)
+        .arg(&len)
+        .arg(&inner_int);
+    // Safety: x holds outer * len * inner floats and out outer * inner
+    check("sum", unsafe {
+        launch.launch(LaunchConfig::for_num_elems((outer * inner) as u32))
+    });
+    let out = check("sum", stream.memcpy_dtov(&out));
+    let mut out_shape = shape.to_vec();
+    out_shape.remove(axis);
+    Some(ArrayD::from_shape_vec(IxDyn(&out_shape), out).unwrap())
+}
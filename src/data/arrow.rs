@@ -0,0 +1,327 @@
+// Arrow IPC and Parquet files as datasets, behind the `arrow` feature, so feature pipelines
+// built on polars or pyarrow can feed training directly:
+//
+//     let data = ArrowDataset::parquet("train.parquet")?.targets(&["label"]).batch_size(256);
+//     for batch in data.batches()? {
+//         let (features, targets) = batch?; // [256, F] and [256, 1]
+//     }
+//
+// Only the selected columns are read, in chunks, so files larger than memory stream through.
+// Every numeric or boolean column is converted to f32, nulls become NaN.
+
+use crate::data::IterableDataset;
+use crate::tensor::Tensor;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float16Type, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type,
+    UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, RecordBatch};
+use arrow_ipc::reader::FileReader;
+use arrow_schema::{ArrowError, DataType, Schema};
+use ndarray::Array2;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowFormat {
+    // The Arrow IPC file format (.arrow / .feather v2)
+    Ipc,
+    Parquet,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArrowDataset {
+    path: PathBuf,
+    format: ArrowFormat,
+    // None means every column that isn't a target
+    features: Option<Vec<String>>,
+    targets: Vec<String>,
+    batch_size: usize,
+}
+
+impl ArrowDataset {
+    pub fn parquet(path: impl AsRef<Path>) -> io::Result<ArrowDataset> {
+        ArrowDataset::open(path, ArrowFormat::Parquet)
+    }
+
+    pub fn ipc(path: impl AsRef<Path>) -> io::Result<ArrowDataset> {
+        ArrowDataset::open(path, ArrowFormat::Ipc)
+    }
+
+    fn open(path: impl AsRef<Path>, format: ArrowFormat) -> io::Result<ArrowDataset> {
+        let path = path.as_ref().to_path_buf();
+        // Fail early on a missing file instead of on the first epoch
+        File::open(&path)?;
+        Ok(ArrowDataset {
+            path,
+            format,
+            features: None,
+            targets: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    pub fn features(mut self, columns: &[&str]) -> Self {
+        self.features = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    pub fn targets(mut self, columns: &[&str]) -> Self {
+        self.targets = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    // Rows per batch, also the chunk size Parquet files are read in
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+
+    // Column names in file order
+    pub fn columns(&self) -> io::Result<Vec<String>> {
+        let schema = match self.format {
+            ArrowFormat::Parquet => {
+                ParquetRecordBatchReaderBuilder::try_new(File::open(&self.path)?)
+                    .map_err(other)?
+                    .schema()
+                    .clone()
+            }
+            ArrowFormat::Ipc => FileReader::try_new(File::open(&self.path)?, None)
+                .map_err(arrow_error)?
+                .schema(),
+        };
+        Ok(column_names(&schema))
+    }
+
+    // One pass over the file as ([batch, features], [batch, targets]) pairs, the last batch
+    // may be smaller
+    pub fn batches(&self) -> io::Result<ArrowBatches> {
+        let columns = self.columns()?;
+        let features = match &self.features {
+            Some(features) => features.clone(),
+            None => columns
+                .iter()
+                .filter(|c| !self.targets.contains(c))
+                .cloned()
+                .collect(),
+        };
+        let mut indices = Vec::new();
+        for name in features.iter().chain(&self.targets) {
+            let index = columns.iter().position(|c| c == name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} has no column {:?}", self.path.display(), name),
+                )
+            })?;
+            indices.push(index);
+        }
+        indices.sort_unstable();
+        indices.dedup();
+
+        let reader: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> = match self.format {
+            ArrowFormat::Parquet => {
+                let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&self.path)?)
+                    .map_err(other)?;
+                let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+                Box::new(
+                    builder
+                        .with_projection(mask)
+                        .with_batch_size(self.batch_size)
+                        .build()
+                        .map_err(other)?,
+                )
+            }
+            ArrowFormat::Ipc => Box::new(
+                FileReader::try_new(File::open(&self.path)?, Some(indices)).map_err(arrow_error)?,
+            ),
+        };
+        Ok(ArrowBatches {
+            reader,
+            features,
+            targets: self.targets.clone(),
+            batch_size: self.batch_size,
+            pending_features: Vec::new(),
+            pending_targets: Vec::new(),
+            pending_rows: 0,
+        })
+    }
+}
+
+// Record batches re-chunked to exactly `batch_size` rows
+pub struct ArrowBatches {
+    reader: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>,
+    features: Vec<String>,
+    targets: Vec<String>,
+    batch_size: usize,
+    // Row-major values read but not handed out yet
+    pending_features: Vec<f32>,
+    pending_targets: Vec<f32>,
+    pending_rows: usize,
+}
+
+impl ArrowBatches {
+    fn append(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        append_rows(batch, &self.features, &mut self.pending_features)?;
+        append_rows(batch, &self.targets, &mut self.pending_targets)?;
+        self.pending_rows += batch.num_rows();
+        Ok(())
+    }
+}
+
+impl Iterator for ArrowBatches {
+    type Item = io::Result<(Tensor, Tensor)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending_rows < self.batch_size {
+            match self.reader.next() {
+                Some(Ok(batch)) => {
+                    if let Err(e) = self.append(&batch) {
+                        return Some(Err(e));
+                    }
+                }
+                Some(Err(e)) => return Some(Err(arrow_error(e))),
+                None => break,
+            }
+        }
+        if self.pending_rows == 0 {
+            return None;
+        }
+        let rows = self.pending_rows.min(self.batch_size);
+        self.pending_rows -= rows;
+        let features = take_rows(&mut self.pending_features, rows, self.features.len());
+        let targets = take_rows(&mut self.pending_targets, rows, self.targets.len());
+        Some(Ok((Tensor::from(features), Tensor::from(targets))))
+    }
+}
+
+// Sample by sample, for StreamLoader. Read errors panic like TextLines does.
+impl IterableDataset for ArrowDataset {
+    type Iter = ArrowSamples;
+
+    fn iter(&self) -> ArrowSamples {
+        let batches = self
+            .batches()
+            .unwrap_or_else(|e| panic!("failed to read {}: {}", self.path.display(), e));
+        ArrowSamples {
+            batches,
+            current: None,
+            row: 0,
+        }
+    }
+}
+
+pub struct ArrowSamples {
+    batches: ArrowBatches,
+    current: Option<(Tensor, Tensor)>,
+    row: usize,
+}
+
+impl Iterator for ArrowSamples {
+    type Item = (Tensor, Tensor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((features, targets)) = &self.current {
+                if self.row < features.shape()[0] {
+                    let sample = (features.row(self.row), targets.row(self.row));
+                    self.row += 1;
+                    return Some(sample);
+                }
+            }
+            let batch = self.batches.next()?;
+            self.current = Some(batch.unwrap_or_else(|e| panic!("failed to read batch: {}", e)));
+            self.row = 0;
+        }
+    }
+}
+
+fn column_names(schema: &Schema) -> Vec<String> {
+    schema.fields().iter().map(|f| f.name().clone()).collect()
+}
+
+// Interleave the named columns of `batch` into row-major values
+fn append_rows(batch: &RecordBatch, names: &[String], out: &mut Vec<f32>) -> io::Result<()> {
+    let columns = names
+        .iter()
+        .map(|name| {
+            let column = batch.column_by_name(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("batch has no column {:?}", name),
+                )
+            })?;
+            to_f32(name, column.as_ref())
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    out.reserve(batch.num_rows() * names.len());
+    for row in 0..batch.num_rows() {
+        out.extend(columns.iter().map(|column| column[row]));
+    }
+    Ok(())
+}
+
+fn take_rows(pending: &mut Vec<f32>, rows: usize, columns: usize) -> ndarray::ArrayD<f32> {
+    let values: Vec<f32> = pending.drain(..rows * columns).collect();
+    // [rows, 0] when no columns were selected, e.g. no targets
+    Array2::from_shape_vec((rows, columns), values)
+        .unwrap()
+        .into_dyn()
+}
+
+fn to_f32(name: &str, column: &dyn Array) -> io::Result<Vec<f32>> {
+    macro_rules! convert {
+        ($ty:ty) => {
+            column
+                .as_primitive::<$ty>()
+                .iter()
+                .map(|v| v.map_or(f32::NAN, |v| v as f32))
+                .collect()
+        };
+    }
+    Ok(match column.data_type() {
+        DataType::Float32 => convert!(Float32Type),
+        DataType::Float64 => convert!(Float64Type),
+        DataType::Float16 => column
+            .as_primitive::<Float16Type>()
+            .iter()
+            .map(|v| v.map_or(f32::NAN, |v| v.to_f32()))
+            .collect(),
+        DataType::Int8 => convert!(Int8Type),
+        DataType::Int16 => convert!(Int16Type),
+        DataType::Int32 => convert!(Int32Type),
+        DataType::Int64 => convert!(Int64Type),
+        DataType::UInt8 => convert!(UInt8Type),
+        DataType::UInt16 => convert!(UInt16Type),
+        DataType::UInt32 => convert!(UInt32Type),
+        DataType::UInt64 => convert!(UInt64Type),
+        DataType::Boolean => column
+            .as_boolean()
+            .iter()
+            .map(|v| v.map_or(f32::NAN, |v| v as u8 as f32))
+            .collect(),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("column {:?} has non-numeric type {}", name, other),
+            ))
+        }
+    })
+}
+
+fn arrow_error(e: ArrowError) -> io::Error {
+    match e {
+        ArrowError::IoError(_, e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+fn other(e: parquet::errors::ParquetError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,267 @@
+// Delimited text files as datasets, for tabular regression and classification
+
+use crate::data::Dataset;
+use crate::tensor::Tensor;
+use ndarray::{Array1, Array2, Axis};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scaling {
+    #[default]
+    None,
+    // Rescale every feature column to [0, 1]
+    MinMax,
+    // Zero mean and unit variance per feature column
+    Standard,
+}
+
+// Per-column `(x - offset) / scale`, computed on one dataset so the exact same
+// transformation can be applied to another one (e.g. fit on train, reuse on test)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scaler {
+    pub offset: Array1<f32>,
+    pub scale: Array1<f32>,
+}
+
+impl Scaler {
+    pub fn fit(features: &Array2<f32>, scaling: Scaling) -> Scaler {
+        let columns = features.ncols();
+        let (offset, scale) = match scaling {
+            Scaling::None => (Array1::zeros(columns), Array1::ones(columns)),
+            Scaling::MinMax => {
+                let min = features.fold_axis(Axis(0), f32::INFINITY, |m, &x| m.min(x));
+                let max = features.fold_axis(Axis(0), f32::NEG_INFINITY, |m, &x| m.max(x));
+                let range = &max - &min;
+                (min, range)
+            }
+            Scaling::Standard => {
+                let mean = features
+                    .mean_axis(Axis(0))
+                    .unwrap_or_else(|| Array1::zeros(columns));
+                let std = features.std_axis(Axis(0), 0.0);
+                (mean, std)
+            }
+        };
+        // Constant columns would divide by zero, leave them unscaled
+        let scale = scale.mapv(|s| if s > 0.0 { s } else { 1.0 });
+        Scaler { offset, scale }
+    }
+
+    pub fn apply(&self, features: &mut Array2<f32>) {
+        *features -= &self.offset;
+        *features /= &self.scale;
+    }
+}
+
+// Builder for CsvDataset:
+// `CsvOptions::new().targets(&["price"]).scaling(Scaling::Standard).load("houses.csv")`
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    delimiter: char,
+    has_header: bool,
+    features: Option<Vec<String>>,
+    targets: Vec<String>,
+    scaling: Scaling,
+    scaler: Option<Scaler>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            has_header: true,
+            features: None,
+            targets: Vec::new(),
+            scaling: Scaling::None,
+            scaler: None,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> CsvOptions {
+        CsvOptions::default()
+    }
+
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    // Without a header row columns are named by their position: "0", "1", ...
+    pub fn has_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    // Feature columns, defaults to every column that isn't a target
+    pub fn features(mut self, columns: &[&str]) -> Self {
+        self.features = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    pub fn targets(mut self, columns: &[&str]) -> Self {
+        self.targets = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    // Fit a scaling of the feature columns on this file
+    pub fn scaling(mut self, scaling: Scaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    // Reuse a scaler fitted on another dataset instead, takes precedence over scaling()
+    pub fn scaler(mut self, scaler: Scaler) -> Self {
+        self.scaler = Some(scaler);
+        self
+    }
+
+    pub fn load(&self, path: impl AsRef<Path>) -> io::Result<CsvDataset> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), msg),
+            )
+        };
+
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let mut records: Vec<(usize, Vec<String>)> = Vec::new();
+        let header = if self.has_header {
+            let (_, line) = lines.next().ok_or_else(|| invalid("empty file".into()))?;
+            split_record(line, self.delimiter)
+        } else {
+            Vec::new()
+        };
+        records.extend(lines.map(|(i, line)| (i + 1, split_record(line, self.delimiter))));
+
+        let num_columns = if self.has_header {
+            header.len()
+        } else {
+            records.first().map_or(0, |(_, r)| r.len())
+        };
+        let names: Vec<String> = if self.has_header {
+            header.iter().map(|h| h.trim().to_string()).collect()
+        } else {
+            (0..num_columns).map(|i| i.to_string()).collect()
+        };
+        let column = |name: &String| {
+            names
+                .iter()
+                .position(|n| n == name)
+                .ok_or_else(|| invalid(format!("no column named {:?}", name)))
+        };
+
+        let target_columns = self
+            .targets
+            .iter()
+            .map(column)
+            .collect::<io::Result<Vec<_>>>()?;
+        let feature_columns = match &self.features {
+            Some(features) => features
+                .iter()
+                .map(column)
+                .collect::<io::Result<Vec<_>>>()?,
+            None => (0..num_columns)
+                .filter(|i| !target_columns.contains(i))
+                .collect(),
+        };
+
+        let mut features = Array2::<f32>::zeros((records.len(), feature_columns.len()));
+        let mut targets = Array2::<f32>::zeros((records.len(), target_columns.len()));
+        for (row, (line_number, record)) in records.iter().enumerate() {
+            if record.len() != num_columns {
+                return Err(invalid(format!(
+                    "line {} has {} fields, expected {}",
+                    line_number,
+                    record.len(),
+                    num_columns
+                )));
+            }
+            let parse = |i: usize| {
+                record[i].trim().parse::<f32>().map_err(|_| {
+                    invalid(format!(
+                        "line {}, column {:?}: {:?} is not a number",
+                        line_number, names[i], record[i]
+                    ))
+                })
+            };
+            for (j, &i) in feature_columns.iter().enumerate() {
+                features[[row, j]] = parse(i)?;
+            }
+            for (j, &i) in target_columns.iter().enumerate() {
+                targets[[row, j]] = parse(i)?;
+            }
+        }
+
+        let scaler = self
+            .scaler
+            .clone()
+            .unwrap_or_else(|| Scaler::fit(&features, self.scaling));
+        scaler.apply(&mut features);
+
+        Ok(CsvDataset {
+            features,
+            targets,
+            feature_names: feature_columns.iter().map(|&i| names[i].clone()).collect(),
+            target_names: target_columns.iter().map(|&i| names[i].clone()).collect(),
+            scaler,
+        })
+    }
+}
+
+// Split one line on the delimiter, double-quoted fields may contain the delimiter and
+// escape quotes by doubling them
+fn split_record(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ch if ch == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            ch => field.push(ch),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// Samples are a [F] feature vector and the target values, a scalar when there's a single
+// target column
+pub struct CsvDataset {
+    features: Array2<f32>,
+    targets: Array2<f32>,
+    pub feature_names: Vec<String>,
+    pub target_names: Vec<String>,
+    pub scaler: Scaler,
+}
+
+impl Dataset for CsvDataset {
+    fn len(&self) -> usize {
+        self.features.nrows()
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor) {
+        let features = self.features.row(index).to_owned().into_dyn();
+        let targets = self.targets.row(index).to_owned().into_dyn();
+        let targets = if targets.len() == 1 {
+            targets.into_shape(Vec::<usize>::new()).unwrap()
+        } else {
+            targets
+        };
+        (Tensor::from(features), Tensor::from(targets))
+    }
+}
@@ -0,0 +1,101 @@
+// Image classification datasets laid out as one directory per class:
+// root/cat/001.jpg, root/cat/002.png, root/dog/001.jpg, ...
+
+use crate::data::Dataset;
+use crate::tensor::Tensor;
+use image::imageops::FilterType;
+use ndarray::Array3;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+// Images are decoded lazily in get(), resized to `height` x `width` and returned as
+// [C, H, W] floats in [0, 1] (C = 3, or 1 in grayscale mode), the target is the class index
+pub struct ImageFolder {
+    pub classes: Vec<String>,
+    samples: Vec<(PathBuf, usize)>,
+    height: u32,
+    width: u32,
+    grayscale: bool,
+}
+
+impl ImageFolder {
+    pub fn new(root: impl AsRef<Path>, height: u32, width: u32) -> io::Result<ImageFolder> {
+        let root = root.as_ref();
+        // Sorted so class indices don't depend on the file system's listing order
+        let mut classes: Vec<String> = fs::read_dir(root)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        classes.sort();
+
+        let mut samples = Vec::new();
+        for (class_index, class) in classes.iter().enumerate() {
+            let mut paths: Vec<PathBuf> = fs::read_dir(root.join(class))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                })
+                .collect();
+            paths.sort();
+            samples.extend(paths.into_iter().map(|path| (path, class_index)));
+        }
+
+        Ok(ImageFolder {
+            classes,
+            samples,
+            height,
+            width,
+            grayscale: false,
+        })
+    }
+
+    // Decode to a single luminance channel instead of RGB
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    pub fn path(&self, index: usize) -> &Path {
+        &self.samples[index].0
+    }
+
+    pub fn load_image(&self, index: usize) -> image::ImageResult<Array3<f32>> {
+        let image = image::open(self.path(index))?;
+        let image = image.resize_exact(self.width, self.height, FilterType::Triangle);
+        let (height, width) = (self.height as usize, self.width as usize);
+
+        let chw = if self.grayscale {
+            let pixels = image.to_luma8();
+            Array3::from_shape_fn((1, height, width), |(_, y, x)| {
+                pixels.get_pixel(x as u32, y as u32)[0] as f32 / 255.0
+            })
+        } else {
+            let pixels = image.to_rgb8();
+            Array3::from_shape_fn((3, height, width), |(c, y, x)| {
+                pixels.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+            })
+        };
+        Ok(chw)
+    }
+}
+
+impl Dataset for ImageFolder {
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor) {
+        let image = self
+            .load_image(index)
+            .unwrap_or_else(|e| panic!("failed to load {}: {}", self.path(index).display(), e));
+        let class = ndarray::arr0(self.samples[index].1 as f32).into_dyn();
+        (Tensor::from(image.into_dyn()), Tensor::from(class))
+    }
+}
@@ -0,0 +1,101 @@
+// MNIST in the original IDX format, http://yann.lecun.com/exdb/mnist/
+// The crate doesn't download datasets. Fetch the four .gz files listed in FILES from a mirror,
+// e.g. https://ossci-datasets.s3.amazonaws.com/mnist/train-images-idx3-ubyte.gz, into one
+// directory and `load` decompresses them on first use.
+
+use crate::data::TensorDataset;
+use flate2::read::GzDecoder;
+use ndarray::ArrayD;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+
+const MIRROR: &str = "https://ossci-datasets.s3.amazonaws.com/mnist";
+const FILES: [&str; 4] = [
+    "train-images-idx3-ubyte",
+    "train-labels-idx1-ubyte",
+    "t10k-images-idx3-ubyte",
+    "t10k-labels-idx1-ubyte",
+];
+
+// Images are [N, 28, 28] scaled to [0, 1], targets the digit as a float [N]
+pub struct Mnist {
+    pub train: TensorDataset,
+    pub test: TensorDataset,
+}
+
+// Load the four IDX files from `dir`, decompressing the ones only present as .gz
+pub fn load(dir: impl AsRef<Path>) -> io::Result<Mnist> {
+    let dir = dir.as_ref();
+    for name in FILES {
+        let path = dir.join(name);
+        if path.exists() {
+            continue;
+        }
+        let gz = dir.join(format!("{}.gz", name));
+        if !gz.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} is missing, download {}/{}.gz into {}",
+                    name,
+                    MIRROR,
+                    name,
+                    dir.display()
+                ),
+            ));
+        }
+        gunzip(&gz, &path)?;
+    }
+    let read = |name: &str| read_idx(dir.join(name));
+    Ok(Mnist {
+        train: TensorDataset::new(read(FILES[0])? / 255.0, read(FILES[1])?),
+        test: TensorDataset::new(read(FILES[2])? / 255.0, read(FILES[3])?),
+    })
+}
+
+// Decompress to a temporary name first, so an interrupted run doesn't leave a truncated file
+// that load() would pick up
+fn gunzip(gz: &Path, path: &Path) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(gz)?));
+    io::copy(&mut decoder, &mut File::create(&partial)?)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", gz.display(), e)))?;
+    fs::rename(&partial, path)?;
+    fs::remove_file(gz)
+}
+
+// Parse an unsigned byte IDX file: 2 zero bytes, type code 0x08, number of dimensions,
+// one big-endian u32 per dimension and then the data
+pub fn read_idx(path: impl AsRef<Path>) -> io::Result<ArrayD<f32>> {
+    let bytes = fs::read(path.as_ref())?;
+    let invalid = |msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.as_ref().display(), msg),
+        )
+    };
+
+    if bytes.len() < 4 || bytes[0] != 0 || bytes[1] != 0 {
+        return Err(invalid("not an IDX file"));
+    }
+    if bytes[2] != 0x08 {
+        return Err(invalid("only unsigned byte IDX data is supported"));
+    }
+    let ndim = bytes[3] as usize;
+    let header_len = 4 + 4 * ndim;
+    if bytes.len() < header_len {
+        return Err(invalid("truncated header"));
+    }
+    let shape: Vec<usize> = bytes[4..header_len]
+        .chunks(4)
+        .map(|dim| u32::from_be_bytes([dim[0], dim[1], dim[2], dim[3]]) as usize)
+        .collect();
+
+    let data = &bytes[header_len..];
+    if data.len() != shape.iter().product::<usize>() {
+        return Err(invalid("data length doesn't match the header shape"));
+    }
+    let values = data.iter().map(|&b| b as f32).collect();
+    Ok(ArrayD::from_shape_vec(shape, values).unwrap())
+}
@@ -0,0 +1,333 @@
+// Datasets and batching for training loops
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod image_folder;
+pub mod mnist;
+pub mod sampler;
+pub mod split;
+pub mod stream;
+pub mod synthetic;
+pub mod transforms;
+
+#[cfg(feature = "arrow")]
+pub use arrow::{ArrowDataset, ArrowFormat};
+pub use csv::{CsvDataset, CsvOptions, Scaling};
+pub use image_folder::ImageFolder;
+pub use sampler::{
+    DistributedSampler, RandomSampler, Sampler, SequentialSampler, WeightedRandomSampler,
+};
+pub use split::{split, KFold, Subset};
+pub use stream::{IterableDataset, StreamLoader, TextLines};
+pub use transforms::{Transform, Transformed};
+
+use crate::determinism::is_deterministic;
+use crate::random::{manual_seed, with_rng};
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, Axis};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Indexable collection of (input, target) samples
+pub trait Dataset {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor);
+
+    // Apply `transform` to every input, e.g. `dataset.transform(RandomHorizontalFlip::new(0.5))`
+    fn transform<T: Transform>(self, transform: T) -> Transformed<Self, T>
+    where
+        Self: Sized,
+    {
+        Transformed::new(self, transform)
+    }
+}
+
+// Dataset over two arrays whose first axis indexes the samples
+pub struct TensorDataset {
+    inputs: ArrayD<f32>,
+    targets: ArrayD<f32>,
+}
+
+impl TensorDataset {
+    pub fn new(inputs: ArrayD<f32>, targets: ArrayD<f32>) -> TensorDataset {
+        assert!(
+            inputs.ndim() > 0
+                && targets.ndim() > 0
+                && inputs.len_of(Axis(0)) == targets.len_of(Axis(0)),
+            "TensorDataset needs as many inputs as targets along axis 0, got shapes {:?} and {:?}",
+            inputs.shape(),
+            targets.shape()
+        );
+        TensorDataset { inputs, targets }
+    }
+
+    // Copies of the current values, e.g. of the data::synthetic generators
+    pub fn from_tensors(inputs: &Tensor, targets: &Tensor) -> TensorDataset {
+        TensorDataset::new(inputs.borrow().data.clone(), targets.borrow().data.clone())
+    }
+}
+
+impl Dataset for TensorDataset {
+    fn len(&self) -> usize {
+        self.inputs.len_of(Axis(0))
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor) {
+        (
+            Tensor::from(self.inputs.index_axis(Axis(0), index).to_owned()),
+            Tensor::from(self.targets.index_axis(Axis(0), index).to_owned()),
+        )
+    }
+}
+
+// Stack samples along a new leading batch axis, samples must all have the same shapes
+pub fn collate(samples: &[(Tensor, Tensor)]) -> (Tensor, Tensor) {
+    let inputs: Vec<ArrayD<f32>> = samples
+        .iter()
+        .map(|(x, _)| x.borrow().data.clone())
+        .collect();
+    let targets: Vec<ArrayD<f32>> = samples
+        .iter()
+        .map(|(_, y)| y.borrow().data.clone())
+        .collect();
+    (Tensor::from(stack(&inputs)), Tensor::from(stack(&targets)))
+}
+
+fn stack(arrays: &[ArrayD<f32>]) -> ArrayD<f32> {
+    let views: Vec<_> = arrays.iter().map(|a| a.view()).collect();
+    ndarray::stack(Axis(0), &views).unwrap_or_else(|_| {
+        panic!(
+            "can't batch samples of different shapes, e.g. {:?} and {:?}",
+            arrays[0].shape(),
+            arrays
+                .iter()
+                .find(|a| a.shape() != arrays[0].shape())
+                .unwrap()
+                .shape()
+        )
+    })
+}
+
+// Arrays rather than Tensors so batches can cross threads, Tensor is Rc based
+type ArrayBatch = (ArrayD<f32>, ArrayD<f32>);
+
+fn load_batch<D: Dataset>(dataset: &D, indices: &[usize]) -> ArrayBatch {
+    let (inputs, targets): (Vec<_>, Vec<_>) = indices
+        .iter()
+        .map(|&i| dataset.get(i))
+        .map(|(x, y)| (x.borrow().data.clone(), y.borrow().data.clone()))
+        .unzip();
+    (stack(&inputs), stack(&targets))
+}
+
+// Starts the worker threads for one epoch, see DataLoader::num_workers
+type SpawnWorkers<D> = fn(&Arc<D>, Vec<Vec<usize>>, usize, usize) -> Receiver<(usize, ArrayBatch)>;
+
+fn spawn_workers<D: Dataset + Send + Sync + 'static>(
+    dataset: &Arc<D>,
+    batches: Vec<Vec<usize>>,
+    num_workers: usize,
+    prefetch: usize,
+) -> Receiver<(usize, ArrayBatch)> {
+    // Workers take batches in order from a shared queue, so they can only run ahead of the
+    // consumer by about num_workers + prefetch batches
+    let (sender, receiver) = sync_channel(prefetch);
+    // Each batch gets a seed from this thread's RNG, so manual_seed covers the augmentations
+    // whichever worker ends up loading the batch
+    let jobs: Vec<(u64, Vec<usize>)> = batches
+        .into_iter()
+        .map(|indices| (with_rng(|rng| rng.gen()), indices))
+        .collect();
+    let jobs = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    for _ in 0..num_workers {
+        let (dataset, jobs, sender) = (Arc::clone(dataset), Arc::clone(&jobs), sender.clone());
+        thread::spawn(move || loop {
+            let job = jobs.lock().unwrap().next();
+            let Some((batch_index, (seed, indices))) = job else {
+                break;
+            };
+            manual_seed(seed);
+            // The receiver is gone when the epoch was abandoned early
+            if sender
+                .send((batch_index, load_batch(&*dataset, &indices)))
+                .is_err()
+            {
+                break;
+            }
+        });
+    }
+    receiver
+}
+
+// Batches a dataset, configured builder-style:
+// `DataLoader::new(dataset, 32).shuffle(true).drop_last(true)`
+pub struct DataLoader<D: Dataset> {
+    dataset: Arc<D>,
+    batch_size: usize,
+    sampler: Box<dyn Sampler>,
+    drop_last: bool,
+    num_workers: usize,
+    prefetch: usize,
+    spawn: Option<SpawnWorkers<D>>,
+}
+
+impl<D: Dataset> DataLoader<D> {
+    pub fn new(dataset: D, batch_size: usize) -> DataLoader<D> {
+        assert!(batch_size > 0, "DataLoader batch_size must be positive");
+        DataLoader {
+            dataset: Arc::new(dataset),
+            batch_size,
+            sampler: Box::new(SequentialSampler),
+            drop_last: false,
+            num_workers: 0,
+            prefetch: 2,
+            spawn: None,
+        }
+    }
+
+    // Reshuffle the sample order at the start of every epoch, using the global RNG.
+    // Shorthand for a RandomSampler, false goes back to the SequentialSampler.
+    pub fn shuffle(self, shuffle: bool) -> Self {
+        if shuffle {
+            self.sampler(RandomSampler)
+        } else {
+            self.sampler(SequentialSampler)
+        }
+    }
+
+    pub fn sampler(mut self, sampler: impl Sampler + 'static) -> Self {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    // Skip the last batch when it would be smaller than batch_size
+    pub fn drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+
+    // Number of batches per epoch
+    pub fn len(&self) -> usize {
+        let num_samples = self.sampler.num_samples(self.dataset.len());
+        if self.drop_last {
+            num_samples / self.batch_size
+        } else {
+            num_samples.div_ceil(self.batch_size)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // One epoch worth of (inputs, targets) batches
+    pub fn iter(&self) -> Batches<'_, D> {
+        let indices = self.sampler.indices(self.dataset.len());
+        let mut batches: Vec<Vec<usize>> = indices
+            .chunks(self.batch_size)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        if self.drop_last && batches.last().is_some_and(|b| b.len() < self.batch_size) {
+            batches.pop();
+        }
+
+        let source = match self.spawn {
+            Some(spawn) if self.num_workers > 0 && !is_deterministic() => BatchSource::Workers {
+                receiver: spawn(&self.dataset, batches, self.num_workers, self.prefetch),
+                pending: BTreeMap::new(),
+            },
+            _ => BatchSource::Sequential(batches),
+        };
+        Batches {
+            loader: self,
+            source,
+            next_batch: 0,
+            num_batches: self.len(),
+        }
+    }
+}
+
+impl<D: Dataset + Send + Sync + 'static> DataLoader<D> {
+    // Load and collate batches on `num_workers` background threads, 0 loads on the calling
+    // thread. Batches still come out in sampling order. Ignored in deterministic mode.
+    pub fn num_workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers;
+        self.spawn = Some(spawn_workers::<D>);
+        self
+    }
+
+    // How many finished batches may wait in the queue for the training loop
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+}
+
+impl<'a, D: Dataset> IntoIterator for &'a DataLoader<D> {
+    type Item = (Tensor, Tensor);
+    type IntoIter = Batches<'a, D>;
+    fn into_iter(self) -> Batches<'a, D> {
+        self.iter()
+    }
+}
+
+enum BatchSource {
+    Sequential(Vec<Vec<usize>>),
+    Workers {
+        receiver: Receiver<(usize, ArrayBatch)>,
+        // Batches that finished before the ones ahead of them
+        pending: BTreeMap<usize, ArrayBatch>,
+    },
+}
+
+pub struct Batches<'a, D: Dataset> {
+    loader: &'a DataLoader<D>,
+    source: BatchSource,
+    next_batch: usize,
+    num_batches: usize,
+}
+
+impl<D: Dataset> Iterator for Batches<'_, D> {
+    type Item = (Tensor, Tensor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_batch >= self.num_batches {
+            return None;
+        }
+        let (inputs, targets) = match &mut self.source {
+            BatchSource::Sequential(batches) => {
+                load_batch(&*self.loader.dataset, &batches[self.next_batch])
+            }
+            BatchSource::Workers { receiver, pending } => loop {
+                if let Some(batch) = pending.remove(&self.next_batch) {
+                    break batch;
+                }
+                let (batch_index, batch) =
+                    receiver.recv().expect("DataLoader worker thread panicked");
+                pending.insert(batch_index, batch);
+            },
+        };
+        self.next_batch += 1;
+        Some((Tensor::from(inputs), Tensor::from(targets)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.num_batches - self.next_batch;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<D: Dataset> ExactSizeIterator for Batches<'_, D> {}
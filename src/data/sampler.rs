@@ -0,0 +1,146 @@
+// Strategies for the order (and multiplicity) in which a DataLoader visits samples
+
+use crate::random::{with_rng, Generator};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::cell::Cell;
+
+pub trait Sampler {
+    // Sample indices for one epoch of a dataset with `dataset_len` samples
+    fn indices(&self, dataset_len: usize) -> Vec<usize>;
+
+    // Number of indices one epoch yields
+    fn num_samples(&self, dataset_len: usize) -> usize {
+        dataset_len
+    }
+}
+
+// 0, 1, 2, ... in order
+pub struct SequentialSampler;
+
+impl Sampler for SequentialSampler {
+    fn indices(&self, dataset_len: usize) -> Vec<usize> {
+        (0..dataset_len).collect()
+    }
+}
+
+// Every index once, in a fresh random permutation each epoch
+pub struct RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn indices(&self, dataset_len: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..dataset_len).collect();
+        with_rng(|rng| indices.shuffle(rng));
+        indices
+    }
+}
+
+// Draw `num_samples` indices with replacement, index i with probability proportional to
+// weights[i]. Weighting each sample by 1 / (size of its class) balances an imbalanced dataset.
+pub struct WeightedRandomSampler {
+    distribution: WeightedIndex<f32>,
+    num_weights: usize,
+    num_samples: usize,
+}
+
+impl WeightedRandomSampler {
+    pub fn new(weights: &[f32], num_samples: usize) -> WeightedRandomSampler {
+        let distribution = WeightedIndex::new(weights)
+            .unwrap_or_else(|e| panic!("invalid WeightedRandomSampler weights: {}", e));
+        WeightedRandomSampler {
+            distribution,
+            num_weights: weights.len(),
+            num_samples,
+        }
+    }
+}
+
+impl Sampler for WeightedRandomSampler {
+    fn indices(&self, dataset_len: usize) -> Vec<usize> {
+        assert!(
+            self.num_weights == dataset_len,
+            "WeightedRandomSampler has {} weights for a dataset of {}",
+            self.num_weights,
+            dataset_len
+        );
+        with_rng(|rng| {
+            (0..self.num_samples)
+                .map(|_| self.distribution.sample(rng))
+                .collect()
+        })
+    }
+
+    fn num_samples(&self, _dataset_len: usize) -> usize {
+        self.num_samples
+    }
+}
+
+// This rank's share of the dataset in distributed training (see the distributed module):
+// every `world_size`-th index of one shared order, starting at `rank`. The order is padded by
+// repeating indices from its start so all ranks get the same number of samples and take the
+// same number of steps. Shuffling uses its own generator seeded with seed + epoch, so every
+// rank draws the same permutation without communicating; the epoch advances on every call.
+pub struct DistributedSampler {
+    rank: usize,
+    world_size: usize,
+    shuffle: bool,
+    seed: u64,
+    epoch: Cell<u64>,
+}
+
+impl DistributedSampler {
+    pub fn new(rank: usize, world_size: usize) -> DistributedSampler {
+        assert!(
+            rank < world_size,
+            "rank {} out of range for a world of {}",
+            rank,
+            world_size
+        );
+        DistributedSampler {
+            rank,
+            world_size,
+            shuffle: false,
+            seed: 0,
+            epoch: Cell::new(0),
+        }
+    }
+
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    // Must be the same on every rank
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // Epoch of the next call to `indices`, e.g. when resuming from a checkpoint
+    pub fn epoch(self, epoch: u64) -> Self {
+        self.epoch.set(epoch);
+        self
+    }
+}
+
+impl Sampler for DistributedSampler {
+    fn indices(&self, dataset_len: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..dataset_len).collect();
+        if self.shuffle {
+            let epoch = self.epoch.get();
+            let mut rng = Generator::seed_from_u64(self.seed.wrapping_add(epoch));
+            order.shuffle(&mut rng);
+        }
+        self.epoch.set(self.epoch.get() + 1);
+        let total = self.num_samples(dataset_len) * self.world_size;
+        (self.rank..total)
+            .step_by(self.world_size)
+            .map(|i| order[i % dataset_len])
+            .collect()
+    }
+
+    fn num_samples(&self, dataset_len: usize) -> usize {
+        dataset_len.div_ceil(self.world_size)
+    }
+}
@@ -0,0 +1,131 @@
+// Train/validation splits and k-fold cross-validation as index views over one dataset
+
+use crate::data::Dataset;
+use crate::tensor::Tensor;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+// The samples of `dataset` at `indices`, in that order
+pub struct Subset<D> {
+    dataset: Arc<D>,
+    indices: Vec<usize>,
+}
+
+impl<D: Dataset> Subset<D> {
+    pub fn new(dataset: Arc<D>, indices: Vec<usize>) -> Subset<D> {
+        Subset { dataset, indices }
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+}
+
+impl<D: Dataset> Dataset for Subset<D> {
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor) {
+        self.dataset.get(self.indices[index])
+    }
+}
+
+// Permutation of 0..len from its own seeded generator, so splits are reproducible
+// independently of the global RNG
+fn permutation(len: usize, seed: u64) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..len).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    indices
+}
+
+// Randomly partition `dataset` into subsets of the given fractions, e.g. `&[0.8, 0.2]`.
+// Sizes are rounded down, the last subset takes whatever is left over.
+pub fn split<D: Dataset>(dataset: D, fractions: &[f32], seed: u64) -> Vec<Subset<D>> {
+    let total: f32 = fractions.iter().sum();
+    assert!(
+        !fractions.is_empty() && fractions.iter().all(|&f| f >= 0.0) && (total - 1.0).abs() < 1e-4,
+        "split fractions must be non-negative and sum to 1, got {:?}",
+        fractions
+    );
+    let len = dataset.len();
+    let dataset = Arc::new(dataset);
+    let indices = permutation(len, seed);
+
+    let mut start = 0;
+    fractions
+        .iter()
+        .enumerate()
+        .map(|(i, fraction)| {
+            let end = if i == fractions.len() - 1 {
+                len
+            } else {
+                (start + (fraction * len as f32) as usize).min(len)
+            };
+            let subset = Subset::new(Arc::clone(&dataset), indices[start..end].to_vec());
+            start = end;
+            subset
+        })
+        .collect()
+}
+
+// Iterator over the k (train, validation) splits of k-fold cross-validation, every sample
+// is in exactly one validation fold. The first len % k folds get one extra sample.
+pub struct KFold<D> {
+    dataset: Arc<D>,
+    indices: Vec<usize>,
+    k: usize,
+    fold: usize,
+}
+
+impl<D: Dataset> KFold<D> {
+    pub fn new(dataset: D, k: usize) -> KFold<D> {
+        assert!(
+            k >= 2 && k <= dataset.len(),
+            "KFold needs 2 <= k <= {} samples, got k = {}",
+            dataset.len(),
+            k
+        );
+        let indices = (0..dataset.len()).collect();
+        KFold {
+            dataset: Arc::new(dataset),
+            indices,
+            k,
+            fold: 0,
+        }
+    }
+
+    // Shuffle the samples before dividing them into folds
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        self.indices = permutation(self.indices.len(), seed);
+        self
+    }
+
+    fn fold_range(&self, fold: usize) -> (usize, usize) {
+        let (base, extra) = (self.indices.len() / self.k, self.indices.len() % self.k);
+        let start = fold * base + fold.min(extra);
+        let size = base + usize::from(fold < extra);
+        (start, start + size)
+    }
+}
+
+impl<D: Dataset> Iterator for KFold<D> {
+    type Item = (Subset<D>, Subset<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fold == self.k {
+            return None;
+        }
+        let (start, end) = self.fold_range(self.fold);
+        self.fold += 1;
+
+        let val = self.indices[start..end].to_vec();
+        let train = [&self.indices[..start], &self.indices[end..]].concat();
+        Some((
+            Subset::new(Arc::clone(&self.dataset), train),
+            Subset::new(Arc::clone(&self.dataset), val),
+        ))
+    }
+}
@@ -0,0 +1,129 @@
+// Datasets that can only be iterated, e.g. a text file too large to index, batched without
+// knowing their length up front
+
+use crate::data::collate;
+use crate::tensor::Tensor;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+pub trait IterableDataset {
+    type Iter: Iterator<Item = (Tensor, Tensor)>;
+
+    // A fresh pass over the data, called once per epoch
+    fn iter(&self) -> Self::Iter;
+}
+
+// Parses a text file one line at a time, `parse` returns None for lines to skip
+// (headers, comments, ...). The file is reopened for every epoch.
+pub struct TextLines<F> {
+    path: PathBuf,
+    parse: F,
+}
+
+impl<F> TextLines<F>
+where
+    F: Fn(&str) -> Option<(Tensor, Tensor)> + Clone,
+{
+    pub fn new(path: impl AsRef<Path>, parse: F) -> io::Result<TextLines<F>> {
+        let path = path.as_ref().to_path_buf();
+        // Fail early on a missing file instead of on the first epoch
+        File::open(&path)?;
+        Ok(TextLines { path, parse })
+    }
+}
+
+impl<F> IterableDataset for TextLines<F>
+where
+    F: Fn(&str) -> Option<(Tensor, Tensor)> + Clone,
+{
+    type Iter = TextLinesIter<F>;
+
+    fn iter(&self) -> TextLinesIter<F> {
+        let file = File::open(&self.path)
+            .unwrap_or_else(|e| panic!("failed to open {}: {}", self.path.display(), e));
+        TextLinesIter {
+            lines: BufReader::new(file).lines(),
+            parse: self.parse.clone(),
+        }
+    }
+}
+
+pub struct TextLinesIter<F> {
+    lines: io::Lines<BufReader<File>>,
+    parse: F,
+}
+
+impl<F> Iterator for TextLinesIter<F>
+where
+    F: Fn(&str) -> Option<(Tensor, Tensor)>,
+{
+    type Item = (Tensor, Tensor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            let line = line.unwrap_or_else(|e| panic!("failed to read line: {}", e));
+            if let Some(sample) = (self.parse)(&line) {
+                return Some(sample);
+            }
+        }
+        None
+    }
+}
+
+// DataLoader counterpart for iterable datasets: batches samples in stream order
+pub struct StreamLoader<D: IterableDataset> {
+    dataset: D,
+    batch_size: usize,
+    drop_last: bool,
+}
+
+impl<D: IterableDataset> StreamLoader<D> {
+    pub fn new(dataset: D, batch_size: usize) -> StreamLoader<D> {
+        assert!(batch_size > 0, "StreamLoader batch_size must be positive");
+        StreamLoader {
+            dataset,
+            batch_size,
+            drop_last: false,
+        }
+    }
+
+    pub fn drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+
+    pub fn iter(&self) -> StreamBatches<D::Iter> {
+        StreamBatches {
+            samples: self.dataset.iter(),
+            batch_size: self.batch_size,
+            drop_last: self.drop_last,
+        }
+    }
+}
+
+impl<D: IterableDataset> IntoIterator for &StreamLoader<D> {
+    type Item = (Tensor, Tensor);
+    type IntoIter = StreamBatches<D::Iter>;
+    fn into_iter(self) -> StreamBatches<D::Iter> {
+        self.iter()
+    }
+}
+
+pub struct StreamBatches<I> {
+    samples: I,
+    batch_size: usize,
+    drop_last: bool,
+}
+
+impl<I: Iterator<Item = (Tensor, Tensor)>> Iterator for StreamBatches<I> {
+    type Item = (Tensor, Tensor);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch: Vec<(Tensor, Tensor)> = self.samples.by_ref().take(self.batch_size).collect();
+        if batch.is_empty() || (self.drop_last && batch.len() < self.batch_size) {
+            return None;
+        }
+        Some(collate(&batch))
+    }
+}
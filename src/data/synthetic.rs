@@ -0,0 +1,155 @@
+// Small generated datasets with known structure, for examples and for checking that a model
+// can learn at all without downloading anything. Every generator returns (inputs, targets)
+// with the samples shuffled, drawn from the global RNG so manual_seed makes them
+// reproducible:
+//
+//     let (x, y) = synthetic::make_moons(200, 0.1);
+//     let loader = DataLoader::new(TensorDataset::from_tensors(&x, &y), 32).shuffle(true);
+//
+// Classification targets are [N] class indices stored as f32, regression targets [N, 1].
+
+use crate::random::with_rng;
+use crate::tensor::Tensor;
+use ndarray::{Array1, Array2, Axis};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use std::f32::consts::PI;
+
+// Two interleaving half circles in 2-D, class 0 the upper one, with Gaussian `noise` (the
+// standard deviation) added to every coordinate. Not linearly separable.
+pub fn make_moons(n_samples: usize, noise: f32) -> (Tensor, Tensor) {
+    let n_outer = n_samples / 2;
+    let mut points = Vec::with_capacity(n_samples);
+    for i in 0..n_samples {
+        let (class, t) = if i < n_outer {
+            (0, PI * fraction(i, n_outer))
+        } else {
+            (1, PI * fraction(i - n_outer, n_samples - n_outer))
+        };
+        let point = if class == 0 {
+            [t.cos(), t.sin()]
+        } else {
+            [1.0 - t.cos(), 0.5 - t.sin()]
+        };
+        points.push((point.to_vec(), class));
+    }
+    finish(points, noise)
+}
+
+// A small circle (class 1, radius `factor` between 0 and 1) inside a unit circle (class 0)
+pub fn make_circles(n_samples: usize, noise: f32, factor: f32) -> (Tensor, Tensor) {
+    assert!(
+        0.0 < factor && factor < 1.0,
+        "make_circles factor must be between 0 and 1, got {}",
+        factor
+    );
+    let n_outer = n_samples / 2;
+    let mut points = Vec::with_capacity(n_samples);
+    for i in 0..n_samples {
+        let (class, radius, t) = if i < n_outer {
+            (0, 1.0, 2.0 * PI * i as f32 / n_outer as f32)
+        } else {
+            let j = i - n_outer;
+            (
+                1,
+                factor,
+                2.0 * PI * j as f32 / (n_samples - n_outer) as f32,
+            )
+        };
+        points.push((vec![radius * t.cos(), radius * t.sin()], class));
+    }
+    finish(points, noise)
+}
+
+// Isotropic Gaussian clusters with standard deviation `std` in `features` dimensions, one per
+// class, around centers drawn uniformly from [-10, 10]^features. Samples are spread evenly
+// over the centers.
+pub fn make_blobs(n_samples: usize, centers: usize, features: usize, std: f32) -> (Tensor, Tensor) {
+    assert!(centers > 0, "make_blobs needs at least one center");
+    let centers: Vec<Vec<f32>> = with_rng(|rng| {
+        (0..centers)
+            .map(|_| (0..features).map(|_| rng.gen_range(-10.0..10.0)).collect())
+            .collect()
+    });
+    let points = (0..n_samples)
+        .map(|i| {
+            let class = i % centers.len();
+            (centers[class].clone(), class)
+        })
+        .collect();
+    finish(points, std)
+}
+
+// Inputs drawn from N(0, 1) and targets `inputs . weights + bias` plus Gaussian `noise`, so a
+// Linear layer trained with mse should recover `weights` and `bias`
+pub fn make_regression(
+    n_samples: usize,
+    weights: &[f32],
+    bias: f32,
+    noise: f32,
+) -> (Tensor, Tensor) {
+    let features = weights.len();
+    let (inputs, targets) = with_rng(|rng| {
+        let inputs = Array2::from_shape_simple_fn((n_samples, features), || {
+            rng.sample::<f32, _>(StandardNormal)
+        });
+        let targets = inputs
+            .dot(&Array1::from(weights.to_vec()))
+            .mapv(|y| y + bias + noise * rng.sample::<f32, _>(StandardNormal));
+        (inputs, targets)
+    });
+    (
+        Tensor::from(inputs.into_dyn()),
+        Tensor::from(targets.insert_axis(Axis(1)).into_dyn()),
+    )
+}
+
+// `classes` interleaved spiral arms in 2-D, each turning once from the origin out to radius
+// 1, with Gaussian `noise` (in radians) on the angle
+pub fn spiral(n_samples: usize, classes: usize, noise: f32) -> (Tensor, Tensor) {
+    assert!(classes > 0, "spiral needs at least one class");
+    let points = with_rng(|rng| {
+        (0..n_samples)
+            .map(|i| {
+                let class = i % classes;
+                let per_class = (n_samples - class).div_ceil(classes);
+                let r = fraction(i / classes, per_class);
+                let t = 2.0 * PI * (class as f32 / classes as f32 + r)
+                    + noise * rng.sample::<f32, _>(StandardNormal);
+                (vec![r * t.sin(), r * t.cos()], class)
+            })
+            .collect()
+    });
+    finish(points, 0.0)
+}
+
+// i / (n - 1), evenly covering [0, 1]
+fn fraction(i: usize, n: usize) -> f32 {
+    if n > 1 {
+        i as f32 / (n - 1) as f32
+    } else {
+        0.0
+    }
+}
+
+// Shuffle the labelled points, add Gaussian `noise` to every coordinate and stack them into
+// [N, D] inputs and [N] targets
+fn finish(mut points: Vec<(Vec<f32>, usize)>, noise: f32) -> (Tensor, Tensor) {
+    let n = points.len();
+    let dim = points.first().map_or(0, |(point, _)| point.len());
+    with_rng(|rng| {
+        points.shuffle(rng);
+        let mut inputs = Array2::<f32>::zeros((n, dim));
+        for (mut row, (point, _)) in inputs.outer_iter_mut().zip(&points) {
+            for (x, &p) in row.iter_mut().zip(point) {
+                *x = p + noise * rng.sample::<f32, _>(StandardNormal);
+            }
+        }
+        let targets = Array1::from_iter(points.iter().map(|&(_, class)| class as f32));
+        (
+            Tensor::from(inputs.into_dyn()),
+            Tensor::from(targets.into_dyn()),
+        )
+    })
+}
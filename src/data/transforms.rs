@@ -0,0 +1,201 @@
+// Per-sample preprocessing and augmentation of dataset inputs. Image transforms expect
+// [C, H, W] arrays, like ImageFolder produces.
+// Randomness comes from the thread-local RNG. DataLoader workers seed it per batch from the
+// loader's RNG, so manual_seed covers augmentations with workers as well.
+
+use crate::data::Dataset;
+use crate::random::with_rng;
+use crate::tensor::Tensor;
+use ndarray::{s, ArrayD, Axis, Ix3};
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+pub trait Transform {
+    fn apply(&self, input: ArrayD<f32>) -> ArrayD<f32>;
+}
+
+// Runs transforms in order: `Compose::new().then(RandomHorizontalFlip::new(0.5)).then(...)`
+#[derive(Default)]
+pub struct Compose {
+    transforms: Vec<Box<dyn Transform + Send + Sync>>,
+}
+
+impl Compose {
+    pub fn new() -> Compose {
+        Compose::default()
+    }
+
+    pub fn then(mut self, transform: impl Transform + Send + Sync + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+}
+
+impl Transform for Compose {
+    fn apply(&self, input: ArrayD<f32>) -> ArrayD<f32> {
+        self.transforms
+            .iter()
+            .fold(input, |input, transform| transform.apply(input))
+    }
+}
+
+fn to_chw(input: ArrayD<f32>, name: &str) -> ndarray::Array3<f32> {
+    let shape = input.shape().to_vec();
+    input
+        .into_dimensionality::<Ix3>()
+        .unwrap_or_else(|_| panic!("{} expects a [C, H, W] input, got shape {:?}", name, shape))
+}
+
+// Crop a random `height` x `width` window, after zero padding every side by `padding`
+pub struct RandomCrop {
+    pub height: usize,
+    pub width: usize,
+    pub padding: usize,
+}
+
+impl RandomCrop {
+    pub fn new(height: usize, width: usize) -> RandomCrop {
+        RandomCrop {
+            height,
+            width,
+            padding: 0,
+        }
+    }
+
+    pub fn padding(mut self, padding: usize) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+impl Transform for RandomCrop {
+    fn apply(&self, input: ArrayD<f32>) -> ArrayD<f32> {
+        let input = to_chw(input, "RandomCrop");
+        let (c, h, w) = input.dim();
+        let (padded_h, padded_w) = (h + 2 * self.padding, w + 2 * self.padding);
+        assert!(
+            self.height <= padded_h && self.width <= padded_w,
+            "RandomCrop of {}x{} doesn't fit a padded {}x{} input",
+            self.height,
+            self.width,
+            padded_h,
+            padded_w
+        );
+
+        let mut padded = ndarray::Array3::<f32>::zeros((c, padded_h, padded_w));
+        let p = self.padding;
+        padded.slice_mut(s![.., p..p + h, p..p + w]).assign(&input);
+
+        let (top, left) = with_rng(|rng| {
+            (
+                rng.gen_range(0..=padded_h - self.height),
+                rng.gen_range(0..=padded_w - self.width),
+            )
+        });
+        padded
+            .slice(s![.., top..top + self.height, left..left + self.width])
+            .to_owned()
+            .into_dyn()
+    }
+}
+
+// Mirror the last (width) axis with probability `p`
+pub struct RandomHorizontalFlip {
+    pub p: f64,
+}
+
+impl RandomHorizontalFlip {
+    pub fn new(p: f64) -> RandomHorizontalFlip {
+        RandomHorizontalFlip { p }
+    }
+}
+
+impl Transform for RandomHorizontalFlip {
+    fn apply(&self, mut input: ArrayD<f32>) -> ArrayD<f32> {
+        if with_rng(|rng| rng.gen_bool(self.p)) {
+            let last = Axis(input.ndim() - 1);
+            input.invert_axis(last);
+            input = input.as_standard_layout().into_owned();
+        }
+        input
+    }
+}
+
+// (x - mean[c]) / std[c] per channel, a single value applies to every channel
+pub struct Normalize {
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+}
+
+impl Normalize {
+    pub fn new(mean: &[f32], std: &[f32]) -> Normalize {
+        assert!(
+            mean.len() == std.len(),
+            "Normalize needs as many means as stds"
+        );
+        Normalize {
+            mean: mean.to_vec(),
+            std: std.to_vec(),
+        }
+    }
+}
+
+impl Transform for Normalize {
+    fn apply(&self, input: ArrayD<f32>) -> ArrayD<f32> {
+        let mut input = to_chw(input, "Normalize");
+        let channels = input.dim().0;
+        assert!(
+            self.mean.len() == 1 || self.mean.len() == channels,
+            "Normalize has {} values for {} channels",
+            self.mean.len(),
+            channels
+        );
+        for (c, mut channel) in input.outer_iter_mut().enumerate() {
+            let i = if self.mean.len() == 1 { 0 } else { c };
+            channel.mapv_inplace(|x| (x - self.mean[i]) / self.std[i]);
+        }
+        input.into_dyn()
+    }
+}
+
+// Add zero-mean gaussian noise with standard deviation `std` to every element
+pub struct GaussianNoise {
+    pub std: f32,
+}
+
+impl GaussianNoise {
+    pub fn new(std: f32) -> GaussianNoise {
+        GaussianNoise { std }
+    }
+}
+
+impl Transform for GaussianNoise {
+    fn apply(&self, mut input: ArrayD<f32>) -> ArrayD<f32> {
+        with_rng(|rng| input.mapv_inplace(|x| x + self.std * rng.sample::<f32, _>(StandardNormal)));
+        input
+    }
+}
+
+// Dataset whose inputs go through a transform on every get(), see Dataset::transform
+pub struct Transformed<D, T> {
+    dataset: D,
+    transform: T,
+}
+
+impl<D: Dataset, T: Transform> Transformed<D, T> {
+    pub fn new(dataset: D, transform: T) -> Transformed<D, T> {
+        Transformed { dataset, transform }
+    }
+}
+
+impl<D: Dataset, T: Transform> Dataset for Transformed<D, T> {
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+
+    fn get(&self, index: usize) -> (Tensor, Tensor) {
+        let (input, target) = self.dataset.get(index);
+        let input = input.borrow().data.clone();
+        (Tensor::from(self.transform.apply(input)), target)
+    }
+}
@@ -0,0 +1,32 @@
+// Deterministic mode, so two runs of the same program produce bit-identical results, e.g. to
+// compare training curves across commits:
+//
+//     rust_ml::set_deterministic(true);
+//
+// It makes sure that
+// - the RNG is seeded: the calling thread gets DETERMINISTIC_SEED unless manual_seed was
+//   already called, and other threads start from that seed instead of entropy
+// - everything runs on the calling thread, DataLoader ignores num_workers, since worker
+//   threads applying random transforms would interleave differently from run to run
+//
+// Reductions (sums, gradient accumulation, losses) already run in a fixed order on a single
+// thread, in both modes.
+
+use crate::random;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const DETERMINISTIC_SEED: u64 = 0;
+
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+
+// Process-wide, switch it on before creating models or data loaders
+pub fn set_deterministic(deterministic: bool) {
+    DETERMINISTIC.store(deterministic, Ordering::SeqCst);
+    if deterministic {
+        random::seed_if_unseeded(DETERMINISTIC_SEED);
+    }
+}
+
+pub fn is_deterministic() -> bool {
+    DETERMINISTIC.load(Ordering::SeqCst)
+}
@@ -0,0 +1,240 @@
+// Devices: where a tensor's data lives. Every tensor is on one, ops need all their operands on
+// the same device and put their output there, `to` moves a tensor between devices.
+//
+//     let x = x.to(Device::Gpu(0));
+//     let y = model.forward(&x); // parameters have to be on gpu:0 as well
+//
+// Without a GPU backend only the CPU is available and `to` anything else fails with
+// DeviceUnavailable. Backends add their GPUs with `register_gpus`. Devices are per process.
+//
+// Ops with a GPU kernel compute through the functions at the bottom, which hand tensors on a
+// GPU to the backend and fall back to ndarray when there's none or it can't take the operands.
+// The "cuda" feature is the backend, experimental for now, see the cuda module.
+
+use crate::error::TensorError;
+use crate::inference;
+use crate::op::Op;
+use crate::tensor::{accumulate_grad, Tensor, TensorData};
+use ndarray::{Array2, ArrayD, ArrayView2, Axis};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::panic::Location;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    #[default]
+    Cpu,
+    // By index, in the order the backend lists them
+    Gpu(usize),
+}
+
+static GPUS: AtomicUsize = AtomicUsize::new(0);
+
+// Number of GPUs the backend found, 0 without one
+pub fn gpu_count() -> usize {
+    GPUS.load(Ordering::Relaxed)
+}
+
+// For backends, once they've found `count` usable GPUs
+pub fn register_gpus(count: usize) {
+    GPUS.store(count, Ordering::Relaxed);
+}
+
+impl Device {
+    pub fn is_available(self) -> bool {
+        match self {
+            Device::Cpu => true,
+            Device::Gpu(index) => index < gpu_count(),
+        }
+    }
+}
+
+// "cpu", "gpu:0"
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Device::Cpu => f.write_str("cpu"),
+            Device::Gpu(index) => write!(f, "gpu:{}", index),
+        }
+    }
+}
+
+// What Display prints, "gpu" and "cuda" are gpu:0 and "cuda:1" is gpu:1 as well
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Device, String> {
+        let invalid = || format!("unknown device {:?}, expected cpu or gpu:<index>", s);
+        match s.split_once(':') {
+            None if s == "cpu" => Ok(Device::Cpu),
+            None if s == "gpu" || s == "cuda" => Ok(Device::Gpu(0)),
+            Some(("gpu" | "cuda", index)) => index.parse().map(Device::Gpu).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Tensor {
+    pub fn device(&self) -> Device {
+        self.borrow().device
+    }
+
+    // A copy on `device`, or this tensor if it's already there. Differentiable: the gradient
+    // goes back to the source device.
+    #[track_caller]
+    pub fn to(&self, device: Device) -> Tensor {
+        self.try_to(device).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_to(&self, device: Device) -> Result<Tensor, TensorError> {
+        if device == self.device() {
+            return Ok(self.clone());
+        }
+        if !device.is_available() {
+            return Err(TensorError::DeviceUnavailable { device });
+        }
+        let data = self.borrow().data.clone();
+        if inference::is_enabled() {
+            let out = Tensor::from(data);
+            out.borrow_mut().device = device;
+            return Ok(out);
+        }
+        let mut new_tensor_data = TensorData::new(data);
+        new_tensor_data._op = Some(Op::To { device });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            accumulate_grad(&out._children[0], out.grad.clone().unwrap());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        let out = Tensor::new(new_tensor_data);
+        out.borrow_mut().device = device;
+        Ok(out)
+    }
+
+    // Inference outputs are leaves, they take the device of the op's input here rather than
+    // from their children like recorded outputs do
+    pub(crate) fn on_device_of(self, input: &Tensor) -> Tensor {
+        let device = input.device();
+        if device != Device::Cpu {
+            self.borrow_mut().device = device;
+        }
+        self
+    }
+}
+
+// Error unless all `operands` of `op` are on the same device
+#[track_caller]
+pub(crate) fn check_same(op: &'static str, operands: &[&Tensor]) -> Result<(), TensorError> {
+    let Some((first, rest)) = operands.split_first() else {
+        return Ok(());
+    };
+    let device = first.device();
+    match rest
+        .iter()
+        .map(|operand| operand.device())
+        .find(|&d| d != device)
+    {
+        None => Ok(()),
+        Some(other) => Err(TensorError::DeviceMismatch {
+            op,
+            lhs: device,
+            rhs: other,
+            location: if cfg!(debug_assertions) {
+                Some(Location::caller())
+            } else {
+                None
+            },
+        }),
+    }
+}
+
+// Element-wise ops with a GPU kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unary {
+    Tanh,
+    Exp,
+    Relu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Binary {
+    Add,
+    Mul,
+}
+
+#[cfg(feature = "cuda")]
+use crate::cuda as backend;
+
+// Without a backend every op runs on the CPU
+#[cfg(not(feature = "cuda"))]
+mod backend {
+    use super::{Binary, Unary};
+    use ndarray::{Array2, ArrayD, ArrayView2};
+
+    pub(super) fn matmul(_: usize, _: ArrayView2<f32>, _: ArrayView2<f32>) -> Option<Array2<f32>> {
+        None
+    }
+
+    pub(super) fn map(_: usize, _: Unary, _: &ArrayD<f32>) -> Option<ArrayD<f32>> {
+        None
+    }
+
+    pub(super) fn zip(
+        _: usize,
+        _: Binary,
+        _: &ArrayD<f32>,
+        _: &ArrayD<f32>,
+    ) -> Option<ArrayD<f32>> {
+        None
+    }
+
+    pub(super) fn sum_axis(_: usize, _: &ArrayD<f32>, _: usize) -> Option<ArrayD<f32>> {
+        None
+    }
+}
+
+// The GPU index to hand an op on `device` to, None for the CPU
+fn gpu_index(device: Device) -> Option<usize> {
+    match device {
+        Device::Cpu => None,
+        Device::Gpu(index) => Some(index),
+    }
+}
+
+pub(crate) fn matmul(device: Device, lhs: ArrayView2<f32>, rhs: ArrayView2<f32>) -> Array2<f32> {
+    gpu_index(device)
+        .and_then(|index| backend::matmul(index, lhs, rhs))
+        .unwrap_or_else(|| lhs.dot(&rhs))
+}
+
+pub(crate) fn map(device: Device, op: Unary, x: &ArrayD<f32>) -> ArrayD<f32> {
+    gpu_index(device)
+        .and_then(|index| backend::map(index, op, x))
+        .unwrap_or_else(|| match op {
+            Unary::Tanh => x.mapv(f32::tanh),
+            Unary::Exp => x.mapv(f32::exp),
+            Unary::Relu => x.mapv(|x| if x > 0.0 { x } else { 0.0 }),
+        })
+}
+
+// Broadcasting like ndarray's operators
+pub(crate) fn zip(device: Device, op: Binary, lhs: &ArrayD<f32>, rhs: &ArrayD<f32>) -> ArrayD<f32> {
+    gpu_index(device)
+        .and_then(|index| backend::zip(index, op, lhs, rhs))
+        .unwrap_or_else(|| match op {
+            Binary::Add => lhs + rhs,
+            Binary::Mul => lhs * rhs,
+        })
+}
+
+pub(crate) fn sum_axis(device: Device, x: &ArrayD<f32>, axis: usize) -> ArrayD<f32> {
+    gpu_index(device)
+        .and_then(|index| backend::sum_axis(index, x, axis))
+        .unwrap_or_else(|| x.sum_axis(Axis(axis)))
+}
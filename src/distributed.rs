@@ -0,0 +1,415 @@
+// Multi-process data-parallel training over TCP. Every process trains a full copy of the model
+// on its own part of the data (see DistributedSampler) and gradients are averaged with a ring
+// all-reduce after each backward pass, so all copies take the same optimizer step.
+//
+// Processes find each other through rank 0, started with the same variables as PyTorch:
+//
+//     RANK=0 WORLD_SIZE=2 MASTER_ADDR=10.0.0.1 MASTER_PORT=29500 ./train &
+//     RANK=1 WORLD_SIZE=2 MASTER_ADDR=10.0.0.1 MASTER_PORT=29500 ./train
+//
+//     let group = ProcessGroup::from_env()?;
+//     let sampler = DistributedSampler::new(group.rank(), group.world_size()).shuffle(true);
+//     let loader = DataLoader::new(dataset, 32).sampler(sampler);
+//     let model = DistributedDataParallel::new(model, group)?;
+//     let mut optimizer = Sgd::new(model.parameters(), 0.1);
+//     Trainer::new(&model, &mut optimizer, loss_fn).callback(model.grad_sync()).fit(&loader);
+//
+// grad_sync also replaces the epoch's train and validation loss with their mean over the ranks,
+// so add it before the other callbacks: EarlyStopping then stops every rank after the same
+// epoch, instead of leaving the others blocked in the next all-reduce. Every rank has to attach
+// the same stopping callbacks. Checkpoints only belong on rank 0, the ranks hold the same
+// weights and would all write to the same path:
+//
+//     if group.rank() == 0 {
+//         trainer = trainer.callback(ModelCheckpoint::new("best.ckpt"));
+//     }
+//
+// After rendezvous every rank only talks to its two ring neighbours, each all-reduce sends
+// about 2 * (world_size - 1) / world_size times the buffer size per rank.
+
+use crate::error::TensorError;
+use crate::nn::{ForwardHooks, Module, TracedLayer};
+use crate::shape::ShapedLayer;
+use crate::tensor::Tensor;
+use crate::train::{Callback, Context, EpochMetrics};
+use ndarray::ArrayD;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long ranks keep retrying to reach rank 0, which may start last
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct ProcessGroup {
+    rank: usize,
+    world_size: usize,
+    // Ring neighbours, None for a single process
+    next: Option<TcpStream>,
+    prev: Option<TcpStream>,
+}
+
+impl ProcessGroup {
+    // Rendezvous at `master`, where rank 0 listens. Blocks until all ranks have joined.
+    pub fn init(master: impl ToSocketAddrs, rank: usize, world_size: usize) -> io::Result<Self> {
+        assert!(
+            rank < world_size,
+            "rank {} out of range for a world of {}",
+            rank,
+            world_size
+        );
+        if world_size == 1 {
+            return Ok(ProcessGroup {
+                rank,
+                world_size,
+                next: None,
+                prev: None,
+            });
+        }
+        let master = master
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("master address doesn't resolve"))?;
+
+        // Ring listener of every rank, indexed by rank
+        let (listener, peers) = if rank == 0 {
+            let listener = TcpListener::bind(master)?;
+            let mut joined = Vec::with_capacity(world_size - 1);
+            let mut peers = vec![master; world_size];
+            while joined.len() < world_size - 1 {
+                let (mut stream, _) = listener.accept()?;
+                let peer = read_u32(&mut stream)? as usize;
+                let addr = read_addr(&mut stream)?;
+                if peer == 0 || peer >= world_size {
+                    return Err(invalid(&format!(
+                        "rank {} joined a world of {}",
+                        peer, world_size
+                    )));
+                }
+                peers[peer] = addr;
+                joined.push(stream);
+            }
+            for mut stream in joined {
+                for addr in &peers[1..] {
+                    write_addr(&mut stream, addr)?;
+                }
+            }
+            (listener, peers)
+        } else {
+            let mut stream = connect_retrying(master)?;
+            // Listen on the interface that reaches rank 0, so the other ranks can reach it too
+            let listener = TcpListener::bind((stream.local_addr()?.ip(), 0))?;
+            write_u32(&mut stream, rank as u32)?;
+            write_addr(&mut stream, &listener.local_addr()?)?;
+            let mut peers = vec![master];
+            for _ in 1..world_size {
+                peers.push(read_addr(&mut stream)?);
+            }
+            (listener, peers)
+        };
+
+        // Connect before accepting, the connection completes in the backlog without waiting
+        // for the neighbour to accept it
+        let mut next = TcpStream::connect(peers[(rank + 1) % world_size])?;
+        next.set_nodelay(true)?;
+        write_u32(&mut next, rank as u32)?;
+        let (mut prev, _) = listener.accept()?;
+        let expected = (rank + world_size - 1) % world_size;
+        let got = read_u32(&mut prev)? as usize;
+        if got != expected {
+            return Err(invalid(&format!(
+                "rank {} expected ring neighbour {}, got {}",
+                rank, expected, got
+            )));
+        }
+        Ok(ProcessGroup {
+            rank,
+            world_size,
+            next: Some(next),
+            prev: Some(prev),
+        })
+    }
+
+    // From RANK, WORLD_SIZE, MASTER_ADDR and MASTER_PORT. Without RANK or WORLD_SIZE this is a
+    // single-process group, so the same binary runs undistributed too.
+    pub fn from_env() -> io::Result<Self> {
+        let var = |name: &str| env::var(name).ok();
+        let number = |name: &str| -> io::Result<Option<usize>> {
+            var(name)
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| invalid(&format!("{} isn't a number", name)))
+                })
+                .transpose()
+        };
+        let (Some(rank), Some(world_size)) = (number("RANK")?, number("WORLD_SIZE")?) else {
+            return ProcessGroup::init("127.0.0.1:0", 0, 1);
+        };
+        let addr = var("MASTER_ADDR").unwrap_or_else(|| String::from("127.0.0.1"));
+        let port = number("MASTER_PORT")?.unwrap_or(29500);
+        ProcessGroup::init((addr.as_str(), port as u16), rank, world_size)
+    }
+
+    pub fn rank(&self) -> usize {
+        self.rank
+    }
+
+    pub fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    // Sum `values` element-wise over all ranks, every rank ends up with the total. All ranks
+    // must call it with buffers of the same length.
+    pub fn all_reduce(&self, values: &mut [f32]) -> io::Result<()> {
+        let (Some(next), Some(prev)) = (&self.next, &self.prev) else {
+            return Ok(());
+        };
+        let (rank, size, len) = (self.rank, self.world_size, values.len());
+        let segment = |i: usize| {
+            let i = i % size;
+            i * len / size..(i + 1) * len / size
+        };
+        // Reduce-scatter: after size - 1 steps rank r holds the full sum of segment r + 1
+        for step in 0..size - 1 {
+            let send = segment(rank + size - step);
+            let recv = segment(rank + 2 * size - step - 1);
+            let received = exchange(next, prev, &values[send], recv.len())?;
+            for (v, r) in values[recv].iter_mut().zip(received) {
+                *v += r;
+            }
+        }
+        // All-gather: pass the finished segments around the ring
+        for step in 0..size - 1 {
+            let send = segment(rank + 1 + size - step);
+            let recv = segment(rank + size - step);
+            let received = exchange(next, prev, &values[send], recv.len())?;
+            values[recv].copy_from_slice(&received);
+        }
+        Ok(())
+    }
+
+    // Copy `values` from `root` to every rank
+    pub fn broadcast(&self, values: &mut [f32], root: usize) -> io::Result<()> {
+        if self.rank != root {
+            values.fill(0.0);
+        }
+        // Adding zeros is exact, so a sum does the job
+        self.all_reduce(values)
+    }
+
+    // Wait until every rank got here
+    pub fn barrier(&self) -> io::Result<()> {
+        self.all_reduce(&mut [0.0])
+    }
+}
+
+// Send `values` to the next rank while receiving `len` values from the previous one. The write
+// runs on its own thread, with both sides writing first a full socket buffer would deadlock.
+fn exchange(
+    next: &TcpStream,
+    prev: &TcpStream,
+    values: &[f32],
+    len: usize,
+) -> io::Result<Vec<f32>> {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let mut received = vec![0u8; len * size_of::<f32>()];
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            let mut next = next;
+            next.write_all(&bytes)
+        });
+        let mut prev = prev;
+        let read = prev.read_exact(&mut received);
+        writer.join().expect("all_reduce writer panicked")?;
+        read
+    })?;
+    Ok(received
+        .chunks_exact(size_of::<f32>())
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect())
+}
+
+fn connect_retrying(addr: SocketAddr) -> io::Result<TcpStream> {
+    let started = Instant::now();
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if started.elapsed() >= CONNECT_TIMEOUT => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_addr(writer: &mut impl Write, addr: &SocketAddr) -> io::Result<()> {
+    let text = addr.to_string();
+    write_u32(writer, text.len() as u32)?;
+    writer.write_all(text.as_bytes())
+}
+
+fn read_addr(reader: &mut impl Read) -> io::Result<SocketAddr> {
+    let mut text = vec![0u8; read_u32(reader)? as usize];
+    reader.read_exact(&mut text)?;
+    String::from_utf8(text)
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| invalid("bad address in rendezvous"))
+}
+
+// Wraps the local copy of the model. Construction copies rank 0's weights to every rank, after
+// that `sync_grads` after each backward pass keeps the copies identical.
+pub struct DistributedDataParallel<M: Module> {
+    model: M,
+    group: ProcessGroup,
+}
+
+impl<M: Module> DistributedDataParallel<M> {
+    pub fn new(model: M, group: ProcessGroup) -> io::Result<Self> {
+        let params = model.parameters();
+        let mut values = flatten(params.iter().map(|p| p.borrow().data.clone()));
+        group.broadcast(&mut values, 0)?;
+        unflatten(&values, &params, |param, data| {
+            param.borrow_mut().data = data
+        });
+        Ok(DistributedDataParallel { model, group })
+    }
+
+    pub fn module(&self) -> &M {
+        &self.model
+    }
+
+    pub fn group(&self) -> &ProcessGroup {
+        &self.group
+    }
+
+    // Replace every trainable parameter's gradient with its mean over the ranks, in a single
+    // all-reduce. A parameter without a gradient counts as zero. Sparse gradients are densified.
+    pub fn sync_grads(&self) -> io::Result<()> {
+        let params: Vec<Tensor> = self
+            .model
+            .parameters()
+            .into_iter()
+            .filter(|p| p.requires_grad())
+            .collect();
+        let mut values = flatten(params.iter().map(|p| {
+            p.grad_array()
+                .unwrap_or_else(|| ArrayD::zeros(p.borrow().data.raw_dim()))
+        }));
+        self.group.all_reduce(&mut values)?;
+        let scale = 1.0 / self.group.world_size() as f32;
+        values.iter_mut().for_each(|v| *v *= scale);
+        unflatten(&values, &params, |param, grad| {
+            let mut param = param.borrow_mut();
+            param.grad = Some(grad);
+            param.sparse_grad = None;
+            param.retrack();
+        });
+        Ok(())
+    }
+
+    // Mean of a value, e.g. the loss, over the ranks
+    pub fn average(&self, value: f32) -> io::Result<f32> {
+        let mut values = [value];
+        self.group.all_reduce(&mut values)?;
+        Ok(values[0] / self.group.world_size() as f32)
+    }
+
+    // Trainer callback calling sync_grads between backward and the optimizer step
+    pub fn grad_sync(&self) -> GradSync<'_, M> {
+        GradSync(self)
+    }
+}
+
+fn flatten(arrays: impl Iterator<Item = ArrayD<f32>>) -> Vec<f32> {
+    arrays.flat_map(|a| a.into_iter()).collect()
+}
+
+// Hand each tensor its slice of `values`, in the order they were flattened
+fn unflatten(values: &[f32], tensors: &[Tensor], mut set: impl FnMut(&Tensor, ArrayD<f32>)) {
+    let mut offset = 0;
+    for tensor in tensors {
+        let shape = tensor.borrow().data.raw_dim();
+        let len = tensor.numel();
+        let data = ArrayD::from_shape_vec(shape, values[offset..offset + len].to_vec()).unwrap();
+        set(tensor, data);
+        offset += len;
+    }
+}
+
+impl<M: Module> Module for DistributedDataParallel<M> {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.model.forward(input)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.model.named_parameters()
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        self.model.forward_traced(input, name, visit)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.model.output_shape(input_shape)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.model.output_shape_traced(input_shape, name, visit)
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        self.model.set_cache(enabled);
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        self.model.forward_hooks()
+    }
+}
+
+// See DistributedDataParallel::grad_sync: averages the gradients after every backward and the
+// epoch metrics over the ranks. Panics when a rank drops out, the other ranks would block in
+// the next all-reduce anyway.
+pub struct GradSync<'a, M: Module>(&'a DistributedDataParallel<M>);
+
+impl<M: Module> Callback for GradSync<'_, M> {
+    fn on_backward(&mut self, _ctx: &mut Context) {
+        self.0
+            .sync_grads()
+            .unwrap_or_else(|e| panic!("gradient all-reduce failed: {}", e));
+    }
+
+    fn on_metrics(&mut self, _ctx: &mut Context, metrics: &mut EpochMetrics) {
+        let ddp = self.0;
+        let average = |value| {
+            ddp.average(value)
+                .unwrap_or_else(|e| panic!("metric all-reduce failed: {}", e))
+        };
+        // Every rank validates or none does, all of them take part in each all-reduce
+        metrics.val_loss = metrics.val_loss.map(average);
+        metrics.train_loss = average(metrics.train_loss);
+    }
+}
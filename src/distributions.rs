@@ -0,0 +1,98 @@
+// Probability distributions with differentiable parameters, the building blocks of variational
+// models. A VAE's encoder outputs the posterior, the KL term against the prior comes in closed
+// form instead of being derived by hand:
+//
+//     let posterior = Normal::new(&mean, &log_std.exp());
+//     let z = posterior.rsample(); // gradients reach the encoder through z
+//     let kl = posterior.kl_divergence(&Normal::standard(&mean.shape()));
+//     let loss = &loss::mse(&decoder.forward(&z), &x, Reduction::Sum) + &kl;
+
+use crate::loss::{fused_loss, Reduction};
+use crate::op::LossKind;
+use crate::profiler;
+use crate::random::{self, Gradient};
+use crate::tensor::{broadcast_shape, constant, Tensor};
+use ndarray::{ArrayD, IxDyn};
+
+// N(mean, std^2) elementwise, mean and std broadcast together
+#[derive(Debug, Clone)]
+pub struct Normal {
+    pub mean: Tensor,
+    pub std: Tensor,
+}
+
+impl Normal {
+    pub fn new(mean: &Tensor, std: &Tensor) -> Normal {
+        assert!(
+            broadcast_shape(&mean.shape(), &std.shape()).is_some(),
+            "Normal: mean {:?} and std {:?} can't be broadcast together",
+            mean.shape(),
+            std.shape()
+        );
+        Normal {
+            mean: mean.clone(),
+            std: std.clone(),
+        }
+    }
+
+    // N(0, 1) of the given shape with constant parameters, the usual VAE prior
+    pub fn standard(shape: &[usize]) -> Normal {
+        Normal {
+            mean: constant(ArrayD::zeros(IxDyn(shape))),
+            std: constant(ArrayD::ones(IxDyn(shape))),
+        }
+    }
+
+    pub fn shape(&self) -> Vec<usize> {
+        broadcast_shape(&self.mean.shape(), &self.std.shape()).unwrap()
+    }
+
+    // A sample that gradients don't flow through
+    pub fn sample(&self) -> Tensor {
+        random::normal(&self.mean, &self.std, Gradient::None)
+    }
+
+    // Reparameterized sample, mean + std * eps, differentiable in mean and std
+    pub fn rsample(&self) -> Tensor {
+        random::normal(&self.mean, &self.std, Gradient::Reparameterized)
+    }
+
+    // KL(self || other) summed over all elements
+    pub fn kl_divergence(&self, other: &Normal) -> Tensor {
+        self.kl_divergence_with(other, Reduction::Sum)
+    }
+
+    // Elementwise ln(s2 / s1) + (s1^2 + (m1 - m2)^2) / (2 s2^2) - 1/2 for self = N(m1, s1^2)
+    // and other = N(m2, s2^2), reduced
+    pub fn kl_divergence_with(&self, other: &Normal, reduction: Reduction) -> Tensor {
+        let _timer = profiler::forward("kl_normal");
+        let shape = broadcast_shape(&self.shape(), &other.shape()).unwrap_or_else(|| {
+            panic!(
+                "kl_divergence: shapes {:?} and {:?} can't be broadcast together",
+                self.shape(),
+                other.shape()
+            )
+        });
+        let parameters = [&self.mean, &self.std, &other.mean, &other.std];
+        let [m1, s1, m2, s2] =
+            parameters.map(|p| p.borrow().data.broadcast(IxDyn(&shape)).unwrap().to_owned());
+
+        let diff = &m1 - &m2;
+        let var2 = s2.mapv(|s| s * s);
+        let spread = s1.mapv(|s| s * s) + diff.mapv(|d| d * d);
+        let mut losses = &spread / (&var2 * 2.0) - 0.5;
+        losses.zip_mut_with(&(&s2 / &s1), |l, &ratio| *l += ratio.ln());
+        let (value, scale) = reduction.apply(losses);
+
+        let d_m1 = &diff / &var2 * scale;
+        let d_m2 = -&d_m1;
+        let d_s1 = (&s1 / &var2 - s1.mapv(|s| 1.0 / s)) * scale;
+        let d_s2 = (s2.mapv(|s| 1.0 / s) - &spread / (&var2 * &s2)) * scale;
+        fused_loss(
+            LossKind::KlNormal,
+            parameters.map(Tensor::clone).to_vec(),
+            value,
+            vec![d_m1, d_s1, d_m2, d_s2],
+        )
+    }
+}
@@ -0,0 +1,235 @@
+// Several models combined into one, usually copies of an architecture trained from different
+// seeds or checkpoints:
+//
+//     let ensemble = Ensemble::from_checkpoints(&["a.ckpt", "b.ckpt", "c.ckpt"], build_model)?;
+//     let accuracy = metrics::accuracy(&ensemble.predict(&x), &y);
+//
+// Members are named by position like the layers of a Sequential ("0.weight", ...).
+
+use crate::checkpoint;
+use crate::error::TensorError;
+use crate::nn::{ForwardHooks, Module, TracedLayer};
+use crate::shape::{self, ShapedLayer};
+use crate::tensor::{constant, Tensor};
+use ndarray::{arr0, Array2, Axis, Ix2};
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    // Average of the member outputs, e.g. of probabilities or regression targets
+    Mean,
+    // Majority vote for [N, classes] outputs: the fraction of members whose highest score is
+    // each class, so argmax picks the most voted class (the lowest index on ties). Not
+    // differentiable.
+    Vote,
+}
+
+pub struct Ensemble {
+    pub members: Vec<Box<dyn Module>>,
+    pub combine: Combine,
+    pub hooks: ForwardHooks,
+}
+
+impl Ensemble {
+    pub fn mean() -> Ensemble {
+        Ensemble {
+            members: Vec::new(),
+            combine: Combine::Mean,
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn vote() -> Ensemble {
+        Ensemble {
+            members: Vec::new(),
+            combine: Combine::Vote,
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, member: impl Module + 'static) -> Self {
+        self.members.push(Box::new(member));
+        self
+    }
+
+    // Averaging ensemble of one `build()` model per checkpoint, each loaded with the model
+    // state saved there
+    pub fn from_checkpoints<M: Module + 'static>(
+        paths: &[impl AsRef<Path>],
+        build: impl Fn() -> M,
+    ) -> io::Result<Ensemble> {
+        let mut ensemble = Ensemble::mean();
+        for path in paths {
+            let model = build();
+            let state = checkpoint::load(path)?.model;
+            model.load_state_dict(&state).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.as_ref().display(), e),
+                )
+            })?;
+            ensemble = ensemble.add(model);
+        }
+        Ok(ensemble)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    fn combine_outputs(&self, outputs: Vec<Tensor>) -> Tensor {
+        assert!(!outputs.is_empty(), "Ensemble has no members");
+        match self.combine {
+            Combine::Mean => {
+                let scale = constant(arr0(1.0 / outputs.len() as f32).into_dyn());
+                let sum = outputs[1..]
+                    .iter()
+                    .fold(outputs[0].clone(), |sum, output| &sum + output);
+                &sum * &scale
+            }
+            Combine::Vote => vote(&outputs),
+        }
+    }
+
+    // Shape rule of combine_outputs: the members' outputs broadcast together, voting needs
+    // [N, classes] scores
+    fn combine_shapes(&self, outputs: Vec<Vec<usize>>) -> Result<Vec<usize>, TensorError> {
+        assert!(!outputs.is_empty(), "Ensemble has no members");
+        let combined = outputs[1..]
+            .iter()
+            .try_fold(outputs[0].clone(), |sum, output| {
+                shape::broadcast("+", &sum, output)
+            })?;
+        if self.combine == Combine::Vote
+            && (combined.len() != 2 || outputs.iter().any(|o| *o != combined))
+        {
+            return Err(shape::mismatch("Ensemble voting", &outputs[0], &combined));
+        }
+        Ok(combined)
+    }
+}
+
+fn vote(outputs: &[Tensor]) -> Tensor {
+    let shape = outputs[0].shape();
+    assert!(
+        shape.len() == 2,
+        "Ensemble voting expects [N, classes] outputs, got shape {:?}",
+        shape
+    );
+    let mut votes = Array2::<f32>::zeros((shape[0], shape[1]));
+    let weight = 1.0 / outputs.len() as f32;
+    for output in outputs {
+        assert_eq!(
+            output.shape(),
+            shape,
+            "Ensemble members disagree on the output shape"
+        );
+        let scores = output
+            .borrow()
+            .data
+            .clone()
+            .into_dimensionality::<Ix2>()
+            .unwrap();
+        for (n, row) in scores.axis_iter(Axis(0)).enumerate() {
+            let class = row
+                .iter()
+                .enumerate()
+                .fold(0, |best, (k, &v)| if v > row[best] { k } else { best });
+            votes[[n, class]] += weight;
+        }
+    }
+    constant(votes.into_dyn())
+}
+
+impl Module for Ensemble {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let outputs = self
+            .members
+            .iter()
+            .map(|member| member.forward(input))
+            .collect();
+        self.hooks.run(input, self.combine_outputs(outputs))
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        let outputs = self
+            .members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| {
+                let child = if name.is_empty() {
+                    i.to_string()
+                } else {
+                    format!("{}.{}", name, i)
+                };
+                member.forward_traced(input, &child, visit)
+            })
+            .collect();
+        self.hooks.run(input, self.combine_outputs(outputs))
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let outputs = self
+            .members
+            .iter()
+            .map(|member| member.output_shape(input_shape))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.combine_shapes(outputs)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        let outputs = self
+            .members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| {
+                let child = if name.is_empty() {
+                    i.to_string()
+                } else {
+                    format!("{}.{}", name, i)
+                };
+                member.output_shape_traced(input_shape, &child, visit)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.combine_shapes(outputs)
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        for member in &self.members {
+            member.set_cache(enabled);
+        }
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.members
+            .iter()
+            .enumerate()
+            .flat_map(|(i, member)| {
+                member
+                    .named_parameters()
+                    .into_iter()
+                    .map(move |(name, param)| (format!("{}.{}", i, name), param))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,248 @@
+use crate::device::{self, Device};
+use crate::tensor::Tensor;
+use std::fmt;
+use std::panic::Location;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TensorError {
+    // Operand shapes don't fit the op, e.g. can't be broadcast together. `location` is the user code that called the op,
+    // only captured in debug builds
+    ShapeMismatch {
+        op: &'static str,
+        lhs: Vec<usize>,
+        rhs: Vec<usize>,
+        // What's known about the lhs and rhs tensors, when the error is about tensors
+        operands: Option<Box<[Operand; 2]>>,
+        location: Option<&'static Location<'static>>,
+    },
+    InvalidAxis {
+        axis: usize,
+        ndim: usize,
+    },
+    IndexOutOfBounds {
+        index: Vec<usize>,
+        shape: Vec<usize>,
+    },
+    // item() on a tensor that doesn't hold exactly one value
+    NonScalarItem {
+        shape: Vec<usize>,
+    },
+    SingularMatrix,
+    // cholesky() on a matrix that isn't symmetric positive definite
+    NotPositiveDefinite,
+    // A state dict entry a module expected isn't there
+    MissingParameter {
+        name: String,
+    },
+    // A label that isn't a class index in 0..num_classes, e.g. for one_hot
+    InvalidClass {
+        value: f32,
+        num_classes: usize,
+    },
+    // compile() met something it can't replay, e.g. an op without a kernel
+    Untraceable {
+        reason: String,
+    },
+    // The dim names of the operands don't line up, e.g. [batch, seq] + [seq, batch] of equal
+    // sizes. See Tensor::with_dim_names.
+    DimNameMismatch {
+        op: &'static str,
+        lhs: Vec<&'static str>,
+        rhs: Vec<&'static str>,
+        location: Option<&'static Location<'static>>,
+    },
+    // sum_dim and the like with a name the tensor doesn't have
+    UnknownDimName {
+        name: String,
+        names: Vec<&'static str>,
+    },
+    // A shape contract failed, see shape::check_shapes. `expected` is the pattern as written,
+    // e.g. "[batch, 784]", and `reason` the dim that broke it.
+    UnexpectedShape {
+        shape: Vec<usize>,
+        expected: String,
+        reason: String,
+        operand: Option<Box<Operand>>,
+        location: Option<&'static Location<'static>>,
+    },
+    // Operands of one op on different devices, e.g. a tensor moved to a GPU and a parameter
+    // left on the CPU. See Tensor::to.
+    DeviceMismatch {
+        op: &'static str,
+        lhs: Device,
+        rhs: Device,
+        location: Option<&'static Location<'static>>,
+    },
+    // `to` a device the process doesn't have
+    DeviceUnavailable {
+        device: Device,
+    },
+}
+
+impl fmt::Display for TensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TensorError::ShapeMismatch {
+                op,
+                lhs,
+                rhs,
+                operands,
+                location,
+            } => {
+                let operand = |shape: &Vec<usize>, i: usize| {
+                    let Some(operand) = operands.as_ref().map(|operands| &operands[i]) else {
+                        return format!("{:?}", shape);
+                    };
+                    let mut described = match &operand.name {
+                        Some(name) => format!("{} {:?}", name, shape),
+                        None => format!("{:?}", shape),
+                    };
+                    if let Some(created_at) = operand.created_at {
+                        described += &format!(" (created at {})", created_at);
+                    }
+                    described
+                };
+                write!(
+                    f,
+                    "shape mismatch in `{}`: {} and {} are incompatible",
+                    op,
+                    operand(lhs, 0),
+                    operand(rhs, 1)
+                )?;
+                if let Some(location) = location {
+                    write!(f, " (at {})", location)?;
+                }
+                Ok(())
+            }
+            TensorError::InvalidAxis { axis, ndim } => {
+                write!(
+                    f,
+                    "invalid axis {} for a tensor with {} dimensions",
+                    axis, ndim
+                )
+            }
+            TensorError::IndexOutOfBounds { index, shape } => {
+                write!(
+                    f,
+                    "index {:?} is out of bounds for shape {:?}",
+                    index, shape
+                )
+            }
+            TensorError::NonScalarItem { shape } => {
+                write!(
+                    f,
+                    "item() requires a single-element tensor, got shape {:?}",
+                    shape
+                )
+            }
+            TensorError::SingularMatrix => write!(f, "matrix is singular"),
+            TensorError::NotPositiveDefinite => write!(f, "matrix is not positive definite"),
+            TensorError::MissingParameter { name } => {
+                write!(f, "missing parameter {:?} in state dict", name)
+            }
+            TensorError::InvalidClass { value, num_classes } => {
+                write!(
+                    f,
+                    "expected a class index in 0..{}, got {}",
+                    num_classes, value
+                )
+            }
+            TensorError::Untraceable { reason } => write!(f, "can't compile the model: {}", reason),
+            TensorError::DimNameMismatch {
+                op,
+                lhs,
+                rhs,
+                location,
+            } => {
+                write!(
+                    f,
+                    "dim names don't line up in `{}`: [{}] and [{}]",
+                    op,
+                    lhs.join(", "),
+                    rhs.join(", ")
+                )?;
+                if let Some(location) = location {
+                    write!(f, " (at {})", location)?;
+                }
+                Ok(())
+            }
+            TensorError::UnknownDimName { name, names } => {
+                write!(f, "no dim named `{}` in [{}]", name, names.join(", "))
+            }
+            TensorError::UnexpectedShape {
+                shape,
+                expected,
+                reason,
+                operand,
+                location,
+            } => {
+                write!(f, "expected shape {}", expected)?;
+                if let Some(operand) = operand {
+                    if let Some(name) = &operand.name {
+                        write!(f, " for {}", name)?;
+                    }
+                    if let Some(created_at) = operand.created_at {
+                        write!(f, " (created at {})", created_at)?;
+                    }
+                }
+                write!(f, ", got {:?}: {}", shape, reason)?;
+                if let Some(location) = location {
+                    write!(f, " (at {})", location)?;
+                }
+                Ok(())
+            }
+            TensorError::DeviceMismatch {
+                op,
+                lhs,
+                rhs,
+                location,
+            } => {
+                write!(
+                    f,
+                    "tensors on different devices in `{}`: {} and {}",
+                    op, lhs, rhs
+                )?;
+                if let Some(location) = location {
+                    write!(f, " (at {})", location)?;
+                }
+                Ok(())
+            }
+            TensorError::DeviceUnavailable { device } => write!(
+                f,
+                "device {} isn't available, {} GPUs found",
+                device,
+                device::gpu_count()
+            ),
+        }
+    }
+}
+
+impl TensorError {
+    // A shape mismatch between the tensors `lhs` and `rhs`, other errors as is
+    pub(crate) fn with_operands(mut self, lhs: &Tensor, rhs: &Tensor) -> TensorError {
+        if let TensorError::ShapeMismatch { operands, .. } = &mut self {
+            *operands = Some(Box::new([Operand::of(lhs), Operand::of(rhs)]));
+        }
+        self
+    }
+}
+
+// An operand of a failed op, boxed in the error to keep it small
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Operand {
+    // See Tensor::named
+    pub name: Option<String>,
+    // See Tensor::created_at
+    pub created_at: Option<&'static Location<'static>>,
+}
+
+impl Operand {
+    pub(crate) fn of(tensor: &Tensor) -> Operand {
+        Operand {
+            name: tensor.label(),
+            created_at: tensor.created_at(),
+        }
+    }
+}
+
+impl std::error::Error for TensorError {}
@@ -0,0 +1,104 @@
+// k-fold cross-validation: train a fresh model on k - 1 folds, score it with a metric on the
+// held-out one, for every fold, and summarize the k scores.
+//
+//     let cv = cross_validate(
+//         |train| {
+//             let model = Sequential::new().add(Linear::new(4, 3));
+//             let mut optimizer = Adam::new(model.parameters(), 1e-2);
+//             let loader = DataLoader::new(train, 32).shuffle(true);
+//             Trainer::new(&model, &mut optimizer, loss_fn).epochs(20).fit(&loader);
+//             model
+//         },
+//         dataset,
+//         5,
+//         Accuracy::default(),
+//     );
+//     println!("accuracy {}", cv);
+//
+// The factory gets the training folds and returns the trained model, so it decides the
+// architecture, optimizer and epochs, and has to build a new model every call for the folds
+// to be independent.
+
+use crate::data::{DataLoader, Dataset, KFold, Subset};
+use crate::metrics::Metric;
+use crate::nn::Module;
+use std::fmt;
+
+// Validation batches are only for memory, the metric accumulates over the whole fold
+const EVAL_BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossValidation {
+    // Metric value on each validation fold, in fold order
+    pub scores: Vec<f32>,
+}
+
+impl CrossValidation {
+    pub fn mean(&self) -> f32 {
+        self.scores.iter().sum::<f32>() / self.scores.len() as f32
+    }
+
+    // Population standard deviation of the fold scores
+    pub fn std(&self) -> f32 {
+        let mean = self.mean();
+        let variance = self
+            .scores
+            .iter()
+            .map(|score| (score - mean).powi(2))
+            .sum::<f32>()
+            / self.scores.len() as f32;
+        variance.sqrt()
+    }
+}
+
+impl fmt::Display for CrossValidation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.4} ± {:.4} over {} folds",
+            self.mean(),
+            self.std(),
+            self.scores.len()
+        )
+    }
+}
+
+// `k`-fold cross-validation over `dataset` shuffled with a fixed seed, so sorted data (e.g. by
+// class) still gets mixed folds and reruns compare like for like. See cross_validate_folds
+// for other folds.
+pub fn cross_validate<D, M>(
+    model_factory: impl FnMut(Subset<D>) -> M,
+    dataset: D,
+    k: usize,
+    metric: impl Metric,
+) -> CrossValidation
+where
+    D: Dataset,
+    M: Module,
+{
+    cross_validate_folds(model_factory, KFold::new(dataset, k).shuffle(0), metric)
+}
+
+// Cross-validation over the given folds. The metric is reset before every fold.
+pub fn cross_validate_folds<D, M>(
+    mut model_factory: impl FnMut(Subset<D>) -> M,
+    folds: KFold<D>,
+    mut metric: impl Metric,
+) -> CrossValidation
+where
+    D: Dataset,
+    M: Module,
+{
+    let mut scores = Vec::new();
+    for (fold, (train, val)) in folds.enumerate() {
+        let model = model_factory(train);
+        metric.reset();
+        for (inputs, targets) in &DataLoader::new(val, EVAL_BATCH_SIZE) {
+            metric.update(&model.predict(&inputs), &targets);
+        }
+        let score = metric.value();
+        log::info!(fold, score; "fold {}: {}", fold + 1, score);
+        scores.push(score);
+    }
+    CrossValidation { scores }
+}
@@ -0,0 +1,197 @@
+//! C API for running models from other languages, behind the `ffi` feature. Building with
+//! `--features ffi` regenerates `include/rust_ml.h` and the cdylib exports these functions.
+//!
+//! Handles are opaque and owned by the caller, who frees them with the matching `_free`
+//! function. A handle must only be used from the thread that created it. Functions that can
+//! fail return NULL, and `rml_last_error` then describes what went wrong.
+
+use crate::io::onnx::{self, OnnxModel};
+use crate::nn::Module;
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, IxDyn};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// A tensor owned by the caller.
+pub struct RmlTensor(Tensor);
+
+/// A model loaded from an ONNX file.
+pub struct RmlModel(OnnxModel);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl Into<String>) {
+    // Interior NULs can't be represented in a C string, replace them
+    let message = message.into().replace('\0', " ");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(CString::new(message).unwrap()));
+}
+
+// Run `f`, turning a panic into NULL plus an error message instead of unwinding into C
+fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(message)) => {
+            set_error(message);
+            None
+        }
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| String::from("unknown panic"));
+            set_error(message);
+            None
+        }
+    }
+}
+
+/// Message of the last failed call on this thread, or NULL if nothing failed yet. The
+/// pointer stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn rml_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Copy `numel(shape)` floats from `data` into a new tensor of the given shape.
+///
+/// # Safety
+/// `shape` must point to `ndim` values and `data` to as many floats as their product (one for
+/// `ndim == 0`). NULL pointers and shapes that overflow are reported as errors.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_new(
+    data: *const f32,
+    shape: *const usize,
+    ndim: usize,
+) -> *mut RmlTensor {
+    guard(|| {
+        if shape.is_null() && ndim > 0 {
+            return Err(String::from("rml_tensor_new: NULL shape"));
+        }
+        let shape = if ndim == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(shape, ndim)
+        };
+        let numel = shape
+            .iter()
+            .try_fold(1usize, |n, &dim| n.checked_mul(dim))
+            .ok_or_else(|| format!("rml_tensor_new: shape {:?} is too large", shape))?;
+        // A 0-dimensional tensor still holds one value
+        if data.is_null() && numel > 0 {
+            return Err(String::from("rml_tensor_new: NULL data"));
+        }
+        let values = if numel == 0 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(data, numel).to_vec()
+        };
+        ArrayD::from_shape_vec(IxDyn(shape), values)
+            .map_err(|e| format!("rml_tensor_new: shape {:?}: {}", shape, e))
+    })
+    .map_or(ptr::null_mut(), |array| {
+        Box::into_raw(Box::new(RmlTensor(Tensor::from(array))))
+    })
+}
+
+/// Number of dimensions.
+///
+/// # Safety
+/// `tensor` must be a live handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_ndim(tensor: *const RmlTensor) -> usize {
+    (*tensor).0.ndim()
+}
+
+/// Size of dimension `axis`, 0 if the tensor has fewer dimensions.
+///
+/// # Safety
+/// `tensor` must be a live handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_dim(tensor: *const RmlTensor, axis: usize) -> usize {
+    (*tensor).0.shape().get(axis).copied().unwrap_or(0)
+}
+
+/// Total number of values.
+///
+/// # Safety
+/// `tensor` must be a live handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_numel(tensor: *const RmlTensor) -> usize {
+    (*tensor).0.numel()
+}
+
+/// Copy up to `capacity` values in row-major order into `out`, returns the tensor's total
+/// number of values so a short buffer can be detected.
+///
+/// # Safety
+/// `tensor` must be a live handle and `out` must have room for `capacity` floats.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_read(
+    tensor: *const RmlTensor,
+    out: *mut f32,
+    capacity: usize,
+) -> usize {
+    let data = &(*tensor).0.borrow().data;
+    if !out.is_null() {
+        for (i, value) in data.iter().take(capacity).enumerate() {
+            *out.add(i) = *value;
+        }
+    }
+    data.len()
+}
+
+/// # Safety
+/// `tensor` must be NULL or a handle that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rml_tensor_free(tensor: *mut RmlTensor) {
+    if !tensor.is_null() {
+        drop(Box::from_raw(tensor));
+    }
+}
+
+/// Load an ONNX model (see `io::onnx` for the supported ops), NULL on failure.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rml_model_load_onnx(path: *const c_char) -> *mut RmlModel {
+    if path.is_null() {
+        set_error("rml_model_load_onnx: NULL path");
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    guard(|| onnx::load(&path).map_err(|e| format!("{}: {}", path, e)))
+        .map_or(ptr::null_mut(), |model| {
+            Box::into_raw(Box::new(RmlModel(model)))
+        })
+}
+
+/// Run the model on `input`, returns a new tensor the caller frees, NULL on failure
+/// (e.g. an input shape the model can't take).
+///
+/// # Safety
+/// `model` and `input` must be live handles from this library.
+#[no_mangle]
+pub unsafe extern "C" fn rml_model_forward(
+    model: *const RmlModel,
+    input: *const RmlTensor,
+) -> *mut RmlTensor {
+    let (model, input) = (&(*model).0, &(*input).0);
+    guard(|| Ok(model.forward(input))).map_or(ptr::null_mut(), |out| {
+        Box::into_raw(Box::new(RmlTensor(out)))
+    })
+}
+
+/// # Safety
+/// `model` must be NULL or a handle that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rml_model_free(model: *mut RmlModel) {
+    if !model.is_null() {
+        drop(Box::from_raw(model));
+    }
+}
@@ -0,0 +1,379 @@
+// Autoregressive generation: feed the tokens so far, pick the next one from the logits of the
+// last position, append, repeat.
+//
+//     let tokens = Generation::new(200).temperature(0.8).top_k(40).run(&model, &prompt);
+//     println!("{}", tokenizer.decode(&tokens));
+//
+// The model takes ids stored as f32 with shape [T] and returns logits [T, vocab] (a row per
+// position, as an Embedding followed by Linear layers does) or [vocab] for the last position
+// only. Sampling draws from the global RNG, so manual_seed makes generation reproducible.
+//
+// With `cached` the model keeps a KV cache (see CausalSelfAttention) and only the latest token
+// is fed after the prompt, so each step costs O(n) instead of rerunning the whole sequence.
+//
+// BeamSearch keeps the `beam_size` most likely sequences instead of sampling, over any Decoder:
+// a Module as above, or e.g. a seq2seq model whose state holds the encoded source.
+//
+//     let best = &BeamSearch::new(4, 50).stop_token(eos).run(&model, &[bos], ())[0];
+
+use crate::nn::Module;
+use crate::random::with_rng;
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, Axis, IxDyn};
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generation {
+    pub max_new_tokens: usize,
+    // 0 picks the most likely token (greedy), higher flattens the distribution
+    pub temperature: f32,
+    // Only sample among the k most likely tokens
+    pub top_k: Option<usize>,
+    // Nucleus sampling: only among the most likely tokens whose probabilities add up to p
+    pub top_p: Option<f32>,
+    // Stop after generating this token, it's included in the output
+    pub stop_token: Option<usize>,
+    // Feed at most this many of the latest tokens, for models with a fixed context length
+    pub context: Option<usize>,
+    // Incremental decoding on the model's KV cache, see set_cache
+    pub cached: bool,
+}
+
+impl Generation {
+    // Plain sampling at temperature 1
+    pub fn new(max_new_tokens: usize) -> Generation {
+        Generation {
+            max_new_tokens,
+            temperature: 1.0,
+            top_k: None,
+            top_p: None,
+            stop_token: None,
+            context: None,
+            cached: false,
+        }
+    }
+
+    pub fn greedy(self) -> Self {
+        self.temperature(0.0)
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        assert!(
+            temperature >= 0.0,
+            "temperature must be non-negative, got {}",
+            temperature
+        );
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn top_k(mut self, k: usize) -> Self {
+        assert!(k > 0, "top_k must be at least 1");
+        self.top_k = Some(k);
+        self
+    }
+
+    pub fn top_p(mut self, p: f32) -> Self {
+        assert!(0.0 < p && p <= 1.0, "top_p must be in (0, 1], got {}", p);
+        self.top_p = Some(p);
+        self
+    }
+
+    pub fn stop_token(mut self, token: usize) -> Self {
+        self.stop_token = Some(token);
+        self
+    }
+
+    pub fn context(mut self, tokens: usize) -> Self {
+        assert!(tokens > 0, "context must hold at least 1 token");
+        self.context = Some(tokens);
+        self
+    }
+
+    // The cache holds every position fed so far, so this can't be combined with `context`
+    pub fn cached(mut self) -> Self {
+        self.cached = true;
+        self
+    }
+
+    // Generated tokens following `prompt`, the prompt itself not included
+    pub fn run(&self, model: &dyn Module, prompt: &[usize]) -> Vec<usize> {
+        assert!(!prompt.is_empty(), "generation needs a non-empty prompt");
+        assert!(
+            !(self.cached && self.context.is_some()),
+            "cached generation can't truncate the context"
+        );
+        if self.cached {
+            model.set_cache(true);
+        }
+        let mut tokens = prompt.to_vec();
+        for _ in 0..self.max_new_tokens {
+            let start = if self.cached && tokens.len() > prompt.len() {
+                tokens.len() - 1
+            } else {
+                self.context
+                    .map_or(0, |context| tokens.len().saturating_sub(context))
+            };
+            let logits = next_token_logits(model, &tokens[start..]);
+            let token = self.sample(&logits);
+            tokens.push(token);
+            if self.stop_token == Some(token) {
+                break;
+            }
+        }
+        if self.cached {
+            model.set_cache(false);
+        }
+        tokens.split_off(prompt.len())
+    }
+
+    // Pick a token from one position's logits with this strategy
+    pub fn sample(&self, logits: &[f32]) -> usize {
+        if self.temperature == 0.0 || self.top_k == Some(1) {
+            return argmax(logits);
+        }
+        // Candidates from most to least likely, with their probabilities
+        let mut candidates: Vec<(usize, f32)> = softmax(logits, self.temperature)
+            .into_iter()
+            .enumerate()
+            .collect();
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        if let Some(k) = self.top_k {
+            candidates.truncate(k);
+        }
+        if let Some(p) = self.top_p {
+            let mut total = 0.0;
+            let keep = candidates
+                .iter()
+                .position(|&(_, prob)| {
+                    total += prob;
+                    total >= p
+                })
+                .map_or(candidates.len(), |i| i + 1);
+            candidates.truncate(keep);
+        }
+        let total: f32 = candidates.iter().map(|&(_, prob)| prob).sum();
+        let mut draw = with_rng(|rng| rng.gen::<f32>()) * total;
+        for &(token, prob) in &candidates {
+            if draw < prob {
+                return token;
+            }
+            draw -= prob;
+        }
+        // Rounding left the draw just past the end
+        candidates.last().unwrap().0
+    }
+}
+
+// A model beam search can query: logits for the token after `prev_tokens`. Each hypothesis has
+// its own state, cloned when it branches, for whatever the model carries between steps.
+pub trait Decoder {
+    type State: Clone;
+
+    fn logits(&self, prev_tokens: &[usize], state: &mut Self::State) -> Vec<f32>;
+}
+
+// Stateless, runs the whole sequence through forward in inference mode
+impl<M: Module + ?Sized> Decoder for M {
+    type State = ();
+
+    fn logits(&self, prev_tokens: &[usize], _state: &mut ()) -> Vec<f32> {
+        next_token_logits(self, prev_tokens)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamSearch {
+    pub beam_size: usize,
+    pub max_new_tokens: usize,
+    // Scores are log_prob / length^length_penalty: 0 ranks by plain log probability, which
+    // favors short sequences, higher values favor longer ones
+    pub length_penalty: f32,
+    // Ends a hypothesis, it's included in its tokens
+    pub stop_token: Option<usize>,
+    // Stop as soon as beam_size hypotheses are finished, instead of when the live ones can't
+    // beat them anymore
+    pub early_stopping: bool,
+}
+
+// A finished sequence, best first in BeamSearch::run's output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis {
+    // Generated tokens, the prompt not included
+    pub tokens: Vec<usize>,
+    pub log_prob: f32,
+    // Length-normalized log probability the hypotheses are ranked by
+    pub score: f32,
+}
+
+struct Beam<S> {
+    tokens: Vec<usize>,
+    log_prob: f32,
+    state: S,
+}
+
+impl BeamSearch {
+    pub fn new(beam_size: usize, max_new_tokens: usize) -> BeamSearch {
+        assert!(beam_size > 0, "beam_size must be at least 1");
+        BeamSearch {
+            beam_size,
+            max_new_tokens,
+            length_penalty: 1.0,
+            stop_token: None,
+            early_stopping: false,
+        }
+    }
+
+    pub fn length_penalty(mut self, length_penalty: f32) -> Self {
+        self.length_penalty = length_penalty;
+        self
+    }
+
+    pub fn stop_token(mut self, token: usize) -> Self {
+        self.stop_token = Some(token);
+        self
+    }
+
+    pub fn early_stopping(mut self, early_stopping: bool) -> Self {
+        self.early_stopping = early_stopping;
+        self
+    }
+
+    // Up to beam_size hypotheses continuing `prompt`, best first. `state` is the decoder's
+    // starting state, shared by all beams until they branch.
+    pub fn run<D: Decoder + ?Sized>(
+        &self,
+        decoder: &D,
+        prompt: &[usize],
+        state: D::State,
+    ) -> Vec<Hypothesis> {
+        assert!(!prompt.is_empty(), "beam search needs a non-empty prompt");
+        let mut beams = vec![Beam {
+            tokens: prompt.to_vec(),
+            log_prob: 0.0,
+            state,
+        }];
+        let mut finished: Vec<Hypothesis> = Vec::new();
+        for step in 1..=self.max_new_tokens {
+            // (beam, token, log_prob) of every continuation
+            let mut candidates = Vec::new();
+            for (b, beam) in beams.iter_mut().enumerate() {
+                let logits = decoder.logits(&beam.tokens, &mut beam.state);
+                for (token, logp) in log_softmax(&logits).into_iter().enumerate() {
+                    candidates.push((b, token, beam.log_prob + logp));
+                }
+            }
+            candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+            // Twice the beam size, so there are enough live ones left if some finish
+            let mut next = Vec::new();
+            for (rank, &(b, token, log_prob)) in
+                candidates.iter().take(2 * self.beam_size).enumerate()
+            {
+                let mut tokens = beams[b].tokens.clone();
+                tokens.push(token);
+                if self.stop_token == Some(token) {
+                    // Only when it'd have made the beam, not as a filler
+                    if rank < self.beam_size {
+                        finished.push(self.hypothesis(tokens, log_prob, prompt.len()));
+                    }
+                } else {
+                    next.push(Beam {
+                        tokens,
+                        log_prob,
+                        state: beams[b].state.clone(),
+                    });
+                }
+                if next.len() == self.beam_size {
+                    break;
+                }
+            }
+            beams = next;
+            if beams.is_empty() || self.done(&mut finished, &beams, step) {
+                break;
+            }
+        }
+        for beam in beams {
+            finished.push(self.hypothesis(beam.tokens, beam.log_prob, prompt.len()));
+        }
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(self.beam_size);
+        finished
+    }
+
+    fn hypothesis(&self, mut tokens: Vec<usize>, log_prob: f32, prompt_len: usize) -> Hypothesis {
+        let tokens = tokens.split_off(prompt_len);
+        Hypothesis {
+            score: log_prob / (tokens.len() as f32).powf(self.length_penalty),
+            tokens,
+            log_prob,
+        }
+    }
+
+    // Whether enough hypotheses are finished. Without early stopping, also that the best live
+    // beam, scored at its current length, doesn't beat the worst of the best finished ones.
+    fn done<S>(&self, finished: &mut [Hypothesis], beams: &[Beam<S>], step: usize) -> bool {
+        if finished.len() < self.beam_size {
+            return false;
+        }
+        if self.early_stopping {
+            return true;
+        }
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let worst = finished[self.beam_size - 1].score;
+        let best_live = beams
+            .iter()
+            .map(|beam| beam.log_prob)
+            .fold(f32::NEG_INFINITY, f32::max)
+            / (step as f32).powf(self.length_penalty);
+        best_live <= worst
+    }
+}
+
+fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+    let log_sum = logits.iter().map(|&x| (x - max).exp()).sum::<f32>().ln();
+    logits.iter().map(|&x| x - max - log_sum).collect()
+}
+
+// Logits for the token after `tokens`, from a forward pass in inference mode
+fn next_token_logits<M: Module + ?Sized>(model: &M, tokens: &[usize]) -> Vec<f32> {
+    let ids = ArrayD::from_shape_vec(
+        IxDyn(&[tokens.len()]),
+        tokens.iter().map(|&t| t as f32).collect(),
+    )
+    .unwrap();
+    let logits = model.predict(&Tensor::from(ids));
+    let logits = &logits.borrow().data;
+    match logits.ndim() {
+        1 => logits.iter().copied().collect(),
+        2 => logits
+            .index_axis(Axis(0), logits.len_of(Axis(0)) - 1)
+            .iter()
+            .copied()
+            .collect(),
+        _ => panic!(
+            "generation expects [T, vocab] or [vocab] logits, got shape {:?}",
+            logits.shape()
+        ),
+    }
+}
+
+fn argmax(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .expect("empty logits")
+}
+
+fn softmax(logits: &[f32], temperature: f32) -> Vec<f32> {
+    let max = logits.iter().fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+    let exp: Vec<f32> = logits
+        .iter()
+        .map(|&x| ((x - max) / temperature).exp())
+        .collect();
+    let sum: f32 = exp.iter().sum();
+    exp.into_iter().map(|e| e / sum).collect()
+}
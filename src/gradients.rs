@@ -0,0 +1,372 @@
+// Gradient norms per parameter and per layer, to spot vanishing or exploding gradients in
+// deep stacks. Call after backward:
+//
+//     loss.backward();
+//     let norms = gradients::grad_norms(&model);
+//     for (layer, norm) in &norms.modules { println!("{:>12} {:.3e}", layer, norm) }
+//
+// or let a `GradNormHistory` record them on every batch of a Trainer and read the columns back
+// as tensors. `GradFlow` looks at the whole graph instead, intermediate results included, and
+// flags the nodes whose gradient vanished or exploded:
+//
+//     let flow = GradFlow::with_names(&loss, &model);
+//     flow.save("flow.dot")?;  // or flow.png for a bar chart of the parameters, with "viz"
+
+use crate::nn::Module;
+use crate::op::Op;
+use crate::tensor::Tensor;
+use crate::train::{Callback, Context};
+#[cfg(feature = "viz")]
+use crate::viz::BarChart;
+use ndarray::{Array1, Array2};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+// L2 norms of the gradients left by the last backward
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradNorms {
+    // By parameter name, in named_parameters order
+    pub parameters: Vec<(String, f32)>,
+    // By the layer owning the parameters, the name up to the last dot ("0" for "0.weight",
+    // empty for parameters of the root), in order of first appearance
+    pub modules: Vec<(String, f32)>,
+    // Over all the parameters, what clipping by global norm compares against
+    pub total: f32,
+}
+
+// Frozen parameters are left out, trainable ones backward didn't reach count as 0. A parameter
+// shared by several layers is reported once, under its first name.
+pub fn grad_norms(module: &dyn Module) -> GradNorms {
+    let mut seen = HashSet::new();
+    let mut parameters = Vec::new();
+    let mut modules: Vec<(String, f32)> = Vec::new();
+    let mut total = 0.0;
+    for (name, param) in module.named_parameters() {
+        if !param.requires_grad() || !seen.insert(param.borrow()._uuid) {
+            continue;
+        }
+        let squared = squared_norm(&param);
+        total += squared;
+        let owner = name.rfind('.').map_or("", |i| &name[..i]);
+        match modules.iter_mut().find(|(layer, _)| layer == owner) {
+            Some((_, sum)) => *sum += squared,
+            None => modules.push((owner.to_string(), squared)),
+        }
+        parameters.push((name, squared.sqrt()));
+    }
+    for (_, sum) in &mut modules {
+        *sum = sum.sqrt();
+    }
+    GradNorms {
+        parameters,
+        modules,
+        total: total.sqrt(),
+    }
+}
+
+fn squared_norm(param: &Tensor) -> f32 {
+    param
+        .borrow()
+        .grad
+        .as_ref()
+        .map_or(0.0, |grad| grad.iter().map(|g| g * g).sum())
+}
+
+// Gradient norms over training steps. The columns are fixed by the first record, later steps
+// missing one of them (e.g. after freezing a layer) record 0 for it.
+#[derive(Debug, Clone, Default)]
+pub struct GradNormHistory {
+    parameter_names: Vec<String>,
+    module_names: Vec<String>,
+    parameters: Vec<Vec<f32>>,
+    modules: Vec<Vec<f32>>,
+    totals: Vec<f32>,
+}
+
+impl GradNormHistory {
+    pub fn new() -> GradNormHistory {
+        GradNormHistory::default()
+    }
+
+    // Add a step with the current gradients of `module`
+    pub fn record(&mut self, module: &dyn Module) -> GradNorms {
+        let norms = grad_norms(module);
+        if self.totals.is_empty() {
+            self.parameter_names = norms.parameters.iter().map(|(n, _)| n.clone()).collect();
+            self.module_names = norms.modules.iter().map(|(n, _)| n.clone()).collect();
+        }
+        self.parameters
+            .push(row(&self.parameter_names, &norms.parameters));
+        self.modules.push(row(&self.module_names, &norms.modules));
+        self.totals.push(norms.total);
+        norms
+    }
+
+    pub fn len(&self) -> usize {
+        self.totals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.totals.is_empty()
+    }
+
+    // Column names of parameter_history
+    pub fn parameter_names(&self) -> &[String] {
+        &self.parameter_names
+    }
+
+    // Column names of module_history
+    pub fn module_names(&self) -> &[String] {
+        &self.module_names
+    }
+
+    // [steps, parameters]
+    pub fn parameter_history(&self) -> Tensor {
+        table(&self.parameters, self.parameter_names.len())
+    }
+
+    // [steps, modules]
+    pub fn module_history(&self) -> Tensor {
+        table(&self.modules, self.module_names.len())
+    }
+
+    // [steps]
+    pub fn total_history(&self) -> Tensor {
+        Tensor::from(Array1::from(self.totals.clone()).into_dyn())
+    }
+}
+
+fn row(names: &[String], norms: &[(String, f32)]) -> Vec<f32> {
+    names
+        .iter()
+        .map(|name| {
+            norms
+                .iter()
+                .find(|(n, _)| n == name)
+                .map_or(0.0, |&(_, norm)| norm)
+        })
+        .collect()
+}
+
+fn table(rows: &[Vec<f32>], columns: usize) -> Tensor {
+    let data = rows.iter().flatten().copied().collect();
+    Tensor::from(
+        Array2::from_shape_vec((rows.len(), columns), data)
+            .unwrap()
+            .into_dyn(),
+    )
+}
+
+// Records every batch, after backward and before the optimizer step
+impl Callback for GradNormHistory {
+    fn on_backward(&mut self, ctx: &mut Context) {
+        self.record(ctx.model);
+    }
+}
+
+// How large a gradient is compared to the thresholds of a GradFlow. Non-finite norms count as
+// exploding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradHealth {
+    Vanishing,
+    Healthy,
+    Exploding,
+}
+
+// Gradient norms over the whole graph of a loss, leaves and intermediate results alike
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradFlow {
+    // Children first, like io::Graph
+    pub nodes: Vec<GradFlowNode>,
+    // Index of the tensor the flow was captured from
+    pub output: usize,
+    // Norms below `vanishing` or above `exploding` get flagged
+    pub vanishing: f32,
+    pub exploding: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradFlowNode {
+    // None for leaves
+    pub op: Option<Op>,
+    // Parameter name (see `with_names`), otherwise the tensor's label
+    pub name: Option<String>,
+    pub shape: Vec<usize>,
+    pub children: Vec<usize>,
+    // L2 norm of the gradient, None where backward left none
+    pub grad_norm: Option<f32>,
+    pub requires_grad: bool,
+}
+
+impl GradFlow {
+    // Everything `loss` was computed from, after `loss.backward()`
+    pub fn capture(loss: &Tensor) -> GradFlow {
+        GradFlow::capture_named(loss, &HashMap::new())
+    }
+
+    // Same as capture, with the leaves that are parameters of `module` named after them
+    pub fn with_names(loss: &Tensor, module: &dyn Module) -> GradFlow {
+        let mut names = HashMap::new();
+        for (name, param) in module.named_parameters() {
+            names.entry(param.borrow()._uuid).or_insert(name);
+        }
+        GradFlow::capture_named(loss, &names)
+    }
+
+    fn capture_named(loss: &Tensor, names: &HashMap<Uuid, String>) -> GradFlow {
+        let mut flow = GradFlow {
+            nodes: Vec::new(),
+            output: 0,
+            vanishing: 1e-7,
+            exploding: 1e3,
+        };
+        let mut index = HashMap::new();
+        flow.output = flow.visit(loss, names, &mut index);
+        flow
+    }
+
+    fn visit(
+        &mut self,
+        tensor: &Tensor,
+        names: &HashMap<Uuid, String>,
+        index: &mut HashMap<Uuid, usize>,
+    ) -> usize {
+        let uuid = tensor.borrow()._uuid;
+        if let Some(&i) = index.get(&uuid) {
+            return i;
+        }
+        let children = tensor
+            .borrow()
+            ._children
+            .iter()
+            .map(|child| self.visit(child, names, index))
+            .collect();
+        let inner = tensor.borrow();
+        self.nodes.push(GradFlowNode {
+            op: inner._op,
+            name: names.get(&uuid).cloned().or_else(|| inner.label.clone()),
+            shape: inner.data.shape().to_vec(),
+            children,
+            grad_norm: tensor
+                .grad_array()
+                .map(|grad| grad.iter().map(|g| g * g).sum::<f32>().sqrt()),
+            requires_grad: inner.requires_grad,
+        });
+        index.insert(uuid, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub fn thresholds(mut self, vanishing: f32, exploding: f32) -> Self {
+        self.vanishing = vanishing;
+        self.exploding = exploding;
+        self
+    }
+
+    // None for nodes that don't take gradients. Nodes that do but got none count as vanishing,
+    // backward didn't reach them.
+    pub fn health(&self, node: usize) -> Option<GradHealth> {
+        let node = &self.nodes[node];
+        if !node.requires_grad {
+            return None;
+        }
+        let norm = node.grad_norm.unwrap_or(0.0);
+        Some(if !norm.is_finite() || norm > self.exploding {
+            GradHealth::Exploding
+        } else if norm < self.vanishing {
+            GradHealth::Vanishing
+        } else {
+            GradHealth::Healthy
+        })
+    }
+
+    // Indices of the vanishing and exploding nodes
+    pub fn flagged(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&i| matches!(self.health(i), Some(h) if h != GradHealth::Healthy))
+            .collect()
+    }
+
+    // Graphviz source, nodes colored by health and edges pointing from input to result:
+    //
+    //     dot -Tpng flow.dot -o flow.png
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph grad_flow {\n    rankdir=BT;\n");
+        dot.push_str("    node [shape=box, style=\"filled,rounded\", fontname=monospace];\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let color = match self.health(i) {
+                None => "#f0f0f0",
+                Some(GradHealth::Healthy) => "#e5f5e0",
+                Some(GradHealth::Vanishing) => "#fdd0a2",
+                Some(GradHealth::Exploding) => "#fc9272",
+            };
+            let title = match (&node.name, &node.op) {
+                (Some(name), Some(op)) => format!("{} = {}", name, op),
+                (Some(name), None) => name.clone(),
+                (None, op) => op.map_or("leaf", |op| op.name()).to_string(),
+            };
+            let norm = node
+                .grad_norm
+                .map_or("-".to_string(), |norm| format!("{:.2e}", norm));
+            dot.push_str(&format!(
+                "    n{} [label=\"{}\\n{:?}\\n|grad| {}\", fillcolor=\"{}\"];\n",
+                i,
+                title.replace('"', "\\\""),
+                node.shape,
+                norm,
+                color
+            ));
+            for child in &node.children {
+                dot.push_str(&format!("    n{} -> n{};\n", child, i));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Gradient norm of every parameter on a log scale, flagged ones in red. Parameters are the
+    // named trainable leaves, or all of them (inputs included) when none has a name.
+    #[cfg(feature = "viz")]
+    pub fn chart(&self) -> BarChart {
+        let named = self
+            .nodes
+            .iter()
+            .any(|node| node.op.is_none() && node.name.is_some());
+        let mut chart = BarChart::new("Gradient norms")
+            .x_label("gradient L2 norm")
+            .log_x(true);
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.op.is_some() || !node.requires_grad || (named && node.name.is_none()) {
+                continue;
+            }
+            let label = node
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{:?}", node.shape));
+            let flagged = self.health(i) != Some(GradHealth::Healthy);
+            chart = chart.bar(&label, node.grad_norm.unwrap_or(0.0), flagged);
+        }
+        chart
+    }
+
+    // Graphviz source for .dot, otherwise the chart as SVG or PNG by the extension, which
+    // needs the "viz" feature
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if path.extension().and_then(|e| e.to_str()) == Some("dot") {
+            return fs::write(path, self.to_dot());
+        }
+        #[cfg(feature = "viz")]
+        return self.chart().save(path);
+        #[cfg(not(feature = "viz"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "can't save {}: only .dot without the \"viz\" feature",
+                path.display()
+            ),
+        ))
+    }
+}
@@ -0,0 +1,45 @@
+// Inference mode: ops compute their values but record nothing for backward. Outputs are plain
+// leaves without children, op name, backward function or saved values, so inputs and
+// intermediates are freed as soon as the next op is done with them, and tensors get a cheap
+// counter id instead of a random uuid.
+//
+//     let probs = inference::inference_mode(|| model.forward(&x).softmax(1));
+//     let out = model.predict(&x); // the same for a single forward
+//
+// Calling backward on anything computed inside only reaches that tensor itself. Inference mode
+// is per thread.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+// One counter for all threads, so ids are unique process-wide like uuids
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+// Run `f` in inference mode, the previous mode is restored afterwards, also on panic
+pub fn inference_mode<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ENABLED.with(|enabled| enabled.set(self.0));
+        }
+    }
+
+    let _restore = Restore(ENABLED.with(|enabled| enabled.replace(true)));
+    f()
+}
+
+// Version 4 uuids always have their version bits set, a 0 in the high half can't collide with
+// them
+pub(crate) fn next_id() -> Uuid {
+    Uuid::from_u64_pair(0, NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
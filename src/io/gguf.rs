@@ -0,0 +1,277 @@
+// GGUF, the llama.cpp model format, https://github.com/ggerganov/ggml/blob/master/docs/gguf.md
+// Reads versions 2 and 3: all metadata, and tensors stored as F32, F16, BF16 or Q8_0, which
+// are dequantized to f32. GGUF lists dimensions innermost first, shapes here are reversed to
+// the usual row-major order, so a [out, in] weight comes back as [out, in].
+
+use crate::io::{bf16_to_f32, f16_to_f32};
+use crate::nn::StateDict;
+use ndarray::{ArrayD, IxDyn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"GGUF";
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+// ggml tensor types that can be read
+const GGML_F32: u32 = 0;
+const GGML_F16: u32 = 1;
+const GGML_Q8_0: u32 = 8;
+const GGML_BF16: u32 = 30;
+// Q8_0 block: an f16 scale followed by 32 int8 values
+const Q8_0_BLOCK: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Bool(bool),
+    String(String),
+    Array(Vec<MetadataValue>),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl MetadataValue {
+    // Any integer type that fits, e.g. for "llama.block_count" which writers store as u32 or u64
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            MetadataValue::U8(v) => Some(v as u64),
+            MetadataValue::U16(v) => Some(v as u64),
+            MetadataValue::U32(v) => Some(v as u64),
+            MetadataValue::U64(v) => Some(v),
+            MetadataValue::I8(v) => u64::try_from(v).ok(),
+            MetadataValue::I16(v) => u64::try_from(v).ok(),
+            MetadataValue::I32(v) => u64::try_from(v).ok(),
+            MetadataValue::I64(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetadataValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+pub struct Gguf {
+    pub version: u32,
+    pub metadata: BTreeMap<String, MetadataValue>,
+    pub tensors: StateDict,
+}
+
+impl Gguf {
+    // Tensors under module parameter names, `rename` maps a GGUF name such as
+    // "blk.0.attn_q.weight" to a parameter name, tensors it returns None for are dropped.
+    // The result goes straight into Module::load_state_dict.
+    pub fn state_dict(&self, rename: impl Fn(&str) -> Option<String>) -> StateDict {
+        self.tensors
+            .iter()
+            .filter_map(|(name, array)| rename(name).map(|name| (name, array.clone())))
+            .collect()
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<Gguf> {
+    parse(&fs::read(path)?)
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<Gguf> {
+    let mut cursor = Cursor { bytes, pos: 0 };
+    if cursor.take(4)? != MAGIC {
+        return Err(invalid("not a GGUF file"));
+    }
+    let version = cursor.u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("GGUF version {} is not supported", version),
+        ));
+    }
+    let tensor_count = cursor.u64()?;
+    let metadata_count = cursor.u64()?;
+
+    let mut metadata = BTreeMap::new();
+    for _ in 0..metadata_count {
+        let key = cursor.string()?;
+        let value_type = cursor.u32()?;
+        metadata.insert(key, cursor.value(value_type)?);
+    }
+
+    let mut infos = Vec::new();
+    for _ in 0..tensor_count {
+        let name = cursor.string()?;
+        let n_dims = cursor.u32()?;
+        let mut shape = (0..n_dims)
+            .map(|_| {
+                let dim = cursor.u64()?;
+                usize::try_from(dim).map_err(|_| invalid(format!("tensor {:?} is too large", name)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        shape.reverse();
+        let ggml_type = cursor.u32()?;
+        let offset = cursor.u64()?;
+        infos.push((name, shape, ggml_type, offset));
+    }
+
+    // Tensor data starts at the next multiple of the alignment, offsets are relative to it
+    let alignment = metadata
+        .get("general.alignment")
+        .and_then(MetadataValue::as_u64)
+        .unwrap_or(DEFAULT_ALIGNMENT);
+    if alignment == 0 {
+        return Err(invalid("general.alignment is 0"));
+    }
+    let data_start = (cursor.pos as u64)
+        .checked_next_multiple_of(alignment)
+        .ok_or_else(|| invalid(format!("general.alignment {} is too large", alignment)))?;
+
+    let mut tensors = StateDict::new();
+    for (name, shape, ggml_type, offset) in infos {
+        // Sizes and offsets come from the file, so none of this arithmetic may overflow
+        let too_large = || invalid(format!("tensor {:?} is too large", name));
+        let numel = shape
+            .iter()
+            .try_fold(1usize, |numel, &dim| numel.checked_mul(dim))
+            .ok_or_else(too_large)?;
+        let start = data_start
+            .checked_add(offset)
+            .and_then(|start| usize::try_from(start).ok())
+            .ok_or_else(too_large)?;
+        let len = |size: usize| numel.checked_mul(size).ok_or_else(too_large);
+        let values = match ggml_type {
+            GGML_F32 => tensor_bytes(bytes, start, len(4)?, &name)?
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            GGML_F16 => tensor_bytes(bytes, start, len(2)?, &name)?
+                .chunks_exact(2)
+                .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect(),
+            GGML_BF16 => tensor_bytes(bytes, start, len(2)?, &name)?
+                .chunks_exact(2)
+                .map(|b| bf16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect(),
+            GGML_Q8_0 => {
+                if !numel.is_multiple_of(Q8_0_BLOCK) {
+                    return Err(invalid(format!(
+                        "Q8_0 tensor {:?} has {} values, not a multiple of {}",
+                        name, numel, Q8_0_BLOCK
+                    )));
+                }
+                let block_bytes = (numel / Q8_0_BLOCK)
+                    .checked_mul(2 + Q8_0_BLOCK)
+                    .ok_or_else(too_large)?;
+                tensor_bytes(bytes, start, block_bytes, &name)?
+                    .chunks_exact(2 + Q8_0_BLOCK)
+                    .flat_map(|block| {
+                        let scale = f16_to_f32(u16::from_le_bytes([block[0], block[1]]));
+                        block[2..].iter().map(move |&q| q as i8 as f32 * scale)
+                    })
+                    .collect()
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("tensor {:?} has unsupported ggml type {}", name, other),
+                ))
+            }
+        };
+        let array =
+            ArrayD::from_shape_vec(IxDyn(&shape), values).map_err(|e| invalid(e.to_string()))?;
+        tensors.insert(name, array);
+    }
+
+    Ok(Gguf {
+        version,
+        metadata,
+        tensors,
+    })
+}
+
+fn tensor_bytes<'a>(bytes: &'a [u8], start: usize, len: usize, name: &str) -> io::Result<&'a [u8]> {
+    bytes
+        .get(start..)
+        .and_then(|rest| rest.get(..len))
+        .ok_or_else(|| {
+            invalid(format!(
+                "data of tensor {:?} runs past the end of the file",
+                name
+            ))
+        })
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// Little-endian reads over the header
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| invalid("truncated GGUF header"))?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u64()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("string is not utf-8"))
+    }
+
+    fn value(&mut self, value_type: u32) -> io::Result<MetadataValue> {
+        Ok(match value_type {
+            0 => MetadataValue::U8(self.array::<1>()?[0]),
+            1 => MetadataValue::I8(self.array::<1>()?[0] as i8),
+            2 => MetadataValue::U16(u16::from_le_bytes(self.array()?)),
+            3 => MetadataValue::I16(i16::from_le_bytes(self.array()?)),
+            4 => MetadataValue::U32(self.u32()?),
+            5 => MetadataValue::I32(i32::from_le_bytes(self.array()?)),
+            6 => MetadataValue::F32(f32::from_le_bytes(self.array()?)),
+            7 => MetadataValue::Bool(self.array::<1>()?[0] != 0),
+            8 => MetadataValue::String(self.string()?),
+            9 => {
+                let element_type = self.u32()?;
+                let len = self.u64()?;
+                MetadataValue::Array(
+                    (0..len)
+                        .map(|_| self.value(element_type))
+                        .collect::<io::Result<_>>()?,
+                )
+            }
+            10 => MetadataValue::U64(self.u64()?),
+            11 => MetadataValue::I64(i64::from_le_bytes(self.array()?)),
+            12 => MetadataValue::F64(f64::from_le_bytes(self.array()?)),
+            other => return Err(invalid(format!("unknown metadata value type {}", other))),
+        })
+    }
+}
@@ -0,0 +1,230 @@
+// Computation graphs as JSON, for external visualizers and regression snapshots of graph
+// structure. Nodes are listed children first and refer to each other by index:
+//
+//     {"nodes": [{"op": null, "shape": [2, 3], "children": [], "saved": [], "values": [..]},
+//                {"op": "relu", "shape": [2, 3], "children": [0], "saved": [], "values": null}],
+//      "output": 1}
+//
+// Labelled tensors also get a "name". Leaf values are only stored when asked for, without
+// them a snapshot only changes when the structure does. `rebuild` replays the recorded ops on
+// the leaves to get a live graph back.
+
+use crate::op::Op;
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, IxDyn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    // Index of the tensor the graph was captured from
+    pub output: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    // None for leaves
+    pub op: Option<Op>,
+    // The tensor's label, see Tensor::named. Left out of the JSON when there is none, so
+    // snapshots of unlabelled graphs stay as they were.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub shape: Vec<usize>,
+    pub children: Vec<usize>,
+    // Values backward needs besides the children, such as the indices of `gather_rows`,
+    // flattened
+    pub saved: Vec<Vec<f32>>,
+    // Flattened leaf data, only with `with_values`
+    pub values: Option<Vec<f32>>,
+}
+
+impl Graph {
+    // Everything `root` was computed from
+    pub fn capture(root: &Tensor, with_values: bool) -> Graph {
+        let mut nodes = Vec::new();
+        let mut index: HashMap<Uuid, usize> = HashMap::new();
+        let output = visit(root, with_values, &mut nodes, &mut index);
+        Graph { nodes, output }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Graph> {
+        let graph: Graph = serde_json::from_str(json)?;
+        graph.validate()?;
+        Ok(graph)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Graph> {
+        Graph::from_json(&fs::read_to_string(path)?)
+    }
+
+    // Replay the ops to get a differentiable graph again, leaves without stored values are
+    // zeros. Returns the output tensor; the leaves are reachable through its children.
+    pub fn rebuild(&self) -> io::Result<Tensor> {
+        let mut tensors: Vec<Tensor> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let children: Vec<&Tensor> = node.children.iter().map(|&i| &tensors[i]).collect();
+            let tensor = match node.op {
+                None => {
+                    let values = node
+                        .values
+                        .clone()
+                        .unwrap_or_else(|| vec![0.0; node.shape.iter().product()]);
+                    ArrayD::from_shape_vec(IxDyn(&node.shape), values)
+                        .map(Tensor::from)
+                        .map_err(|e| invalid(e.to_string()))?
+                }
+                Some(op) => replay(op, &children)?,
+            };
+            if tensor.shape() != node.shape {
+                return Err(invalid(format!(
+                    "replaying `{}` gave shape {:?}, the graph recorded {:?}",
+                    node.name
+                        .as_deref()
+                        .unwrap_or(node.op.map_or("leaf", |op| op.name())),
+                    tensor.shape(),
+                    node.shape
+                )));
+            }
+            if let Some(name) = &node.name {
+                tensor.set_label(name);
+            }
+            tensors.push(tensor);
+        }
+        Ok(tensors.swap_remove(self.output))
+    }
+
+    // Children must come before their parents, which also rules out cycles
+    fn validate(&self) -> io::Result<()> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some(&child) = node.children.iter().find(|&&child| child >= i) {
+                return Err(invalid(format!(
+                    "node {} refers to node {} which doesn't come before it",
+                    i, child
+                )));
+            }
+        }
+        if self.output >= self.nodes.len() {
+            return Err(invalid(format!(
+                "output {} is not a node of the graph",
+                self.output
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn visit(
+    tensor: &Tensor,
+    with_values: bool,
+    nodes: &mut Vec<Node>,
+    index: &mut HashMap<Uuid, usize>,
+) -> usize {
+    let uuid = tensor.borrow()._uuid;
+    if let Some(&i) = index.get(&uuid) {
+        return i;
+    }
+    let children = tensor
+        .borrow()
+        ._children
+        .iter()
+        .map(|child| visit(child, with_values, nodes, index))
+        .collect();
+    let inner = tensor.borrow();
+    let is_leaf = inner._op.is_none();
+    nodes.push(Node {
+        op: inner._op,
+        name: inner.label.clone(),
+        shape: inner.data.shape().to_vec(),
+        children,
+        saved: inner
+            ._saved
+            .iter()
+            .map(|s| s.iter().copied().collect())
+            .collect(),
+        values: (with_values && is_leaf).then(|| inner.data.iter().copied().collect()),
+    });
+    index.insert(uuid, nodes.len() - 1);
+    nodes.len() - 1
+}
+
+// Ops that can be recomputed from their inputs, the fused losses need their targets which
+// aren't part of the graph
+fn replay(op: Op, children: &[&Tensor]) -> io::Result<Tensor> {
+    let arity = |n: usize| {
+        if children.len() == n {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "`{}` needs {} inputs, got {}",
+                op,
+                n,
+                children.len()
+            )))
+        }
+    };
+    let tensor = match op {
+        Op::Add => {
+            arity(2)?;
+            children[0].try_add(children[1])
+        }
+        Op::Mul => {
+            arity(2)?;
+            children[0].try_mul(children[1])
+        }
+        Op::Matmul => {
+            arity(2)?;
+            children[0].try_matmul(children[1])
+        }
+        Op::Transpose => {
+            arity(1)?;
+            if children[0].ndim() != 2 {
+                return Err(invalid("`t` needs a 2-D input"));
+            }
+            Ok(children[0].t())
+        }
+        Op::Relu => {
+            arity(1)?;
+            Ok(children[0].relu())
+        }
+        Op::Tanh => {
+            arity(1)?;
+            Ok(children[0].tanh())
+        }
+        Op::Softmax { axis } => {
+            arity(1)?;
+            children[0].try_softmax(axis)
+        }
+        Op::Sum { axis } => {
+            arity(1)?;
+            children[0].try_sum_axis(axis)
+        }
+        Op::Row { index } => {
+            arity(1)?;
+            children[0].try_row(index)
+        }
+        op => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("op `{}` can't be replayed", op),
+            ))
+        }
+    };
+    tensor.map_err(|e| invalid(e.to_string()))
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
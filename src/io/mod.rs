@@ -0,0 +1,34 @@
+// Reading and writing tensors in external file formats
+
+pub mod gguf;
+pub mod graph;
+pub mod npy;
+pub mod onnx;
+pub(crate) mod protobuf;
+pub mod safetensors;
+pub mod torch;
+
+pub use npy::{load_npz, save_npz};
+
+// IEEE 754 half precision bits to f32
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    let bits = match exponent {
+        // Zero and subnormals, value is mantissa * 2^-24
+        0 => {
+            let value = mantissa as f32 * f32::powi(2.0, -24);
+            return if sign != 0 { -value } else { value };
+        }
+        // Infinity and NaN
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+// bfloat16 is the upper half of an f32
+pub(crate) fn bf16_to_f32(bits: u16) -> f32 {
+    f32::from_bits((bits as u32) << 16)
+}
@@ -0,0 +1,210 @@
+// NumPy's .npy (single array) and .npz (zip of named .npy files) formats,
+// https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+// Arrays of any numeric dtype are read and converted to f32, writing always uses '<f4'.
+
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, IxDyn};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl Tensor {
+    pub fn from_npy(path: impl AsRef<Path>) -> io::Result<Tensor> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_npy(&mut reader).map(Tensor::from)
+    }
+
+    pub fn save_npy(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_npy(&mut writer, &self.borrow().data)?;
+        writer.flush()
+    }
+}
+
+pub fn write_npy(writer: &mut impl Write, array: &ArrayD<f32>) -> io::Result<()> {
+    let shape = match array.shape() {
+        [] => String::from("()"),
+        [n] => format!("({},)", n),
+        dims => format!(
+            "({})",
+            dims.iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // Pad with spaces so the data starts at a multiple of 64 bytes, header ends in a newline
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in array.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_npy(reader: &mut impl Read) -> io::Result<ArrayD<f32>> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid("not a .npy file"));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(invalid(format!("unsupported .npy version {}", version))),
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")?;
+    let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+    let fortran_order = header_value(&header, "fortran_order")? == "True";
+    let shape: Vec<usize> = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            d.parse::<usize>()
+                .map_err(|_| invalid(format!("invalid shape dimension {:?}", d)))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let values = decode(descr, &bytes)?;
+    let numel: usize = shape.iter().product();
+    if values.len() < numel {
+        return Err(invalid(format!(
+            "expected {} values for shape {:?}, found {}",
+            numel,
+            shape,
+            values.len()
+        )));
+    }
+    let values = values[..numel].to_vec();
+
+    if fortran_order {
+        // Column-major data is the row-major layout of the reversed shape, transposed
+        let reversed: Vec<usize> = shape.iter().rev().copied().collect();
+        let array = ArrayD::from_shape_vec(IxDyn(&reversed), values).unwrap();
+        Ok(array.reversed_axes().as_standard_layout().into_owned())
+    } else {
+        Ok(ArrayD::from_shape_vec(IxDyn(&shape), values).unwrap())
+    }
+}
+
+// Raw value of `key` in the header's python dict literal, e.g. "(2, 3)" for 'shape'
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let missing = || invalid(format!(".npy header has no {:?}: {}", key, header));
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)? + key.len() + 2;
+    let rest = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?
+        .trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    }
+    .ok_or_else(missing)?;
+    Ok(rest[..end].trim())
+}
+
+fn decode(descr: &str, bytes: &[u8]) -> io::Result<Vec<f32>> {
+    // '|' means byte order doesn't apply, '=' is native which is little-endian on anything we run on
+    let (big_endian, kind) = match descr.as_bytes().first() {
+        Some(b'>') => (true, &descr[1..]),
+        Some(b'<') | Some(b'|') | Some(b'=') => (false, &descr[1..]),
+        _ => (false, descr),
+    };
+    macro_rules! convert {
+        ($ty:ty) => {{
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            bytes
+                .chunks_exact(SIZE)
+                .map(|chunk| {
+                    let raw: [u8; SIZE] = chunk.try_into().unwrap();
+                    let value = if big_endian {
+                        <$ty>::from_be_bytes(raw)
+                    } else {
+                        <$ty>::from_le_bytes(raw)
+                    };
+                    value as f32
+                })
+                .collect()
+        }};
+    }
+    Ok(match kind {
+        "f4" => convert!(f32),
+        "f8" => convert!(f64),
+        "i1" => convert!(i8),
+        "i2" => convert!(i16),
+        "i4" => convert!(i32),
+        "i8" => convert!(i64),
+        "u1" | "b1" => convert!(u8),
+        "u2" => convert!(u16),
+        "u4" => convert!(u32),
+        "u8" => convert!(u64),
+        _ => return Err(invalid(format!("unsupported .npy dtype {:?}", descr))),
+    })
+}
+
+// Write named tensors as an uncompressed .npz archive, load with numpy.load(path)[name]
+pub fn save_npz(path: impl AsRef<Path>, tensors: &[(&str, &Tensor)]) -> io::Result<()> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+    for (name, tensor) in tensors {
+        zip.start_file(
+            format!("{}.npy", name),
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored),
+        )
+        .map_err(io::Error::other)?;
+        write_npy(&mut zip, &tensor.borrow().data)?;
+    }
+    zip.finish().map_err(io::Error::other)?.flush()
+}
+
+// Named tensors of an .npz archive (numpy.savez or savez_compressed), in archive order
+pub fn load_npz(path: impl AsRef<Path>) -> io::Result<Vec<(String, Tensor)>> {
+    read_npz(BufReader::new(File::open(path)?))
+}
+
+fn read_npz(reader: impl Read + Seek) -> io::Result<Vec<(String, Tensor)>> {
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::other)?;
+    let mut tensors = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(io::Error::other)?;
+        let name = file.name().trim_end_matches(".npy").to_string();
+        let array = read_npy(&mut file)?;
+        tensors.push((name, Tensor::from(array)));
+    }
+    Ok(tensors)
+}
@@ -0,0 +1,661 @@
+// ONNX export and import, https://onnx.ai/onnx/repo-docs/IR.html
+// Export traces the forward pass on a sample input and maps every recorded op to its ONNX
+// counterpart. Parameters become initializers under their state dict names. Shapes are
+// static, taken from the sample input, and checked by shape inference before tracing.
+// Import goes the other way for a small op subset: the graph becomes an `OnnxModel` whose
+// float initializers are trainable parameters, so the model can be fine-tuned like any Module.
+
+use crate::io::f16_to_f32;
+use crate::io::protobuf::{Reader, Writer};
+use crate::nn::{ForwardHooks, Module};
+use crate::op::Op;
+use crate::tensor::Tensor;
+use ndarray::{arr0, ArrayD, IxDyn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+const IR_VERSION: i64 = 8;
+const OPSET_VERSION: i64 = 17;
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+const FLOAT16: i64 = 10;
+const ATTRIBUTE_INT: i64 = 2;
+
+// Ops `load` understands, anything else in the graph is rejected up front
+const SUPPORTED_OPS: &[&str] = &[
+    "Add",
+    "Mul",
+    "MatMul",
+    "Gemm",
+    "Transpose",
+    "Relu",
+    "Tanh",
+    "Softmax",
+    "Gather",
+    "Identity",
+];
+
+pub fn export(model: &dyn Module, sample_input: &Tensor, path: impl AsRef<Path>) -> io::Result<()> {
+    fs::write(path, export_bytes(model, sample_input)?)
+}
+
+// Serialized ModelProto
+pub fn export_bytes(model: &dyn Module, sample_input: &Tensor) -> io::Result<Vec<u8>> {
+    // A model that doesn't fit the sample fails here, before a forward on it
+    model
+        .output_shape(&sample_input.shape())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    // Trace on a fresh leaf so the graph ends at our input and not at whatever produced it
+    let input = Tensor::from(sample_input.borrow().data.clone());
+    let output = model.forward(&input);
+
+    let mut names: HashMap<Uuid, String> = model
+        .named_parameters()
+        .into_iter()
+        .map(|(name, param)| (param.borrow()._uuid, name))
+        .collect();
+    names.insert(input.borrow()._uuid, String::from("input"));
+
+    let mut order = Vec::new();
+    topo_sort(&output, &mut HashSet::new(), &mut order);
+
+    let mut graph = Writer::default();
+    let mut initializers = Writer::default();
+    let mut counter = 0;
+    for node in &order {
+        let inner = node.borrow();
+        let uuid = inner._uuid;
+        let Some(op) = inner._op else {
+            // Parameters keep their state dict names, any other leaf besides the input is a
+            // constant baked into the graph
+            let name = names.entry(uuid).or_insert_with(|| {
+                counter += 1;
+                format!("const_{}", counter)
+            });
+            if name != "input" {
+                write_float_tensor(&mut initializers, name, &inner.data);
+            }
+            continue;
+        };
+
+        let op_type = match op {
+            Op::Add => "Add",
+            Op::Mul => "Mul",
+            Op::Matmul => "MatMul",
+            Op::Transpose => "Transpose",
+            Op::Relu => "Relu",
+            Op::Tanh => "Tanh",
+            Op::Softmax { .. } => "Softmax",
+            Op::Row { .. } => "Gather",
+            op => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("op `{}` has no ONNX mapping", op),
+                ))
+            }
+        };
+        let name = if node_is(&output, uuid) {
+            String::from("output")
+        } else {
+            counter += 1;
+            format!("{}_{}", op_type.to_lowercase(), counter)
+        };
+        let mut inputs: Vec<String> = inner
+            ._children
+            .iter()
+            .map(|child| names[&child.borrow()._uuid].clone())
+            .collect();
+        // row(i) is a Gather along axis 0 with the index as a scalar int64 initializer
+        let mut axis = None;
+        if let Op::Row { index } = op {
+            let index_name = format!("{}_index", name);
+            write_int64_scalar(&mut initializers, &index_name, index as i64);
+            inputs.push(index_name);
+            axis = Some(0);
+        } else if let Op::Softmax { axis: softmax_axis } = op {
+            axis = Some(softmax_axis as i64);
+        }
+        graph.message(1, |n| {
+            for input in &inputs {
+                n.string(1, input);
+            }
+            n.string(2, &name);
+            n.string(3, &name);
+            n.string(4, op_type);
+            if let Some(axis) = axis {
+                n.message(5, |a| {
+                    a.string(1, "axis");
+                    a.int(3, axis);
+                    a.int(20, ATTRIBUTE_INT);
+                });
+            }
+        });
+        names.insert(uuid, name);
+    }
+
+    // A model that returns its input (or a parameter) unchanged still needs a node
+    if output.borrow()._op.is_none() {
+        let source = names[&output.borrow()._uuid].clone();
+        graph.message(1, |n| {
+            n.string(1, &source);
+            n.string(2, "output");
+            n.string(4, "Identity");
+        });
+    }
+
+    graph.string(2, "rust-ml");
+    graph.buf.extend_from_slice(&initializers.buf);
+    write_value_info(&mut graph, 11, "input", &input.shape());
+    write_value_info(&mut graph, 12, "output", &output.shape());
+
+    let mut model_proto = Writer::default();
+    model_proto.int(1, IR_VERSION);
+    model_proto.string(2, "rust-ml");
+    model_proto.string(3, env!("CARGO_PKG_VERSION"));
+    model_proto.message(7, |g| g.buf.extend_from_slice(&graph.buf));
+    model_proto.message(8, |o| {
+        o.string(1, "");
+        o.int(2, OPSET_VERSION);
+    });
+    Ok(model_proto.buf)
+}
+
+fn node_is(tensor: &Tensor, uuid: Uuid) -> bool {
+    tensor.borrow()._uuid == uuid
+}
+
+// Children before parents
+fn topo_sort(tensor: &Tensor, visited: &mut HashSet<Uuid>, order: &mut Vec<Tensor>) {
+    if visited.insert(tensor.borrow()._uuid) {
+        for child in tensor.borrow()._children.iter() {
+            topo_sort(child, visited, order);
+        }
+        order.push(tensor.clone());
+    }
+}
+
+// GraphProto.initializer (field 5) holding an f32 TensorProto
+fn write_float_tensor(graph: &mut Writer, name: &str, data: &ArrayD<f32>) {
+    let dims: Vec<i64> = data.shape().iter().map(|&d| d as i64).collect();
+    let raw: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+    graph.message(5, |t| {
+        t.packed_ints(1, &dims);
+        t.int(2, FLOAT);
+        t.string(8, name);
+        t.bytes(9, &raw);
+    });
+}
+
+fn write_int64_scalar(graph: &mut Writer, name: &str, value: i64) {
+    graph.message(5, |t| {
+        t.int(2, INT64);
+        t.string(8, name);
+        t.bytes(9, &value.to_le_bytes());
+    });
+}
+
+// ValueInfoProto with a float tensor type of a fixed shape
+fn write_value_info(graph: &mut Writer, field: u64, name: &str, shape: &[usize]) {
+    graph.message(field, |v| {
+        v.string(1, name);
+        v.message(2, |ty| {
+            ty.message(1, |tensor| {
+                tensor.int(1, FLOAT);
+                tensor.message(2, |s| {
+                    for &dim in shape {
+                        s.message(1, |d| d.int(1, dim as i64));
+                    }
+                });
+            });
+        });
+    });
+}
+
+// A loaded single-input, single-output ONNX graph. Nodes run in file order, which ONNX
+// requires to be topological.
+pub struct OnnxModel {
+    nodes: Vec<Node>,
+    params: Vec<(String, Tensor)>,
+    // int64 initializers, only used as op arguments such as Gather indices
+    int_constants: HashMap<String, Vec<i64>>,
+    input: String,
+    output: String,
+    hooks: ForwardHooks,
+}
+
+struct Node {
+    op_type: String,
+    inputs: Vec<String>,
+    output: String,
+    attributes: HashMap<String, Attribute>,
+}
+
+#[derive(Clone, Debug)]
+enum Attribute {
+    Float(f32),
+    Int(i64),
+    Ints(Vec<i64>),
+    Other,
+}
+
+impl Node {
+    fn int(&self, name: &str, default: i64) -> i64 {
+        match self.attributes.get(name) {
+            Some(Attribute::Int(v)) => *v,
+            _ => default,
+        }
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        match self.attributes.get(name) {
+            Some(Attribute::Float(v)) => *v,
+            _ => default,
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<OnnxModel> {
+    load_bytes(&fs::read(path)?)
+}
+
+pub fn load_bytes(bytes: &[u8]) -> io::Result<OnnxModel> {
+    let mut graph = None;
+    let mut opset = OPSET_VERSION;
+    for field in Reader::new(bytes) {
+        match field? {
+            (7, value) => graph = Some(value.bytes()?),
+            (8, value) => {
+                let mut domain = String::new();
+                let mut version = 0;
+                for field in Reader::new(value.bytes()?) {
+                    match field? {
+                        (1, value) => domain = value.string()?,
+                        (2, value) => version = value.int()?,
+                        _ => {}
+                    }
+                }
+                if domain.is_empty() || domain == "ai.onnx" {
+                    opset = version;
+                }
+            }
+            _ => {}
+        }
+    }
+    let graph = graph.ok_or_else(|| invalid("model has no graph"))?;
+
+    let mut nodes = Vec::new();
+    let mut params = Vec::new();
+    let mut int_constants = HashMap::new();
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for field in Reader::new(graph) {
+        match field? {
+            (1, value) => nodes.push(read_node(value.bytes()?, opset)?),
+            (5, value) => match read_tensor(value.bytes()?)? {
+                (name, Initializer::Float(data)) => params.push((name, Tensor::from(data))),
+                (name, Initializer::Int(data)) => {
+                    int_constants.insert(name, data);
+                }
+            },
+            (11, value) => inputs.push(read_value_info_name(value.bytes()?)?),
+            (12, value) => outputs.push(read_value_info_name(value.bytes()?)?),
+            _ => {}
+        }
+    }
+
+    // Before IR version 4 initializers were also listed as graph inputs
+    let is_initializer = |name: &String| {
+        params.iter().any(|(param, _)| param == name) || int_constants.contains_key(name)
+    };
+    inputs.retain(|name| !is_initializer(name));
+    let (input, output) = match (&inputs[..], &outputs[..]) {
+        ([input], [output]) => (input.clone(), output.clone()),
+        _ => {
+            return Err(invalid(&format!(
+                "only single-input, single-output graphs are supported, got inputs {:?} and outputs {:?}",
+                inputs, outputs
+            )))
+        }
+    };
+
+    // Check every node can run before handing out the model, so forward only fails on shapes
+    let mut defined: HashSet<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+    defined.insert(&input);
+    for node in &nodes {
+        validate_node(node, &defined, &int_constants)?;
+        defined.insert(&node.output);
+    }
+    if !defined.contains(output.as_str()) {
+        return Err(invalid(&format!(
+            "graph output `{}` is never produced",
+            output
+        )));
+    }
+
+    Ok(OnnxModel {
+        nodes,
+        params,
+        int_constants,
+        input,
+        output,
+        hooks: ForwardHooks::default(),
+    })
+}
+
+fn validate_node(
+    node: &Node,
+    defined: &HashSet<&str>,
+    int_constants: &HashMap<String, Vec<i64>>,
+) -> io::Result<()> {
+    if !SUPPORTED_OPS.contains(&node.op_type.as_str()) {
+        return Err(unsupported(&format!("op `{}`", node.op_type)));
+    }
+    let arity = match node.op_type.as_str() {
+        "Add" | "Mul" | "MatMul" | "Gather" => 2..=2,
+        "Gemm" => 2..=3,
+        _ => 1..=1,
+    };
+    if !arity.contains(&node.inputs.len()) {
+        return Err(invalid(&format!(
+            "`{}` takes {:?} inputs, got {}",
+            node.op_type,
+            arity,
+            node.inputs.len()
+        )));
+    }
+    let (values, indices) = match node.op_type.as_str() {
+        "Gather" => (&node.inputs[..1], Some(&node.inputs[1])),
+        _ => (&node.inputs[..], None),
+    };
+    for name in values {
+        if !defined.contains(name.as_str()) {
+            return Err(invalid(&format!(
+                "`{}` reads `{}` before it is defined",
+                node.op_type, name
+            )));
+        }
+    }
+    match node.op_type.as_str() {
+        "Transpose" => match node.attributes.get("perm") {
+            None => Ok(()),
+            Some(Attribute::Ints(perm)) if perm[..] == [1, 0] => Ok(()),
+            Some(perm) => Err(unsupported(&format!("Transpose with perm {:?}", perm))),
+        },
+        "Gather" => {
+            if node.int("axis", 0) != 0 {
+                return Err(unsupported("Gather along an axis other than 0"));
+            }
+            match indices.and_then(|name| int_constants.get(name)) {
+                Some(index) if index.len() == 1 => Ok(()),
+                _ => Err(unsupported(
+                    "Gather with indices that aren't a scalar initializer",
+                )),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+impl OnnxModel {
+    fn run(&self, node: &Node, values: &HashMap<&str, Tensor>) -> Tensor {
+        let arg = |i: usize| &values[node.inputs[i].as_str()];
+        match node.op_type.as_str() {
+            "Add" => arg(0) + arg(1),
+            "Mul" => arg(0) * arg(1),
+            "MatMul" => arg(0).matmul(arg(1)),
+            "Relu" => arg(0).relu(),
+            "Tanh" => arg(0).tanh(),
+            "Transpose" => arg(0).t(),
+            "Identity" => arg(0).clone(),
+            "Softmax" => {
+                let x = arg(0);
+                let axis = node.int("axis", -1);
+                let axis = if axis < 0 {
+                    axis + x.ndim() as i64
+                } else {
+                    axis
+                };
+                x.softmax(axis as usize)
+            }
+            "Gather" => {
+                let x = arg(0);
+                let index = self.int_constants[&node.inputs[1]][0];
+                let index = if index < 0 {
+                    index + x.shape()[0] as i64
+                } else {
+                    index
+                };
+                x.row(index as usize)
+            }
+            "Gemm" => {
+                // Y = alpha * A' B' + beta * C, with A', B' optionally transposed
+                let a = if node.int("transA", 0) != 0 {
+                    arg(0).t()
+                } else {
+                    arg(0).clone()
+                };
+                let b = if node.int("transB", 0) != 0 {
+                    arg(1).t()
+                } else {
+                    arg(1).clone()
+                };
+                let mut y = scale(a.matmul(&b), node.float("alpha", 1.0));
+                if node.inputs.len() == 3 {
+                    y = &y + &scale(arg(2).clone(), node.float("beta", 1.0));
+                }
+                y
+            }
+            op => unreachable!("op `{}` passed validation", op),
+        }
+    }
+}
+
+impl Module for OnnxModel {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let mut values: HashMap<&str, Tensor> = self
+            .params
+            .iter()
+            .map(|(name, param)| (name.as_str(), param.clone()))
+            .collect();
+        values.insert(&self.input, input.clone());
+        for node in &self.nodes {
+            let out = self.run(node, &values);
+            values.insert(&node.output, out);
+        }
+        let output = values.remove(self.output.as_str()).unwrap();
+        self.hooks.run(input, output)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.params.clone()
+    }
+}
+
+// Multiply by a constant, skipped for the common factor of 1 so the graph stays small
+fn scale(x: Tensor, factor: f32) -> Tensor {
+    if factor == 1.0 {
+        x
+    } else {
+        &x * &Tensor::from(arr0(factor).into_dyn())
+    }
+}
+
+fn read_node(bytes: &[u8], opset: i64) -> io::Result<Node> {
+    let mut node = Node {
+        op_type: String::new(),
+        inputs: Vec::new(),
+        output: String::new(),
+        attributes: HashMap::new(),
+    };
+    let mut outputs = Vec::new();
+    for field in Reader::new(bytes) {
+        match field? {
+            (1, value) => node.inputs.push(value.string()?),
+            (2, value) => outputs.push(value.string()?),
+            (4, value) => node.op_type = value.string()?,
+            (5, value) => {
+                let (name, attribute) = read_attribute(value.bytes()?)?;
+                node.attributes.insert(name, attribute);
+            }
+            _ => {}
+        }
+    }
+    // Optional inputs are left out with an empty name
+    while node.inputs.last().is_some_and(|name| name.is_empty()) {
+        node.inputs.pop();
+    }
+    node.output = match &outputs[..] {
+        [output] => output.clone(),
+        _ => {
+            return Err(unsupported(&format!(
+                "`{}` with {} outputs",
+                node.op_type,
+                outputs.len()
+            )))
+        }
+    };
+    // Softmax normalized over the flattened trailing axes before opset 13, which matches
+    // axis=1 on the 2-D inputs this crate works with
+    if node.op_type == "Softmax" && opset < 13 && !node.attributes.contains_key("axis") {
+        node.attributes
+            .insert(String::from("axis"), Attribute::Int(1));
+    }
+    Ok(node)
+}
+
+fn read_attribute(bytes: &[u8]) -> io::Result<(String, Attribute)> {
+    let mut name = String::new();
+    let mut float = None;
+    let mut int = None;
+    let mut ints = Vec::new();
+    let mut kind = 0;
+    for field in Reader::new(bytes) {
+        match field? {
+            (1, value) => name = value.string()?,
+            (2, value) => float = Some(value.float()?),
+            (3, value) => int = Some(value.int()?),
+            (8, value) => value.ints_into(&mut ints)?,
+            (20, value) => kind = value.int()?,
+            _ => {}
+        }
+    }
+    let attribute = match (kind, float, int) {
+        (1, Some(f), _) => Attribute::Float(f),
+        (2, _, Some(i)) => Attribute::Int(i),
+        (7, _, _) => Attribute::Ints(ints),
+        // Very old writers leave out the type
+        (0, Some(f), None) => Attribute::Float(f),
+        (0, None, Some(i)) => Attribute::Int(i),
+        (0, None, None) if !ints.is_empty() => Attribute::Ints(ints),
+        _ => Attribute::Other,
+    };
+    Ok((name, attribute))
+}
+
+enum Initializer {
+    Float(ArrayD<f32>),
+    Int(Vec<i64>),
+}
+
+fn read_tensor(bytes: &[u8]) -> io::Result<(String, Initializer)> {
+    let mut name = String::new();
+    let mut dims = Vec::new();
+    let mut data_type = 0;
+    let mut float_data = Vec::new();
+    let mut int64_data = Vec::new();
+    let mut raw_data: &[u8] = &[];
+    for field in Reader::new(bytes) {
+        match field? {
+            (1, value) => value.ints_into(&mut dims)?,
+            (2, value) => data_type = value.int()?,
+            (4, value) => value.floats_into(&mut float_data)?,
+            (7, value) => value.ints_into(&mut int64_data)?,
+            (8, value) => name = value.string()?,
+            (9, value) => raw_data = value.bytes()?,
+            (13, _) => return Err(unsupported("tensors with external data")),
+            _ => {}
+        }
+    }
+    let shape: Vec<usize> = dims.iter().map(|&d| d as usize).collect();
+    let numel: usize = shape.iter().product();
+    let initializer = match data_type {
+        FLOAT => {
+            if !raw_data.is_empty() {
+                float_data = raw_data
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+            }
+            Initializer::Float(to_array(&name, &shape, float_data)?)
+        }
+        // float16 values live in int32_data when not raw, only raw is handled here
+        FLOAT16 => {
+            let values = raw_data
+                .chunks_exact(2)
+                .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect();
+            Initializer::Float(to_array(&name, &shape, values)?)
+        }
+        INT64 => {
+            if !raw_data.is_empty() {
+                int64_data = raw_data
+                    .chunks_exact(8)
+                    .map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+            }
+            if int64_data.len() != numel {
+                return Err(invalid(&format!(
+                    "initializer `{}` has {} values for shape {:?}",
+                    name,
+                    int64_data.len(),
+                    shape
+                )));
+            }
+            Initializer::Int(int64_data)
+        }
+        other => {
+            return Err(unsupported(&format!(
+                "initializer `{}` of data type {}",
+                name, other
+            )))
+        }
+    };
+    Ok((name, initializer))
+}
+
+fn to_array(name: &str, shape: &[usize], values: Vec<f32>) -> io::Result<ArrayD<f32>> {
+    let len = values.len();
+    ArrayD::from_shape_vec(IxDyn(shape), values).map_err(|_| {
+        invalid(&format!(
+            "initializer `{}` has {} values for shape {:?}",
+            name, len, shape
+        ))
+    })
+}
+
+fn read_value_info_name(bytes: &[u8]) -> io::Result<String> {
+    for field in Reader::new(bytes) {
+        if let (1, value) = field? {
+            return value.string();
+        }
+    }
+    Err(invalid("value info without a name"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported by the ONNX importer", what),
+    )
+}
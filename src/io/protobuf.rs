@@ -0,0 +1,224 @@
+// Just enough of the protobuf wire format to read and write ONNX models and TensorBoard
+// events without generated code, https://protobuf.dev/programming-guides/encoding/
+
+use std::io;
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub buf: Vec<u8>,
+}
+
+impl Writer {
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.raw_varint((field << 3) | wire_type);
+    }
+
+    // int32/int64/enum fields, negative values are sign extended to 64 bits like protobuf does
+    pub fn int(&mut self, field: u64, value: i64) {
+        self.key(field, VARINT);
+        self.raw_varint(value as u64);
+    }
+
+    pub fn float(&mut self, field: u64, value: f32) {
+        self.key(field, FIXED32);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn double(&mut self, field: u64, value: f64) {
+        self.key(field, FIXED64);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, field: u64, value: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.raw_varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    pub fn string(&mut self, field: u64, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    // Packed repeated int64
+    pub fn packed_ints(&mut self, field: u64, values: &[i64]) {
+        let mut packed = Writer::default();
+        for &value in values {
+            packed.raw_varint(value as u64);
+        }
+        self.bytes(field, &packed.buf);
+    }
+
+    // Packed repeated double
+    pub fn packed_doubles(&mut self, field: u64, values: &[f64]) {
+        let packed: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.bytes(field, &packed);
+    }
+
+    // Embedded message built by `build`
+    pub fn message(&mut self, field: u64, build: impl FnOnce(&mut Writer)) {
+        let mut inner = Writer::default();
+        build(&mut inner);
+        self.bytes(field, &inner.buf);
+    }
+}
+
+// Field value as it appears on the wire, interpreting it is up to the caller who knows the schema
+#[derive(Clone, Copy)]
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn int(self) -> io::Result<i64> {
+        match self {
+            Value::Varint(v) | Value::Fixed64(v) => Ok(v as i64),
+            Value::Fixed32(v) => Ok(v as i64),
+            Value::Bytes(_) => Err(invalid("expected an integer field")),
+        }
+    }
+
+    pub fn float(self) -> io::Result<f32> {
+        match self {
+            Value::Fixed32(v) => Ok(f32::from_bits(v)),
+            _ => Err(invalid("expected a float field")),
+        }
+    }
+
+    pub fn bytes(self) -> io::Result<&'a [u8]> {
+        match self {
+            Value::Bytes(b) => Ok(b),
+            _ => Err(invalid("expected a length-delimited field")),
+        }
+    }
+
+    pub fn string(self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid("string is not utf-8"))
+    }
+
+    // Repeated int64, appended to `out` whether the writer packed it or not
+    pub fn ints_into(self, out: &mut Vec<i64>) -> io::Result<()> {
+        match self {
+            Value::Bytes(mut packed) => {
+                while !packed.is_empty() {
+                    out.push(read_varint(&mut packed)? as i64);
+                }
+                Ok(())
+            }
+            value => {
+                out.push(value.int()?);
+                Ok(())
+            }
+        }
+    }
+
+    // Repeated float, packed or not
+    pub fn floats_into(self, out: &mut Vec<f32>) -> io::Result<()> {
+        match self {
+            Value::Bytes(packed) => {
+                if packed.len() % 4 != 0 {
+                    return Err(invalid("packed floats aren't a multiple of 4 bytes"));
+                }
+                out.extend(
+                    packed
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                );
+                Ok(())
+            }
+            value => {
+                out.push(value.float()?);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Iterates the (field number, value) pairs of one encoded message
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf }
+    }
+
+    fn field(&mut self) -> io::Result<(u64, Value<'a>)> {
+        let key = read_varint(&mut self.buf)?;
+        let value = match key & 7 {
+            VARINT => Value::Varint(read_varint(&mut self.buf)?),
+            FIXED64 => Value::Fixed64(u64::from_le_bytes(
+                take(&mut self.buf, 8)?.try_into().unwrap(),
+            )),
+            LENGTH_DELIMITED => {
+                let len = read_varint(&mut self.buf)? as usize;
+                Value::Bytes(take(&mut self.buf, len)?)
+            }
+            FIXED32 => Value::Fixed32(u32::from_le_bytes(
+                take(&mut self.buf, 4)?.try_into().unwrap(),
+            )),
+            wire_type => return Err(invalid(&format!("unsupported wire type {}", wire_type))),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = io::Result<(u64, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // Don't keep yielding garbage after a malformed field
+            self.buf = &[];
+        }
+        Some(field)
+    }
+}
+
+fn read_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid("truncated varint"))?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is longer than 10 bytes"))
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(invalid("truncated field"));
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
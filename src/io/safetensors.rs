@@ -0,0 +1,69 @@
+// Hugging Face's safetensors format, https://huggingface.co/docs/safetensors
+// Loading converts every floating point dtype to f32, saving writes F32.
+
+use crate::io::{bf16_to_f32, f16_to_f32};
+use crate::nn::StateDict;
+use ::safetensors::tensor::{Dtype, TensorView};
+use ::safetensors::SafeTensors;
+use ndarray::{ArrayD, IxDyn};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub fn save(path: impl AsRef<Path>, state: &StateDict) -> io::Result<()> {
+    // Standard layout, little-endian bytes per tensor
+    let bytes: Vec<(&String, Vec<usize>, Vec<u8>)> = state
+        .iter()
+        .map(|(name, array)| {
+            let data = array.iter().flat_map(|v| v.to_le_bytes()).collect();
+            (name, array.shape().to_vec(), data)
+        })
+        .collect();
+    let views = bytes
+        .iter()
+        .map(|(name, shape, data)| {
+            TensorView::new(Dtype::F32, shape.clone(), data).map(|view| (name.as_str(), view))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::other)?;
+    ::safetensors::serialize_to_file(views, &None, path.as_ref()).map_err(io::Error::other)
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<StateDict> {
+    let bytes = fs::read(path)?;
+    let tensors = SafeTensors::deserialize(&bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut state = StateDict::new();
+    for (name, view) in tensors.tensors() {
+        let data = view.data();
+        let values: Vec<f32> = match view.dtype() {
+            Dtype::F32 => data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            Dtype::F64 => data
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+                .collect(),
+            Dtype::F16 => data
+                .chunks_exact(2)
+                .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect(),
+            Dtype::BF16 => data
+                .chunks_exact(2)
+                .map(|b| bf16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect(),
+            dtype => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("tensor {:?} has unsupported dtype {:?}", name, dtype),
+                ))
+            }
+        };
+        let array = ArrayD::from_shape_vec(IxDyn(view.shape()), values)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        state.insert(name, array);
+    }
+    Ok(state)
+}
@@ -0,0 +1,512 @@
+// State dicts written by PyTorch's `torch.save(model.state_dict(), path)`: a zip archive with
+// the pickled dict in `<name>/data.pkl` and every tensor storage as raw little-endian bytes
+// in `<name>/data/<key>`.
+// The unpickler only understands what a state dict needs (dicts, tuples, numbers, strings,
+// tensor rebuild calls) and refuses every other global, so loading an untrusted file can't
+// run arbitrary code like torch.load without weights_only can. Tensors of any dtype are
+// converted to f32. Nested dicts are flattened with dotted names and non-tensor entries are
+// skipped, so a checkpoint {"model": state_dict, "epoch": 3} yields "model.<param>" keys.
+
+use crate::io::{bf16_to_f32, f16_to_f32};
+use crate::nn::StateDict;
+use ndarray::{ArrayD, IxDyn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<StateDict> {
+    read(BufReader::new(File::open(path)?))
+}
+
+fn read(reader: impl Read + Seek) -> io::Result<StateDict> {
+    let mut zip = ZipArchive::new(reader).map_err(|e| invalid(e.to_string()))?;
+    // The top-level directory is named after the file that was saved, find it via data.pkl
+    let pickle_name = zip
+        .file_names()
+        .find(|name| name.ends_with("data.pkl"))
+        .ok_or_else(|| invalid("no data.pkl in archive, not a torch.save zip file"))?
+        .to_string();
+    let prefix = &pickle_name[..pickle_name.len() - "data.pkl".len()];
+    if let Ok(mut byteorder) = zip.by_name(&format!("{}byteorder", prefix)) {
+        let mut order = String::new();
+        byteorder.read_to_string(&mut order)?;
+        if order.trim() != "little" {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} endian storages are not supported", order.trim()),
+            ));
+        }
+    }
+
+    let mut pickle = Vec::new();
+    zip.by_name(&pickle_name)
+        .map_err(|e| invalid(e.to_string()))?
+        .read_to_end(&mut pickle)?;
+
+    let mut unpickler = Unpickler {
+        zip: &mut zip,
+        prefix: prefix.to_string(),
+        storages: HashMap::new(),
+    };
+    let root = unpickler.run(&pickle)?;
+    let mut state = StateDict::new();
+    flatten(root, "", &mut state)?;
+    Ok(state)
+}
+
+fn flatten(value: Value, prefix: &str, state: &mut StateDict) -> io::Result<()> {
+    let Value::Dict(entries) = value else {
+        return Err(invalid("pickle does not contain a dict"));
+    };
+    for (key, value) in entries {
+        let Value::String(key) = key else {
+            continue;
+        };
+        let name = format!("{}{}", prefix, key);
+        match value {
+            Value::Tensor(array) => {
+                state.insert(name, array);
+            }
+            value @ Value::Dict(_) => flatten(value, &format!("{}.", name), state)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Mark,
+    None,
+    // Booleans and floats (requires_grad flags, hyperparameters in a checkpoint) are never
+    // looked at, only their place on the stack matters
+    Bool,
+    Int(i64),
+    Float,
+    String(String),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+    Global(String),
+    // torch.<Dtype>Storage reference from a persistent id, `key` names the file in data/
+    Storage { dtype: String, key: String },
+    Tensor(ArrayD<f32>),
+}
+
+// Globals a state dict pickle refers to, anything else is rejected
+const ALLOWED_GLOBALS: &[&str] = &[
+    "collections.OrderedDict",
+    "torch._utils._rebuild_tensor_v2",
+    "torch._utils._rebuild_parameter",
+    "torch.FloatStorage",
+    "torch.DoubleStorage",
+    "torch.HalfStorage",
+    "torch.BFloat16Storage",
+    "torch.LongStorage",
+    "torch.IntStorage",
+    "torch.ShortStorage",
+    "torch.CharStorage",
+    "torch.ByteStorage",
+    "torch.BoolStorage",
+];
+
+struct Unpickler<'a, R> {
+    zip: &'a mut ZipArchive<R>,
+    prefix: String,
+    // Storages decoded so far by key, tensors sharing one storage only decode it once
+    storages: HashMap<String, Vec<f32>>,
+}
+
+impl<R: Read + Seek> Unpickler<'_, R> {
+    fn run(&mut self, bytes: &[u8]) -> io::Result<Value> {
+        let mut input = bytes;
+        let mut stack: Vec<Value> = Vec::new();
+        let mut memo: HashMap<u64, Value> = HashMap::new();
+
+        loop {
+            let opcode = take(&mut input, 1)?[0];
+            match opcode {
+                // PROTO, FRAME: version and framing carry no data for us
+                0x80 => {
+                    take(&mut input, 1)?;
+                }
+                0x95 => {
+                    take(&mut input, 8)?;
+                }
+                b'.' => return stack.pop().ok_or_else(|| invalid("empty stack at STOP")),
+                b'(' => stack.push(Value::Mark),
+                b'N' => stack.push(Value::None),
+                0x88 | 0x89 => stack.push(Value::Bool),
+                b'}' => stack.push(Value::Dict(Vec::new())),
+                b']' => stack.push(Value::List(Vec::new())),
+                b')' => stack.push(Value::Tuple(Vec::new())),
+                b'J' => stack.push(Value::Int(i32::from_le_bytes(array(&mut input)?) as i64)),
+                b'K' => stack.push(Value::Int(take(&mut input, 1)?[0] as i64)),
+                b'M' => stack.push(Value::Int(u16::from_le_bytes(array(&mut input)?) as i64)),
+                0x8a => {
+                    let len = take(&mut input, 1)?[0] as usize;
+                    stack.push(Value::Int(long(take(&mut input, len)?)?));
+                }
+                b'G' => {
+                    take(&mut input, 8)?;
+                    stack.push(Value::Float);
+                }
+                b'X' => {
+                    let len = u32::from_le_bytes(array(&mut input)?) as usize;
+                    stack.push(Value::String(utf8(take(&mut input, len)?)?));
+                }
+                0x8c => {
+                    let len = take(&mut input, 1)?[0] as usize;
+                    stack.push(Value::String(utf8(take(&mut input, len)?)?));
+                }
+                0x8d => {
+                    let len = u64::from_le_bytes(array(&mut input)?) as usize;
+                    stack.push(Value::String(utf8(take(&mut input, len)?)?));
+                }
+                // BINSTRING, SHORT_BINSTRING: python 2 str, only seen as the dtype of old storages
+                b'T' => {
+                    let len = u32::from_le_bytes(array(&mut input)?) as usize;
+                    stack.push(Value::String(utf8(take(&mut input, len)?)?));
+                }
+                b'U' => {
+                    let len = take(&mut input, 1)?[0] as usize;
+                    stack.push(Value::String(utf8(take(&mut input, len)?)?));
+                }
+                b'c' => {
+                    let module = line(&mut input)?;
+                    let name = line(&mut input)?;
+                    stack.push(global(&module, &name)?);
+                }
+                0x93 => {
+                    let name = pop_string(&mut stack)?;
+                    let module = pop_string(&mut stack)?;
+                    stack.push(global(&module, &name)?);
+                }
+                b'q' => {
+                    let index = take(&mut input, 1)?[0] as u64;
+                    memo.insert(index, top(&stack)?.clone());
+                }
+                b'r' => {
+                    let index = u32::from_le_bytes(array(&mut input)?) as u64;
+                    memo.insert(index, top(&stack)?.clone());
+                }
+                0x94 => {
+                    memo.insert(memo.len() as u64, top(&stack)?.clone());
+                }
+                b'h' | b'j' => {
+                    let index = if opcode == b'h' {
+                        take(&mut input, 1)?[0] as u64
+                    } else {
+                        u32::from_le_bytes(array(&mut input)?) as u64
+                    };
+                    let value = memo
+                        .get(&index)
+                        .ok_or_else(|| invalid(format!("memo entry {} is missing", index)))?;
+                    stack.push(value.clone());
+                }
+                b'0' => {
+                    stack.pop();
+                }
+                b'1' => {
+                    pop_mark(&mut stack)?;
+                }
+                b'2' => stack.push(top(&stack)?.clone()),
+                b't' => {
+                    let items = pop_mark(&mut stack)?;
+                    stack.push(Value::Tuple(items));
+                }
+                0x85..=0x87 => {
+                    let n = (opcode - 0x84) as usize;
+                    if stack.len() < n {
+                        return Err(invalid("stack underflow building a tuple"));
+                    }
+                    let items = stack.split_off(stack.len() - n);
+                    stack.push(Value::Tuple(items));
+                }
+                b'l' => {
+                    let items = pop_mark(&mut stack)?;
+                    stack.push(Value::List(items));
+                }
+                b'd' => {
+                    let items = pop_mark(&mut stack)?;
+                    stack.push(Value::Dict(pairs(items)?));
+                }
+                b'a' | b'e' => {
+                    let items = if opcode == b'a' {
+                        vec![pop(&mut stack)?]
+                    } else {
+                        pop_mark(&mut stack)?
+                    };
+                    match stack.last_mut() {
+                        Some(Value::List(list)) => list.extend(items),
+                        _ => return Err(invalid("APPEND on something that isn't a list")),
+                    }
+                }
+                b's' | b'u' => {
+                    let items = if opcode == b's' {
+                        let value = pop(&mut stack)?;
+                        let key = pop(&mut stack)?;
+                        vec![key, value]
+                    } else {
+                        pop_mark(&mut stack)?
+                    };
+                    let items = pairs(items)?;
+                    match stack.last_mut() {
+                        Some(Value::Dict(dict)) => dict.extend(items),
+                        _ => return Err(invalid("SETITEM on something that isn't a dict")),
+                    }
+                }
+                // BUILD sets attributes, e.g. the _metadata of a state dict OrderedDict, not needed
+                b'b' => {
+                    pop(&mut stack)?;
+                }
+                b'Q' => {
+                    let pid = pop(&mut stack)?;
+                    stack.push(storage(pid)?);
+                }
+                b'R' => {
+                    let args = pop(&mut stack)?;
+                    let callable = pop(&mut stack)?;
+                    let result = self.reduce(callable, args)?;
+                    stack.push(result);
+                }
+                other => {
+                    return Err(invalid(format!(
+                        "pickle opcode 0x{:02x} is not supported",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+
+    // Call one of the allowed globals
+    fn reduce(&mut self, callable: Value, args: Value) -> io::Result<Value> {
+        let (Value::Global(name), Value::Tuple(args)) = (callable, args) else {
+            return Err(invalid("REDUCE needs a global and an argument tuple"));
+        };
+        match (name.as_str(), &args[..]) {
+            ("collections.OrderedDict", []) => Ok(Value::Dict(Vec::new())),
+            // (storage, storage_offset, size, stride, requires_grad, backward_hooks, [metadata])
+            (
+                "torch._utils._rebuild_tensor_v2",
+                [Value::Storage { dtype, key }, Value::Int(offset), Value::Tuple(size), Value::Tuple(stride), ..],
+            ) => {
+                let size = ints(size)?;
+                let stride = ints(stride)?;
+                let data = self.storage_data(dtype, key)?;
+                strided(data, *offset as usize, &size, &stride).map(Value::Tensor)
+            }
+            ("torch._utils._rebuild_parameter", [tensor @ Value::Tensor(_), ..]) => {
+                Ok(tensor.clone())
+            }
+            (name, _) => Err(invalid(format!("unexpected arguments for {}", name))),
+        }
+    }
+
+    fn storage_data(&mut self, dtype: &str, key: &str) -> io::Result<&[f32]> {
+        if !self.storages.contains_key(key) {
+            let mut bytes = Vec::new();
+            self.zip
+                .by_name(&format!("{}data/{}", self.prefix, key))
+                .map_err(|e| invalid(format!("storage {}: {}", key, e)))?
+                .read_to_end(&mut bytes)?;
+            self.storages
+                .insert(key.to_string(), decode(dtype, &bytes)?);
+        }
+        Ok(&self.storages[key])
+    }
+}
+
+// Gather a tensor out of its storage the way as_strided does
+fn strided(
+    data: &[f32],
+    offset: usize,
+    size: &[usize],
+    stride: &[usize],
+) -> io::Result<ArrayD<f32>> {
+    if size.len() != stride.len() {
+        return Err(invalid("tensor size and stride have different lengths"));
+    }
+    let numel: usize = size.iter().product();
+    if numel > 0 {
+        let last = offset
+            + size
+                .iter()
+                .zip(stride)
+                .map(|(n, s)| (n - 1) * s)
+                .sum::<usize>();
+        if last >= data.len() {
+            return Err(invalid(format!(
+                "tensor of size {:?} reads past its storage of {} elements",
+                size,
+                data.len()
+            )));
+        }
+    }
+    Ok(ArrayD::from_shape_fn(IxDyn(size), |index| {
+        let position: usize = (0..size.len()).map(|i| index[i] * stride[i]).sum();
+        data[offset + position]
+    }))
+}
+
+fn decode(dtype: &str, bytes: &[u8]) -> io::Result<Vec<f32>> {
+    macro_rules! convert {
+        ($ty:ty) => {{
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            bytes
+                .chunks_exact(SIZE)
+                .map(|chunk| <$ty>::from_le_bytes(chunk.try_into().unwrap()) as f32)
+                .collect()
+        }};
+    }
+    Ok(match dtype {
+        "FloatStorage" => convert!(f32),
+        "DoubleStorage" => convert!(f64),
+        "HalfStorage" => bytes
+            .chunks_exact(2)
+            .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+            .collect(),
+        "BFloat16Storage" => bytes
+            .chunks_exact(2)
+            .map(|b| bf16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+            .collect(),
+        "LongStorage" => convert!(i64),
+        "IntStorage" => convert!(i32),
+        "ShortStorage" => convert!(i16),
+        "CharStorage" => convert!(i8),
+        "ByteStorage" | "BoolStorage" => convert!(u8),
+        _ => return Err(invalid(format!("unsupported storage type {}", dtype))),
+    })
+}
+
+fn global(module: &str, name: &str) -> io::Result<Value> {
+    let full = format!("{}.{}", module, name);
+    if ALLOWED_GLOBALS.contains(&full.as_str()) {
+        Ok(Value::Global(full))
+    } else {
+        Err(invalid(format!(
+            "refusing to load global {}, only tensor state dicts are supported",
+            full
+        )))
+    }
+}
+
+// Persistent id ('storage', torch.FloatStorage, key, location, numel)
+fn storage(pid: Value) -> io::Result<Value> {
+    match pid {
+        Value::Tuple(items) => match &items[..] {
+            [Value::String(kind), Value::Global(class), Value::String(key), ..]
+                if kind == "storage" =>
+            {
+                Ok(Value::Storage {
+                    dtype: class.trim_start_matches("torch.").to_string(),
+                    key: key.clone(),
+                })
+            }
+            _ => Err(invalid(format!("unexpected persistent id {:?}", items))),
+        },
+        other => Err(invalid(format!("unexpected persistent id {:?}", other))),
+    }
+}
+
+fn ints(values: &[Value]) -> io::Result<Vec<usize>> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Int(v) if *v >= 0 => Ok(*v as usize),
+            other => Err(invalid(format!("expected a size, got {:?}", other))),
+        })
+        .collect()
+}
+
+fn pairs(items: Vec<Value>) -> io::Result<Vec<(Value, Value)>> {
+    if !items.len().is_multiple_of(2) {
+        return Err(invalid("odd number of items for a dict"));
+    }
+    let mut items = items.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        pairs.push((key, value));
+    }
+    Ok(pairs)
+}
+
+fn pop(stack: &mut Vec<Value>) -> io::Result<Value> {
+    stack.pop().ok_or_else(|| invalid("pickle stack underflow"))
+}
+
+fn top(stack: &[Value]) -> io::Result<&Value> {
+    stack
+        .last()
+        .ok_or_else(|| invalid("pickle stack underflow"))
+}
+
+fn pop_string(stack: &mut Vec<Value>) -> io::Result<String> {
+    match pop(stack)? {
+        Value::String(s) => Ok(s),
+        other => Err(invalid(format!("expected a string, got {:?}", other))),
+    }
+}
+
+// Everything above the topmost mark, in push order
+fn pop_mark(stack: &mut Vec<Value>) -> io::Result<Vec<Value>> {
+    let mark = stack
+        .iter()
+        .rposition(|value| matches!(value, Value::Mark))
+        .ok_or_else(|| invalid("no MARK on the pickle stack"))?;
+    let items = stack.split_off(mark + 1);
+    stack.pop();
+    Ok(items)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(invalid("truncated pickle"));
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+fn array<const N: usize>(input: &mut &[u8]) -> io::Result<[u8; N]> {
+    Ok(take(input, N)?.try_into().unwrap())
+}
+
+fn line(input: &mut &[u8]) -> io::Result<String> {
+    let end = input
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid("truncated pickle"))?;
+    let text = utf8(&input[..end])?;
+    *input = &input[end + 1..];
+    Ok(text)
+}
+
+fn utf8(bytes: &[u8]) -> io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| invalid("pickle string is not utf-8"))
+}
+
+// LONG1 payload: little-endian two's complement of up to 8 bytes
+fn long(bytes: &[u8]) -> io::Result<i64> {
+    if bytes.len() > 8 {
+        return Err(invalid("integer does not fit in 64 bits"));
+    }
+    let mut value = 0i64;
+    for (i, &b) in bytes.iter().enumerate() {
+        value |= (b as i64) << (8 * i);
+    }
+    // Sign extend from the top byte
+    if let Some(&last) = bytes.last() {
+        if last & 0x80 != 0 && bytes.len() < 8 {
+            value -= 1i64 << (8 * bytes.len());
+        }
+    }
+    Ok(value)
+}
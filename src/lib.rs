@@ -0,0 +1,53 @@
+pub mod anomaly;
+pub mod architecture;
+pub mod checkpoint;
+pub mod compile;
+#[cfg(feature = "cuda")]
+pub mod cuda;
+pub mod data;
+pub mod determinism;
+pub mod device;
+pub mod distributed;
+pub mod distributions;
+pub mod ensemble;
+pub mod error;
+pub mod evaluate;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod generate;
+pub mod gradients;
+pub mod inference;
+pub mod io;
+pub mod linalg;
+pub mod logging;
+pub mod loss;
+pub mod macros;
+pub mod memory;
+pub mod metrics;
+pub mod named;
+pub mod nn;
+pub mod op;
+pub mod optim;
+pub mod parallel;
+pub mod profiler;
+pub mod prune;
+#[cfg(feature = "python")]
+mod python;
+pub mod quantize;
+pub mod random;
+pub mod regularization;
+pub mod shape;
+pub mod spatial;
+pub mod tensor;
+pub mod text;
+pub mod train;
+pub mod tune;
+#[cfg(feature = "viz")]
+pub mod viz;
+
+// Re-exported so the macros can reach ndarray through `$crate` from downstream crates
+pub use determinism::{is_deterministic, set_deterministic};
+pub use error::TensorError;
+pub use ndarray;
+pub use random::manual_seed;
+pub use tensor::{Tensor, TensorData};
@@ -0,0 +1,625 @@
+// Linear algebra on 2-D tensors with gradients. Decompositions run in f64 internally, small
+// pivots lose too much precision in f32.
+
+use crate::device;
+use crate::error::TensorError;
+use crate::inference;
+use crate::op::Op;
+use crate::profiler;
+use crate::tensor::{accumulate_grad, Tensor, TensorData};
+use ndarray::{arr0, s, Array1, Array2, ArrayD, ArrayView2, Axis, Ix1, Ix2, IxDyn};
+use std::panic::Location;
+
+// PA = LU with partial pivoting, L (unit diagonal, below) and U packed in one matrix
+pub(crate) struct Lu {
+    lu: Array2<f64>,
+    // Row i of PA is row perm[i] of A
+    perm: Vec<usize>,
+    // Determinant of P, -1 for an odd number of row swaps
+    sign: f64,
+}
+
+impl Lu {
+    pub(crate) fn new(a: ArrayView2<f32>) -> Result<Lu, TensorError> {
+        let n = a.nrows();
+        let mut lu = a.mapv(f64::from);
+        let mut perm: Vec<usize> = (0..n).collect();
+        let mut sign = 1.0;
+        // Pivots this small relative to the matrix are rounding noise around 0
+        let tolerance = lu.fold(0.0f64, |m, &x| m.max(x.abs())) * n as f64 * f64::EPSILON;
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&i, &j| lu[[i, k]].abs().total_cmp(&lu[[j, k]].abs()))
+                .unwrap();
+            if lu[[pivot, k]].abs() <= tolerance {
+                return Err(TensorError::SingularMatrix);
+            }
+            if pivot != k {
+                for j in 0..n {
+                    lu.swap([k, j], [pivot, j]);
+                }
+                perm.swap(k, pivot);
+                sign = -sign;
+            }
+            for i in k + 1..n {
+                let factor = lu[[i, k]] / lu[[k, k]];
+                lu[[i, k]] = factor;
+                for j in k + 1..n {
+                    lu[[i, j]] -= factor * lu[[k, j]];
+                }
+            }
+        }
+        Ok(Lu { lu, perm, sign })
+    }
+
+    // X with AX = B, column by column
+    pub(crate) fn solve(&self, b: &Array2<f64>) -> Array2<f64> {
+        let n = self.lu.nrows();
+        let mut x = Array2::zeros(b.raw_dim());
+        for c in 0..b.ncols() {
+            // Forward substitution through L, then back substitution through U
+            let mut y: Vec<f64> = self.perm.iter().map(|&p| b[[p, c]]).collect();
+            for i in 0..n {
+                for j in 0..i {
+                    y[i] -= self.lu[[i, j]] * y[j];
+                }
+            }
+            for i in (0..n).rev() {
+                for j in i + 1..n {
+                    y[i] -= self.lu[[i, j]] * y[j];
+                }
+                y[i] /= self.lu[[i, i]];
+            }
+            x.column_mut(c).assign(&ndarray::Array1::from(y));
+        }
+        x
+    }
+
+    // Sign and ln|det A|
+    pub(crate) fn log_abs_det(&self) -> (f64, f64) {
+        let diagonal = self.lu.diag();
+        let sign = diagonal.fold(self.sign, |s, &u| s * u.signum());
+        (sign, diagonal.fold(0.0, |sum, &u| sum + u.abs().ln()))
+    }
+}
+
+// Lower triangular L with A = L L^T, from the lower triangle of A
+fn cholesky_factor(a: ArrayView2<f32>) -> Result<Array2<f64>, TensorError> {
+    let n = a.nrows();
+    let mut l = Array2::<f64>::zeros((n, n));
+    for j in 0..n {
+        let mut diagonal = f64::from(a[[j, j]]);
+        for k in 0..j {
+            diagonal -= l[[j, k]] * l[[j, k]];
+        }
+        if diagonal <= 0.0 || !diagonal.is_finite() {
+            return Err(TensorError::NotPositiveDefinite);
+        }
+        l[[j, j]] = diagonal.sqrt();
+        for i in j + 1..n {
+            let mut value = f64::from(a[[i, j]]);
+            for k in 0..j {
+                value -= l[[i, k]] * l[[j, k]];
+            }
+            l[[i, j]] = value / l[[j, j]];
+        }
+    }
+    Ok(l)
+}
+
+// X with U X = B for an upper triangular U
+fn solve_upper(u: ArrayView2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    let n = u.nrows();
+    let mut x = b.clone();
+    for c in 0..x.ncols() {
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                x[[i, c]] -= u[[i, j]] * x[[j, c]];
+            }
+            x[[i, c]] /= u[[i, i]];
+        }
+    }
+    x
+}
+
+// Reduced QR of an [m, n] matrix with m >= n by Householder reflections: Q [m, n] with
+// orthonormal columns and upper triangular R [n, n] with a non-negative diagonal, which makes
+// the factorization unique for full-rank A
+fn householder_qr(a: ArrayView2<f32>) -> (Array2<f64>, Array2<f64>) {
+    let (m, n) = a.dim();
+    let mut r = a.mapv(f64::from);
+    let mut reflectors = Vec::with_capacity(n);
+    for k in 0..n {
+        let x = r.slice(s![k.., k]);
+        let norm = x.dot(&x).sqrt();
+        let alpha = if x[0] > 0.0 { -norm } else { norm };
+        let mut v = x.to_owned();
+        v[0] -= alpha;
+        let v_norm = v.dot(&v).sqrt();
+        if v_norm > 0.0 {
+            v /= v_norm;
+            let mut block = r.slice_mut(s![k.., k..]);
+            let projection = v.dot(&block);
+            for (i, &vi) in v.iter().enumerate() {
+                block.row_mut(i).scaled_add(-2.0 * vi, &projection);
+            }
+        }
+        reflectors.push(v);
+    }
+    // Q = H_0 ... H_{n-1} applied to the first n columns of the identity
+    let mut q = Array2::<f64>::eye(m).slice(s![.., ..n]).to_owned();
+    for (k, v) in reflectors.iter().enumerate().rev() {
+        let mut block = q.slice_mut(s![k.., ..]);
+        let projection = v.dot(&block);
+        for (i, &vi) in v.iter().enumerate() {
+            block.row_mut(i).scaled_add(-2.0 * vi, &projection);
+        }
+    }
+    let mut r = r.slice(s![..n, ..]).to_owned();
+    for i in 0..n {
+        for j in 0..i {
+            r[[i, j]] = 0.0;
+        }
+        if r[[i, i]] < 0.0 {
+            r.row_mut(i).mapv_inplace(|x| -x);
+            q.column_mut(i).mapv_inplace(|x| -x);
+        }
+    }
+    (q, r)
+}
+
+// Reduced SVD A = U diag(S) V^T of an [m, n] matrix, k = min(m, n): U [m, k], S [k] in
+// descending order, V^T [k, n]. One-sided Jacobi rotations orthogonalize the columns.
+fn jacobi_svd(a: ArrayView2<f32>) -> (Array2<f64>, Array1<f64>, Array2<f64>) {
+    let (m, n) = a.dim();
+    if m < n {
+        let (u, s, vt) = jacobi_svd(a.t());
+        return (vt.reversed_axes(), s, u.reversed_axes());
+    }
+    let mut w = a.mapv(f64::from);
+    let mut v = Array2::<f64>::eye(n);
+    for _ in 0..100 {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in p + 1..n {
+                let alpha = w.column(p).dot(&w.column(p));
+                let beta = w.column(q).dot(&w.column(q));
+                let gamma = w.column(p).dot(&w.column(q));
+                if gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() || gamma == 0.0 {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                for matrix in [&mut w, &mut v] {
+                    for mut row in matrix.rows_mut() {
+                        let (x, y) = (row[p], row[q]);
+                        row[p] = c * x - s * y;
+                        row[q] = s * x + c * y;
+                    }
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    let norms: Vec<f64> = w.columns().into_iter().map(|c| c.dot(&c).sqrt()).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+    let mut u = Array2::zeros((m, n));
+    let mut vt = Array2::zeros((n, n));
+    for (k, &j) in order.iter().enumerate() {
+        if norms[j] > 0.0 {
+            u.column_mut(k).assign(&(&w.column(j) / norms[j]));
+        }
+        vt.row_mut(k).assign(&v.column(j));
+    }
+    let s = order.iter().map(|&j| norms[j]).collect();
+    (u, s, vt)
+}
+
+// Gradient wrt A of a reduced QR given the gradients of Q and R, for full-rank A:
+// (G_Q + Q copyltu(M)) R^-T with M = R G_R^T - G_Q^T Q and copyltu mirroring the lower
+// triangle of M onto the upper one
+fn qr_grad(
+    q: &Array2<f64>,
+    r: &Array2<f64>,
+    grad_q: Option<Array2<f64>>,
+    grad_r: Option<Array2<f64>>,
+) -> Array2<f64> {
+    let grad_q = grad_q.unwrap_or_else(|| Array2::zeros(q.raw_dim()));
+    let grad_r = grad_r.unwrap_or_else(|| Array2::zeros(r.raw_dim()));
+    let mut m = r.dot(&grad_r.t()) - grad_q.t().dot(q);
+    for i in 0..m.nrows() {
+        for j in i + 1..m.ncols() {
+            m[[i, j]] = m[[j, i]];
+        }
+    }
+    let b = grad_q + q.dot(&m);
+    solve_upper(r.view(), &b.reversed_axes()).reversed_axes()
+}
+
+// Gradient wrt A of a reduced SVD given the gradients of U, S and V^T, for full rank and
+// distinct singular values (Townsend, 2016)
+fn svd_grad(
+    u: &Array2<f64>,
+    s: &Array1<f64>,
+    vt: &Array2<f64>,
+    grad_u: Option<Array2<f64>>,
+    grad_s: Option<Array1<f64>>,
+    grad_vt: Option<Array2<f64>>,
+) -> Array2<f64> {
+    let k = s.len();
+    let v = vt.t();
+    // F_ij = 1 / (s_j^2 - s_i^2) off the diagonal
+    let f = Array2::from_shape_fn((k, k), |(i, j)| {
+        if i == j {
+            0.0
+        } else {
+            1.0 / (s[j] * s[j] - s[i] * s[i])
+        }
+    });
+    let mut inner = Array2::from_diag(&grad_s.unwrap_or_else(|| Array1::zeros(k)));
+    let mut grad = Array2::zeros((u.nrows(), v.nrows()));
+    if let Some(grad_u) = grad_u {
+        let ut_gu = u.t().dot(&grad_u);
+        let j = &f * &(&ut_gu - &ut_gu.t());
+        inner += &(j * s.view().insert_axis(Axis(0)));
+        // Component of G_U outside the span of U
+        let outside = &grad_u - &u.dot(&ut_gu);
+        grad += &(outside / s.view().insert_axis(Axis(0))).dot(vt);
+    }
+    if let Some(grad_vt) = grad_vt {
+        let grad_v = grad_vt.t();
+        let vt_gv = v.t().dot(&grad_v);
+        let kk = &f * &(&vt_gv - &vt_gv.t());
+        inner += &(kk * s.view().insert_axis(Axis(1)));
+        let outside = &grad_v - &v.dot(&vt_gv);
+        grad += &u.dot(&(outside.t().to_owned() / s.view().insert_axis(Axis(1))));
+    }
+    grad + u.dot(&inner).dot(vt)
+}
+
+#[track_caller]
+fn check_square(op: &'static str, shape: Vec<usize>) -> Result<(), TensorError> {
+    if shape.len() != 2 || shape[0] != shape[1] {
+        let transposed = shape.iter().rev().copied().collect();
+        return Err(shape_error(op, shape, transposed));
+    }
+    Ok(())
+}
+
+#[track_caller]
+fn shape_error(op: &'static str, lhs: Vec<usize>, rhs: Vec<usize>) -> TensorError {
+    TensorError::ShapeMismatch {
+        op,
+        lhs,
+        rhs,
+        operands: None,
+        location: if cfg!(debug_assertions) {
+            Some(Location::caller())
+        } else {
+            None
+        },
+    }
+}
+
+fn as_matrix(data: &ArrayD<f32>) -> ArrayView2<'_, f32> {
+    data.view().into_dimensionality::<Ix2>().unwrap()
+}
+
+fn to_f32(a: Array2<f64>) -> ArrayD<f32> {
+    a.mapv(|x| x as f32).into_dyn()
+}
+
+// Output node of a decomposition, `saved` holds all its factors for the backward pass
+#[track_caller]
+fn factor_node(
+    op: Op,
+    factor: ArrayD<f32>,
+    input: &Tensor,
+    saved: &[ArrayD<f32>],
+    backward: fn(&TensorData),
+) -> Tensor {
+    if inference::is_enabled() {
+        return Tensor::from(factor).on_device_of(input);
+    }
+    let mut new_tensor_data = TensorData::new(factor);
+    new_tensor_data._op = Some(op);
+    new_tensor_data._children = vec![input.clone()];
+    new_tensor_data._saved = saved.to_vec();
+    new_tensor_data._backward = Some(backward);
+    Tensor::new(new_tensor_data)
+}
+
+fn saved_matrix(out: &TensorData, i: usize) -> Array2<f64> {
+    as_matrix(&out._saved[i]).mapv(f64::from)
+}
+
+fn grad_matrix(out: &TensorData) -> Option<Array2<f64>> {
+    Some(as_matrix(out.grad.as_ref().unwrap()).mapv(f64::from))
+}
+
+// Columns of `b` as an [n, k] matrix, a vector is a single column
+fn as_columns(b: &ArrayD<f32>) -> Array2<f64> {
+    let n = b.shape()[0];
+    b.mapv(f64::from).into_shape((n, b.len() / n)).unwrap()
+}
+
+impl Tensor {
+    // A^-1 of a square [n, n] matrix, panics if it's singular
+    #[track_caller]
+    pub fn inverse(&self) -> Tensor {
+        self.try_inverse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_inverse(&self) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("inverse");
+        check_square("inverse", self.shape())?;
+        let inverse = {
+            let data = &self.borrow().data;
+            let lu = Lu::new(as_matrix(data))?;
+            to_f32(lu.solve(&Array2::eye(data.shape()[0])))
+        };
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(inverse).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(inverse);
+        new_tensor_data._op = Some(Op::Inverse);
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_matrix(out.grad.as_ref().unwrap());
+            let inverse = as_matrix(&out.data);
+
+            // d(A^-1) = -A^-1 dA A^-1, so dL/dA = -A^-T G A^-T
+            let grad_input = -inverse.t().dot(&grad).dot(&inverse.t());
+            accumulate_grad(&out._children[0], grad_input.into_dyn());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // X with AX = B for a square [n, n] A and B of [n] or [n, k], without forming A^-1
+    #[track_caller]
+    pub fn solve(&self, b: &Tensor) -> Tensor {
+        self.try_solve(b).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_solve(&self, b: &Tensor) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("solve");
+        device::check_same("solve", &[self, b])?;
+        let (lhs, rhs) = (self.shape(), b.shape());
+        if lhs.len() != 2
+            || lhs[0] != lhs[1]
+            || !(rhs.len() == 1 || rhs.len() == 2)
+            || rhs[0] != lhs[0]
+        {
+            return Err(shape_error("solve", lhs, rhs).with_operands(self, b));
+        }
+        let solution = {
+            let lu = Lu::new(as_matrix(&self.borrow().data))?;
+            let x = lu.solve(&as_columns(&b.borrow().data));
+            to_f32(x).into_shape(IxDyn(&rhs)).unwrap()
+        };
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(solution).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(solution);
+        new_tensor_data._op = Some(Op::Solve);
+        new_tensor_data._children = vec![self.clone(), b.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_columns(out.grad.as_ref().unwrap());
+            let x = as_columns(&out.data);
+            let a = out._children[0].borrow().data.clone();
+
+            // dL/dB = A^-T G, and since dX = A^-1 (dB - dA X), dL/dA = -dL/dB X^T
+            let lu = Lu::new(as_matrix(&a).t()).expect("solve was run on this matrix");
+            let grad_b = lu.solve(&grad);
+            let grad_a = -grad_b.dot(&x.t());
+            let b_shape = out._children[1].borrow().data.raw_dim();
+            accumulate_grad(&out._children[0], to_f32(grad_a));
+            accumulate_grad(
+                &out._children[1],
+                to_f32(grad_b).into_shape(b_shape).unwrap(),
+            );
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Lower triangular L with A = L L^T for a symmetric positive definite [n, n] A. Only the
+    // lower triangle of A is read, and the gradient is symmetrized to match.
+    #[track_caller]
+    pub fn cholesky(&self) -> Tensor {
+        self.try_cholesky().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_cholesky(&self) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("cholesky");
+        check_square("cholesky", self.shape())?;
+        let factor = to_f32(cholesky_factor(as_matrix(&self.borrow().data))?);
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(factor).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(factor);
+        new_tensor_data._op = Some(Op::Cholesky);
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_matrix(out.grad.as_ref().unwrap()).mapv(f64::from);
+            let l = as_matrix(&out.data).mapv(f64::from);
+
+            // Phi(L^T G) with Phi taking the lower triangle and halving the diagonal, then
+            // A-bar = L^-T Phi L^-1 symmetrized (Murray, 2016)
+            let mut phi = l.t().dot(&grad);
+            for ((i, j), value) in phi.indexed_iter_mut() {
+                if j > i {
+                    *value = 0.0;
+                } else if i == j {
+                    *value *= 0.5;
+                }
+            }
+            let left = solve_upper(l.t(), &phi);
+            let grad_input = solve_upper(l.t(), &left.t().to_owned()).reversed_axes();
+            let grad_input = (&grad_input + &grad_input.t()) * 0.5;
+            accumulate_grad(&out._children[0], to_f32(grad_input));
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // ln det A of a square matrix as a scalar, NaN if the determinant is negative and an error
+    // if it's 0
+    #[track_caller]
+    pub fn logdet(&self) -> Tensor {
+        self.try_logdet().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_logdet(&self) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("logdet");
+        check_square("logdet", self.shape())?;
+        let (value, inverse) = {
+            let data = &self.borrow().data;
+            let lu = Lu::new(as_matrix(data))?;
+            let (sign, log_abs_det) = lu.log_abs_det();
+            let value = if sign < 0.0 {
+                f32::NAN
+            } else {
+                log_abs_det as f32
+            };
+            (
+                arr0(value).into_dyn(),
+                lu.solve(&Array2::eye(data.shape()[0])),
+            )
+        };
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(value).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(value);
+        new_tensor_data._op = Some(Op::Logdet);
+        new_tensor_data._children = vec![self.clone()];
+        new_tensor_data._saved = vec![to_f32(inverse)];
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.as_ref().unwrap().first().copied().unwrap();
+
+            // d ln det A / dA = A^-T
+            let grad_input = as_matrix(&out._saved[0]).t().mapv(|x| x * grad);
+            accumulate_grad(&out._children[0], grad_input.into_dyn());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Reduced QR of an [m, n] matrix with m >= n: Q [m, n] with orthonormal columns and upper
+    // triangular R [n, n] with a non-negative diagonal. Gradients need A to have full rank.
+    #[track_caller]
+    pub fn qr(&self) -> (Tensor, Tensor) {
+        self.try_qr().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_qr(&self) -> Result<(Tensor, Tensor), TensorError> {
+        let _timer = profiler::forward("qr");
+        let shape = self.shape();
+        if shape.len() != 2 || shape[0] < shape[1] {
+            let transposed = shape.iter().rev().copied().collect();
+            return Err(shape_error("qr", shape, transposed));
+        }
+        let (q, r) = householder_qr(as_matrix(&self.borrow().data));
+        let saved = [to_f32(q), to_f32(r)];
+
+        fn backward_q(out: &TensorData) {
+            let grad = qr_grad(
+                &saved_matrix(out, 0),
+                &saved_matrix(out, 1),
+                grad_matrix(out),
+                None,
+            );
+            accumulate_grad(&out._children[0], to_f32(grad));
+        }
+        fn backward_r(out: &TensorData) {
+            let grad = qr_grad(
+                &saved_matrix(out, 0),
+                &saved_matrix(out, 1),
+                None,
+                grad_matrix(out),
+            );
+            accumulate_grad(&out._children[0], to_f32(grad));
+        }
+        Ok((
+            factor_node(Op::QrQ, saved[0].clone(), self, &saved, backward_q),
+            factor_node(Op::QrR, saved[1].clone(), self, &saved, backward_r),
+        ))
+    }
+
+    // Reduced SVD A = U diag(S) V^T of an [m, n] matrix, k = min(m, n): U [m, k], S [k] in
+    // descending order and V^T [k, n]. Gradients need full rank and distinct singular values,
+    // and a loss that doesn't depend on the signs of the singular vectors.
+    #[track_caller]
+    pub fn svd(&self) -> (Tensor, Tensor, Tensor) {
+        self.try_svd().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_svd(&self) -> Result<(Tensor, Tensor, Tensor), TensorError> {
+        let _timer = profiler::forward("svd");
+        let shape = self.shape();
+        if shape.len() != 2 {
+            return Err(TensorError::InvalidAxis {
+                axis: 1,
+                ndim: shape.len(),
+            });
+        }
+        let (u, s, vt) = jacobi_svd(as_matrix(&self.borrow().data));
+        let saved = [to_f32(u), s.mapv(|x| x as f32).into_dyn(), to_f32(vt)];
+
+        fn factors(out: &TensorData) -> (Array2<f64>, Array1<f64>, Array2<f64>) {
+            let s = out._saved[1].view().into_dimensionality::<Ix1>().unwrap();
+            (
+                saved_matrix(out, 0),
+                s.mapv(f64::from),
+                saved_matrix(out, 2),
+            )
+        }
+        fn backward_u(out: &TensorData) {
+            let (u, s, vt) = factors(out);
+            let grad = svd_grad(&u, &s, &vt, grad_matrix(out), None, None);
+            accumulate_grad(&out._children[0], to_f32(grad));
+        }
+        fn backward_s(out: &TensorData) {
+            let (u, s, vt) = factors(out);
+            let grad_s = out.grad.as_ref().unwrap().mapv(f64::from);
+            let grad_s = grad_s.into_dimensionality::<Ix1>().unwrap();
+            let grad = svd_grad(&u, &s, &vt, None, Some(grad_s), None);
+            accumulate_grad(&out._children[0], to_f32(grad));
+        }
+        fn backward_vt(out: &TensorData) {
+            let (u, s, vt) = factors(out);
+            let grad = svd_grad(&u, &s, &vt, None, None, grad_matrix(out));
+            accumulate_grad(&out._children[0], to_f32(grad));
+        }
+        Ok((
+            factor_node(Op::SvdU, saved[0].clone(), self, &saved, backward_u),
+            factor_node(Op::SvdS, saved[1].clone(), self, &saved, backward_s),
+            factor_node(Op::SvdVt, saved[2].clone(), self, &saved, backward_vt),
+        ))
+    }
+}
@@ -0,0 +1,171 @@
+// Training logs readable by standard tooling. `TensorBoardWriter` writes TFEvents files, point
+// `tensorboard --logdir <dir>` at the directory it was created with:
+//
+//     let mut writer = TensorBoardWriter::new("runs/xor")?;
+//     writer.add_scalar("loss", loss.item(), step)?;
+//     writer.add_histogram("fc.weight", &weight.to_vec(), step)?;
+//
+// A TFEvents file is a sequence of TFRecords, each holding a serialized tensorflow.Event:
+// length u64, masked crc32c of the length, payload, masked crc32c of the payload.
+
+use crate::io::protobuf::Writer;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Equal-width buckets per histogram
+const HISTOGRAM_BUCKETS: usize = 30;
+
+pub struct TensorBoardWriter {
+    file: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl TensorBoardWriter {
+    // Start a new events file in `log_dir`, created if needed
+    pub fn new(log_dir: impl AsRef<Path>) -> io::Result<TensorBoardWriter> {
+        fs::create_dir_all(&log_dir)?;
+        let host = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| String::from("localhost"));
+        let path = log_dir.as_ref().join(format!(
+            "events.out.tfevents.{}.{}",
+            wall_time() as u64,
+            host
+        ));
+        let mut writer = TensorBoardWriter {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+        };
+        // The first event names the format version, readers skip files without it
+        let mut event = Writer::default();
+        event.double(1, wall_time());
+        event.string(3, "brain.Event:2");
+        writer.write_record(&event.buf)?;
+        Ok(writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: u64) -> io::Result<()> {
+        self.write_summary(step, |v| {
+            v.string(1, tag);
+            v.float(2, value);
+        })
+    }
+
+    // Distribution of `values`, e.g. a parameter's weights or gradient. Non-finite values are
+    // left out, they'd make every bucket infinitely wide.
+    pub fn add_histogram(&mut self, tag: &str, values: &[f32], step: u64) -> io::Result<()> {
+        let values: Vec<f64> = values
+            .iter()
+            .filter(|v| v.is_finite())
+            .map(|&v| v as f64)
+            .collect();
+        let (min, max) = values
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                (lo.min(v), hi.max(v))
+            });
+        let (limits, counts) = if values.is_empty() {
+            (Vec::new(), Vec::new())
+        } else if min == max {
+            (vec![max], vec![values.len() as f64])
+        } else {
+            let width = (max - min) / HISTOGRAM_BUCKETS as f64;
+            let mut counts = vec![0.0; HISTOGRAM_BUCKETS];
+            for &v in &values {
+                let bucket = (((v - min) / width) as usize).min(HISTOGRAM_BUCKETS - 1);
+                counts[bucket] += 1.0;
+            }
+            // Right edges, the last one is exactly max so floating point can't leave it out
+            let mut limits: Vec<f64> = (1..=HISTOGRAM_BUCKETS)
+                .map(|i| min + width * i as f64)
+                .collect();
+            limits[HISTOGRAM_BUCKETS - 1] = max;
+            (limits, counts)
+        };
+
+        self.write_summary(step, |v| {
+            v.string(1, tag);
+            v.message(5, |h| {
+                h.double(1, if values.is_empty() { 0.0 } else { min });
+                h.double(2, if values.is_empty() { 0.0 } else { max });
+                h.double(3, values.len() as f64);
+                h.double(4, values.iter().sum());
+                h.double(5, values.iter().map(|v| v * v).sum());
+                h.packed_doubles(6, &limits);
+                h.packed_doubles(7, &counts);
+            });
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    // Event { wall_time, step, summary: Summary { value: [<built by `value`>] } }
+    fn write_summary(&mut self, step: u64, value: impl FnOnce(&mut Writer)) -> io::Result<()> {
+        let mut event = Writer::default();
+        event.double(1, wall_time());
+        event.int(2, step as i64);
+        event.message(5, |summary| summary.message(1, value));
+        self.write_record(&event.buf)?;
+        // Flush every event so a running TensorBoard sees progress as it happens
+        self.file.flush()
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&len)?;
+        self.file.write_all(&masked_crc(&len).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc(data).to_le_bytes())
+    }
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+// CRC-32C (Castagnoli), reflected polynomial 0x82f63b78
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+// TFRecord's masking, so CRCs of data that itself contains CRCs stay well distributed
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
@@ -0,0 +1,872 @@
+// Loss functions, each one a single fused graph node so the backward pass doesn't have to
+// walk through a chain of elementwise ops
+
+use crate::device;
+use crate::error::{Operand, TensorError};
+use crate::inference;
+use crate::op::{LossKind, Op};
+use crate::profiler;
+use crate::tensor::{accumulate_grad, Tensor, TensorData};
+use ndarray::{arr0, Array1, Array2, ArrayD, Axis, Ix2};
+use std::panic::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    #[default]
+    Mean,
+    Sum,
+    // Keep the per-element losses
+    None,
+}
+
+impl Reduction {
+    // Reduced loss and the factor each element's gradient gets scaled by
+    pub(crate) fn apply(self, losses: ArrayD<f32>) -> (ArrayD<f32>, f32) {
+        match self {
+            Reduction::Mean => {
+                let n = losses.len().max(1) as f32;
+                (arr0(losses.sum() / n).into_dyn(), 1.0 / n)
+            }
+            Reduction::Sum => (arr0(losses.sum()).into_dyn(), 1.0),
+            Reduction::None => (losses, 1.0),
+        }
+    }
+}
+
+#[track_caller]
+fn check_same_shape(op: &'static str, pred: &Tensor, target: &Tensor) {
+    let (lhs, rhs) = (pred.shape(), target.shape());
+    if lhs != rhs {
+        let location = if cfg!(debug_assertions) {
+            Some(Location::caller())
+        } else {
+            None
+        };
+        panic!(
+            "{}",
+            TensorError::ShapeMismatch {
+                op,
+                lhs,
+                rhs,
+                operands: Some(Box::new([Operand::of(pred), Operand::of(target)])),
+                location,
+            }
+        );
+    }
+}
+
+// Output node for a fused loss: `d_children[i]` is the derivative of `value` wrt
+// `children[i]`, already scaled by the reduction
+#[track_caller]
+pub(crate) fn fused_loss(
+    loss: LossKind,
+    children: Vec<Tensor>,
+    value: ArrayD<f32>,
+    d_children: Vec<ArrayD<f32>>,
+) -> Tensor {
+    device::check_same(loss.name(), &children.iter().collect::<Vec<_>>())
+        .unwrap_or_else(|e| panic!("{}", e));
+    if inference::is_enabled() {
+        return Tensor::from(value).on_device_of(&children[0]);
+    }
+    let mut new_tensor_data = TensorData::new(value);
+    new_tensor_data._op = Some(Op::Loss(loss));
+    new_tensor_data._children = children;
+    new_tensor_data._saved = d_children;
+
+    fn backward(out: &TensorData) {
+        let grad = out.grad.clone().unwrap();
+        for (child, d_child) in out._children.iter().zip(out._saved.iter()) {
+            // Unreduced per-sample losses (e.g. [N] for [N, D] inputs) get one gradient per
+            // sample, add trailing axes so it lines up with the rows
+            let mut grad = grad.clone();
+            while grad.ndim() > 0 && grad.ndim() < d_child.ndim() {
+                let axis = Axis(grad.ndim());
+                grad = grad.insert_axis(axis);
+            }
+            accumulate_grad(child, &grad * d_child);
+        }
+    }
+    new_tensor_data._backward = Some(backward);
+
+    Tensor::new(new_tensor_data)
+}
+
+// Losses of the form f(pred - target): `d_pred` is the elementwise derivative wrt `pred`,
+// the derivative wrt `target` is its negation
+#[track_caller]
+fn pointwise_loss(
+    loss: LossKind,
+    pred: &Tensor,
+    target: &Tensor,
+    losses: ArrayD<f32>,
+    d_pred: ArrayD<f32>,
+    reduction: Reduction,
+) -> Tensor {
+    let (value, scale) = reduction.apply(losses);
+    let d_pred = d_pred * scale;
+    let d_target = -&d_pred;
+    fused_loss(
+        loss,
+        vec![pred.clone(), target.clone()],
+        value,
+        vec![d_pred, d_target],
+    )
+}
+
+// Mean squared error: (pred - target)^2
+#[track_caller]
+pub fn mse(pred: &Tensor, target: &Tensor, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("mse");
+    check_same_shape("mse", pred, target);
+    let diff = &pred.borrow().data - &target.borrow().data;
+    let losses = diff.mapv(|d| d * d);
+    pointwise_loss(LossKind::Mse, pred, target, losses, diff * 2.0, reduction)
+}
+
+// Mean absolute error: |pred - target|, gradient sign(pred - target) with 0 at the kink
+#[track_caller]
+pub fn l1(pred: &Tensor, target: &Tensor, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("l1");
+    check_same_shape("l1", pred, target);
+    let diff = &pred.borrow().data - &target.borrow().data;
+    let losses = diff.mapv(f32::abs);
+    let d_pred = diff.mapv(|d| if d == 0.0 { 0.0 } else { d.signum() });
+    pointwise_loss(LossKind::L1, pred, target, losses, d_pred, reduction)
+}
+
+// Quadratic within `delta` of the target and linear outside, so outliers get a bounded gradient
+#[track_caller]
+pub fn huber(pred: &Tensor, target: &Tensor, delta: f32, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("huber");
+    check_same_shape("huber", pred, target);
+    assert!(delta > 0.0, "huber delta must be positive, got {}", delta);
+    let (losses, d_pred) = huber_parts(pred, target, delta);
+    pointwise_loss(LossKind::Huber, pred, target, losses, d_pred, reduction)
+}
+
+// PyTorch's SmoothL1: huber with delta = beta, divided by beta so the linear part has slope 1.
+// beta = 0 is l1, as in PyTorch.
+#[track_caller]
+pub fn smooth_l1(pred: &Tensor, target: &Tensor, beta: f32, reduction: Reduction) -> Tensor {
+    assert!(
+        beta >= 0.0,
+        "smooth_l1 beta must be non-negative, got {}",
+        beta
+    );
+    if beta == 0.0 {
+        return l1(pred, target, reduction);
+    }
+    let _timer = profiler::forward("smooth_l1");
+    check_same_shape("smooth_l1", pred, target);
+    let (losses, d_pred) = huber_parts(pred, target, beta);
+    pointwise_loss(
+        LossKind::SmoothL1,
+        pred,
+        target,
+        losses / beta,
+        d_pred / beta,
+        reduction,
+    )
+}
+
+fn huber_parts(pred: &Tensor, target: &Tensor, delta: f32) -> (ArrayD<f32>, ArrayD<f32>) {
+    let diff = &pred.borrow().data - &target.borrow().data;
+    let losses = diff.mapv(|d| {
+        if d.abs() <= delta {
+            0.5 * d * d
+        } else {
+            delta * (d.abs() - 0.5 * delta)
+        }
+    });
+    let d_pred = diff.mapv(|d| d.clamp(-delta, delta));
+    (losses, d_pred)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrossEntropyOptions<'a> {
+    // Per-class rescaling weight, length must match the number of classes
+    pub weight: Option<&'a [f32]>,
+    pub reduction: Reduction,
+    // Mix the one-hot targets with a uniform distribution: (1 - eps) * one_hot + eps / C
+    pub label_smoothing: f32,
+    // Targets with this value (e.g. -100 for padding tokens) add nothing to the loss or the
+    // gradient and don't count towards the mean
+    pub ignore_index: Option<i64>,
+}
+
+// Cross-entropy of [N, C] logits against an [N] tensor of class indices, like the labels
+// one_hot takes, mean over the batch
+#[track_caller]
+pub fn cross_entropy(logits: &Tensor, targets: &Tensor) -> Tensor {
+    cross_entropy_with(logits, targets, CrossEntropyOptions::default())
+}
+
+// log_softmax + NLL fused, the gradient wrt the logits is w * (softmax - one_hot).
+// With class weights the mean is taken over the summed target weights, like PyTorch does,
+// also for the label smoothing term. Ignored targets are left out of that sum, a batch with
+// nothing but ignored targets has loss 0.
+#[track_caller]
+pub fn cross_entropy_with(
+    logits: &Tensor,
+    targets: &Tensor,
+    options: CrossEntropyOptions,
+) -> Tensor {
+    let _timer = profiler::forward("cross_entropy");
+    let targets = class_indices("cross_entropy", targets);
+    let logits_data = logits.borrow().data.clone();
+    let shape = logits_data.shape().to_vec();
+    let logits_data = logits_data
+        .into_dimensionality::<Ix2>()
+        .unwrap_or_else(|_| panic!("cross_entropy expects [N, C] logits, got shape {:?}", shape));
+    let (n, c) = logits_data.dim();
+    assert!(
+        targets.len() == n,
+        "cross_entropy got {} targets for a batch of {}",
+        targets.len(),
+        n
+    );
+    if let Some(weight) = options.weight {
+        assert!(
+            weight.len() == c,
+            "cross_entropy got {} class weights for {} classes",
+            weight.len(),
+            c
+        );
+    }
+
+    let eps = options.label_smoothing;
+    assert!(
+        (0.0..=1.0).contains(&eps),
+        "cross_entropy label_smoothing must be in [0, 1], got {}",
+        eps
+    );
+    let class_weight = options
+        .weight
+        .map_or_else(|| Array1::ones(c), |weight| Array1::from(weight.to_vec()));
+
+    let mut losses = Array1::<f32>::zeros(n);
+    let mut d_logits = Array2::<f32>::zeros((n, c));
+    let mut total_weight = 0.0;
+    for (i, row) in logits_data.outer_iter().enumerate() {
+        let target = targets[i];
+        if options.ignore_index == Some(target) {
+            continue;
+        }
+        assert!(
+            (0..c as i64).contains(&target),
+            "cross_entropy target {} is out of range for {} classes",
+            target,
+            c
+        );
+        let target = target as usize;
+        let w = class_weight[target];
+
+        // Shift by the max so exp() can't overflow
+        let max = row.fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+        let log_sum_exp = max + row.mapv(|x| (x - max).exp()).sum().ln();
+        let log_probs = row.mapv(|x| x - log_sum_exp);
+        let probs = log_probs.mapv(f32::exp);
+
+        // (1 - eps) * w_t * -log p_t + eps / C * sum_c w_c * -log p_c
+        let smooth = eps / c as f32;
+        losses[i] = -(1.0 - eps) * w * log_probs[target] - smooth * class_weight.dot(&log_probs);
+        let mut d_row = d_logits.row_mut(i);
+        d_row.assign(&(&probs * ((1.0 - eps) * w + smooth * class_weight.sum())));
+        d_row.scaled_add(-smooth, &class_weight);
+        d_row[target] -= (1.0 - eps) * w;
+        total_weight += w;
+    }
+
+    let (value, scale) = match options.reduction {
+        Reduction::Mean if total_weight == 0.0 => (arr0(0.0).into_dyn(), 0.0),
+        Reduction::Mean => (
+            arr0(losses.sum() / total_weight).into_dyn(),
+            1.0 / total_weight,
+        ),
+        Reduction::Sum => (arr0(losses.sum()).into_dyn(), 1.0),
+        Reduction::None => (losses.into_dyn(), 1.0),
+    };
+
+    fused_loss(
+        LossKind::CrossEntropy,
+        vec![logits.clone()],
+        value,
+        vec![(d_logits * scale).into_dyn()],
+    )
+}
+
+// The labels of `targets` as integers, panics on a value that isn't a whole number. `op`
+// names the loss in the messages.
+#[track_caller]
+fn class_indices(op: &str, targets: &Tensor) -> Vec<i64> {
+    let shape = targets.shape();
+    assert!(
+        shape.len() == 1,
+        "{} expects [N] targets, got shape {:?}",
+        op,
+        shape
+    );
+    targets
+        .to_vec()
+        .iter()
+        .map(|&t| {
+            assert!(
+                t.fract() == 0.0,
+                "{} targets must be class indices, got {}",
+                op,
+                t
+            );
+            t as i64
+        })
+        .collect()
+}
+
+// KL(q || p) with `log_p` given as log-probabilities and `q` as probabilities, matching
+// PyTorch's kl_div(input, target): q * (ln q - log_p), where q = 0 contributes nothing
+#[track_caller]
+pub fn kl_div(log_p: &Tensor, q: &Tensor, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("kl_div");
+    check_same_shape("kl_div", log_p, q);
+    let log_p_data = log_p.borrow().data.clone();
+    let q_data = q.borrow().data.clone();
+
+    let log_q = q_data.mapv(|x| if x > 0.0 { x.ln() } else { 0.0 });
+    let losses = &q_data * &(&log_q - &log_p_data);
+    let (value, scale) = reduction.apply(losses);
+
+    let d_log_p = -&q_data * scale;
+    let mut d_q = (&log_q - &log_p_data + 1.0) * scale;
+    d_q.zip_mut_with(&q_data, |d, &x| {
+        if x <= 0.0 {
+            *d = 0.0
+        }
+    });
+    fused_loss(
+        LossKind::KlDiv,
+        vec![log_p.clone(), q.clone()],
+        value,
+        vec![d_log_p, d_q],
+    )
+}
+
+// Rows of `x1` and `x2` ([N, D]) are pulled together where the [N] labels `y` are 1 (loss
+// 1 - cos) and pushed apart where they are -1 (loss max(0, cos - margin))
+#[track_caller]
+pub fn cosine_embedding(
+    x1: &Tensor,
+    x2: &Tensor,
+    y: &Tensor,
+    margin: f32,
+    reduction: Reduction,
+) -> Tensor {
+    let _timer = profiler::forward("cosine_embedding");
+    const EPS: f32 = 1e-8;
+    check_same_shape("cosine_embedding", x1, x2);
+    let shape = x1.shape();
+    let a = x1
+        .borrow()
+        .data
+        .clone()
+        .into_dimensionality::<Ix2>()
+        .unwrap_or_else(|_| {
+            panic!(
+                "cosine_embedding expects [N, D] inputs, got shape {:?}",
+                shape
+            )
+        });
+    let b = x2
+        .borrow()
+        .data
+        .clone()
+        .into_dimensionality::<Ix2>()
+        .unwrap();
+    let n = a.nrows();
+    assert!(
+        y.shape() == [n],
+        "cosine_embedding expects [{}] labels, got shape {:?}",
+        n,
+        y.shape()
+    );
+    let y = y.to_vec();
+
+    let mut losses = Array1::<f32>::zeros(n);
+    let mut d_a = Array2::<f32>::zeros(a.raw_dim());
+    let mut d_b = Array2::<f32>::zeros(b.raw_dim());
+    for i in 0..n {
+        let (ra, rb) = (a.row(i), b.row(i));
+        let norm_a = ra.dot(&ra).sqrt().max(EPS);
+        let norm_b = rb.dot(&rb).sqrt().max(EPS);
+        let cos = ra.dot(&rb) / (norm_a * norm_b);
+
+        // d cos / d a = b / (|a| |b|) - cos * a / |a|^2, symmetric for b
+        let (sign, loss) = match y[i] {
+            1.0 => (-1.0, 1.0 - cos),
+            -1.0 if cos > margin => (1.0, cos - margin),
+            -1.0 => (0.0, 0.0),
+            label => panic!("cosine_embedding labels must be 1 or -1, got {}", label),
+        };
+        losses[i] = loss;
+        d_a.row_mut(i)
+            .assign(&((&rb / (norm_a * norm_b) - &ra * (cos / (norm_a * norm_a))) * sign));
+        d_b.row_mut(i)
+            .assign(&((&ra / (norm_a * norm_b) - &rb * (cos / (norm_b * norm_b))) * sign));
+    }
+
+    let (value, scale) = reduction.apply(losses.into_dyn());
+    fused_loss(
+        LossKind::CosineEmbedding,
+        vec![x1.clone(), x2.clone()],
+        value,
+        vec![(d_a * scale).into_dyn(), (d_b * scale).into_dyn()],
+    )
+}
+
+// Binary hinge loss max(0, 1 - target * pred) for targets in {-1, 1}
+#[track_caller]
+pub fn hinge(pred: &Tensor, target: &Tensor, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("hinge");
+    check_same_shape("hinge", pred, target);
+    let pred_data = pred.borrow().data.clone();
+    let target_data = target.borrow().data.clone();
+
+    let margins = 1.0 - &pred_data * &target_data;
+    let active = margins.mapv(|m| if m > 0.0 { 1.0 } else { 0.0 });
+    let (value, scale) = reduction.apply(margins.mapv(|m| m.max(0.0)));
+
+    let d_pred = -&target_data * &active * scale;
+    let d_target = -&pred_data * &active * scale;
+    fused_loss(
+        LossKind::Hinge,
+        vec![pred.clone(), target.clone()],
+        value,
+        vec![d_pred, d_target],
+    )
+}
+
+// Sigmoid focal loss (Lin et al.) on raw logits with {0, 1} targets:
+// alpha_t * (1 - p_t)^gamma * bce, which down-weights the easy, well-classified examples.
+// `alpha` weighs the positive class, None disables the weighting; targets get no gradient.
+#[track_caller]
+pub fn focal(
+    logits: &Tensor,
+    targets: &Tensor,
+    alpha: Option<f32>,
+    gamma: f32,
+    reduction: Reduction,
+) -> Tensor {
+    let _timer = profiler::forward("focal");
+    check_same_shape("focal", logits, targets);
+    let logits_data = logits.borrow().data.clone();
+    let targets_data = targets.borrow().data.clone();
+
+    let mut losses = logits_data.clone();
+    let mut d_logits = logits_data.clone();
+    ndarray::Zip::from(&mut losses)
+        .and(&mut d_logits)
+        .and(&logits_data)
+        .and(&targets_data)
+        .for_each(|loss, d, &x, &t| {
+            let p = 1.0 / (1.0 + (-x).exp());
+            // Stable binary cross-entropy with logits
+            let bce = x.max(0.0) - x * t + (-x.abs()).exp().ln_1p();
+            let p_t = p * t + (1.0 - p) * (1.0 - t);
+            let alpha_t = alpha.map_or(1.0, |a| a * t + (1.0 - a) * (1.0 - t));
+            let modulator = (1.0 - p_t).powf(gamma);
+
+            // d/dx [(1 - p_t)^gamma] = -gamma * (1 - p_t)^(gamma - 1) * (2t - 1) * p * (1 - p)
+            let d_modulator = if gamma == 0.0 || p_t >= 1.0 {
+                0.0
+            } else {
+                -gamma * (1.0 - p_t).powf(gamma - 1.0) * (2.0 * t - 1.0) * p * (1.0 - p)
+            };
+            *loss = alpha_t * modulator * bce;
+            *d = alpha_t * (d_modulator * bce + modulator * (p - t));
+        });
+
+    let (value, scale) = reduction.apply(losses);
+    fused_loss(
+        LossKind::Focal,
+        vec![logits.clone()],
+        value,
+        vec![d_logits * scale],
+    )
+}
+
+// max(0, |a - p| - |a - n| + margin) over rows of [N, D] inputs, euclidean distance
+#[track_caller]
+pub fn triplet_margin(
+    anchor: &Tensor,
+    positive: &Tensor,
+    negative: &Tensor,
+    margin: f32,
+    reduction: Reduction,
+) -> Tensor {
+    let _timer = profiler::forward("triplet_margin");
+    const EPS: f32 = 1e-6;
+    check_same_shape("triplet_margin", anchor, positive);
+    check_same_shape("triplet_margin", anchor, negative);
+    let shape = anchor.shape();
+    let to_2d = |t: &Tensor| {
+        t.borrow()
+            .data
+            .clone()
+            .into_dimensionality::<Ix2>()
+            .unwrap_or_else(|_| {
+                panic!(
+                    "triplet_margin expects [N, D] inputs, got shape {:?}",
+                    shape
+                )
+            })
+    };
+    let (a, p, n) = (to_2d(anchor), to_2d(positive), to_2d(negative));
+
+    let mut losses = Array1::<f32>::zeros(a.nrows());
+    let mut d_a = Array2::<f32>::zeros(a.raw_dim());
+    let mut d_p = Array2::<f32>::zeros(a.raw_dim());
+    let mut d_n = Array2::<f32>::zeros(a.raw_dim());
+    for i in 0..a.nrows() {
+        let diff_p = &a.row(i) - &p.row(i);
+        let diff_n = &a.row(i) - &n.row(i);
+        let dist_p = diff_p.dot(&diff_p).sqrt().max(EPS);
+        let dist_n = diff_n.dot(&diff_n).sqrt().max(EPS);
+
+        let loss = dist_p - dist_n + margin;
+        if loss > 0.0 {
+            losses[i] = loss;
+            // d|x| / dx = x / |x|
+            let unit_p = diff_p / dist_p;
+            let unit_n = diff_n / dist_n;
+            d_a.row_mut(i).assign(&(&unit_p - &unit_n));
+            d_p.row_mut(i).assign(&-&unit_p);
+            d_n.row_mut(i).assign(&unit_n);
+        }
+    }
+
+    let (value, scale) = reduction.apply(losses.into_dyn());
+    fused_loss(
+        LossKind::TripletMargin,
+        vec![anchor.clone(), positive.clone(), negative.clone()],
+        value,
+        vec![
+            (d_a * scale).into_dyn(),
+            (d_p * scale).into_dyn(),
+            (d_n * scale).into_dyn(),
+        ],
+    )
+}
+
+// InfoNCE / NT-Xent style contrastive loss on a [N, N] similarity matrix where entry (i, j)
+// compares sample i of one view with sample j of the other: row i should pick column i.
+// This is cross-entropy over the rows of similarity / temperature with diagonal targets.
+#[track_caller]
+pub fn info_nce(similarity: &Tensor, temperature: f32, reduction: Reduction) -> Tensor {
+    let _timer = profiler::forward("info_nce");
+    let shape = similarity.shape();
+    assert!(
+        shape.len() == 2 && shape[0] == shape[1],
+        "info_nce expects a square [N, N] similarity matrix, got shape {:?}",
+        shape
+    );
+    let n = shape[0];
+
+    let logits = &similarity.borrow().data / temperature;
+    let mut losses = Array1::<f32>::zeros(n);
+    let mut d_sim = ArrayD::<f32>::zeros(logits.raw_dim());
+    for (i, row) in logits.outer_iter().enumerate() {
+        let max = row.fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+        let log_sum_exp = max + row.mapv(|x| (x - max).exp()).sum().ln();
+
+        losses[i] = log_sum_exp - row[i];
+        let mut d_row = d_sim.index_axis_mut(Axis(0), i);
+        d_row.assign(&row.mapv(|x| (x - log_sum_exp).exp() / temperature));
+        d_row[i] -= 1.0 / temperature;
+    }
+
+    let (value, scale) = reduction.apply(losses.into_dyn());
+    fused_loss(
+        LossKind::InfoNce,
+        vec![similarity.clone()],
+        value,
+        vec![d_sim * scale],
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DistillationOptions {
+    // Softens both distributions, higher values carry more of the teacher's ranking of the
+    // wrong classes
+    pub temperature: f32,
+    // Weight of the soft-target term, the hard-label term gets 1 - alpha
+    pub alpha: f32,
+    pub reduction: Reduction,
+}
+
+impl Default for DistillationOptions {
+    fn default() -> DistillationOptions {
+        DistillationOptions {
+            temperature: 2.0,
+            alpha: 0.5,
+            reduction: Reduction::Mean,
+        }
+    }
+}
+
+// Knowledge distillation (Hinton et al.) of [N, C] student logits towards a teacher's, per
+// sample alpha * T^2 * KL(softmax(teacher / T) || softmax(student / T)) plus
+// (1 - alpha) * cross-entropy against the hard [N] class indices in `targets`, stored as f32
+// like for cross_entropy. The T^2 keeps the soft gradients on the scale of the hard ones as T
+// changes. The teacher logits get no gradient, see train::Distiller for training with a frozen
+// teacher.
+#[track_caller]
+pub fn distillation(
+    student_logits: &Tensor,
+    teacher_logits: &Tensor,
+    targets: &Tensor,
+    options: DistillationOptions,
+) -> Tensor {
+    let _timer = profiler::forward("distillation");
+    check_same_shape("distillation", student_logits, teacher_logits);
+    let targets = class_indices("distillation", targets);
+    let shape = student_logits.shape();
+    assert!(
+        shape.len() == 2,
+        "distillation expects [N, C] logits, got shape {:?}",
+        shape
+    );
+    let (n, c) = (shape[0], shape[1]);
+    assert!(
+        targets.len() == n,
+        "distillation got {} targets for a batch of {}",
+        targets.len(),
+        n
+    );
+    let DistillationOptions {
+        temperature,
+        alpha,
+        reduction,
+    } = options;
+    assert!(
+        temperature > 0.0,
+        "distillation temperature must be positive, got {}",
+        temperature
+    );
+    let student = student_logits.borrow().data.clone();
+    let student = student.into_dimensionality::<Ix2>().unwrap();
+    let teacher = teacher_logits.borrow().data.clone();
+    let teacher = teacher.into_dimensionality::<Ix2>().unwrap();
+
+    let mut losses = Array1::<f32>::zeros(n);
+    let mut d_student = Array2::<f32>::zeros((n, c));
+    for i in 0..n {
+        let target = targets[i];
+        assert!(
+            (0..c as i64).contains(&target),
+            "distillation target {} is out of range for {} classes",
+            target,
+            c
+        );
+        let target = target as usize;
+        let row = student.row(i);
+        let log_p = log_softmax(row.mapv(|x| x / temperature));
+        let log_q = log_softmax(teacher.row(i).mapv(|x| x / temperature));
+        let log_probs = log_softmax(row.to_owned());
+        let p = log_p.mapv(f32::exp);
+        let q = log_q.mapv(f32::exp);
+
+        let kl = (&q * &(&log_q - &log_p)).sum();
+        losses[i] = alpha * temperature * temperature * kl - (1.0 - alpha) * log_probs[target];
+        // d/ds of T^2 KL is T (p - q), of the cross-entropy softmax - one_hot
+        let mut d_row = d_student.row_mut(i);
+        d_row.assign(&((&p - &q) * (alpha * temperature)));
+        d_row.scaled_add(1.0 - alpha, &log_probs.mapv(f32::exp));
+        d_row[target] -= 1.0 - alpha;
+    }
+
+    let (value, scale) = reduction.apply(losses.into_dyn());
+    fused_loss(
+        LossKind::Distillation,
+        vec![student_logits.clone()],
+        value,
+        vec![(d_student * scale).into_dyn()],
+    )
+}
+
+// Shifted by the max so exp() can't overflow
+fn log_softmax(row: Array1<f32>) -> Array1<f32> {
+    let max = row.fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+    let log_sum_exp = max + row.mapv(|x| (x - max).exp()).sum().ln();
+    row - log_sum_exp
+}
+
+// log(exp(a) + exp(b)) without leaving log-space
+fn log_add(a: f32, b: f32) -> f32 {
+    if a == f32::NEG_INFINITY {
+        return b;
+    }
+    if b == f32::NEG_INFINITY {
+        return a;
+    }
+    let max = a.max(b);
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+// Connectionist temporal classification loss (Graves et al.) on [T, N, C] log-probabilities,
+// the same layout as PyTorch. `targets[b]` holds the label sequence of sample b (without
+// blanks) and `input_lengths[b]` its number of valid time steps. Mean reduction divides each
+// loss by its target length before averaging over the batch, like PyTorch.
+// Samples whose targets can't be aligned within their input length get an infinite loss
+// and no gradient.
+#[track_caller]
+pub fn ctc(
+    log_probs: &Tensor,
+    targets: &[Vec<i64>],
+    input_lengths: &[usize],
+    blank: usize,
+    reduction: Reduction,
+) -> Tensor {
+    let _timer = profiler::forward("ctc");
+    let lp = log_probs.borrow().data.clone();
+    let shape = lp.shape().to_vec();
+    let lp = lp
+        .into_dimensionality::<ndarray::Ix3>()
+        .unwrap_or_else(|_| panic!("ctc expects [T, N, C] log_probs, got shape {:?}", shape));
+    let (max_t, n, c) = lp.dim();
+    assert!(
+        targets.len() == n && input_lengths.len() == n,
+        "ctc got {} targets and {} input lengths for a batch of {}",
+        targets.len(),
+        input_lengths.len(),
+        n
+    );
+    assert!(
+        blank < c,
+        "ctc blank {} is out of range for {} classes",
+        blank,
+        c
+    );
+
+    let mut losses = Array1::<f32>::zeros(n);
+    let mut d_lp = ndarray::Array3::<f32>::zeros((max_t, n, c));
+    for b in 0..n {
+        let t_len = input_lengths[b];
+        assert!(
+            t_len > 0 && t_len <= max_t,
+            "ctc input length {} is out of range for {} time steps",
+            t_len,
+            max_t
+        );
+        // Extended label sequence with blanks around every label: _ l0 _ l1 _ ... _
+        let mut ext = vec![blank];
+        for &label in &targets[b] {
+            assert!(
+                (0..c as i64).contains(&label) && label as usize != blank,
+                "ctc target label {} is invalid for {} classes with blank {}",
+                label,
+                c,
+                blank
+            );
+            ext.push(label as usize);
+            ext.push(blank);
+        }
+        let s_len = ext.len();
+        // A label may skip the blank before it, unless it repeats the previous label
+        let can_skip = |s: usize| s >= 2 && ext[s] != blank && ext[s] != ext[s - 2];
+
+        // Forward variables alpha_t(s), both alpha and beta include the emission at t
+        let mut alpha = Array2::<f32>::from_elem((t_len, s_len), f32::NEG_INFINITY);
+        alpha[[0, 0]] = lp[[0, b, ext[0]]];
+        if s_len > 1 {
+            alpha[[0, 1]] = lp[[0, b, ext[1]]];
+        }
+        for t in 1..t_len {
+            for s in 0..s_len {
+                let mut acc = alpha[[t - 1, s]];
+                if s >= 1 {
+                    acc = log_add(acc, alpha[[t - 1, s - 1]]);
+                }
+                if can_skip(s) {
+                    acc = log_add(acc, alpha[[t - 1, s - 2]]);
+                }
+                alpha[[t, s]] = acc + lp[[t, b, ext[s]]];
+            }
+        }
+
+        let mut beta = Array2::<f32>::from_elem((t_len, s_len), f32::NEG_INFINITY);
+        beta[[t_len - 1, s_len - 1]] = lp[[t_len - 1, b, ext[s_len - 1]]];
+        if s_len > 1 {
+            beta[[t_len - 1, s_len - 2]] = lp[[t_len - 1, b, ext[s_len - 2]]];
+        }
+        for t in (0..t_len - 1).rev() {
+            for s in 0..s_len {
+                let mut acc = beta[[t + 1, s]];
+                if s + 1 < s_len {
+                    acc = log_add(acc, beta[[t + 1, s + 1]]);
+                }
+                if s + 2 < s_len && can_skip(s + 2) {
+                    acc = log_add(acc, beta[[t + 1, s + 2]]);
+                }
+                beta[[t, s]] = acc + lp[[t, b, ext[s]]];
+            }
+        }
+
+        let mut log_likelihood = alpha[[t_len - 1, s_len - 1]];
+        if s_len > 1 {
+            log_likelihood = log_add(log_likelihood, alpha[[t_len - 1, s_len - 2]]);
+        }
+        losses[b] = -log_likelihood;
+        if log_likelihood == f32::NEG_INFINITY {
+            continue;
+        }
+
+        // d loss / d log y_t(k) = -sum_{s: ext[s] = k} exp(alpha_t(s) + beta_t(s) - log y_t(k) - log p)
+        let scale = match reduction {
+            Reduction::Mean => 1.0 / targets[b].len().max(1) as f32,
+            Reduction::Sum | Reduction::None => 1.0,
+        };
+        for t in 0..t_len {
+            for s in 0..s_len {
+                let k = ext[s];
+                let log_occupancy = alpha[[t, s]] + beta[[t, s]] - lp[[t, b, k]] - log_likelihood;
+                d_lp[[t, b, k]] -= scale * log_occupancy.exp();
+            }
+        }
+    }
+
+    let value = match reduction {
+        Reduction::Mean => {
+            let per_label = losses
+                .iter()
+                .zip(targets)
+                .map(|(loss, target)| loss / target.len().max(1) as f32)
+                .sum::<f32>();
+            arr0(per_label / n as f32).into_dyn()
+        }
+        Reduction::Sum => arr0(losses.sum()).into_dyn(),
+        Reduction::None => losses.into_dyn(),
+    };
+    if reduction == Reduction::Mean {
+        d_lp /= n as f32;
+    }
+
+    if inference::is_enabled() {
+        return Tensor::from(value).on_device_of(log_probs);
+    }
+    let mut new_tensor_data = TensorData::new(value);
+    new_tensor_data._op = Some(Op::Loss(LossKind::Ctc));
+    new_tensor_data._children = vec![log_probs.clone()];
+    new_tensor_data._saved = vec![d_lp.into_dyn()];
+
+    fn backward(out: &TensorData) {
+        let mut grad = out.grad.clone().unwrap();
+        // Unreduced losses are indexed by the batch, which is the middle axis of [T, N, C]
+        if grad.ndim() == 1 {
+            let n = grad.len();
+            grad = grad.into_shape(vec![1, n, 1]).unwrap();
+        }
+        accumulate_grad(&out._children[0], &grad * &out._saved[0]);
+    }
+    new_tensor_data._backward = Some(backward);
+
+    Tensor::new(new_tensor_data)
+}
@@ -0,0 +1,54 @@
+// Build a Tensor from a nested literal, mirrors ndarray's `array!`:
+// tensor![1.0, 2.0], tensor![[1.0, 2.0], [3.0, 4.0]], tensor![[[1.0], [2.0]], [[3.0], [4.0]]]
+#[macro_export]
+macro_rules! tensor {
+    ($($elems:tt)*) => {
+        $crate::tensor::Tensor::from($crate::ndarray::array![$($elems)*].into_dyn())
+    };
+}
+
+// Panic unless two tensors are allclose, defaults to rtol=1e-5 and atol=1e-8 like numpy:
+// assert_tensor_close!(a, b) or assert_tensor_close!(a, b, rtol, atol)
+#[macro_export]
+macro_rules! assert_tensor_close {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::assert_tensor_close!($left, $right, 1e-5, 1e-8)
+    };
+    ($left:expr, $right:expr, $rtol:expr, $atol:expr $(,)?) => {{
+        let (left, right) = (&$left, &$right);
+        if !left.allclose(right, $rtol, $atol) {
+            panic!(
+                "assertion `left ≈ right` failed (rtol={}, atol={})\n  left: {}\n right: {}",
+                $rtol, $atol, left, right
+            );
+        }
+    }};
+}
+
+// Panic unless the tensors fit their shape patterns, see the shape module:
+// expect_shape!(x, [batch, 784]) or expect_shape!(q, [batch, seq, dim], k, [batch, seq, dim])
+#[macro_export]
+macro_rules! expect_shape {
+    ($($tensor:expr, [$($dim:tt),* $(,)?]),+ $(,)?) => {
+        $crate::shape::check_shapes(&[$((&$tensor, &[$($crate::shape_dim!($dim)),*][..])),+])
+            .unwrap_or_else(|e| panic!("{}", e))
+    };
+}
+
+// One dim of an expect_shape! pattern
+#[doc(hidden)]
+#[macro_export]
+macro_rules! shape_dim {
+    (_) => {
+        $crate::shape::Dim::Any
+    };
+    (..) => {
+        $crate::shape::Dim::Rest
+    };
+    ($name:ident) => {
+        $crate::shape::Dim::Named(stringify!($name))
+    };
+    ($size:expr) => {
+        $crate::shape::Dim::Size($size)
+    };
+}
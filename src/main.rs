@@ -1,68 +1,384 @@
-mod tensor;
-use ndarray::arr1;
-use tensor::Tensor;
+// `rust-ml`, experiments from a config file instead of Rust code:
+//
+//     cargo run --release --features cli -- train experiment.toml
+//     cargo run --release --features cli -- predict experiment.toml new_rows.csv
+//
+// `train` fits the model on the CSV file of the config and writes to its output directory:
+// last.ckpt after the final epoch, best.ckpt with the lowest validation loss (when there's a
+// validation split), metrics.csv and TensorBoard events. `predict` loads a checkpoint
+// (best.ckpt if there is one, otherwise last.ckpt, or `--checkpoint PATH`) and writes one
+// row of predictions per input row to stdout or `--output PATH`. Inputs are scaled like the
+// training data, which is read again for that.
+//
+// A config, in TOML or JSON (by file extension):
+//
+//     [model]
+//     layers = [
+//         { type = "linear", in_features = 4, out_features = 16 },
+//         { type = "relu" },
+//         { type = "linear", in_features = 16, out_features = 3 },
+//     ]                          # any architecture::ModelSpec
+//
+//     [data]
+//     path = "iris.csv"
+//     targets = ["species"]
+//     scaling = "standard"       # none (default), minmax or standard
+//     batch_size = 16            # default 32
+//     validation_split = 0.2     # default 0, no validation
+//
+//     [optimizer]                # default adam with lr 1e-3
+//     type = "sgd"               # sgd or adam
+//     lr = 0.1
+//     momentum = 0.9             # sgd only
+//
+//     [training]
+//     epochs = 50
+//     loss = "cross_entropy"     # mse, l1 or cross_entropy
+//     output_dir = "runs/iris"
+//     seed = 0                   # default 0
+//     early_stopping = 5         # patience in epochs, needs a validation split
 
-fn _test_basic_add_multiply() {
-    let a = Tensor::from(arr1(&[2.0, 3.0]).into_dyn());
-    let b = Tensor::from(arr1(&[-1.0, 6.0]).into_dyn());
-    let c = Tensor::from(arr1(&[4.0, 0.0]).into_dyn());
+use rust_ml::architecture::ModelSpec;
+use rust_ml::checkpoint;
+use rust_ml::data::{split, CsvDataset, CsvOptions, DataLoader, Scaling};
+use rust_ml::logging::TensorBoardWriter;
+use rust_ml::loss::{self, Reduction};
+use rust_ml::nn::Module;
+use rust_ml::optim::{Adam, Optimizer, Sgd};
+use rust_ml::random::{manual_seed, rng_state};
+use rust_ml::tensor::Tensor;
+use rust_ml::train::{EarlyStopping, ModelCheckpoint, ProgressBar, Trainer};
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-    let d = &b + &a;
-    let e = &d * &c;
-    println!("[1.0, 9.0]: {:?}", d.borrow(),);
-    println!("[4.0, 0.0]: {:?}", e.borrow(),);
-    println!("[2.0, 3.0]: {:?}", a.borrow(),);
-    println!("[-1.0, 6.0]: {:?}", b.borrow(),);
-    println!("[4.0, 0.0]: {:?}", c.borrow(),);
+const USAGE: &str = "usage: rust-ml train CONFIG
+       rust-ml predict CONFIG INPUT.csv [--checkpoint PATH] [--output PATH]";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Experiment {
+    model: ModelSpec,
+    data: DataConfig,
+    #[serde(default)]
+    optimizer: OptimizerConfig,
+    training: TrainingConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DataConfig {
+    path: PathBuf,
+    targets: Vec<String>,
+    // Every other column by default
+    features: Option<Vec<String>>,
+    #[serde(default)]
+    scaling: ScalingConfig,
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+    #[serde(default)]
+    validation_split: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ScalingConfig {
+    #[default]
+    None,
+    Minmax,
+    Standard,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum OptimizerConfig {
+    Sgd {
+        lr: f32,
+        #[serde(default)]
+        momentum: f32,
+        #[serde(default)]
+        weight_decay: f32,
+    },
+    Adam {
+        #[serde(default = "default_adam_lr")]
+        lr: f32,
+        #[serde(default)]
+        weight_decay: f32,
+    },
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> OptimizerConfig {
+        OptimizerConfig::Adam {
+            lr: default_adam_lr(),
+            weight_decay: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrainingConfig {
+    epochs: usize,
+    loss: LossConfig,
+    output_dir: PathBuf,
+    #[serde(default)]
+    seed: u64,
+    early_stopping: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LossConfig {
+    Mse,
+    L1,
+    CrossEntropy,
 }
 
-fn _verify_micrograd_logic() {
-    let a = Tensor::from(arr1(&[2.0]).into_dyn());
-    let b = Tensor::from(arr1(&[-3.0]).into_dyn());
-    let c = Tensor::from(arr1(&[10.0]).into_dyn());
+fn default_batch_size() -> usize {
+    32
+}
+
+fn default_adam_lr() -> f32 {
+    1e-3
+}
 
-    let _d = &a + &b;
-    let _e = &a * &b;
-    let f = &a + &(&b * &c);
-    let g = Tensor::from(arr1(&[4.0]).into_dyn());
-    let l = &g * &f;
+type Result<T> = std::result::Result<T, String>;
 
-    println!("{:?}", l)
+fn read_config(path: &Path) -> Result<Experiment> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|e| e.to_string()),
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        _ => Err(String::from("config files must end in .toml or .json")),
+    };
+    parsed.map_err(|e| format!("{}: {}", path.display(), e))
 }
 
-fn _verify_micrograd_backward() {
-    let a = Tensor::from(arr1(&[2.0]).into_dyn());
-    let b = Tensor::from(arr1(&[0.0]).into_dyn());
-    let c = Tensor::from(arr1(&[-3.0]).into_dyn());
+fn build_optimizer(config: &OptimizerConfig, params: Vec<Tensor>) -> Box<dyn Optimizer> {
+    match *config {
+        OptimizerConfig::Sgd {
+            lr,
+            momentum,
+            weight_decay,
+        } => {
+            let mut sgd = Sgd::new(params, lr);
+            sgd.momentum = momentum;
+            sgd.weight_decay = weight_decay;
+            Box::new(sgd)
+        }
+        OptimizerConfig::Adam { lr, weight_decay } => {
+            let mut adam = Adam::new(params, lr);
+            adam.weight_decay = weight_decay;
+            Box::new(adam)
+        }
+    }
+}
 
-    let d = Tensor::from(arr1(&[1.0]).into_dyn());
-    let e = Tensor::from(arr1(&[6.8813735870195432]).into_dyn());
-    let f = &a * &c;
-    let g = &b * &d;
-    let h = &f + &g;
+fn compute_loss(kind: LossConfig, pred: &Tensor, target: &Tensor) -> Tensor {
+    match kind {
+        LossConfig::CrossEntropy => loss::cross_entropy(pred, target),
+        // A single target column comes as [N], line it up with [N, 1] outputs
+        LossConfig::Mse => loss::mse(pred, &target.reshape(&pred.shape()), Reduction::Mean),
+        LossConfig::L1 => loss::l1(pred, &target.reshape(&pred.shape()), Reduction::Mean),
+    }
+}
 
-    let i = &h + &e;
+fn load_training_data(config: &DataConfig) -> Result<CsvDataset> {
+    let targets: Vec<&str> = config.targets.iter().map(String::as_str).collect();
+    let mut options = CsvOptions::new()
+        .targets(&targets)
+        .scaling(match config.scaling {
+            ScalingConfig::None => Scaling::None,
+            ScalingConfig::Minmax => Scaling::MinMax,
+            ScalingConfig::Standard => Scaling::Standard,
+        });
+    if let Some(features) = &config.features {
+        let features: Vec<&str> = features.iter().map(String::as_str).collect();
+        options = options.features(&features);
+    }
+    options.load(&config.path).map_err(|e| e.to_string())
+}
 
-    let j = i.tanh();
+fn io_error(path: &Path) -> impl Fn(io::Error) -> String + '_ {
+    move |e| format!("{}: {}", path.display(), e)
+}
 
-    j.backward();
+fn train(config_path: &Path) -> Result<()> {
+    let config = read_config(config_path)?;
+    let output_dir = &config.training.output_dir;
+    fs::create_dir_all(output_dir).map_err(io_error(output_dir))?;
+    manual_seed(config.training.seed);
 
-    println!("{:?}", j)
+    let dataset = load_training_data(&config.data)?;
+    let validation = config.data.validation_split;
+    if !(0.0..1.0).contains(&validation) {
+        return Err(format!(
+            "validation_split must be in [0, 1), got {}",
+            validation
+        ));
+    }
+    if config.training.early_stopping.is_some() && validation == 0.0 {
+        return Err(String::from("early_stopping needs a validation_split"));
+    }
+    let model = config.model.build();
+    let mut optimizer = build_optimizer(&config.optimizer, model.parameters());
+    let loss_kind = config.training.loss;
+    let mut trainer = Trainer::new(&model, optimizer.as_mut(), move |pred, target| {
+        compute_loss(loss_kind, pred, target)
+    })
+    .epochs(config.training.epochs)
+    .callback(ProgressBar::new());
+    if let Some(patience) = config.training.early_stopping {
+        trainer = trainer.callback(EarlyStopping::new(patience));
+    }
+
+    let history = if validation > 0.0 {
+        let mut parts = split(
+            dataset,
+            &[1.0 - validation, validation],
+            config.training.seed,
+        )
+        .into_iter();
+        let (train_set, val_set) = (parts.next().unwrap(), parts.next().unwrap());
+        let train_loader = DataLoader::new(train_set, config.data.batch_size).shuffle(true);
+        let val_loader = DataLoader::new(val_set, config.data.batch_size);
+        trainer = trainer.callback(ModelCheckpoint::new(output_dir.join("best.ckpt")));
+        trainer.fit_with_validation(&train_loader, &val_loader)
+    } else {
+        let loader = DataLoader::new(dataset, config.data.batch_size).shuffle(true);
+        trainer.fit(&loader)
+    };
+    drop(trainer);
+
+    let last = output_dir.join("last.ckpt");
+    checkpoint::save(
+        &last,
+        &model,
+        optimizer.as_ref(),
+        history.len() as u64,
+        &rng_state(),
+    )
+    .map_err(io_error(&last))?;
+
+    let metrics_path = output_dir.join("metrics.csv");
+    let mut metrics = String::from("epoch,train_loss,val_loss\n");
+    let mut events = TensorBoardWriter::new(output_dir).map_err(io_error(output_dir))?;
+    for epoch in &history {
+        let val_loss = epoch.val_loss.map_or(String::new(), |l| l.to_string());
+        metrics.push_str(&format!(
+            "{},{},{}\n",
+            epoch.epoch, epoch.train_loss, val_loss
+        ));
+        let step = epoch.epoch as u64;
+        let write = |events: &mut TensorBoardWriter| -> io::Result<()> {
+            events.add_scalar("train_loss", epoch.train_loss, step)?;
+            if let Some(val_loss) = epoch.val_loss {
+                events.add_scalar("val_loss", val_loss, step)?;
+            }
+            Ok(())
+        };
+        write(&mut events).map_err(io_error(events.path()))?;
+    }
+    events.flush().map_err(io_error(output_dir))?;
+    fs::write(&metrics_path, metrics).map_err(io_error(&metrics_path))?;
+    eprintln!(
+        "trained {} epochs, checkpoints and metrics in {}",
+        history.len(),
+        output_dir.display()
+    );
+    Ok(())
 }
 
-fn _check_operation_double_variable() {
-    let a = Tensor::from(arr1(&[3.0]).into_dyn());
-    let b = &a + &a;
-    b.backward();
-    println!("{:?}", b);
+fn predict(
+    config_path: &Path,
+    input: &Path,
+    checkpoint_path: Option<PathBuf>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let config = read_config(config_path)?;
+    // Same feature columns and scaling as in training
+    let training = load_training_data(&config.data)?;
+    let features: Vec<&str> = training.feature_names.iter().map(String::as_str).collect();
+    let inputs = CsvOptions::new()
+        .features(&features)
+        .scaler(training.scaler.clone())
+        .load(input)
+        .map_err(|e| e.to_string())?;
+
+    let model = config.model.build();
+    let checkpoint_path = checkpoint_path.unwrap_or_else(|| {
+        let best = config.training.output_dir.join("best.ckpt");
+        if best.exists() {
+            best
+        } else {
+            config.training.output_dir.join("last.ckpt")
+        }
+    });
+    let saved = checkpoint::load(&checkpoint_path).map_err(io_error(&checkpoint_path))?;
+    model
+        .load_state_dict(&saved.model)
+        .map_err(|e| format!("{}: {}", checkpoint_path.display(), e))?;
+
+    // Class ids for classifiers, raw outputs named after the targets otherwise
+    let classify = config.training.loss == LossConfig::CrossEntropy;
+    let mut out = if classify {
+        String::from("class\n")
+    } else {
+        format!("{}\n", training.target_names.join(","))
+    };
+    for (batch, _) in &DataLoader::new(inputs, 256) {
+        for row in model.predict(&batch).to_vec2() {
+            let line = if classify {
+                let class = row
+                    .iter()
+                    .enumerate()
+                    .fold(0, |best, (i, &v)| if v > row[best] { i } else { best });
+                class.to_string()
+            } else {
+                row.iter().map(f32::to_string).collect::<Vec<_>>().join(",")
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    match output {
+        Some(path) => fs::write(&path, out).map_err(io_error(&path)),
+        None => io::stdout()
+            .write_all(out.as_bytes())
+            .map_err(|e| e.to_string()),
+    }
+}
 
-    let c = Tensor::from(arr1(&[3.0]).into_dyn());
-    let d = &c * &c;
-    d.backward();
-    println!("{:?}", d);
+fn run(args: &[String]) -> Result<()> {
+    match args {
+        [command, config] if command == "train" => train(Path::new(config)),
+        [command, config, input, options @ ..] if command == "predict" => {
+            let (mut checkpoint_path, mut output) = (None, None);
+            let mut options = options.iter();
+            while let Some(flag) = options.next() {
+                let value = options
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))?;
+                match flag.as_str() {
+                    "--checkpoint" => checkpoint_path = Some(PathBuf::from(value)),
+                    "--output" => output = Some(PathBuf::from(value)),
+                    _ => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+                }
+            }
+            predict(Path::new(config), Path::new(input), checkpoint_path, output)
+        }
+        _ => Err(String::from(USAGE)),
+    }
 }
 
 fn main() {
-    _check_operation_double_variable();
-    // _test_basic_add_multiply();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("rust-ml: {}", e);
+        process::exit(1);
+    }
 }
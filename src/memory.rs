@@ -0,0 +1,67 @@
+// Memory held by tensors on this thread: how many are alive, how many bytes their data,
+// gradients and saved backward values take, and the peak since the last reset. A live count
+// that keeps growing from one training step to the next usually means graphs are being kept
+// alive, e.g. by storing a loss tensor instead of `loss.item()`.
+//
+//     memory::reset_peak();
+//     let loss = train_step();
+//     let stats = memory::stats();
+//
+// Only changes made through the library (ops, backward, zero_grad) are counted, a gradient
+// assigned by hand is picked up the next time the library updates that tensor's gradient.
+
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub live_tensors: usize,
+    pub bytes: usize,
+    // Highest `bytes` since the last reset_peak
+    pub peak_bytes: usize,
+}
+
+thread_local! {
+    static STATS: Cell<MemoryStats> = const {
+        Cell::new(MemoryStats {
+            live_tensors: 0,
+            bytes: 0,
+            peak_bytes: 0,
+        })
+    };
+}
+
+pub fn stats() -> MemoryStats {
+    STATS.with(Cell::get)
+}
+
+// Start a new peak from the current usage, e.g. at the start of every step
+pub fn reset_peak() {
+    update(|stats| stats.peak_bytes = stats.bytes);
+}
+
+fn update(f: impl FnOnce(&mut MemoryStats)) {
+    STATS.with(|cell| {
+        let mut stats = cell.get();
+        f(&mut stats);
+        stats.peak_bytes = stats.peak_bytes.max(stats.bytes);
+        cell.set(stats);
+    });
+}
+
+pub(crate) fn track_new(bytes: usize) {
+    update(|stats| {
+        stats.live_tensors += 1;
+        stats.bytes += bytes;
+    });
+}
+
+pub(crate) fn track_resize(old_bytes: usize, new_bytes: usize) {
+    update(|stats| stats.bytes = stats.bytes - old_bytes + new_bytes);
+}
+
+pub(crate) fn track_drop(bytes: usize) {
+    update(|stats| {
+        stats.live_tensors -= 1;
+        stats.bytes -= bytes;
+    });
+}
@@ -0,0 +1,620 @@
+// Classification metrics on prediction and label tensors. Predictions are [N, C] scores
+// (logits or probabilities, the highest wins) or, for binary problems, [N] / [N, 1]
+// probabilities of the positive class thresholded at 0.5. Labels are [N] / [N, 1] class ids.
+//
+// The functions score a single batch. For a whole epoch, `update` the matching accumulator
+// with every batch and read `value` at the end:
+//
+//     let mut acc = Accuracy::default();
+//     for (inputs, labels) in &val_loader {
+//         acc.update(&model.forward(&inputs), &labels);
+//     }
+//     println!("accuracy {}", acc.value());
+//
+// Precision, recall and F1 are macro-averaged over the classes that occur in either the
+// predictions or the labels.
+
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, Axis};
+use std::fmt;
+
+// A metric accumulated batch by batch
+pub trait Metric {
+    fn update(&mut self, pred: &Tensor, labels: &Tensor);
+
+    // NaN before any samples were seen
+    fn value(&self) -> f32;
+
+    fn reset(&mut self);
+}
+
+pub fn accuracy(pred: &Tensor, labels: &Tensor) -> f32 {
+    one_shot(Accuracy::default(), pred, labels)
+}
+
+// Fraction of samples whose label is among the `k` highest scores
+pub fn top_k_accuracy(pred: &Tensor, labels: &Tensor, k: usize) -> f32 {
+    one_shot(TopKAccuracy::new(k), pred, labels)
+}
+
+pub fn precision(pred: &Tensor, labels: &Tensor) -> f32 {
+    one_shot(Precision::default(), pred, labels)
+}
+
+pub fn recall(pred: &Tensor, labels: &Tensor) -> f32 {
+    one_shot(Recall::default(), pred, labels)
+}
+
+pub fn f1(pred: &Tensor, labels: &Tensor) -> f32 {
+    one_shot(F1::default(), pred, labels)
+}
+
+// Counts with labels along rows and predicted classes along columns
+pub fn confusion_matrix(pred: &Tensor, labels: &Tensor, num_classes: usize) -> ConfusionMatrix {
+    let mut matrix = ConfusionMatrix::new(num_classes);
+    matrix.update(pred, labels);
+    matrix
+}
+
+// Area under the ROC curve of [N] / [N, 1] scores against 0/1 labels, ties count half
+pub fn auc(scores: &Tensor, labels: &Tensor) -> f32 {
+    one_shot(Auc::default(), scores, labels)
+}
+
+fn one_shot(mut metric: impl Metric, pred: &Tensor, labels: &Tensor) -> f32 {
+    metric.update(pred, labels);
+    metric.value()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Accuracy {
+    correct: usize,
+    total: usize,
+}
+
+impl Metric for Accuracy {
+    fn update(&mut self, pred: &Tensor, labels: &Tensor) {
+        let classes = predicted_classes(pred);
+        let labels = class_ids(labels, classes.len());
+        self.correct += classes.iter().zip(&labels).filter(|(p, l)| p == l).count();
+        self.total += labels.len();
+    }
+
+    fn value(&self) -> f32 {
+        ratio(self.correct, self.total)
+    }
+
+    fn reset(&mut self) {
+        *self = Accuracy::default();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TopKAccuracy {
+    k: usize,
+    correct: usize,
+    total: usize,
+}
+
+impl TopKAccuracy {
+    pub fn new(k: usize) -> TopKAccuracy {
+        assert!(k > 0, "top_k_accuracy needs k > 0");
+        TopKAccuracy {
+            k,
+            correct: 0,
+            total: 0,
+        }
+    }
+}
+
+impl Metric for TopKAccuracy {
+    fn update(&mut self, pred: &Tensor, labels: &Tensor) {
+        let scores = scores_2d(pred, "top_k_accuracy");
+        let labels = class_ids(labels, scores.len_of(Axis(0)));
+        for (row, &label) in scores.axis_iter(Axis(0)).zip(&labels) {
+            // Rank of the label's score, ties resolved in the label's favour
+            let score = row.get(label).copied().unwrap_or(f32::NEG_INFINITY);
+            let higher = row.iter().filter(|&&s| s > score).count();
+            if higher < self.k && label < row.len() {
+                self.correct += 1;
+            }
+        }
+        self.total += labels.len();
+    }
+
+    fn value(&self) -> f32 {
+        ratio(self.correct, self.total)
+    }
+
+    fn reset(&mut self) {
+        self.correct = 0;
+        self.total = 0;
+    }
+}
+
+// Grows to fit the largest class seen, `new` only sets the minimum size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    // counts[label][predicted]
+    counts: Vec<Vec<usize>>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(num_classes: usize) -> ConfusionMatrix {
+        ConfusionMatrix {
+            counts: vec![vec![0; num_classes]; num_classes],
+        }
+    }
+
+    pub fn update(&mut self, pred: &Tensor, labels: &Tensor) {
+        let classes = predicted_classes(pred);
+        let labels = class_ids(labels, classes.len());
+        for (&p, &l) in classes.iter().zip(&labels) {
+            let needed = p.max(l) + 1;
+            if needed > self.counts.len() {
+                for row in &mut self.counts {
+                    row.resize(needed, 0);
+                }
+                self.counts.resize(needed, vec![0; needed]);
+            }
+            self.counts[l][p] += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for row in &mut self.counts {
+            row.fill(0);
+        }
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn counts(&self) -> &[Vec<usize>] {
+        &self.counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        let correct = (0..self.num_classes()).map(|c| self.counts[c][c]).sum();
+        ratio(correct, self.total())
+    }
+
+    // Of the samples predicted as `class`, the fraction that are
+    pub fn precision(&self, class: usize) -> f32 {
+        ratio(self.counts[class][class], self.column_sum(class))
+    }
+
+    // Of the samples labelled `class`, the fraction predicted as such
+    pub fn recall(&self, class: usize) -> f32 {
+        ratio(self.counts[class][class], self.counts[class].iter().sum())
+    }
+
+    pub fn f1(&self, class: usize) -> f32 {
+        // 2tp / (2tp + fp + fn), which stays defined when precision or recall isn't
+        let tp = self.counts[class][class];
+        let predicted = self.column_sum(class);
+        let actual: usize = self.counts[class].iter().sum();
+        ratio(2 * tp, predicted + actual)
+    }
+
+    pub fn macro_precision(&self) -> f32 {
+        self.macro_average(|c| self.precision(c))
+    }
+
+    pub fn macro_recall(&self) -> f32 {
+        self.macro_average(|c| self.recall(c))
+    }
+
+    pub fn macro_f1(&self) -> f32 {
+        self.macro_average(|c| self.f1(c))
+    }
+
+    fn column_sum(&self, class: usize) -> usize {
+        self.counts.iter().map(|row| row[class]).sum()
+    }
+
+    // Mean over the classes that were predicted or labelled at least once, a class never
+    // predicted counts as precision 0
+    fn macro_average(&self, metric: impl Fn(usize) -> f32) -> f32 {
+        let present: Vec<usize> = (0..self.num_classes())
+            .filter(|&c| self.column_sum(c) + self.counts[c].iter().sum::<usize>() > 0)
+            .collect();
+        if present.is_empty() {
+            return f32::NAN;
+        }
+        let sum: f32 = present
+            .iter()
+            .map(|&c| metric(c))
+            .map(|v| if v.is_nan() { 0.0 } else { v })
+            .sum();
+        sum / present.len() as f32
+    }
+
+    // Per-class precision, recall and F1 with averages, like scikit-learn's
+    // classification_report
+    pub fn report(&self) -> ClassificationReport {
+        let classes = (0..self.num_classes())
+            .map(|c| ClassScores {
+                name: c.to_string(),
+                precision: self.precision(c),
+                recall: self.recall(c),
+                f1: self.f1(c),
+                support: self.counts[c].iter().sum(),
+            })
+            .collect();
+        ClassificationReport {
+            classes,
+            accuracy: self.accuracy(),
+        }
+    }
+
+    // The matrix as a text table with `class_names` on the rows and columns, class ids when
+    // empty. Display renders it with class ids.
+    pub fn render(&self, class_names: &[&str]) -> String {
+        let names = self.class_names(class_names);
+        let width = names
+            .iter()
+            .map(String::len)
+            .chain(self.counts.iter().flatten().map(|n| n.to_string().len()))
+            .max()
+            .unwrap_or(1)
+            .max(4);
+        let label_width = names.iter().map(String::len).max().unwrap_or(0).max(5);
+        let mut out = format!("{:>label_width$}  predicted\n{:>label_width$}", "", "label");
+        for name in &names {
+            out.push_str(&format!("  {:>width$}", name));
+        }
+        for (name, row) in names.iter().zip(&self.counts) {
+            out.push_str(&format!("\n{:>label_width$}", name));
+            for count in row {
+                out.push_str(&format!("  {:>width$}", count));
+            }
+        }
+        out
+    }
+
+    // One row per label and one column per predicted class, headed by `class_names` (class
+    // ids when empty), for spreadsheets and plotting scripts
+    pub fn to_csv(&self, class_names: &[&str]) -> String {
+        let names = self.class_names(class_names);
+        let mut out = format!("label,{}\n", names.join(","));
+        for (name, row) in names.iter().zip(&self.counts) {
+            let counts: Vec<String> = row.iter().map(usize::to_string).collect();
+            out.push_str(&format!("{},{}\n", name, counts.join(",")));
+        }
+        out
+    }
+
+    fn class_names(&self, class_names: &[&str]) -> Vec<String> {
+        if class_names.is_empty() {
+            return (0..self.num_classes()).map(|c| c.to_string()).collect();
+        }
+        assert!(
+            class_names.len() == self.num_classes(),
+            "confusion matrix has {} classes, got {} class names",
+            self.num_classes(),
+            class_names.len()
+        );
+        class_names.iter().map(|name| name.to_string()).collect()
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&[]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassScores {
+    pub name: String,
+    // NaN when the class was never predicted (precision) or never labelled (recall)
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    // Samples labelled with the class
+    pub support: usize,
+}
+
+// See ConfusionMatrix::report. Display prints a table:
+//
+//                   precision     recall         f1  support
+//     cat              0.5000     0.5000     0.5000        2
+//     dog              0.6667     0.6667     0.6667        3
+//
+//     accuracy                               0.6000        5
+//     macro avg        0.5833     0.5833     0.5833        5
+//     weighted avg     0.6000     0.6000     0.6000        5
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassificationReport {
+    pub classes: Vec<ClassScores>,
+    pub accuracy: f32,
+}
+
+impl ClassificationReport {
+    // Name the classes in order instead of by id
+    pub fn with_names(mut self, class_names: &[&str]) -> Self {
+        assert!(
+            class_names.len() == self.classes.len(),
+            "report has {} classes, got {} class names",
+            self.classes.len(),
+            class_names.len()
+        );
+        for (class, name) in self.classes.iter_mut().zip(class_names) {
+            class.name = name.to_string();
+        }
+        self
+    }
+
+    pub fn support(&self) -> usize {
+        self.classes.iter().map(|class| class.support).sum()
+    }
+
+    // Unweighted mean (precision, recall, f1) over the classes predicted or labelled at least
+    // once, as ConfusionMatrix::macro_f1 and friends
+    pub fn macro_avg(&self) -> (f32, f32, f32) {
+        self.average(|class| {
+            let absent = class.precision.is_nan() && class.recall.is_nan();
+            if absent {
+                0.0
+            } else {
+                1.0
+            }
+        })
+    }
+
+    // Mean (precision, recall, f1) weighted by support
+    pub fn weighted_avg(&self) -> (f32, f32, f32) {
+        self.average(|class| class.support as f32)
+    }
+
+    // precision, recall, f1 and support per class, then the accuracy and averages as rows
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("class,precision,recall,f1,support\n");
+        for class in &self.classes {
+            out.push_str(&format!(
+                "{},{},{},{},{}\n",
+                class.name, class.precision, class.recall, class.f1, class.support
+            ));
+        }
+        out.push_str(&format!(
+            "accuracy,,,{},{}\n",
+            self.accuracy,
+            self.support()
+        ));
+        for (name, (p, r, f1)) in [
+            ("macro avg", self.macro_avg()),
+            ("weighted avg", self.weighted_avg()),
+        ] {
+            out.push_str(&format!("{},{},{},{},{}\n", name, p, r, f1, self.support()));
+        }
+        out
+    }
+
+    fn average(&self, weight: impl Fn(&ClassScores) -> f32) -> (f32, f32, f32) {
+        let total: f32 = self.classes.iter().map(&weight).sum();
+        if total == 0.0 {
+            return (f32::NAN, f32::NAN, f32::NAN);
+        }
+        let mean = |score: fn(&ClassScores) -> f32| {
+            self.classes
+                .iter()
+                .map(|class| {
+                    let value = score(class);
+                    weight(class) * if value.is_nan() { 0.0 } else { value }
+                })
+                .sum::<f32>()
+                / total
+        };
+        (
+            mean(|class| class.precision),
+            mean(|class| class.recall),
+            mean(|class| class.f1),
+        )
+    }
+}
+
+impl fmt::Display for ClassificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .classes
+            .iter()
+            .map(|class| class.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("weighted avg".len());
+        writeln!(
+            f,
+            "{:width$}  {:>9}  {:>9}  {:>9}  {:>7}",
+            "", "precision", "recall", "f1", "support"
+        )?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "{:width$}  {:>9.4}  {:>9.4}  {:>9.4}  {:>7}",
+                class.name, class.precision, class.recall, class.f1, class.support
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:width$}  {:>9}  {:>9}  {:>9.4}  {:>7}",
+            "accuracy",
+            "",
+            "",
+            self.accuracy,
+            self.support()
+        )?;
+        let (p, r, f1) = self.macro_avg();
+        writeln!(
+            f,
+            "{:width$}  {:>9.4}  {:>9.4}  {:>9.4}  {:>7}",
+            "macro avg",
+            p,
+            r,
+            f1,
+            self.support()
+        )?;
+        let (p, r, f1) = self.weighted_avg();
+        write!(
+            f,
+            "{:width$}  {:>9.4}  {:>9.4}  {:>9.4}  {:>7}",
+            "weighted avg",
+            p,
+            r,
+            f1,
+            self.support()
+        )
+    }
+}
+
+macro_rules! confusion_metric {
+    ($name:ident, $method:ident) => {
+        #[derive(Debug, Clone)]
+        pub struct $name(ConfusionMatrix);
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name(ConfusionMatrix::new(0))
+            }
+        }
+
+        impl Metric for $name {
+            fn update(&mut self, pred: &Tensor, labels: &Tensor) {
+                self.0.update(pred, labels);
+            }
+
+            fn value(&self) -> f32 {
+                self.0.$method()
+            }
+
+            fn reset(&mut self) {
+                self.0.reset();
+            }
+        }
+    };
+}
+
+confusion_metric!(Precision, macro_precision);
+confusion_metric!(Recall, macro_recall);
+confusion_metric!(F1, macro_f1);
+
+// Keeps every score, the ROC curve needs them all ranked at the end
+#[derive(Debug, Clone, Default)]
+pub struct Auc {
+    scores: Vec<(f32, bool)>,
+}
+
+impl Metric for Auc {
+    fn update(&mut self, scores: &Tensor, labels: &Tensor) {
+        let data = scores.borrow().data.clone();
+        assert!(
+            data.ndim() == 1 || (data.ndim() == 2 && data.shape()[1] == 1),
+            "auc expects [N] or [N, 1] scores, got shape {:?}",
+            data.shape()
+        );
+        let labels = class_ids(labels, data.len());
+        assert!(labels.iter().all(|&l| l <= 1), "auc expects 0/1 labels");
+        self.scores
+            .extend(data.iter().zip(&labels).map(|(&s, &l)| (s, l == 1)));
+    }
+
+    // Mann-Whitney U: the probability that a random positive scores above a random negative
+    fn value(&self) -> f32 {
+        let positives = self.scores.iter().filter(|(_, p)| *p).count();
+        let negatives = self.scores.len() - positives;
+        if positives == 0 || negatives == 0 {
+            return f32::NAN;
+        }
+        let mut sorted = self.scores.clone();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // Sum of the positives' ranks (1-based), tied scores share their average rank
+        let mut rank_sum = 0.0f64;
+        let mut i = 0;
+        while i < sorted.len() {
+            let mut j = i;
+            while j < sorted.len() && sorted[j].0 == sorted[i].0 {
+                j += 1;
+            }
+            let average_rank = (i + j + 1) as f64 / 2.0;
+            let tied_positives = sorted[i..j].iter().filter(|(_, p)| *p).count();
+            rank_sum += average_rank * tied_positives as f64;
+            i = j;
+        }
+        let (p, n) = (positives as f64, negatives as f64);
+        ((rank_sum - p * (p + 1.0) / 2.0) / (p * n)) as f32
+    }
+
+    fn reset(&mut self) {
+        self.scores.clear();
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    if denominator == 0 {
+        f32::NAN
+    } else {
+        numerator as f32 / denominator as f32
+    }
+}
+
+fn scores_2d(pred: &Tensor, metric: &str) -> ndarray::Array2<f32> {
+    let data = pred.borrow().data.clone();
+    let shape = data.shape().to_vec();
+    data.into_dimensionality()
+        .unwrap_or_else(|_| panic!("{} expects [N, C] scores, got shape {:?}", metric, shape))
+}
+
+// Argmax of [N, C] scores, or [N] / [N, 1] probabilities thresholded at 0.5
+fn predicted_classes(pred: &Tensor) -> Vec<usize> {
+    let data: ArrayD<f32> = pred.borrow().data.clone();
+    match data.shape() {
+        [_] | [_, 1] => data.iter().map(|&p| (p >= 0.5) as usize).collect(),
+        [_, _] => data
+            .axis_iter(Axis(0))
+            .map(|row| {
+                // First of the highest scores, NaN never wins
+                row.iter()
+                    .enumerate()
+                    .fold((0, f32::NEG_INFINITY), |(best, max), (i, &s)| {
+                        if s > max {
+                            (i, s)
+                        } else {
+                            (best, max)
+                        }
+                    })
+                    .0
+            })
+            .collect(),
+        shape => panic!(
+            "metrics expect [N, C] scores or [N] probabilities, got shape {:?}",
+            shape
+        ),
+    }
+}
+
+fn class_ids(labels: &Tensor, n: usize) -> Vec<usize> {
+    let data = labels.borrow().data.clone();
+    assert!(
+        matches!(data.shape(), [m] | [m, 1] if *m == n),
+        "metrics expect [{}] or [{}, 1] labels, got shape {:?}",
+        n,
+        n,
+        data.shape()
+    );
+    data.iter()
+        .map(|&l| {
+            assert!(
+                l >= 0.0 && l.fract() == 0.0,
+                "labels must be non-negative class ids, got {}",
+                l
+            );
+            l as usize
+        })
+        .collect()
+}
@@ -0,0 +1,142 @@
+// Named dimensions: a tensor can carry a name per axis, e.g. ["batch", "seq", "dim"], which
+// ops check and pass on. Two tensors of the same sizes with their axes swapped then fail at
+// the op that mixes them up instead of silently producing garbage, and reductions can say
+// which axis they mean.
+//
+//     let x = Tensor::from(data).with_dim_names(&["batch", "seq", "dim"]);
+//     let pooled = x.sum_dim("seq"); // [batch, dim]
+//
+// "_" leaves an axis unnamed, it lines up with any name. Elementwise ops, softmax and
+// gather_rows keep the names, `+` and `*` merge them aligned from the last axis like
+// broadcasting, matmul checks the contracted axes and keeps the outer ones, t() swaps them,
+// row() drops the first and sum_axis the summed one. Other ops, and reshape to another shape,
+// return unnamed tensors. Tensors without names never fail these checks.
+
+use crate::error::TensorError;
+use crate::tensor::Tensor;
+use std::panic::Location;
+
+pub const UNNAMED: &str = "_";
+
+impl Tensor {
+    // Panics unless there is one name per axis
+    #[track_caller]
+    pub fn with_dim_names(self, names: &[&'static str]) -> Tensor {
+        self.set_dim_names(names);
+        self
+    }
+
+    #[track_caller]
+    pub fn set_dim_names(&self, names: &[&'static str]) {
+        let shape = self.shape();
+        assert_eq!(
+            names.len(),
+            shape.len(),
+            "{} dim names {:?} for a tensor of shape {:?}",
+            names.len(),
+            names,
+            shape
+        );
+        self.borrow_mut().dim_names = Some(names.to_vec());
+    }
+
+    pub fn dim_names(&self) -> Option<Vec<&'static str>> {
+        self.borrow().dim_names.clone()
+    }
+
+    // Axis of the dim called `name`
+    pub fn axis_of(&self, name: &str) -> Result<usize, TensorError> {
+        let names = self.dim_names().unwrap_or_default();
+        names
+            .iter()
+            .position(|&n| n == name && n != UNNAMED)
+            .ok_or_else(|| TensorError::UnknownDimName {
+                name: name.to_string(),
+                names,
+            })
+    }
+
+    // sum_axis over the dim called `name`
+    #[track_caller]
+    pub fn sum_dim(&self, name: &str) -> Tensor {
+        self.try_sum_dim(name).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_sum_dim(&self, name: &str) -> Result<Tensor, TensorError> {
+        self.try_sum_axis(self.axis_of(name)?)
+    }
+
+    // Output names of an op, set on both the inference and the recording path
+    pub(crate) fn with_dims(self, names: Option<Vec<&'static str>>) -> Tensor {
+        if names.is_some() {
+            self.borrow_mut().dim_names = names;
+        }
+        self
+    }
+}
+
+// Names of `a + b` and friends, aligned from the last axis. None when neither has names.
+#[track_caller]
+pub(crate) fn broadcast(
+    op: &'static str,
+    lhs: &Tensor,
+    rhs: &Tensor,
+) -> Result<Option<Vec<&'static str>>, TensorError> {
+    let (lhs, rhs) = (lhs.dim_names(), rhs.dim_names());
+    if lhs.is_none() && rhs.is_none() {
+        return Ok(None);
+    }
+    let (lhs, rhs) = (lhs.unwrap_or_default(), rhs.unwrap_or_default());
+    let name = |names: &[&'static str], i: usize| {
+        if i < names.len() {
+            names[names.len() - 1 - i]
+        } else {
+            UNNAMED
+        }
+    };
+    let mut names = Vec::with_capacity(lhs.len().max(rhs.len()));
+    for i in 0..lhs.len().max(rhs.len()) {
+        match (name(&lhs, i), name(&rhs, i)) {
+            (a, UNNAMED) => names.push(a),
+            (UNNAMED, b) => names.push(b),
+            (a, b) if a == b => names.push(a),
+            _ => return Err(mismatch(op, lhs, rhs)),
+        }
+    }
+    names.reverse();
+    Ok(Some(names))
+}
+
+// Names of [n, k] x [k, m]: the k axes have to agree
+#[track_caller]
+pub(crate) fn matmul(lhs: &Tensor, rhs: &Tensor) -> Result<Option<Vec<&'static str>>, TensorError> {
+    let (lhs_names, rhs_names) = (lhs.dim_names(), rhs.dim_names());
+    if lhs_names.is_none() && rhs_names.is_none() {
+        return Ok(None);
+    }
+    let unnamed = || vec![UNNAMED; 2];
+    let (lhs_names, rhs_names) = (
+        lhs_names.unwrap_or_else(unnamed),
+        rhs_names.unwrap_or_else(unnamed),
+    );
+    let (inner_lhs, inner_rhs) = (lhs_names[1], rhs_names[0]);
+    if inner_lhs != UNNAMED && inner_rhs != UNNAMED && inner_lhs != inner_rhs {
+        return Err(mismatch("matmul", lhs_names, rhs_names));
+    }
+    Ok(Some(vec![lhs_names[0], rhs_names[1]]))
+}
+
+#[track_caller]
+fn mismatch(op: &'static str, lhs: Vec<&'static str>, rhs: Vec<&'static str>) -> TensorError {
+    TensorError::DimNameMismatch {
+        op,
+        lhs,
+        rhs,
+        location: if cfg!(debug_assertions) {
+            Some(Location::caller())
+        } else {
+            None
+        },
+    }
+}
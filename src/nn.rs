@@ -0,0 +1,1367 @@
+// Layers and the Module trait tying their parameters together
+
+use crate::error::TensorError;
+use crate::inference;
+use crate::random::with_rng;
+use crate::shape::{self, ShapedLayer};
+use crate::spatial::{ConvOptions, Interpolation};
+use crate::tensor::{constant, Tensor};
+use ndarray::{arr0, Array2, ArrayD, Axis, Ix2, IxDyn};
+use rand::Rng;
+use rand_distr::StandardNormal;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// Parameter values by name, a snapshot that doesn't share data with the module
+pub type StateDict = BTreeMap<String, ArrayD<f32>>;
+
+pub trait Module {
+    fn forward(&self, input: &Tensor) -> Tensor;
+
+    // Trainable tensors with stable dotted names, e.g. "0.weight" inside a Sequential
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        Vec::new()
+    }
+
+    fn parameters(&self) -> Vec<Tensor> {
+        self.named_parameters()
+            .into_iter()
+            .map(|(_, param)| param)
+            .collect()
+    }
+
+    // forward in inference mode (see the inference module), without recording a graph
+    fn predict(&self, input: &Tensor) -> Tensor {
+        inference::inference_mode(|| self.forward(input))
+    }
+
+    // Turn on or off the state some layers keep between forward calls for incremental decoding
+    // (see CausalSelfAttention), either way it starts out empty. Containers pass it on to their
+    // children.
+    fn set_cache(&self, _enabled: bool) {}
+
+    // Call `hook` with the input and output of every forward of this module from now on, e.g.
+    // to collect activation statistics or the features of an intermediate layer
+    // (`model.layers[2]`). The module keeps the hook, see forward_hooks. Panics for modules
+    // without hooks of their own, like ReLU: wrap those in Hooked.
+    fn register_forward_hook(&self, hook: ForwardHook) -> HookHandle {
+        match self.forward_hooks() {
+            Some(hooks) => hooks.add(hook),
+            None => panic!(
+                "{} keeps no forward hooks, wrap it in nn::Hooked to hook it",
+                self.type_name()
+            ),
+        }
+    }
+
+    // The hooks register_forward_hook adds to, which forward runs. None by default, layers
+    // with a ForwardHooks field return it and wrappers their inner module's.
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        None
+    }
+
+    fn zero_grad(&self) {
+        for param in self.parameters() {
+            param.zero_grad();
+        }
+    }
+
+    fn state_dict(&self) -> StateDict {
+        self.named_parameters()
+            .into_iter()
+            .map(|(name, param)| (name, param.borrow().data.clone()))
+            .collect()
+    }
+
+    // Stop training the parameters: backward leaves them without a gradient, so optimizers
+    // skip them
+    fn freeze(&self) {
+        for param in self.parameters() {
+            param.set_requires_grad(false);
+        }
+    }
+
+    fn unfreeze(&self) {
+        for param in self.parameters() {
+            param.set_requires_grad(true);
+        }
+    }
+
+    // Short type name for summaries, e.g. "Linear"
+    fn type_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        // Drop the module path, keeping generic arguments intact
+        let end = name.find('<').unwrap_or(name.len());
+        match name[..end].rfind("::") {
+            Some(i) => &name[i + 2..],
+            None => name,
+        }
+    }
+
+    // forward that also hands every layer it runs to `visit`, after its forward. Layers are
+    // leaves by default; containers override this to call it on their children with dotted
+    // names.
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        let output = self.forward(input);
+        visit(TracedLayer {
+            name,
+            kind: self.type_name(),
+            parameters: self.parameters(),
+            output: &output,
+        });
+        output
+    }
+
+    // Shape of forward's output for inputs of `input_shape`, from shapes alone (see the shape
+    // module). Layers override it with their shape rule, the default runs forward on zeros in
+    // inference mode.
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let input = Tensor::from(ArrayD::zeros(IxDyn(input_shape)));
+        Ok(inference::inference_mode(|| self.forward(&input)).shape())
+    }
+
+    // output_shape that also hands every layer to `visit`, the way forward_traced does
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        let output_shape = self.output_shape(input_shape)?;
+        visit(ShapedLayer {
+            name,
+            kind: self.type_name(),
+            parameters: self.parameters(),
+            input_shape,
+            output_shape: &output_shape,
+        });
+        Ok(output_shape)
+    }
+
+    // Layers, output shapes and parameter counts for inputs of `input_shape` (batch axis
+    // included) from shape inference, print it with `println!("{}", ..)`. Panics on a shape
+    // mismatch, see shape::symbolic_shapes to get it as an error.
+    fn summary(&self, input_shape: &[usize]) -> Summary {
+        let mut layers = Vec::new();
+        let output_shape = self.output_shape_traced(input_shape, "", &mut |layer| {
+            let (mut trainable, mut frozen) = (0, 0);
+            for param in &layer.parameters {
+                if param.requires_grad() {
+                    trainable += param.numel();
+                } else {
+                    frozen += param.numel();
+                }
+            }
+            layers.push(LayerSummary {
+                name: layer.name.to_string(),
+                kind: layer.kind.to_string(),
+                output_shape: layer.output_shape.to_vec(),
+                trainable,
+                frozen,
+            });
+        });
+        let output_shape = output_shape.unwrap_or_else(|e| panic!("{}", e));
+        // Counted over the unique parameters, a layer used twice shares its weights
+        let mut seen = std::collections::HashSet::new();
+        let (mut trainable, mut frozen) = (0, 0);
+        for param in self.parameters() {
+            if !seen.insert(param.borrow()._uuid) {
+                continue;
+            }
+            if param.requires_grad() {
+                trainable += param.numel();
+            } else {
+                frozen += param.numel();
+            }
+        }
+        Summary {
+            input_shape: input_shape.to_vec(),
+            output_shape,
+            layers,
+            trainable,
+            frozen,
+        }
+    }
+
+    // Copy values into the existing parameters, so tensors handed out by parameters() (e.g.
+    // to an optimizer) see the new values. Extra entries in `state` are ignored.
+    fn load_state_dict(&self, state: &StateDict) -> Result<(), TensorError> {
+        let params = self.named_parameters();
+        // Check everything before touching any parameter, a failed load leaves the module as is
+        for (name, param) in &params {
+            let value = state
+                .get(name)
+                .ok_or_else(|| TensorError::MissingParameter { name: name.clone() })?;
+            if value.shape() != param.borrow().data.shape() {
+                return Err(TensorError::ShapeMismatch {
+                    op: "load_state_dict",
+                    lhs: param.shape(),
+                    rhs: value.shape().to_vec(),
+                    operands: None,
+                    location: None,
+                });
+            }
+        }
+        for (name, param) in params {
+            param.borrow_mut().data = state[&name].clone();
+        }
+        Ok(())
+    }
+}
+
+// A layer reached by Module::forward_traced
+pub struct TracedLayer<'a> {
+    // Dotted path in the module tree, empty for the root
+    pub name: &'a str,
+    pub kind: &'static str,
+    pub parameters: Vec<Tensor>,
+    pub output: &'a Tensor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerSummary {
+    // Dotted path in the module tree, empty for the root
+    pub name: String,
+    pub kind: String,
+    pub output_shape: Vec<usize>,
+    pub trainable: usize,
+    pub frozen: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub input_shape: Vec<usize>,
+    pub output_shape: Vec<usize>,
+    pub layers: Vec<LayerSummary>,
+    pub trainable: usize,
+    pub frozen: usize,
+}
+
+impl Summary {
+    pub fn total(&self) -> usize {
+        self.trainable + self.frozen
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows: Vec<[String; 3]> = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let name = if layer.name.is_empty() {
+                    layer.kind.clone()
+                } else {
+                    format!("{} ({})", layer.name, layer.kind)
+                };
+                let params = if layer.frozen > 0 {
+                    format!(
+                        "{} ({} frozen)",
+                        layer.trainable + layer.frozen,
+                        layer.frozen
+                    )
+                } else {
+                    layer.trainable.to_string()
+                };
+                [name, format!("{:?}", layer.output_shape), params]
+            })
+            .collect();
+        let headers = ["Layer (type)", "Output shape", "Params"];
+        let widths: Vec<usize> = (0..3)
+            .map(|i| {
+                rows.iter()
+                    .map(|row| row[i].len())
+                    .chain([headers[i].len()])
+                    .max()
+                    .unwrap()
+            })
+            .collect();
+        let rule = "-".repeat(widths.iter().sum::<usize>() + 4);
+        writeln!(
+            f,
+            "{:<w0$}  {:<w1$}  {:>w2$}",
+            headers[0],
+            headers[1],
+            headers[2],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        )?;
+        writeln!(f, "{}", rule)?;
+        for row in &rows {
+            writeln!(
+                f,
+                "{:<w0$}  {:<w1$}  {:>w2$}",
+                row[0],
+                row[1],
+                row[2],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )?;
+        }
+        writeln!(f, "{}", rule)?;
+        writeln!(f, "Input shape: {:?}", self.input_shape)?;
+        writeln!(f, "Output shape: {:?}", self.output_shape)?;
+        writeln!(f, "Total params: {}", self.total())?;
+        writeln!(f, "Trainable params: {}", self.trainable)?;
+        write!(f, "Frozen params: {}", self.frozen)
+    }
+}
+
+// See Module::register_forward_hook, called with (input, output)
+pub type ForwardHook = Box<dyn FnMut(&Tensor, &Tensor)>;
+
+// The forward hooks of one module, owned by it so they move and drop with it. Layers keep one
+// as a field, return it from Module::forward_hooks and end their forward with
+// `self.hooks.run(input, out)`; custom modules can do the same or be wrapped in Hooked.
+#[derive(Default)]
+pub struct ForwardHooks(Rc<RefCell<HookList>>);
+
+#[derive(Default)]
+struct HookList {
+    next_id: usize,
+    hooks: Vec<(usize, ForwardHook)>,
+    // Removed while they were running, see ForwardHooks::run
+    removed: Vec<usize>,
+}
+
+impl ForwardHooks {
+    pub fn new() -> ForwardHooks {
+        ForwardHooks::default()
+    }
+
+    pub fn add(&self, hook: ForwardHook) -> HookHandle {
+        let mut list = self.0.borrow_mut();
+        let id = list.next_id;
+        list.next_id += 1;
+        list.hooks.push((id, hook));
+        HookHandle {
+            hooks: Rc::downgrade(&self.0),
+            id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Call every hook with `input` and `output`, then hand `output` back
+    pub fn run(&self, input: &Tensor, output: Tensor) -> Tensor {
+        // Taken out while they run, so a hook can run the module again or add and remove hooks
+        let mut running = std::mem::take(&mut self.0.borrow_mut().hooks);
+        if running.is_empty() {
+            return output;
+        }
+        for (_, hook) in &mut running {
+            hook(input, &output);
+        }
+        let mut list = self.0.borrow_mut();
+        let removed = std::mem::take(&mut list.removed);
+        running.retain(|(id, _)| !removed.contains(id));
+        let added = std::mem::replace(&mut list.hooks, running);
+        list.hooks.extend(added);
+        output
+    }
+}
+
+// Hooks belong to one module instance, a clone of the module starts without any
+impl Clone for ForwardHooks {
+    fn clone(&self) -> ForwardHooks {
+        ForwardHooks::default()
+    }
+}
+
+impl fmt::Debug for ForwardHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ForwardHooks({})", self.len())
+    }
+}
+
+// Returned by register_forward_hook. Dropping it keeps the hook, call remove to take it off.
+// Removing after the module is gone does nothing.
+#[derive(Debug)]
+pub struct HookHandle {
+    hooks: Weak<RefCell<HookList>>,
+    id: usize,
+}
+
+impl HookHandle {
+    pub fn remove(self) {
+        let Some(list) = self.hooks.upgrade() else {
+            return;
+        };
+        let mut list = list.borrow_mut();
+        let count = list.hooks.len();
+        list.hooks.retain(|(id, _)| *id != self.id);
+        // Not there while its module runs its hooks
+        if list.hooks.len() == count {
+            list.removed.push(self.id);
+        }
+    }
+}
+
+// Forward hooks for a module that doesn't keep its own, e.g. a stateless layer
+// (`Hooked::new(ReLU)`) or a custom module. Everything else passes through to `module`.
+pub struct Hooked<M> {
+    pub module: M,
+    hooks: ForwardHooks,
+}
+
+impl<M: Module> Hooked<M> {
+    pub fn new(module: M) -> Hooked<M> {
+        Hooked {
+            module,
+            hooks: ForwardHooks::new(),
+        }
+    }
+}
+
+impl<M: Module> Module for Hooked<M> {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.hooks.run(input, self.module.forward(input))
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.module.named_parameters()
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.module.type_name()
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        let output = self.module.forward_traced(input, name, visit);
+        self.hooks.run(input, output)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.module.output_shape(input_shape)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.module.output_shape_traced(input_shape, name, visit)
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        self.module.set_cache(enabled);
+    }
+}
+
+fn uniform(shape: &[usize], bound: f32) -> Tensor {
+    let data =
+        with_rng(|rng| ArrayD::from_shape_fn(IxDyn(shape), |_| rng.gen_range(-bound..=bound)));
+    Tensor::from(data)
+}
+
+// y = x W^T + b on [N, in] inputs. Weight is [out, in] like PyTorch, so state dicts map
+// one-to-one, and both are initialized uniform in +-1/sqrt(in)
+pub struct Linear {
+    pub weight: Tensor,
+    pub bias: Option<Tensor>,
+    pub hooks: ForwardHooks,
+}
+
+impl Linear {
+    pub fn new(in_features: usize, out_features: usize) -> Linear {
+        let bound = 1.0 / (in_features as f32).sqrt();
+        Linear {
+            weight: uniform(&[out_features, in_features], bound),
+            bias: Some(uniform(&[out_features], bound)),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn without_bias(in_features: usize, out_features: usize) -> Linear {
+        Linear {
+            bias: None,
+            ..Linear::new(in_features, out_features)
+        }
+    }
+}
+
+impl Module for Linear {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.matmul(&self.weight.t());
+        let out = match &self.bias {
+            Some(bias) => &out + bias,
+            None => out,
+        };
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let bias = self.bias.as_ref().map(|bias| bias.shape());
+        shape::linear(input_shape, &self.weight.shape(), bias.as_deref())
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        let mut params = vec![(String::from("weight"), self.weight.clone())];
+        if let Some(bias) = &self.bias {
+            params.push((String::from("bias"), bias.clone()));
+        }
+        params
+    }
+}
+
+// Lookup table of `num_embeddings` vectors of size `dim`, initialized N(0, 1). Inputs are ids
+// stored as f32 of any shape, the output adds a trailing axis of size `dim`. With `sparse` the
+// weight only gets gradient rows for the ids that were looked up, and optimizers only update
+// those rows, instead of a dense [num_embeddings, dim] gradient every step.
+pub struct Embedding {
+    pub weight: Tensor,
+    pub hooks: ForwardHooks,
+}
+
+impl Embedding {
+    pub fn new(num_embeddings: usize, dim: usize) -> Embedding {
+        let data = with_rng(|rng| {
+            ArrayD::from_shape_fn(IxDyn(&[num_embeddings, dim]), |_| {
+                rng.sample::<f32, _>(StandardNormal)
+            })
+        });
+        Embedding {
+            weight: Tensor::from(data),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn sparse(self, sparse: bool) -> Self {
+        self.weight.set_sparse(sparse);
+        self
+    }
+
+    // Vectors for the given ids, [ids.len(), dim]
+    pub fn lookup(&self, ids: &[usize]) -> Tensor {
+        self.weight.gather_rows(ids)
+    }
+}
+
+impl Module for Embedding {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let (ids, shape) = {
+            let data = &input.borrow().data;
+            let ids: Vec<usize> = data
+                .iter()
+                .map(|&id| {
+                    assert!(
+                        id >= 0.0 && id.fract() == 0.0,
+                        "Embedding expects non-negative integer ids, got {}",
+                        id
+                    );
+                    id as usize
+                })
+                .collect();
+            (ids, data.shape().to_vec())
+        };
+        let rows = self.lookup(&ids);
+        let out = if shape.len() == 1 {
+            rows
+        } else {
+            let dim = self.weight.shape()[1];
+            rows.reshape(&[shape, vec![dim]].concat())
+        };
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    // Any shape of ids, each becomes a vector
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        Ok([input_shape, &[self.weight.shape()[1]]].concat())
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        vec![(String::from("weight"), self.weight.clone())]
+    }
+}
+
+// Keys and values of the positions a CausalSelfAttention has seen so far, a row per position
+#[derive(Debug, Clone, PartialEq)]
+pub struct KvCache {
+    pub keys: Array2<f32>,
+    pub values: Array2<f32>,
+}
+
+impl KvCache {
+    fn new(dim: usize) -> KvCache {
+        KvCache {
+            keys: Array2::zeros((0, dim)),
+            values: Array2::zeros((0, dim)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.nrows()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Single-head causal self-attention on a [T, dim] sequence, position t attends to 0..=t.
+// With the KV cache on (set_cache(true)) the keys and values of past positions are kept between
+// calls and each call only takes the new positions, so generating a token costs one [1, T]
+// attention row instead of the whole [T, T] matrix again. Cached keys and values are plain
+// arrays, gradients don't reach past positions: the cache is meant for inference.
+pub struct CausalSelfAttention {
+    pub query: Linear,
+    pub key: Linear,
+    pub value: Linear,
+    pub output: Linear,
+    cache: RefCell<Option<KvCache>>,
+    hooks: ForwardHooks,
+}
+
+impl CausalSelfAttention {
+    pub fn new(dim: usize) -> CausalSelfAttention {
+        CausalSelfAttention {
+            query: Linear::new(dim, dim),
+            key: Linear::new(dim, dim),
+            value: Linear::new(dim, dim),
+            output: Linear::new(dim, dim),
+            cache: RefCell::new(None),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.query.weight.shape()[1]
+    }
+
+    // Cached keys and values, None while the cache is off
+    pub fn cache(&self) -> Option<KvCache> {
+        self.cache.borrow().clone()
+    }
+}
+
+impl Module for CausalSelfAttention {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let dim = self.dim();
+        let shape = input.shape();
+        assert!(
+            shape.len() == 2 && shape[1] == dim,
+            "CausalSelfAttention expects [T, {}] inputs, got {:?}",
+            dim,
+            shape
+        );
+        let query = self.query.forward(input);
+        let key = self.key.forward(input);
+        let value = self.value.forward(input);
+        // Past positions before the new ones, and keys and values covering both
+        let (past, keys, values) = match self.cache.borrow_mut().as_mut() {
+            None => (0, key, value),
+            Some(cache) => {
+                let past = cache.len();
+                for (cached, new) in [(&mut cache.keys, &key), (&mut cache.values, &value)] {
+                    let new = &new.borrow().data;
+                    cached
+                        .append(Axis(0), new.view().into_dimensionality::<Ix2>().unwrap())
+                        .unwrap();
+                }
+                let keys = constant(cache.keys.clone().into_dyn());
+                let values = constant(cache.values.clone().into_dyn());
+                (past, keys, values)
+            }
+        };
+        let total = past + shape[0];
+        let mask = ArrayD::from_shape_fn(IxDyn(&[shape[0], total]), |i| {
+            if i[1] > past + i[0] {
+                f32::NEG_INFINITY
+            } else {
+                0.0
+            }
+        });
+        let scale = constant(arr0(1.0 / (dim as f32).sqrt()).into_dyn());
+        let scores = &(&query.matmul(&keys.t()) * &scale) + &constant(mask);
+        let out = self.output.forward(&scores.softmax(1).matmul(&values));
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let dim = self.dim();
+        if input_shape.len() != 2 || input_shape[1] != dim {
+            return Err(shape::mismatch("CausalSelfAttention", input_shape, &[dim]));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        *self.cache.borrow_mut() = enabled.then(|| KvCache::new(self.dim()));
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        [
+            ("query", &self.query),
+            ("key", &self.key),
+            ("value", &self.value),
+            ("output", &self.output),
+        ]
+        .into_iter()
+        .flat_map(|(prefix, layer)| {
+            layer
+                .named_parameters()
+                .into_iter()
+                .map(move |(name, param)| (format!("{}.{}", prefix, name), param))
+        })
+        .collect()
+    }
+}
+
+// Convolution weight [out, in / groups, ...kernel] and bias [out], uniform in
+// +-1/sqrt(fan_in) like PyTorch
+fn conv_parameters(
+    in_channels: usize,
+    out_channels: usize,
+    kernel: &[usize],
+    groups: usize,
+) -> (Tensor, Tensor) {
+    assert!(
+        groups > 0 && in_channels.is_multiple_of(groups) && out_channels.is_multiple_of(groups),
+        "{} groups don't divide {} input and {} output channels",
+        groups,
+        in_channels,
+        out_channels
+    );
+    let fan_in = in_channels / groups * kernel.iter().product::<usize>();
+    let bound = 1.0 / (fan_in as f32).sqrt();
+    let shape = [&[out_channels, in_channels / groups], kernel].concat();
+    (uniform(&shape, bound), uniform(&[out_channels], bound))
+}
+
+// 1-D convolution over [N, in_channels, L] sequences, see ConvOptions
+pub struct Conv1d {
+    pub weight: Tensor,
+    pub bias: Option<Tensor>,
+    pub options: ConvOptions,
+    pub hooks: ForwardHooks,
+}
+
+impl Conv1d {
+    pub fn new(in_channels: usize, out_channels: usize, kernel_size: usize) -> Conv1d {
+        let (weight, bias) = conv_parameters(in_channels, out_channels, &[kernel_size], 1);
+        Conv1d {
+            weight,
+            bias: Some(bias),
+            options: ConvOptions::default(),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn without_bias(self) -> Self {
+        Conv1d { bias: None, ..self }
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.options.stride = stride;
+        self
+    }
+
+    pub fn padding(mut self, padding: usize) -> Self {
+        self.options.padding = padding;
+        self
+    }
+
+    pub fn dilation(mut self, dilation: usize) -> Self {
+        self.options.dilation = dilation;
+        self
+    }
+
+    // Reinitializes the weight (and bias) for the grouped shape
+    pub fn groups(mut self, groups: usize) -> Self {
+        let shape = self.weight.shape();
+        let (weight, bias) = conv_parameters(
+            shape[1] * self.options.groups,
+            shape[0],
+            &shape[2..],
+            groups,
+        );
+        self.weight = weight;
+        self.bias = self.bias.map(|_| bias);
+        self.options.groups = groups;
+        self
+    }
+}
+
+impl Module for Conv1d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.conv1d(&self.weight, self.bias.as_ref(), self.options);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let bias = self.bias.as_ref().map(|bias| bias.shape());
+        shape::conv(
+            input_shape,
+            &self.weight.shape(),
+            bias.as_deref(),
+            self.options,
+        )
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        let mut params = vec![(String::from("weight"), self.weight.clone())];
+        if let Some(bias) = &self.bias {
+            params.push((String::from("bias"), bias.clone()));
+        }
+        params
+    }
+}
+
+// 2-D convolution over [N, in_channels, H, W] images with a square kernel, see ConvOptions.
+// `Conv2d::depthwise(32, 3)` filters every channel on its own, MobileNet style.
+pub struct Conv2d {
+    pub weight: Tensor,
+    pub bias: Option<Tensor>,
+    pub options: ConvOptions,
+    pub hooks: ForwardHooks,
+}
+
+impl Conv2d {
+    pub fn new(in_channels: usize, out_channels: usize, kernel_size: usize) -> Conv2d {
+        let kernel = [kernel_size, kernel_size];
+        let (weight, bias) = conv_parameters(in_channels, out_channels, &kernel, 1);
+        Conv2d {
+            weight,
+            bias: Some(bias),
+            options: ConvOptions::default(),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    // groups == channels, one kernel per channel
+    pub fn depthwise(channels: usize, kernel_size: usize) -> Conv2d {
+        Conv2d::new(channels, channels, kernel_size).groups(channels)
+    }
+
+    pub fn without_bias(self) -> Self {
+        Conv2d { bias: None, ..self }
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.options.stride = stride;
+        self
+    }
+
+    pub fn padding(mut self, padding: usize) -> Self {
+        self.options.padding = padding;
+        self
+    }
+
+    pub fn dilation(mut self, dilation: usize) -> Self {
+        self.options.dilation = dilation;
+        self
+    }
+
+    // Reinitializes the weight (and bias) for the grouped shape
+    pub fn groups(mut self, groups: usize) -> Self {
+        let shape = self.weight.shape();
+        let (weight, bias) = conv_parameters(
+            shape[1] * self.options.groups,
+            shape[0],
+            &shape[2..],
+            groups,
+        );
+        self.weight = weight;
+        self.bias = self.bias.map(|_| bias);
+        self.options.groups = groups;
+        self
+    }
+}
+
+impl Module for Conv2d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.conv2d(&self.weight, self.bias.as_ref(), self.options);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let bias = self.bias.as_ref().map(|bias| bias.shape());
+        shape::conv(
+            input_shape,
+            &self.weight.shape(),
+            bias.as_deref(),
+            self.options,
+        )
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        let mut params = vec![(String::from("weight"), self.weight.clone())];
+        if let Some(bias) = &self.bias {
+            params.push((String::from("bias"), bias.clone()));
+        }
+        params
+    }
+}
+
+// Max over windows of `kernel_size` steps of [N, C, L] sequences, the stride defaults to the
+// kernel size so windows don't overlap
+pub struct MaxPool1d {
+    pub kernel_size: usize,
+    pub stride: usize,
+    pub hooks: ForwardHooks,
+}
+
+impl MaxPool1d {
+    pub fn new(kernel_size: usize) -> MaxPool1d {
+        MaxPool1d {
+            kernel_size,
+            stride: kernel_size,
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+}
+
+impl Module for MaxPool1d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.max_pool1d(self.kernel_size, self.stride);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        shape::pool1d("max_pool1d", input_shape, self.kernel_size, self.stride)
+    }
+}
+
+// Mean over windows of `kernel_size` steps, see MaxPool1d
+pub struct AvgPool1d {
+    pub kernel_size: usize,
+    pub stride: usize,
+    pub hooks: ForwardHooks,
+}
+
+impl AvgPool1d {
+    pub fn new(kernel_size: usize) -> AvgPool1d {
+        AvgPool1d {
+            kernel_size,
+            stride: kernel_size,
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+}
+
+impl Module for AvgPool1d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.avg_pool1d(self.kernel_size, self.stride);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        shape::pool1d("avg_pool1d", input_shape, self.kernel_size, self.stride)
+    }
+}
+
+// Averages [N, C, H, W] feature maps of any size down to [N, C, output_size[0], output_size[1]]
+pub struct AdaptiveAvgPool2d {
+    pub output_size: [usize; 2],
+    pub hooks: ForwardHooks,
+}
+
+impl AdaptiveAvgPool2d {
+    pub fn new(output_size: [usize; 2]) -> AdaptiveAvgPool2d {
+        AdaptiveAvgPool2d {
+            output_size,
+            hooks: ForwardHooks::default(),
+        }
+    }
+}
+
+impl Module for AdaptiveAvgPool2d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.adaptive_avg_pool2d(self.output_size);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        shape::adaptive_avg_pool2d(input_shape, self.output_size)
+    }
+}
+
+// [N, C, H, W] -> [N, C] channel means, between a convolutional body and a Linear head
+pub struct GlobalAvgPool2d;
+
+impl Module for GlobalAvgPool2d {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        input.global_avg_pool2d()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let pooled = shape::adaptive_avg_pool2d(input_shape, [1, 1])?;
+        Ok(pooled[..2].to_vec())
+    }
+}
+
+// Resizes [N, C, H, W] feature maps by `scale_factor`, e.g. in a decoder or the expanding
+// path of a U-Net
+pub struct Upsample {
+    pub scale_factor: f32,
+    pub mode: Interpolation,
+    pub hooks: ForwardHooks,
+}
+
+impl Upsample {
+    pub fn new(scale_factor: f32, mode: Interpolation) -> Upsample {
+        Upsample {
+            scale_factor,
+            mode,
+            hooks: ForwardHooks::default(),
+        }
+    }
+}
+
+impl Module for Upsample {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = input.interpolate(self.scale_factor, self.mode);
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        shape::interpolate(input_shape, self.scale_factor)
+    }
+}
+
+pub struct ReLU;
+
+impl Module for ReLU {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        input.relu()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        Ok(input_shape.to_vec())
+    }
+}
+
+pub struct Tanh;
+
+impl Module for Tanh {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        input.tanh()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        Ok(input_shape.to_vec())
+    }
+}
+
+// [N, ...] to [N, features], e.g. between convolutions and a Linear
+pub struct Flatten;
+
+impl Module for Flatten {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let shape = input.shape();
+        assert!(!shape.is_empty(), "Flatten needs a batch axis");
+        input.reshape(&[shape[0], shape[1..].iter().product()])
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        match input_shape.split_first() {
+            Some((&batch, features)) => Ok(vec![batch, features.iter().product()]),
+            None => Err(shape::mismatch("Flatten", input_shape, &[])),
+        }
+    }
+}
+
+// Reshape every sample to `shape`, keeping the batch axis: `Reshape::new(&[1, 28, 28])`
+// turns [N, 784] into [N, 1, 28, 28]
+pub struct Reshape {
+    pub shape: Vec<usize>,
+    pub hooks: ForwardHooks,
+}
+
+impl Reshape {
+    pub fn new(shape: &[usize]) -> Reshape {
+        Reshape {
+            shape: shape.to_vec(),
+            hooks: ForwardHooks::default(),
+        }
+    }
+}
+
+impl Module for Reshape {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let batch = input.shape()[0];
+        let out = input.reshape(&[&[batch], self.shape.as_slice()].concat());
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let Some(&batch) = input_shape.first() else {
+            return Err(shape::mismatch("Reshape", input_shape, &self.shape));
+        };
+        shape::reshape(input_shape, &[&[batch], self.shape.as_slice()].concat())
+    }
+}
+
+// Chains modules, parameters are prefixed with the layer index:
+// `Sequential::new().add(Linear::new(2, 8)).add(ReLU).add(Linear::new(8, 1))`
+#[derive(Default)]
+pub struct Sequential {
+    pub layers: Vec<Box<dyn Module>>,
+    pub hooks: ForwardHooks,
+}
+
+impl Sequential {
+    pub fn new() -> Sequential {
+        Sequential::default()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, layer: impl Module + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+impl Module for Sequential {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = self
+            .layers
+            .iter()
+            .fold(input.clone(), |x, layer| layer.forward(&x));
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        let out = self
+            .layers
+            .iter()
+            .enumerate()
+            .fold(input.clone(), |x, (i, layer)| {
+                let child = if name.is_empty() {
+                    i.to_string()
+                } else {
+                    format!("{}.{}", name, i)
+                };
+                layer.forward_traced(&x, &child, visit)
+            });
+        self.hooks.run(input, out)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.layers
+            .iter()
+            .try_fold(input_shape.to_vec(), |shape, layer| {
+                layer.output_shape(&shape)
+            })
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.layers
+            .iter()
+            .enumerate()
+            .try_fold(input_shape.to_vec(), |shape, (i, layer)| {
+                let child = if name.is_empty() {
+                    i.to_string()
+                } else {
+                    format!("{}.{}", name, i)
+                };
+                layer.output_shape_traced(&shape, &child, visit)
+            })
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        for layer in &self.layers {
+            layer.set_cache(enabled);
+        }
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, layer)| {
+                layer
+                    .named_parameters()
+                    .into_iter()
+                    .map(move |(name, param)| (format!("{}.{}", i, name), param))
+            })
+            .collect()
+    }
+}
+
+// Runs a module and returns the outputs of some of its layers by path, the names summary()
+// lists (e.g. "1" for the second layer of a Sequential, "0.2" inside a nested one), to take
+// embeddings from hidden layers without changing the model:
+//
+//     let extractor = FeatureExtractor::new(model, &["2", "4"]);
+//     let features = extractor.extract(&x);
+//     let embedding = &features["4"];
+//
+// As a Module it runs the wrapped one unchanged.
+pub struct FeatureExtractor<M> {
+    module: M,
+    layers: Vec<String>,
+}
+
+impl<M: Module> FeatureExtractor<M> {
+    pub fn new(module: M, layers: &[&str]) -> FeatureExtractor<M> {
+        FeatureExtractor {
+            module,
+            layers: layers.iter().map(|layer| layer.to_string()).collect(),
+        }
+    }
+
+    // Outputs of the requested layers on `input`, still part of the graph. Panics naming the
+    // available layers when one of them wasn't run.
+    pub fn extract(&self, input: &Tensor) -> BTreeMap<String, Tensor> {
+        self.extract_with_output(input).0
+    }
+
+    // extract, along with the module's output
+    pub fn extract_with_output(&self, input: &Tensor) -> (BTreeMap<String, Tensor>, Tensor) {
+        let mut features = BTreeMap::new();
+        let mut names = Vec::new();
+        let output = self.module.forward_traced(input, "", &mut |layer| {
+            if self.layers.iter().any(|name| name == layer.name) {
+                features.insert(layer.name.to_string(), layer.output.clone());
+            }
+            names.push(layer.name.to_string());
+        });
+        for name in &self.layers {
+            assert!(
+                features.contains_key(name),
+                "FeatureExtractor found no layer {:?}, the layers are {:?}",
+                name,
+                names
+            );
+        }
+        (features, output)
+    }
+
+    pub fn module(&self) -> &M {
+        &self.module
+    }
+
+    pub fn into_inner(self) -> M {
+        self.module
+    }
+}
+
+impl<M: Module> Module for FeatureExtractor<M> {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.module.forward(input)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.module.named_parameters()
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        self.module.forward_traced(input, name, visit)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.module.output_shape(input_shape)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.module.output_shape_traced(input_shape, name, visit)
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        self.module.set_cache(enabled);
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        self.module.forward_hooks()
+    }
+}
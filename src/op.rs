@@ -0,0 +1,177 @@
+// What a node of the autograd graph computes, with the arguments the op was called with, so
+// tooling (compile, ONNX export, graph snapshots) can match on ops instead of parsing names.
+// Values as large as the data (factors, the derivatives of fused losses) stay in
+// TensorData::_saved, gather indices in TensorData::_indices.
+//
+// Ops print and serialize under the names the profiler reports ("matmul", "+", ...), ops with
+// arguments serialize as e.g. {"softmax": {"axis": 1}}.
+
+use crate::device::Device;
+use crate::spatial::{ConvOptions, Interpolation};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "*")]
+    Mul,
+    Matmul,
+    // Of a 2-D tensor
+    #[serde(rename = "t")]
+    Transpose,
+    // To the shape of the node's data
+    Reshape,
+    Tanh,
+    Exp,
+    Relu,
+    Softmax {
+        axis: usize,
+    },
+    Sum {
+        axis: usize,
+    },
+    Row {
+        index: usize,
+    },
+    // A copy on another device, see Tensor::to
+    To {
+        device: Device,
+    },
+    // The indices are in TensorData::_indices, see tensor::gathered_indices
+    GatherRows,
+    // Straight-through, the gradient passes to the probabilities unchanged
+    Bernoulli,
+    Interpolate {
+        scale_factor: f32,
+        mode: Interpolation,
+    },
+    Conv1d(ConvOptions),
+    Conv2d(ConvOptions),
+    Im2col {
+        kernel: [usize; 2],
+        options: ConvOptions,
+    },
+    Col2im {
+        output_size: [usize; 2],
+        kernel: [usize; 2],
+        options: ConvOptions,
+    },
+    AdaptiveAvgPool2d {
+        output_size: [usize; 2],
+    },
+    MaxPool1d {
+        kernel: usize,
+        stride: usize,
+    },
+    AvgPool1d {
+        kernel: usize,
+        stride: usize,
+    },
+    Inverse,
+    Solve,
+    Cholesky,
+    Logdet,
+    // Every factor of qr() and svd() is a node of its own, the decomposition is saved
+    QrQ,
+    QrR,
+    SvdU,
+    SvdS,
+    SvdVt,
+    // Serialized as just the loss name
+    #[serde(untagged)]
+    Loss(LossKind),
+}
+
+// Losses computed in a single node. Backward only scales the derivatives saved in forward, so
+// the loss arguments (margins, temperatures, targets) aren't kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LossKind {
+    Mse,
+    L1,
+    Huber,
+    SmoothL1,
+    CrossEntropy,
+    KlDiv,
+    CosineEmbedding,
+    Hinge,
+    Focal,
+    TripletMargin,
+    InfoNce,
+    Distillation,
+    Ctc,
+    KlNormal,
+    L1Penalty,
+    L2Penalty,
+}
+
+impl Op {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Mul => "*",
+            Op::Matmul => "matmul",
+            Op::Transpose => "t",
+            Op::Reshape => "reshape",
+            Op::Tanh => "tanh",
+            Op::Exp => "exp",
+            Op::Relu => "relu",
+            Op::Softmax { .. } => "softmax",
+            Op::Sum { .. } => "sum",
+            Op::Row { .. } => "row",
+            Op::To { .. } => "to",
+            Op::GatherRows => "gather_rows",
+            Op::Bernoulli => "bernoulli",
+            Op::Interpolate { .. } => "interpolate",
+            Op::Conv1d(_) => "conv1d",
+            Op::Conv2d(_) => "conv2d",
+            Op::Im2col { .. } => "im2col",
+            Op::Col2im { .. } => "col2im",
+            Op::AdaptiveAvgPool2d { .. } => "adaptive_avg_pool2d",
+            Op::MaxPool1d { .. } => "max_pool1d",
+            Op::AvgPool1d { .. } => "avg_pool1d",
+            Op::Inverse => "inverse",
+            Op::Solve => "solve",
+            Op::Cholesky => "cholesky",
+            Op::Logdet => "logdet",
+            Op::QrQ => "qr_q",
+            Op::QrR => "qr_r",
+            Op::SvdU => "svd_u",
+            Op::SvdS => "svd_s",
+            Op::SvdVt => "svd_vt",
+            Op::Loss(loss) => loss.name(),
+        }
+    }
+}
+
+impl LossKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LossKind::Mse => "mse",
+            LossKind::L1 => "l1",
+            LossKind::Huber => "huber",
+            LossKind::SmoothL1 => "smooth_l1",
+            LossKind::CrossEntropy => "cross_entropy",
+            LossKind::KlDiv => "kl_div",
+            LossKind::CosineEmbedding => "cosine_embedding",
+            LossKind::Hinge => "hinge",
+            LossKind::Focal => "focal",
+            LossKind::TripletMargin => "triplet_margin",
+            LossKind::InfoNce => "info_nce",
+            LossKind::Distillation => "distillation",
+            LossKind::Ctc => "ctc",
+            LossKind::KlNormal => "kl_normal",
+            LossKind::L1Penalty => "l1_penalty",
+            LossKind::L2Penalty => "l2_penalty",
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,286 @@
+// Optimizers updating parameters in place from their accumulated gradients
+
+use crate::nn::StateDict;
+use crate::tensor::{SparseGrad, Tensor};
+use ndarray::{arr0, ArrayD, Axis};
+
+pub trait Optimizer {
+    // Update every parameter that has a gradient
+    fn step(&mut self);
+
+    fn parameters(&self) -> &[Tensor];
+
+    // Learning rate, settable between steps so schedules don't need to know the optimizer type
+    fn lr(&self) -> f32;
+
+    fn set_lr(&mut self, lr: f32);
+
+    fn zero_grad(&self) {
+        for param in self.parameters() {
+            param.zero_grad();
+        }
+    }
+
+    // Internal buffers (momentum, moment estimates, ...) needed to resume training exactly
+    fn state_dict(&self) -> StateDict;
+
+    // Entries missing from `state` reset that buffer
+    fn load_state_dict(&mut self, state: &StateDict);
+}
+
+// The gradient of a parameter that only received sparse rows (see Tensor::set_sparse), None
+// when it has a dense part or no gradient at all
+fn sparse_only(param: &Tensor) -> Option<SparseGrad> {
+    let inner = param.borrow();
+    match (&inner.grad, &inner.sparse_grad) {
+        (None, Some(sparse)) => Some(sparse.coalesce()),
+        _ => None,
+    }
+}
+
+// Stochastic gradient descent with optional momentum and L2 weight decay
+pub struct Sgd {
+    params: Vec<Tensor>,
+    pub lr: f32,
+    pub momentum: f32,
+    pub weight_decay: f32,
+    velocity: Vec<Option<ArrayD<f32>>>,
+}
+
+impl Sgd {
+    pub fn new(params: Vec<Tensor>, lr: f32) -> Sgd {
+        let velocity = vec![None; params.len()];
+        Sgd {
+            params,
+            lr,
+            momentum: 0.0,
+            weight_decay: 0.0,
+            velocity,
+        }
+    }
+
+    pub fn momentum(mut self, momentum: f32) -> Self {
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn weight_decay(mut self, weight_decay: f32) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    // Only the rows in the gradient are updated, and only their velocity decays
+    fn sparse_step(&mut self, i: usize, sparse: SparseGrad) {
+        let mut param = self.params[i].borrow_mut();
+        let velocity = &mut self.velocity[i];
+        if self.momentum != 0.0 && velocity.is_none() {
+            *velocity = Some(ArrayD::zeros(param.data.raw_dim()));
+        }
+        for (k, &index) in sparse.indices.iter().enumerate() {
+            let mut grad = sparse.values.index_axis(Axis(0), k).to_owned();
+            let mut row = param.data.index_axis_mut(Axis(0), index);
+            if self.weight_decay != 0.0 {
+                grad.scaled_add(self.weight_decay, &row);
+            }
+            if let Some(velocity) = velocity.as_mut() {
+                let mut v = velocity.index_axis_mut(Axis(0), index);
+                v *= self.momentum;
+                v += &grad;
+                grad.assign(&v);
+            }
+            row.scaled_add(-self.lr, &grad);
+        }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self) {
+        for i in 0..self.params.len() {
+            if let Some(sparse) = sparse_only(&self.params[i]) {
+                self.sparse_step(i, sparse);
+                continue;
+            }
+            let (param, velocity) = (&self.params[i], &mut self.velocity[i]);
+            let Some(mut grad) = param.grad_array() else {
+                continue;
+            };
+            let mut param = param.borrow_mut();
+            if self.weight_decay != 0.0 {
+                grad.scaled_add(self.weight_decay, &param.data);
+            }
+            if self.momentum != 0.0 {
+                let v = match velocity.take() {
+                    Some(v) => v * self.momentum + &grad,
+                    None => grad,
+                };
+                grad = v.clone();
+                *velocity = Some(v);
+            }
+            param.data.scaled_add(-self.lr, &grad);
+        }
+    }
+
+    fn parameters(&self) -> &[Tensor] {
+        &self.params
+    }
+
+    fn lr(&self) -> f32 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f32) {
+        self.lr = lr;
+    }
+
+    fn state_dict(&self) -> StateDict {
+        self.velocity
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.as_ref().map(|v| (format!("{}.velocity", i), v.clone())))
+            .collect()
+    }
+
+    fn load_state_dict(&mut self, state: &StateDict) {
+        for (i, velocity) in self.velocity.iter_mut().enumerate() {
+            *velocity = state.get(&format!("{}.velocity", i)).cloned();
+        }
+    }
+}
+
+// Adam (Kingma & Ba) with bias-corrected moment estimates
+pub struct Adam {
+    params: Vec<Tensor>,
+    pub lr: f32,
+    pub betas: (f32, f32),
+    pub eps: f32,
+    pub weight_decay: f32,
+    step: u64,
+    first_moment: Vec<Option<ArrayD<f32>>>,
+    second_moment: Vec<Option<ArrayD<f32>>>,
+}
+
+impl Adam {
+    pub fn new(params: Vec<Tensor>, lr: f32) -> Adam {
+        let n = params.len();
+        Adam {
+            params,
+            lr,
+            betas: (0.9, 0.999),
+            eps: 1e-8,
+            weight_decay: 0.0,
+            step: 0,
+            first_moment: vec![None; n],
+            second_moment: vec![None; n],
+        }
+    }
+
+    pub fn betas(mut self, beta1: f32, beta2: f32) -> Self {
+        self.betas = (beta1, beta2);
+        self
+    }
+
+    pub fn weight_decay(mut self, weight_decay: f32) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    // Lazy update like PyTorch's SparseAdam: only the rows in the gradient have their moments
+    // updated and move, rows that weren't looked up keep their state untouched
+    fn sparse_step(&mut self, i: usize, sparse: SparseGrad, correction1: f32, correction2: f32) {
+        let (beta1, beta2) = self.betas;
+        let mut param = self.params[i].borrow_mut();
+        let shape = param.data.raw_dim();
+        let m = self.first_moment[i].get_or_insert_with(|| ArrayD::zeros(shape.clone()));
+        let v = self.second_moment[i].get_or_insert_with(|| ArrayD::zeros(shape));
+        for (k, &index) in sparse.indices.iter().enumerate() {
+            let mut grad = sparse.values.index_axis(Axis(0), k).to_owned();
+            let mut row = param.data.index_axis_mut(Axis(0), index);
+            if self.weight_decay != 0.0 {
+                grad.scaled_add(self.weight_decay, &row);
+            }
+            let mut m = m.index_axis_mut(Axis(0), index);
+            let mut v = v.index_axis_mut(Axis(0), index);
+            m.zip_mut_with(&grad, |m, &g| *m = beta1 * *m + (1.0 - beta1) * g);
+            v.zip_mut_with(&grad, |v, &g| *v = beta2 * *v + (1.0 - beta2) * g * g);
+            let update = (&m / correction1) / ((&v / correction2).mapv(f32::sqrt) + self.eps);
+            row.scaled_add(-self.lr, &update);
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self) {
+        self.step += 1;
+        let (beta1, beta2) = self.betas;
+        let correction1 = 1.0 - beta1.powi(self.step as i32);
+        let correction2 = 1.0 - beta2.powi(self.step as i32);
+
+        for i in 0..self.params.len() {
+            if let Some(sparse) = sparse_only(&self.params[i]) {
+                self.sparse_step(i, sparse, correction1, correction2);
+                continue;
+            }
+            let param = &self.params[i];
+            let Some(mut grad) = param.grad_array() else {
+                continue;
+            };
+            let mut param = param.borrow_mut();
+            if self.weight_decay != 0.0 {
+                grad.scaled_add(self.weight_decay, &param.data);
+            }
+            let m = match self.first_moment[i].take() {
+                Some(m) => m * beta1 + &grad * (1.0 - beta1),
+                None => &grad * (1.0 - beta1),
+            };
+            let v = match self.second_moment[i].take() {
+                Some(v) => v * beta2 + &grad * &grad * (1.0 - beta2),
+                None => &grad * &grad * (1.0 - beta2),
+            };
+
+            let update = (&m / correction1) / ((&v / correction2).mapv(f32::sqrt) + self.eps);
+            param.data.scaled_add(-self.lr, &update);
+            self.first_moment[i] = Some(m);
+            self.second_moment[i] = Some(v);
+        }
+    }
+
+    fn parameters(&self) -> &[Tensor] {
+        &self.params
+    }
+
+    fn lr(&self) -> f32 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f32) {
+        self.lr = lr;
+    }
+
+    fn state_dict(&self) -> StateDict {
+        let mut state = StateDict::new();
+        state.insert(String::from("step"), arr0(self.step as f32).into_dyn());
+        for (i, (m, v)) in self
+            .first_moment
+            .iter()
+            .zip(self.second_moment.iter())
+            .enumerate()
+        {
+            if let (Some(m), Some(v)) = (m, v) {
+                state.insert(format!("{}.exp_avg", i), m.clone());
+                state.insert(format!("{}.exp_avg_sq", i), v.clone());
+            }
+        }
+        state
+    }
+
+    fn load_state_dict(&mut self, state: &StateDict) {
+        self.step = state
+            .get("step")
+            .and_then(|s| s.first().copied())
+            .map_or(0, |s| s as u64);
+        for i in 0..self.params.len() {
+            self.first_moment[i] = state.get(&format!("{}.exp_avg", i)).cloned();
+            self.second_moment[i] = state.get(&format!("{}.exp_avg_sq", i)).cloned();
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// Data-parallel training on CPU threads. Tensors are Rc based and can't cross threads, so
+// every worker thread builds its own replica of the model with the same factory. Each step the
+// replicas get the current weights, run forward and backward on their share of the batch, and
+// send their gradients back as plain arrays to be averaged into the main model:
+//
+//     let model = DataParallel::new(|| Sequential::new().add(Linear::new(784, 10)), loss_fn, 4);
+//     let mut optimizer = Sgd::new(model.parameters(), 0.1);
+//     for (inputs, targets) in &loader {
+//         let loss = model.train_step(&mut optimizer, &inputs, &targets);
+//     }
+//
+// The calling thread works on the first share itself. Averaged gradients are dense, a sparse
+// gradient (see Tensor::set_sparse) is densified on the way.
+
+use crate::error::TensorError;
+use crate::nn::{ForwardHooks, Module, StateDict, TracedLayer};
+use crate::optim::Optimizer;
+use crate::shape::ShapedLayer;
+use crate::tensor::Tensor;
+use ndarray::{ArrayD, Axis, Slice};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+type SharedLossFn = Arc<dyn Fn(&Tensor, &Tensor) -> Tensor + Send + Sync>;
+
+struct Job {
+    state: Arc<StateDict>,
+    inputs: ArrayD<f32>,
+    targets: ArrayD<f32>,
+}
+
+// Loss of the share and the gradient of every parameter that got one, by name
+type JobResult = (f32, StateDict);
+
+struct Worker {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<JobResult>,
+    handle: Option<JoinHandle<()>>,
+}
+
+pub struct DataParallel<M: Module> {
+    model: M,
+    loss_fn: SharedLossFn,
+    workers: Vec<Worker>,
+}
+
+impl<M: Module> DataParallel<M> {
+    // Run on `threads` threads, the calling one included. `factory` has to build the same
+    // architecture every time, the initial weights of the replicas don't matter.
+    pub fn new<F>(
+        factory: F,
+        loss_fn: impl Fn(&Tensor, &Tensor) -> Tensor + Send + Sync + 'static,
+        threads: usize,
+    ) -> DataParallel<M>
+    where
+        F: Fn() -> M + Send + Sync + 'static,
+    {
+        assert!(threads > 0, "DataParallel needs at least 1 thread");
+        let factory = Arc::new(factory);
+        let loss_fn: SharedLossFn = Arc::new(loss_fn);
+        let workers = (1..threads)
+            .map(|_| {
+                let (jobs, job_receiver) = channel::<Job>();
+                let (result_sender, results) = channel();
+                let (factory, loss_fn) = (Arc::clone(&factory), Arc::clone(&loss_fn));
+                let handle = thread::spawn(move || {
+                    let replica = factory();
+                    // Ends when the DataParallel is dropped and the job sender with it
+                    for job in job_receiver {
+                        let result = backward_share(&replica, &*loss_fn, job);
+                        if result_sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Worker {
+                    jobs: Some(jobs),
+                    results,
+                    handle: Some(handle),
+                }
+            })
+            .collect();
+        DataParallel {
+            model: factory(),
+            loss_fn,
+            workers,
+        }
+    }
+
+    // The main replica, the one optimizers update
+    pub fn module(&self) -> &M {
+        &self.model
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len() + 1
+    }
+
+    // Forward and backward over the batch split across the threads, leaving the batch mean
+    // gradient on the main model's parameters. Returns the loss, assuming `loss_fn` averages
+    // over the batch. Gradients replace rather than add to existing ones.
+    pub fn backward(&self, inputs: &Tensor, targets: &Tensor) -> f32 {
+        let inputs = inputs.borrow().data.clone();
+        let targets = targets.borrow().data.clone();
+        let n = inputs.len_of(Axis(0));
+        assert_eq!(
+            n,
+            targets.len_of(Axis(0)),
+            "inputs and targets have different batch sizes"
+        );
+        assert!(n > 0, "DataParallel can't train on an empty batch");
+        // Contiguous, near-equal shares, no more of them than samples
+        let shares = self.threads().min(n);
+        let bounds: Vec<(usize, usize)> = (0..shares)
+            .map(|i| (i * n / shares, (i + 1) * n / shares))
+            .collect();
+        let share = |data: &ArrayD<f32>, (start, end): (usize, usize)| {
+            data.slice_axis(Axis(0), Slice::from(start..end)).to_owned()
+        };
+
+        let state = Arc::new(self.model.state_dict());
+        for (worker, &bounds) in self.workers.iter().zip(&bounds[1..]) {
+            let job = Job {
+                state: Arc::clone(&state),
+                inputs: share(&inputs, bounds),
+                targets: share(&targets, bounds),
+            };
+            worker
+                .jobs
+                .as_ref()
+                .unwrap()
+                .send(job)
+                .expect("DataParallel worker thread panicked");
+        }
+
+        self.model.zero_grad();
+        let (mut loss, grads) = {
+            let inputs = Tensor::from(share(&inputs, bounds[0]));
+            let targets = Tensor::from(share(&targets, bounds[0]));
+            let loss = (self.loss_fn)(&self.model.forward(&inputs), &targets);
+            loss.backward();
+            (loss.item(), self.model.named_parameters())
+        };
+        let weight = |(start, end): (usize, usize)| (end - start) as f32 / n as f32;
+        loss *= weight(bounds[0]);
+        let mut total: StateDict = grads
+            .into_iter()
+            .filter_map(|(name, param)| param.grad_array().map(|g| (name, g * weight(bounds[0]))))
+            .collect();
+        for (worker, &bounds) in self.workers.iter().zip(&bounds[1..]) {
+            let (share_loss, share_grads) = worker
+                .results
+                .recv()
+                .expect("DataParallel worker thread panicked");
+            loss += share_loss * weight(bounds);
+            for (name, grad) in share_grads {
+                let grad = grad * weight(bounds);
+                match total.get_mut(&name) {
+                    Some(sum) => *sum += &grad,
+                    None => {
+                        total.insert(name, grad);
+                    }
+                }
+            }
+        }
+
+        for (name, param) in self.model.named_parameters() {
+            let mut param = param.borrow_mut();
+            // Replicas don't know which parameters are frozen, their gradients are dropped
+            if !param.requires_grad {
+                continue;
+            }
+            param.grad = total.remove(&name);
+            param.sparse_grad = None;
+            param.retrack();
+        }
+        loss
+    }
+
+    // zero_grad, backward and an optimizer step over one batch, returns the loss
+    pub fn train_step(
+        &self,
+        optimizer: &mut dyn Optimizer,
+        inputs: &Tensor,
+        targets: &Tensor,
+    ) -> f32 {
+        optimizer.zero_grad();
+        let loss = self.backward(inputs, targets);
+        optimizer.step();
+        loss
+    }
+}
+
+fn backward_share(
+    replica: &dyn Module,
+    loss_fn: &dyn Fn(&Tensor, &Tensor) -> Tensor,
+    job: Job,
+) -> JobResult {
+    replica
+        .load_state_dict(&job.state)
+        .expect("DataParallel replicas must match the main model");
+    replica.zero_grad();
+    let loss = loss_fn(
+        &replica.forward(&Tensor::from(job.inputs)),
+        &Tensor::from(job.targets),
+    );
+    loss.backward();
+    let grads = replica
+        .named_parameters()
+        .into_iter()
+        .filter_map(|(name, param)| param.grad_array().map(|g| (name, g)))
+        .collect();
+    (loss.item(), grads)
+}
+
+// Plain forward on the main replica, e.g. for evaluation. Parameters and state dicts are the
+// main model's, so optimizers, checkpoints and summaries work as for an unwrapped model.
+impl<M: Module> Module for DataParallel<M> {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.model.forward(input)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.model.named_parameters()
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        self.model.forward_traced(input, name, visit)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.model.output_shape(input_shape)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.model.output_shape_traced(input_shape, name, visit)
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        self.model.set_cache(enabled);
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        self.model.forward_hooks()
+    }
+}
+
+impl<M: Module> Drop for DataParallel<M> {
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            // Closing the job channel ends the worker's loop
+            worker.jobs.take();
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Opt-in per-op profiling of the autograd engine, so performance work has data to target:
+//
+//     let profiler = Profiler::start();
+//     trainer.fit(&loader);
+//     println!("{}", profiler.stop().report(10));
+//
+// Forward time covers computing an op's output, backward time running its backward function.
+// Allocated bytes count the output plus the values saved for backward (forward) and the
+// gradients handed to the children (backward). Profiling is per thread, when it's off every op
+// only pays a thread-local lookup.
+//
+// Backward time is also broken down by the label of the node (see Tensor::named), to find
+// which `matmul` of the model is the slow one. Labels are set once the op has run, so forward
+// time can't be broken down the same way.
+
+use crate::tensor::TensorData;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Profile>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub forward_calls: u64,
+    pub forward_time: Duration,
+    pub forward_bytes: u64,
+    pub backward_calls: u64,
+    pub backward_time: Duration,
+    pub backward_bytes: u64,
+}
+
+impl OpStats {
+    pub fn total_time(&self) -> Duration {
+        self.forward_time + self.backward_time
+    }
+}
+
+// Stats per op name, as used in the graph ("matmul", "+", "mse", ...)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub ops: BTreeMap<String, OpStats>,
+    // Backward stats of labelled nodes by label, the forward fields stay 0
+    pub labels: BTreeMap<String, OpStats>,
+    // Wall time between start and stop
+    pub elapsed: Duration,
+}
+
+impl Profile {
+    // Ops by total time, slowest first
+    pub fn hottest(&self) -> Vec<(&str, &OpStats)> {
+        let mut ops: Vec<(&str, &OpStats)> =
+            self.ops.iter().map(|(op, s)| (op.as_str(), s)).collect();
+        ops.sort_by(|a, b| b.1.total_time().cmp(&a.1.total_time()).then(a.0.cmp(b.0)));
+        ops
+    }
+
+    // Table of the `top` hottest ops
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<16} {:>8} {:>12} {:>12} {:>12} {:>7} {:>12}",
+            "op", "calls", "forward", "backward", "total", "%", "allocated"
+        );
+        let op_time: Duration = self.ops.values().map(OpStats::total_time).sum();
+        for (op, stats) in self.hottest().into_iter().take(top) {
+            let share = if op_time.is_zero() {
+                0.0
+            } else {
+                100.0 * stats.total_time().as_secs_f64() / op_time.as_secs_f64()
+            };
+            let _ = writeln!(
+                out,
+                "{:<16} {:>8} {:>12} {:>12} {:>12} {:>6.1}% {:>12}",
+                op,
+                stats.forward_calls,
+                format!("{:.3?}", stats.forward_time),
+                format!("{:.3?}", stats.backward_time),
+                format!("{:.3?}", stats.total_time()),
+                share,
+                format_bytes(stats.forward_bytes + stats.backward_bytes)
+            );
+        }
+        if !self.labels.is_empty() {
+            let _ = writeln!(
+                out,
+                "{:<16} {:>8} {:>12} {:>12}",
+                "label", "calls", "backward", "allocated"
+            );
+            let mut labels: Vec<_> = self.labels.iter().collect();
+            labels.sort_by(|a, b| b.1.backward_time.cmp(&a.1.backward_time).then(a.0.cmp(b.0)));
+            for (label, stats) in labels.into_iter().take(top) {
+                let _ = writeln!(
+                    out,
+                    "{:<16} {:>8} {:>12} {:>12}",
+                    label,
+                    stats.backward_calls,
+                    format!("{:.3?}", stats.backward_time),
+                    format_bytes(stats.backward_bytes)
+                );
+            }
+        }
+        let _ = write!(
+            out,
+            "{:.3?} in ops out of {:.3?} profiled",
+            op_time, self.elapsed
+        );
+        out
+    }
+}
+
+// Records while alive, one at a time per thread. Dropping it without `stop` discards the
+// profile.
+pub struct Profiler {
+    started: Instant,
+    // Tied to the thread it records
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Profiler {
+    pub fn start() -> Profiler {
+        ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            assert!(
+                active.is_none(),
+                "a Profiler is already running on this thread"
+            );
+            *active = Some(Profile::default());
+        });
+        Profiler {
+            started: Instant::now(),
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn stop(self) -> Profile {
+        let mut profile = ACTIVE
+            .with(|active| active.borrow_mut().take())
+            .unwrap_or_default();
+        profile.elapsed = self.started.elapsed();
+        profile
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.borrow_mut().take());
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ACTIVE.with(|active| active.borrow().is_some())
+}
+
+fn with_stats(op: &str, f: impl FnOnce(&mut OpStats)) {
+    ACTIVE.with(|active| {
+        if let Some(profile) = active.borrow_mut().as_mut() {
+            f(entry(&mut profile.ops, op));
+        }
+    });
+}
+
+fn with_label_stats(label: &str, f: impl FnOnce(&mut OpStats)) {
+    ACTIVE.with(|active| {
+        if let Some(profile) = active.borrow_mut().as_mut() {
+            f(entry(&mut profile.labels, label));
+        }
+    });
+}
+
+fn entry<'a>(stats: &'a mut BTreeMap<String, OpStats>, key: &str) -> &'a mut OpStats {
+    // Only allocate the key the first time it's seen
+    if !stats.contains_key(key) {
+        stats.insert(key.to_string(), OpStats::default());
+    }
+    stats.get_mut(key).unwrap()
+}
+
+enum Phase {
+    Forward,
+    Backward { bytes: u64 },
+}
+
+// Adds the time until it's dropped to an op, see `forward` and `backward`
+pub(crate) struct Timer {
+    op: &'static str,
+    // Of the node a backward timer runs for
+    label: Option<String>,
+    phase: Phase,
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        if let (Some(label), Phase::Backward { bytes }) = (&self.label, &self.phase) {
+            with_label_stats(label, |stats| {
+                stats.backward_calls += 1;
+                stats.backward_time += elapsed;
+                stats.backward_bytes += bytes;
+            });
+        }
+        with_stats(self.op, |stats| match self.phase {
+            Phase::Forward => {
+                stats.forward_calls += 1;
+                stats.forward_time += elapsed;
+            }
+            Phase::Backward { bytes } => {
+                stats.backward_calls += 1;
+                stats.backward_time += elapsed;
+                stats.backward_bytes += bytes;
+            }
+        });
+    }
+}
+
+// Time the rest of the calling op's forward pass: `let _timer = profiler::forward("tanh");`
+pub(crate) fn forward(op: &'static str) -> Option<Timer> {
+    is_enabled().then(|| Timer {
+        op,
+        label: None,
+        phase: Phase::Forward,
+        started: Instant::now(),
+    })
+}
+
+// Time the backward function of `out`, counting a gradient for every child
+pub(crate) fn backward(out: &TensorData) -> Option<Timer> {
+    if !is_enabled() {
+        return None;
+    }
+    let bytes = out
+        ._children
+        .iter()
+        .map(|child| (child.borrow().data.len() * size_of::<f32>()) as u64)
+        .sum();
+    Some(Timer {
+        op: out._op.map_or("", |op| op.name()),
+        label: out.label.clone(),
+        phase: Phase::Backward { bytes },
+        started: Instant::now(),
+    })
+}
+
+// Count the output and saved values of a newly created op node
+pub(crate) fn allocated(node: &TensorData) {
+    let Some(op) = node._op else {
+        return;
+    };
+    if !is_enabled() {
+        return;
+    }
+    let values = node.data.len() + node._saved.iter().map(|s| s.len()).sum::<usize>();
+    let bytes = values * size_of::<f32>() + node._indices.len() * size_of::<usize>();
+    with_stats(op.name(), |stats| stats.forward_bytes += bytes as u64);
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,108 @@
+// Magnitude pruning: zero the smallest weights of every weight matrix and keep them at zero
+// while training goes on.
+//
+//     let mut masks = prune::magnitude(&model, 0.9);
+//     Trainer::new(&model, &mut optimizer, |pred, target| {
+//         loss::mse(pred, target, Reduction::Mean)
+//     })
+//     .callback(&mut masks)
+//     .fit(&loader);
+//
+// Outside the Trainer, call `masks.apply()` after every `optimizer.step()`. Pruning again with
+// a higher sparsity continues from the current zeros, for gradual schedules.
+
+use crate::nn::Module;
+use crate::tensor::Tensor;
+use crate::train::{Callback, Context};
+use ndarray::ArrayD;
+
+// Which weights survive, per pruned parameter
+pub struct PruneMasks {
+    // (name, parameter, mask of 1s for kept and 0s for pruned weights)
+    masks: Vec<(String, Tensor, ArrayD<f32>)>,
+}
+
+// Prune `sparsity` (0 to 1) of the weights of every parameter with 2 or more dimensions,
+// smallest magnitude first. Biases and other vectors are left dense.
+pub fn magnitude(module: &dyn Module, sparsity: f32) -> PruneMasks {
+    assert!(
+        (0.0..=1.0).contains(&sparsity),
+        "sparsity must be between 0 and 1, got {}",
+        sparsity
+    );
+    let masks = module
+        .named_parameters()
+        .into_iter()
+        .filter(|(_, param)| param.ndim() >= 2)
+        .map(|(name, param)| {
+            let mask = {
+                let data = &param.borrow().data;
+                let mut order: Vec<usize> = (0..data.len()).collect();
+                let values: Vec<f32> = data.iter().map(|w| w.abs()).collect();
+                order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+                let pruned = (sparsity * data.len() as f32).round() as usize;
+                let mut mask = vec![1.0; data.len()];
+                for &i in &order[..pruned] {
+                    mask[i] = 0.0;
+                }
+                ArrayD::from_shape_vec(data.raw_dim(), mask).unwrap()
+            };
+            (name, param, mask)
+        })
+        .collect();
+    let masks = PruneMasks { masks };
+    masks.apply();
+    masks
+}
+
+impl PruneMasks {
+    // Zero the pruned weights again, after an optimizer step moved them
+    pub fn apply(&self) {
+        for (_, param, mask) in &self.masks {
+            param.borrow_mut().data *= mask;
+        }
+    }
+
+    // Zero the gradients of pruned weights, so optimizer state like momentum doesn't build up
+    // for them
+    pub fn mask_grads(&self) {
+        for (_, param, mask) in &self.masks {
+            if let Some(grad) = param.borrow_mut().grad.as_mut() {
+                *grad *= mask;
+            }
+        }
+    }
+
+    // Fraction of pruned weights per parameter
+    pub fn layer_sparsity(&self) -> Vec<(String, f32)> {
+        self.masks
+            .iter()
+            .map(|(name, _, mask)| (name.clone(), pruned(mask) as f32 / mask.len() as f32))
+            .collect()
+    }
+
+    // Fraction of pruned weights over all pruned parameters
+    pub fn sparsity(&self) -> f32 {
+        let total: usize = self.masks.iter().map(|(_, _, mask)| mask.len()).sum();
+        let pruned: usize = self.masks.iter().map(|(_, _, mask)| pruned(mask)).sum();
+        if total == 0 {
+            0.0
+        } else {
+            pruned as f32 / total as f32
+        }
+    }
+}
+
+fn pruned(mask: &ArrayD<f32>) -> usize {
+    mask.iter().filter(|&&m| m == 0.0).count()
+}
+
+impl Callback for PruneMasks {
+    fn on_backward(&mut self, _ctx: &mut Context) {
+        self.mask_grads();
+    }
+
+    fn on_batch_end(&mut self, _ctx: &mut Context, _batch: usize, _loss: f32) {
+        self.apply();
+    }
+}
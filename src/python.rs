@@ -0,0 +1,398 @@
+// Python bindings, behind the `python` feature. Build and install into the active virtualenv
+// with `maturin develop --features python`, then:
+//
+//     import numpy as np, rust_ml
+//     model = rust_ml.Sequential([rust_ml.Linear(2, 8), rust_ml.Tanh(), rust_ml.Linear(8, 1)])
+//     opt = rust_ml.SGD(model.parameters(), lr=0.1)
+//     loss = rust_ml.mse_loss(model(rust_ml.Tensor(x)), rust_ml.Tensor(y))
+//     opt.zero_grad(); loss.backward(); opt.step()
+//
+// Data crosses the boundary as float32 numpy arrays and is copied once each way. Handing out
+// views into a tensor's buffer isn't safe here: load_state_dict and friends replace the buffer,
+// which would leave numpy pointing at freed memory.
+// Objects are tied to the thread that created them, like the Rc-based graph they wrap.
+
+use crate::error::{Operand, TensorError};
+use crate::loss::{self, Reduction};
+use crate::nn::{self, ForwardHooks, Module, StateDict};
+use crate::optim::{self, Optimizer};
+use crate::random;
+use crate::tensor::Tensor;
+use ndarray::{arr0, Array1};
+use numpy::{IntoPyArray, PyArrayDyn, PyReadonlyArrayDyn, ToPyArray};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::PyClassInitializer;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+impl From<TensorError> for PyErr {
+    fn from(e: TensorError) -> PyErr {
+        PyValueError::new_err(e.to_string())
+    }
+}
+
+#[pyclass(name = "Tensor", unsendable)]
+pub struct PyTensor(pub Tensor);
+
+// A Tensor, or a Python number promoted to a 0-d tensor so `t * 2.0` works
+fn operand(value: &Bound<'_, PyAny>) -> PyResult<Tensor> {
+    if let Ok(tensor) = value.extract::<PyRef<PyTensor>>() {
+        return Ok(tensor.0.clone());
+    }
+    let scalar: f32 = value.extract()?;
+    Ok(Tensor::from(arr0(scalar).into_dyn()))
+}
+
+#[pymethods]
+impl PyTensor {
+    // Anything numpy.asarray accepts: arrays of any dtype, nested lists, numbers
+    #[new]
+    fn new(data: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let numpy = data.py().import("numpy")?;
+        let array = numpy.call_method1("ascontiguousarray", (data, "float32"))?;
+        let array: PyReadonlyArrayDyn<f32> = array.extract()?;
+        Ok(PyTensor(Tensor::from(array.as_array().to_owned())))
+    }
+
+    #[getter]
+    fn shape(&self) -> Vec<usize> {
+        self.0.shape()
+    }
+
+    #[getter]
+    fn grad<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyArrayDyn<f32>>> {
+        self.0.grad_array().map(|grad| grad.into_pyarray(py))
+    }
+
+    fn numpy<'py>(&self, py: Python<'py>) -> Bound<'py, PyArrayDyn<f32>> {
+        self.0.borrow().data.to_pyarray(py)
+    }
+
+    fn item(&self) -> PyResult<f32> {
+        Ok(self.0.try_item()?)
+    }
+
+    fn backward(&self) {
+        self.0.backward();
+    }
+
+    fn zero_grad(&self) {
+        self.0.zero_grad();
+    }
+
+    fn relu(&self) -> PyTensor {
+        PyTensor(self.0.relu())
+    }
+
+    fn tanh(&self) -> PyTensor {
+        PyTensor(self.0.tanh())
+    }
+
+    #[pyo3(signature = (axis = -1))]
+    fn softmax(&self, axis: isize) -> PyResult<PyTensor> {
+        let ndim = self.0.ndim() as isize;
+        let axis = if axis < 0 { axis + ndim } else { axis };
+        if axis < 0 {
+            return Err(PyValueError::new_err(format!(
+                "invalid axis for a tensor with {} dimensions",
+                ndim
+            )));
+        }
+        Ok(PyTensor(self.0.try_softmax(axis as usize)?))
+    }
+
+    fn t(&self) -> PyResult<PyTensor> {
+        if self.0.ndim() != 2 {
+            return Err(PyValueError::new_err(format!(
+                "t() expects a 2-D tensor, got shape {:?}",
+                self.0.shape()
+            )));
+        }
+        Ok(PyTensor(self.0.t()))
+    }
+
+    fn matmul(&self, other: PyRef<PyTensor>) -> PyResult<PyTensor> {
+        Ok(PyTensor(self.0.try_matmul(&other.0)?))
+    }
+
+    fn __matmul__(&self, other: PyRef<PyTensor>) -> PyResult<PyTensor> {
+        self.matmul(other)
+    }
+
+    fn __add__(&self, other: &Bound<'_, PyAny>) -> PyResult<PyTensor> {
+        Ok(PyTensor(self.0.try_add(&operand(other)?)?))
+    }
+
+    fn __radd__(&self, other: &Bound<'_, PyAny>) -> PyResult<PyTensor> {
+        self.__add__(other)
+    }
+
+    fn __mul__(&self, other: &Bound<'_, PyAny>) -> PyResult<PyTensor> {
+        Ok(PyTensor(self.0.try_mul(&operand(other)?)?))
+    }
+
+    fn __rmul__(&self, other: &Bound<'_, PyAny>) -> PyResult<PyTensor> {
+        self.__mul__(other)
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+// A Rust module owned by Python, shared so the same layer can sit in a Python variable and
+// inside a Sequential at once
+struct Shared(Rc<dyn Module>);
+
+impl Module for Shared {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.0.forward(input)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.0.named_parameters()
+    }
+
+    fn set_cache(&self, enabled: bool) {
+        self.0.set_cache(enabled);
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        self.0.forward_hooks()
+    }
+}
+
+#[pyclass(name = "Module", subclass, unsendable)]
+pub struct PyModel(Rc<dyn Module>);
+
+#[pymethods]
+impl PyModel {
+    fn forward(&self, input: PyRef<PyTensor>) -> PyTensor {
+        PyTensor(self.0.forward(&input.0))
+    }
+
+    fn __call__(&self, input: PyRef<PyTensor>) -> PyTensor {
+        self.forward(input)
+    }
+
+    fn parameters(&self) -> Vec<PyTensor> {
+        self.0.parameters().into_iter().map(PyTensor).collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, PyTensor)> {
+        self.0
+            .named_parameters()
+            .into_iter()
+            .map(|(name, param)| (name, PyTensor(param)))
+            .collect()
+    }
+
+    fn zero_grad(&self) {
+        self.0.zero_grad();
+    }
+
+    // Parameter name -> numpy array copy
+    fn state_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        for (name, value) in self.0.state_dict() {
+            dict.set_item(name, value.into_pyarray(py))?;
+        }
+        Ok(dict)
+    }
+
+    fn load_state_dict(&self, state: HashMap<String, PyReadonlyArrayDyn<f32>>) -> PyResult<()> {
+        let state: StateDict = state
+            .into_iter()
+            .map(|(name, array)| (name, array.as_array().to_owned()))
+            .collect();
+        Ok(self.0.load_state_dict(&state)?)
+    }
+}
+
+#[pyclass(name = "Linear", extends = PyModel, unsendable)]
+pub struct PyLinear {
+    weight: Tensor,
+    bias: Option<Tensor>,
+}
+
+#[pymethods]
+impl PyLinear {
+    #[new]
+    #[pyo3(signature = (in_features, out_features, bias = true))]
+    fn new(in_features: usize, out_features: usize, bias: bool) -> PyClassInitializer<PyLinear> {
+        let linear = if bias {
+            nn::Linear::new(in_features, out_features)
+        } else {
+            nn::Linear::without_bias(in_features, out_features)
+        };
+        let layer = PyLinear {
+            weight: linear.weight.clone(),
+            bias: linear.bias.clone(),
+        };
+        PyClassInitializer::from(PyModel(Rc::new(linear))).add_subclass(layer)
+    }
+
+    #[getter]
+    fn weight(&self) -> PyTensor {
+        PyTensor(self.weight.clone())
+    }
+
+    #[getter]
+    fn bias(&self) -> Option<PyTensor> {
+        self.bias.clone().map(PyTensor)
+    }
+}
+
+#[pyclass(name = "ReLU", extends = PyModel, unsendable)]
+pub struct PyReLU;
+
+#[pymethods]
+impl PyReLU {
+    #[new]
+    fn new() -> PyClassInitializer<PyReLU> {
+        PyClassInitializer::from(PyModel(Rc::new(nn::ReLU))).add_subclass(PyReLU)
+    }
+}
+
+#[pyclass(name = "Tanh", extends = PyModel, unsendable)]
+pub struct PyTanh;
+
+#[pymethods]
+impl PyTanh {
+    #[new]
+    fn new() -> PyClassInitializer<PyTanh> {
+        PyClassInitializer::from(PyModel(Rc::new(nn::Tanh))).add_subclass(PyTanh)
+    }
+}
+
+#[pyclass(name = "Sequential", extends = PyModel, unsendable)]
+pub struct PySequential;
+
+#[pymethods]
+impl PySequential {
+    #[new]
+    fn new(layers: Vec<PyRef<PyModel>>) -> PyClassInitializer<PySequential> {
+        let mut sequential = nn::Sequential::new();
+        for layer in layers {
+            sequential.layers.push(Box::new(Shared(layer.0.clone())));
+        }
+        PyClassInitializer::from(PyModel(Rc::new(sequential))).add_subclass(PySequential)
+    }
+}
+
+#[pyclass(name = "Optimizer", subclass, unsendable)]
+pub struct PyOptimizer(Box<dyn Optimizer>);
+
+#[pymethods]
+impl PyOptimizer {
+    fn step(&mut self) {
+        self.0.step();
+    }
+
+    fn zero_grad(&self) {
+        self.0.zero_grad();
+    }
+}
+
+fn tensors(params: Vec<PyRef<PyTensor>>) -> Vec<Tensor> {
+    params.iter().map(|param| param.0.clone()).collect()
+}
+
+#[pyclass(name = "SGD", extends = PyOptimizer, unsendable)]
+pub struct PySgd;
+
+#[pymethods]
+impl PySgd {
+    #[new]
+    #[pyo3(signature = (params, lr, momentum = 0.0, weight_decay = 0.0))]
+    fn new(
+        params: Vec<PyRef<PyTensor>>,
+        lr: f32,
+        momentum: f32,
+        weight_decay: f32,
+    ) -> PyClassInitializer<PySgd> {
+        let sgd = optim::Sgd::new(tensors(params), lr)
+            .momentum(momentum)
+            .weight_decay(weight_decay);
+        PyClassInitializer::from(PyOptimizer(Box::new(sgd))).add_subclass(PySgd)
+    }
+}
+
+#[pyclass(name = "Adam", extends = PyOptimizer, unsendable)]
+pub struct PyAdam;
+
+#[pymethods]
+impl PyAdam {
+    #[new]
+    #[pyo3(signature = (params, lr = 1e-3, betas = (0.9, 0.999), weight_decay = 0.0))]
+    fn new(
+        params: Vec<PyRef<PyTensor>>,
+        lr: f32,
+        betas: (f32, f32),
+        weight_decay: f32,
+    ) -> PyClassInitializer<PyAdam> {
+        let adam = optim::Adam::new(tensors(params), lr)
+            .betas(betas.0, betas.1)
+            .weight_decay(weight_decay);
+        PyClassInitializer::from(PyOptimizer(Box::new(adam))).add_subclass(PyAdam)
+    }
+}
+
+#[pyfunction]
+fn manual_seed(seed: u64) {
+    random::manual_seed(seed);
+}
+
+#[pyfunction]
+fn mse_loss(pred: PyRef<PyTensor>, target: PyRef<PyTensor>) -> PyResult<PyTensor> {
+    if pred.0.shape() != target.0.shape() {
+        return Err(TensorError::ShapeMismatch {
+            op: "mse",
+            lhs: pred.0.shape(),
+            rhs: target.0.shape(),
+            operands: Some(Box::new([Operand::of(&pred.0), Operand::of(&target.0)])),
+            location: None,
+        }
+        .into());
+    }
+    Ok(PyTensor(loss::mse(&pred.0, &target.0, Reduction::Mean)))
+}
+
+#[pyfunction]
+fn cross_entropy(logits: PyRef<PyTensor>, targets: Vec<i64>) -> PyResult<PyTensor> {
+    let shape = logits.0.shape();
+    if shape.len() != 2 || shape[0] != targets.len() {
+        return Err(PyValueError::new_err(format!(
+            "cross_entropy expects [N, C] logits and N targets, got shape {:?} and {} targets",
+            shape,
+            targets.len()
+        )));
+    }
+    if let Some(&bad) = targets.iter().find(|&&t| t < 0 || t as usize >= shape[1]) {
+        return Err(PyValueError::new_err(format!(
+            "target {} is out of range for {} classes",
+            bad, shape[1]
+        )));
+    }
+    let targets = Tensor::from(Array1::from_iter(targets.iter().map(|&t| t as f32)).into_dyn());
+    Ok(PyTensor(loss::cross_entropy(&logits.0, &targets)))
+}
+
+#[pymodule]
+fn rust_ml(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTensor>()?;
+    m.add_class::<PyModel>()?;
+    m.add_class::<PyLinear>()?;
+    m.add_class::<PyReLU>()?;
+    m.add_class::<PyTanh>()?;
+    m.add_class::<PySequential>()?;
+    m.add_class::<PyOptimizer>()?;
+    m.add_class::<PySgd>()?;
+    m.add_class::<PyAdam>()?;
+    m.add_function(wrap_pyfunction!(manual_seed, m)?)?;
+    m.add_function(wrap_pyfunction!(mse_loss, m)?)?;
+    m.add_function(wrap_pyfunction!(cross_entropy, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+// Post-training int8 quantization for CPU inference. Linear layers get int8 weights (symmetric,
+// one scale per output row) and quantize their input to int8 with a range calibrated on
+// sample data, the matmul accumulates in i32 and the result is rescaled to f32:
+//
+//     let model = quantize::quantize(model, &calibration_batches);
+//     let out = model.forward(&x);
+//     let reference = model.dequantize(); // float model with the rounded weights
+//
+// Only Sequential models are handled, layers other than Linear (activations) stay in f32.
+// Quantized modules are inference only, their outputs aren't connected to a graph.
+
+use crate::error::TensorError;
+use crate::nn::{ForwardHooks, Linear, Module, Sequential};
+use crate::shape;
+use crate::tensor::Tensor;
+use ndarray::{Array1, Array2, ArrayD, Axis, Ix2};
+
+// Affine mapping between f32 and i8: real = scale * (q - zero_point)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantParams {
+    pub scale: f32,
+    pub zero_point: i32,
+}
+
+impl QuantParams {
+    // Covers [min, max], widened to include 0 so zero padding and ReLU outputs stay exact
+    pub fn from_range(min: f32, max: f32) -> QuantParams {
+        let (min, max) = (min.min(0.0), max.max(0.0));
+        let scale = if max > min { (max - min) / 255.0 } else { 1.0 };
+        let zero_point = (-128.0 - min / scale).round().clamp(-128.0, 127.0) as i32;
+        QuantParams { scale, zero_point }
+    }
+
+    pub fn quantize(&self, x: f32) -> i8 {
+        ((x / self.scale).round() as i32 + self.zero_point).clamp(-128, 127) as i8
+    }
+
+    pub fn dequantize(&self, q: i8) -> f32 {
+        self.scale * (q as i32 - self.zero_point) as f32
+    }
+}
+
+// Running min/max of the values seen, for calibration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeObserver {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for RangeObserver {
+    fn default() -> Self {
+        RangeObserver {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+}
+
+impl RangeObserver {
+    pub fn observe(&mut self, values: &ArrayD<f32>) {
+        for &v in values.iter().filter(|v| v.is_finite()) {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+    }
+
+    // Panics if nothing was observed
+    pub fn params(&self) -> QuantParams {
+        assert!(
+            self.min <= self.max,
+            "can't quantize without calibration data"
+        );
+        QuantParams::from_range(self.min, self.max)
+    }
+}
+
+pub struct QuantizedLinear {
+    // [out, in], row o is weight_scales[o] * weight[o]
+    pub weight: Array2<i8>,
+    pub weight_scales: Array1<f32>,
+    pub bias: Option<Array1<f32>>,
+    pub input: QuantParams,
+    pub hooks: ForwardHooks,
+}
+
+impl QuantizedLinear {
+    pub fn new(linear: &Linear, input: QuantParams) -> QuantizedLinear {
+        let weight = linear
+            .weight
+            .borrow()
+            .data
+            .clone()
+            .into_dimensionality::<Ix2>()
+            .unwrap();
+        let weight_scales = weight.map_axis(Axis(1), |row| {
+            let max = row.iter().fold(0.0f32, |m, w| m.max(w.abs()));
+            if max > 0.0 {
+                max / 127.0
+            } else {
+                1.0
+            }
+        });
+        let mut quantized = Array2::zeros(weight.raw_dim());
+        for ((o, i), &w) in weight.indexed_iter() {
+            quantized[[o, i]] = (w / weight_scales[o]).round().clamp(-127.0, 127.0) as i8;
+        }
+        let bias = linear
+            .bias
+            .as_ref()
+            .map(|b| b.borrow().data.clone().into_dimensionality().unwrap());
+        QuantizedLinear {
+            weight: quantized,
+            weight_scales,
+            bias,
+            input,
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    // Float layer with the rounded weights, to measure the accuracy cost of quantization
+    pub fn dequantize(&self) -> Linear {
+        let weight =
+            &self.weight.mapv(|q| q as f32) * &self.weight_scales.view().insert_axis(Axis(1));
+        Linear {
+            weight: Tensor::from(weight.into_dyn()),
+            bias: self
+                .bias
+                .as_ref()
+                .map(|b| Tensor::from(b.clone().into_dyn())),
+            hooks: ForwardHooks::default(),
+        }
+    }
+}
+
+impl Module for QuantizedLinear {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let x = input.borrow().data.clone();
+        let shape = x.shape().to_vec();
+        let x = x.into_dimensionality::<Ix2>().unwrap_or_else(|_| {
+            panic!(
+                "QuantizedLinear expects [N, in] inputs, got shape {:?}",
+                shape
+            )
+        });
+        // (q - zero_point) fits i32 comfortably, as do in_features products of two int8s
+        let zero_point = self.input.zero_point;
+        let x = x.mapv(|v| self.input.quantize(v) as i32 - zero_point);
+        let w = self.weight.mapv(|q| q as i32);
+        let acc = x.dot(&w.t());
+        let mut out = Array2::from_shape_fn(acc.raw_dim(), |(n, o)| {
+            acc[[n, o]] as f32 * self.input.scale * self.weight_scales[o]
+        });
+        if let Some(bias) = &self.bias {
+            out += bias;
+        }
+        self.hooks.run(input, Tensor::from(out.into_dyn()))
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        let bias = self.bias.as_ref().map(|bias| bias.shape());
+        shape::linear(input_shape, self.weight.shape(), bias)
+    }
+}
+
+pub enum QuantizedLayer {
+    Linear(QuantizedLinear),
+    Float(Box<dyn Module>),
+}
+
+pub struct QuantizedSequential {
+    pub layers: Vec<QuantizedLayer>,
+    pub hooks: ForwardHooks,
+}
+
+impl QuantizedSequential {
+    // Back to a float Sequential, Linear layers carrying their rounded weights
+    pub fn dequantize(self) -> Sequential {
+        Sequential {
+            layers: self
+                .layers
+                .into_iter()
+                .map(|layer| match layer {
+                    QuantizedLayer::Linear(linear) => {
+                        Box::new(linear.dequantize()) as Box<dyn Module>
+                    }
+                    QuantizedLayer::Float(module) => module,
+                })
+                .collect(),
+            hooks: ForwardHooks::default(),
+        }
+    }
+
+    // Bytes of weights, int8 plus the f32 scales and biases
+    pub fn weight_bytes(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| match layer {
+                QuantizedLayer::Linear(linear) => {
+                    linear.weight.len()
+                        + size_of::<f32>() * linear.weight_scales.len()
+                        + size_of::<f32>() * linear.bias.as_ref().map_or(0, |b| b.len())
+                }
+                QuantizedLayer::Float(module) => {
+                    size_of::<f32>() * module.parameters().iter().map(|p| p.numel()).sum::<usize>()
+                }
+            })
+            .sum()
+    }
+}
+
+impl Module for QuantizedSequential {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let out = self
+            .layers
+            .iter()
+            .fold(input.clone(), |x, layer| match layer {
+                QuantizedLayer::Linear(linear) => linear.forward(&x),
+                QuantizedLayer::Float(module) => module.forward(&x),
+            });
+        self.hooks.run(input, out)
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.layers
+            .iter()
+            .try_fold(input_shape.to_vec(), |shape, layer| match layer {
+                QuantizedLayer::Linear(linear) => linear.output_shape(&shape),
+                QuantizedLayer::Float(module) => module.output_shape(&shape),
+            })
+    }
+}
+
+// Module has no downcasting, layers are recognised by their type name
+fn is_linear(layer: &dyn Module) -> bool {
+    layer.type_name() == "Linear"
+}
+
+// Calibrate the input range of every Linear layer on `calibration` batches, then quantize
+pub fn quantize(model: Sequential, calibration: &[Tensor]) -> QuantizedSequential {
+    assert!(
+        !calibration.is_empty(),
+        "quantize needs at least one calibration batch"
+    );
+    let mut observers = vec![RangeObserver::default(); model.layers.len()];
+    for batch in calibration {
+        let mut x = batch.clone();
+        for (layer, observer) in model.layers.iter().zip(&mut observers) {
+            if is_linear(layer.as_ref()) {
+                observer.observe(&x.borrow().data);
+            }
+            x = layer.predict(&x);
+        }
+    }
+
+    let layers = model
+        .layers
+        .into_iter()
+        .zip(observers)
+        .map(|(layer, observer)| {
+            if !is_linear(layer.as_ref()) {
+                return QuantizedLayer::Float(layer);
+            }
+            let params = layer.named_parameters();
+            let get = |name: &str| {
+                params
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, p)| p.clone())
+            };
+            let linear = Linear {
+                weight: get("weight").expect("Linear without a weight"),
+                bias: get("bias"),
+                hooks: ForwardHooks::default(),
+            };
+            QuantizedLayer::Linear(QuantizedLinear::new(&linear, observer.params()))
+        })
+        .collect();
+    QuantizedSequential {
+        layers,
+        hooks: ForwardHooks::default(),
+    }
+}
@@ -0,0 +1,168 @@
+use crate::determinism::{is_deterministic, DETERMINISTIC_SEED};
+use crate::inference;
+use crate::op::Op;
+use crate::profiler;
+use crate::tensor::{accumulate_grad, broadcast_shape, constant, Tensor, TensorData};
+use ndarray::{ArrayD, Axis, IxDyn};
+use rand::distributions::WeightedIndex;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_distr::StandardNormal;
+use std::cell::{Cell, RefCell};
+
+// Same algorithm as rand's StdRng, but its state can be read back for checkpoints
+pub type Generator = ChaCha12Rng;
+
+// Every random op (init, dropout, shuffling, ...) draws from this generator,
+// so a single manual_seed() makes a whole run reproducible. In deterministic mode threads
+// that were never seeded start from a fixed seed instead of entropy.
+thread_local! {
+    static RNG: RefCell<Generator> = RefCell::new(if is_deterministic() {
+        Generator::seed_from_u64(DETERMINISTIC_SEED)
+    } else {
+        Generator::from_entropy()
+    });
+    // Whether manual_seed or set_rng_state picked the state, rather than initialization
+    static SEEDED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn manual_seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Generator::seed_from_u64(seed));
+    SEEDED.with(|seeded| seeded.set(true));
+}
+
+// Used when deterministic mode is switched on, an explicit seed is left alone
+pub(crate) fn seed_if_unseeded(seed: u64) {
+    if !SEEDED.with(Cell::get) {
+        manual_seed(seed);
+    }
+}
+
+// Run `f` with the thread-local generator, e.g. `with_rng(|rng| rng.gen::<f32>())`
+pub fn with_rng<T>(f: impl FnOnce(&mut Generator) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+// Exact position of the generator, restoring it continues the same random sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RngState {
+    pub seed: [u8; 32],
+    pub stream: u64,
+    pub word_pos: u128,
+}
+
+pub fn rng_state() -> RngState {
+    with_rng(|rng| RngState {
+        seed: rng.get_seed(),
+        stream: rng.get_stream(),
+        word_pos: rng.get_word_pos(),
+    })
+}
+
+pub fn set_rng_state(state: &RngState) {
+    let mut rng = Generator::from_seed(state.seed);
+    rng.set_stream(state.stream);
+    rng.set_word_pos(state.word_pos);
+    RNG.with(|current| *current.borrow_mut() = rng);
+    SEEDED.with(|seeded| seeded.set(true));
+}
+
+// How backward treats a sample from bernoulli or normal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gradient {
+    // The sample is a constant, nothing flows back into the parameters
+    None,
+    // bernoulli only: backward passes the gradient on to the probabilities unchanged, as if the
+    // sample were p itself
+    StraightThrough,
+    // normal only: the sample is mean + std * eps with a constant eps ~ N(0, 1), so it's
+    // differentiable in mean and std
+    Reparameterized,
+}
+
+// 1 with probability p and 0 otherwise, elementwise
+pub fn bernoulli(p: &Tensor, gradient: Gradient) -> Tensor {
+    assert!(
+        gradient != Gradient::Reparameterized,
+        "bernoulli has no reparameterized gradient, use Gradient::StraightThrough"
+    );
+    let _timer = profiler::forward("bernoulli");
+    let sample = {
+        let p = &p.borrow().data;
+        with_rng(|rng| {
+            p.mapv(|p| {
+                assert!(
+                    (0.0..=1.0).contains(&p),
+                    "bernoulli probabilities must be in [0, 1], got {}",
+                    p
+                );
+                if rng.gen::<f32>() < p {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+        })
+    };
+    if gradient == Gradient::None {
+        return constant(sample);
+    }
+    if inference::is_enabled() {
+        return Tensor::from(sample).on_device_of(p);
+    }
+    let mut new_tensor_data = TensorData::new(sample);
+    new_tensor_data._op = Some(Op::Bernoulli);
+    new_tensor_data._children = vec![p.clone()];
+
+    fn backward(out: &TensorData) {
+        accumulate_grad(&out._children[0], out.grad.clone().unwrap());
+    }
+    new_tensor_data._backward = Some(backward);
+
+    Tensor::new(new_tensor_data)
+}
+
+// `num_samples` class indices (as f32) drawn with replacement from the non-negative weights
+// along the last axis: [K] -> [num_samples], or [N, K] -> [N, num_samples] a row at a time.
+// Weights don't need to be normalized. Indices aren't differentiable, the result is a constant.
+pub fn multinomial(probs: &Tensor, num_samples: usize) -> Tensor {
+    let _timer = profiler::forward("multinomial");
+    let probs = &probs.borrow().data;
+    assert!(
+        probs.ndim() == 1 || probs.ndim() == 2,
+        "multinomial expects [K] or [N, K] weights, got shape {:?}",
+        probs.shape()
+    );
+    let mut shape = probs.shape()[..probs.ndim() - 1].to_vec();
+    shape.push(num_samples);
+    let mut samples = Vec::with_capacity(shape.iter().product());
+    for row in probs.lanes(Axis(probs.ndim() - 1)) {
+        let weights = WeightedIndex::new(row.iter())
+            .unwrap_or_else(|e| panic!("invalid multinomial weights {}: {}", row, e));
+        with_rng(|rng| samples.extend((0..num_samples).map(|_| rng.sample(&weights) as f32)));
+    }
+    constant(ArrayD::from_shape_vec(IxDyn(&shape), samples).unwrap())
+}
+
+// Samples of N(mean, std^2), with mean and std broadcast together
+pub fn normal(mean: &Tensor, std: &Tensor, gradient: Gradient) -> Tensor {
+    assert!(
+        gradient != Gradient::StraightThrough,
+        "normal has no straight-through gradient, use Gradient::Reparameterized"
+    );
+    let shape = broadcast_shape(&mean.shape(), &std.shape()).unwrap_or_else(|| {
+        panic!(
+            "normal: mean {:?} and std {:?} can't be broadcast together",
+            mean.shape(),
+            std.shape()
+        )
+    });
+    let eps = with_rng(|rng| {
+        ArrayD::from_shape_fn(IxDyn(&shape), |_| rng.sample::<f32, _>(StandardNormal))
+    });
+    if gradient == Gradient::None {
+        let sample = &mean.borrow().data + &(&std.borrow().data * &eps);
+        return constant(sample);
+    }
+    mean + &(std * &constant(eps))
+}
@@ -0,0 +1,46 @@
+// Explicit parameter penalties to add to a loss, as an alternative to optimizer weight decay
+// that also works with any optimizer and can be restricted to some parameters:
+//
+//     let loss = &loss::mse(&model.forward(&x), &y, Reduction::Mean)
+//         + &(&l2_penalty(&model.parameters()) * &Tensor::from(arr0(1e-4).into_dyn()));
+//
+// Each penalty is a single graph node over all the parameters.
+
+use crate::loss::fused_loss;
+use crate::op::LossKind;
+use crate::profiler;
+use crate::tensor::Tensor;
+use ndarray::arr0;
+
+// Sum of |w| over every element of `params`, gradient sign(w) (0 at 0)
+#[track_caller]
+pub fn l1_penalty(params: &[Tensor]) -> Tensor {
+    let _timer = profiler::forward("l1_penalty");
+    penalty(LossKind::L1Penalty, params, f32::abs, f32::signum)
+}
+
+// Sum of w^2 over every element of `params`, gradient 2w. Weight decay wd in SGD matches
+// adding wd / 2 times this.
+#[track_caller]
+pub fn l2_penalty(params: &[Tensor]) -> Tensor {
+    let _timer = profiler::forward("l2_penalty");
+    penalty(LossKind::L2Penalty, params, |w| w * w, |w| 2.0 * w)
+}
+
+#[track_caller]
+fn penalty(
+    loss: LossKind,
+    params: &[Tensor],
+    value: fn(f32) -> f32,
+    derivative: fn(f32) -> f32,
+) -> Tensor {
+    let mut total = 0.0;
+    let mut d_params = Vec::with_capacity(params.len());
+    for param in params {
+        let data = &param.borrow().data;
+        total += data.iter().map(|&w| value(w)).sum::<f32>();
+        // signum is +-1 at +-0, the subgradient at 0 is taken as 0
+        d_params.push(data.mapv(|w| if w == 0.0 { 0.0 } else { derivative(w) }));
+    }
+    fused_loss(loss, params.to_vec(), arr0(total).into_dyn(), d_params)
+}
@@ -0,0 +1,333 @@
+// Shape inference: the output shape of a module for an input shape, worked out from the shape
+// rules of its layers without allocating or computing any data. Handy to check a model fits
+// its input before a long run, or to find which layer breaks on a new input size.
+//
+//     let shapes = symbolic_shapes(&model, &[32, 1, 28, 28])?;
+//     println!("{:?}", shapes.output_shape);
+//
+// Layers state their rule in Module::output_shape, with the functions below for the ops they
+// call. Layers without one (custom modules, by default) are run once on zeros in inference
+// mode instead, so they still work but cost a forward. Mismatches come back as the
+// ShapeMismatch the op would have reported, without operands or location since there are no
+// tensors yet.
+//
+// Shape contracts are the runtime side: model code states the shapes it assumes and gets a
+// clear error where they break, instead of a mismatch a few ops later.
+//
+//     expect_shape!(x, [batch, 784]);
+//     expect_shape!(q, [batch, seq, dim], k, [batch, seq, dim]);
+//     expect_shape!(images, [_, 3, ..]);
+//
+// Identifiers are names, bound to the size they first meet and required to match it
+// everywhere else in the same call. `_` is any size, `..` any number of dims (once per
+// pattern), anything else an expression for the size. Tensor::expect_shape takes the same as
+// a slice of Dim.
+
+use crate::error::{Operand, TensorError};
+use crate::nn::Module;
+use crate::spatial::ConvOptions;
+use crate::tensor::{broadcast_shape, Tensor};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::Location;
+
+// A layer reached by Module::output_shape_traced
+pub struct ShapedLayer<'a> {
+    // Dotted path in the module tree, empty for the root
+    pub name: &'a str,
+    pub kind: &'static str,
+    pub parameters: Vec<Tensor>,
+    pub input_shape: &'a [usize],
+    pub output_shape: &'a [usize],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerShape {
+    pub name: String,
+    pub kind: &'static str,
+    pub input_shape: Vec<usize>,
+    pub output_shape: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shapes {
+    pub input_shape: Vec<usize>,
+    pub output_shape: Vec<usize>,
+    // In the order forward runs them, like summary()
+    pub layers: Vec<LayerShape>,
+}
+
+pub fn symbolic_shapes(module: &dyn Module, input_shape: &[usize]) -> Result<Shapes, TensorError> {
+    let mut layers = Vec::new();
+    let output_shape = module.output_shape_traced(input_shape, "", &mut |layer| {
+        layers.push(LayerShape {
+            name: layer.name.to_string(),
+            kind: layer.kind,
+            input_shape: layer.input_shape.to_vec(),
+            output_shape: layer.output_shape.to_vec(),
+        })
+    })?;
+    Ok(Shapes {
+        input_shape: input_shape.to_vec(),
+        output_shape,
+        layers,
+    })
+}
+
+// Also for the checks layers make themselves, e.g. CausalSelfAttention's input width
+pub(crate) fn mismatch(op: &'static str, lhs: &[usize], rhs: &[usize]) -> TensorError {
+    TensorError::ShapeMismatch {
+        op,
+        lhs: lhs.to_vec(),
+        rhs: rhs.to_vec(),
+        operands: None,
+        location: None,
+    }
+}
+
+// `a + b`, `a * b` and the other broadcasting ops
+pub fn broadcast(
+    op: &'static str,
+    lhs: &[usize],
+    rhs: &[usize],
+) -> Result<Vec<usize>, TensorError> {
+    broadcast_shape(lhs, rhs).ok_or_else(|| mismatch(op, lhs, rhs))
+}
+
+pub fn matmul(lhs: &[usize], rhs: &[usize]) -> Result<Vec<usize>, TensorError> {
+    if lhs.len() != 2 || rhs.len() != 2 || lhs[1] != rhs[0] {
+        return Err(mismatch("matmul", lhs, rhs));
+    }
+    Ok(vec![lhs[0], rhs[1]])
+}
+
+// x W^T + b with W [out, in], see nn::Linear
+pub fn linear(
+    input: &[usize],
+    weight: &[usize],
+    bias: Option<&[usize]>,
+) -> Result<Vec<usize>, TensorError> {
+    let out = matmul(input, &[weight[1], weight[0]])?;
+    match bias {
+        Some(bias) => broadcast("+", &out, bias),
+        None => Ok(out),
+    }
+}
+
+pub fn reshape(input: &[usize], shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+    if input.iter().product::<usize>() != shape.iter().product::<usize>() {
+        return Err(mismatch("reshape", input, shape));
+    }
+    Ok(shape.to_vec())
+}
+
+// conv1d or conv2d, told apart by the weight's rank
+pub fn conv(
+    input: &[usize],
+    weight: &[usize],
+    bias: Option<&[usize]>,
+    options: ConvOptions,
+) -> Result<Vec<usize>, TensorError> {
+    let op = if weight.len() == 3 {
+        "conv1d"
+    } else {
+        "conv2d"
+    };
+    let groups = options.groups;
+    if input.len() != weight.len()
+        || input.len() < 3
+        || input[1] != weight[1] * groups
+        || !weight[0].is_multiple_of(groups)
+    {
+        return Err(mismatch(op, input, weight));
+    }
+    if let Some(bias) = bias {
+        if bias != [weight[0]] {
+            return Err(mismatch(op, weight, bias));
+        }
+    }
+    let spatial = input[2..]
+        .iter()
+        .zip(&weight[2..])
+        .map(|(&i, &k)| options.output_len(i, k))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| mismatch(op, input, weight))?;
+    Ok([&[input[0], weight[0]], spatial.as_slice()].concat())
+}
+
+// max_pool1d and avg_pool1d, the kernel is the rhs of a mismatch
+pub fn pool1d(
+    op: &'static str,
+    input: &[usize],
+    kernel: usize,
+    stride: usize,
+) -> Result<Vec<usize>, TensorError> {
+    if input.len() != 3 || kernel == 0 || stride == 0 || kernel > input[2] {
+        return Err(mismatch(op, input, &[kernel]));
+    }
+    Ok(vec![input[0], input[1], (input[2] - kernel) / stride + 1])
+}
+
+pub fn adaptive_avg_pool2d(
+    input: &[usize],
+    output_size: [usize; 2],
+) -> Result<Vec<usize>, TensorError> {
+    if input.len() != 4 || output_size.contains(&0) {
+        return Err(mismatch("adaptive_avg_pool2d", input, &output_size));
+    }
+    Ok(vec![input[0], input[1], output_size[0], output_size[1]])
+}
+
+// A mismatch when no pixels are left, with the resized height and width as the rhs
+pub fn interpolate(input: &[usize], scale_factor: f32) -> Result<Vec<usize>, TensorError> {
+    if input.len() != 4 {
+        return Err(mismatch("interpolate", input, &[]));
+    }
+    let (height, width) = (
+        (input[2] as f32 * scale_factor) as usize,
+        (input[3] as f32 * scale_factor) as usize,
+    );
+    if height == 0 || width == 0 {
+        return Err(mismatch("interpolate", input, &[height, width]));
+    }
+    Ok(vec![input[0], input[1], height, width])
+}
+
+// A dim of a shape contract, see expect_shape!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dim {
+    // `_`
+    Any,
+    Size(usize),
+    // The same size wherever the name appears in one check
+    Named(&'static str),
+    // `..`, any number of dims
+    Rest,
+}
+
+impl fmt::Display for Dim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dim::Any => f.write_str("_"),
+            Dim::Size(size) => write!(f, "{}", size),
+            Dim::Named(name) => f.write_str(name),
+            Dim::Rest => f.write_str(".."),
+        }
+    }
+}
+
+// Check every tensor against its pattern, with names shared between them. Errors on the
+// first tensor that doesn't fit.
+#[track_caller]
+pub fn check_shapes(contracts: &[(&Tensor, &[Dim])]) -> Result<(), TensorError> {
+    let location = if cfg!(debug_assertions) {
+        Some(Location::caller())
+    } else {
+        None
+    };
+    let mut bound: HashMap<&'static str, (usize, usize)> = HashMap::new();
+    for (t, &(tensor, pattern)) in contracts.iter().enumerate() {
+        let shape = tensor.shape();
+        match_pattern(&shape, pattern, t, &mut bound).map_err(|reason| {
+            TensorError::UnexpectedShape {
+                shape,
+                expected: format!(
+                    "[{}]",
+                    pattern
+                        .iter()
+                        .map(Dim::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                reason,
+                operand: Some(Box::new(Operand::of(tensor))),
+                location,
+            }
+        })?;
+    }
+    Ok(())
+}
+
+// Binds the names of `pattern` into `bound`, by size and the tensor that bound them
+fn match_pattern(
+    shape: &[usize],
+    pattern: &[Dim],
+    tensor: usize,
+    bound: &mut HashMap<&'static str, (usize, usize)>,
+) -> Result<(), String> {
+    assert!(
+        pattern.iter().filter(|&&dim| dim == Dim::Rest).count() <= 1,
+        "a shape pattern takes `..` at most once"
+    );
+    let rest = pattern.iter().position(|&dim| dim == Dim::Rest);
+    let fixed = pattern.len() - rest.is_some() as usize;
+    match rest {
+        None if shape.len() != fixed => {
+            return Err(format!("{} dims instead of {}", shape.len(), fixed));
+        }
+        Some(_) if shape.len() < fixed => {
+            return Err(format!(
+                "{} dims instead of at least {}",
+                shape.len(),
+                fixed
+            ));
+        }
+        _ => {}
+    }
+    // Dims after `..` line up with the end of the shape
+    let skipped = shape.len() - fixed;
+    let dims = pattern
+        .iter()
+        .filter(|&&dim| dim != Dim::Rest)
+        .enumerate()
+        .map(|(i, &dim)| match rest {
+            Some(r) if i >= r => (i + skipped, dim),
+            _ => (i, dim),
+        });
+    for (axis, dim) in dims {
+        let size = shape[axis];
+        match dim {
+            Dim::Size(expected) if size != expected => {
+                return Err(format!("dim {} is {}, not {}", axis, size, expected));
+            }
+            Dim::Named(name) => match bound.get(name) {
+                Some(&(expected, _)) if size != expected => {
+                    return Err(format!(
+                        "dim {} is {}, but `{}` is {}{}",
+                        axis,
+                        size,
+                        name,
+                        expected,
+                        match bound[name].1 {
+                            t if t == tensor => String::new(),
+                            // Counted from 1 like the arguments of expect_shape!
+                            t => format!(" from tensor {}", t + 1),
+                        }
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    bound.insert(name, (size, tensor));
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Tensor {
+    // Panic unless the shape fits `pattern`, returns self for chaining:
+    // `x.expect_shape(&[Dim::Named("batch"), Dim::Size(784)]).matmul(&w)`
+    #[track_caller]
+    pub fn expect_shape(&self, pattern: &[Dim]) -> &Tensor {
+        self.try_expect_shape(pattern)
+            .unwrap_or_else(|e| panic!("{}", e));
+        self
+    }
+
+    #[track_caller]
+    pub fn try_expect_shape(&self, pattern: &[Dim]) -> Result<(), TensorError> {
+        check_shapes(&[(self, pattern)])
+    }
+}
@@ -0,0 +1,786 @@
+// Ops on batches of sequences laid out [N, C, L] and images laid out [N, C, H, W], channels
+// first like PyTorch
+
+use crate::device;
+use crate::error::TensorError;
+use crate::inference;
+use crate::op::Op;
+use crate::profiler;
+use crate::tensor::{accumulate_grad, Tensor, TensorData};
+use ndarray::{s, Array2, Array3, Array4, ArrayD, ArrayView2, Axis, Ix3, Ix4, IxDyn};
+use serde::{Deserialize, Serialize};
+use std::panic::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // Copy the closest input pixel
+    Nearest,
+    // Weighted average of the 4 closest input pixels, with pixel centers aligned like
+    // PyTorch's align_corners=False
+    Bilinear,
+}
+
+// For each output position along an axis, the input positions it reads and their weights
+fn taps(input: usize, output: usize, mode: Interpolation) -> Vec<Vec<(usize, f32)>> {
+    let scale = input as f32 / output as f32;
+    (0..output)
+        .map(|o| match mode {
+            Interpolation::Nearest => {
+                vec![(((o as f32 * scale) as usize).min(input - 1), 1.0)]
+            }
+            Interpolation::Bilinear => {
+                let source = ((o as f32 + 0.5) * scale - 0.5).max(0.0);
+                let low = (source as usize).min(input - 1);
+                let high = (low + 1).min(input - 1);
+                let t = source - low as f32;
+                vec![(low, 1.0 - t), (high, t)]
+            }
+        })
+        .collect()
+}
+
+// Input range each of `output` adaptive pooling cells averages over
+fn adaptive_bins(input: usize, output: usize) -> Vec<std::ops::Range<usize>> {
+    (0..output)
+        .map(|i| i * input / output..((i + 1) * input).div_ceil(output))
+        .collect()
+}
+
+fn as_images(data: &ArrayD<f32>) -> ndarray::ArrayView4<'_, f32> {
+    data.view().into_dimensionality::<Ix4>().unwrap()
+}
+
+impl Tensor {
+    // Resize [N, C, H, W] images to [N, C, floor(H * scale), floor(W * scale)], e.g. 2.0 to
+    // double the resolution in a decoder
+    #[track_caller]
+    pub fn interpolate(&self, scale_factor: f32, mode: Interpolation) -> Tensor {
+        let _timer = profiler::forward("interpolate");
+        let shape = self.shape();
+        assert!(
+            shape.len() == 4,
+            "interpolate expects [N, C, H, W] images, got shape {:?}",
+            shape
+        );
+        assert!(
+            scale_factor > 0.0,
+            "interpolate scale_factor must be positive, got {}",
+            scale_factor
+        );
+        let (height, width) = (
+            (shape[2] as f32 * scale_factor) as usize,
+            (shape[3] as f32 * scale_factor) as usize,
+        );
+        assert!(
+            height > 0 && width > 0,
+            "interpolate by {} leaves no pixels of {:?}",
+            scale_factor,
+            shape
+        );
+        let rows = taps(shape[2], height, mode);
+        let cols = taps(shape[3], width, mode);
+        let resized = {
+            let input = &self.borrow().data;
+            let input = as_images(input);
+            Array4::from_shape_fn((shape[0], shape[1], height, width), |(n, c, y, x)| {
+                let mut value = 0.0;
+                for &(iy, wy) in &rows[y] {
+                    for &(ix, wx) in &cols[x] {
+                        value += wy * wx * input[[n, c, iy, ix]];
+                    }
+                }
+                value
+            })
+            .into_dyn()
+        };
+
+        if inference::is_enabled() {
+            return Tensor::from(resized).on_device_of(self);
+        }
+        let mut new_tensor_data = TensorData::new(resized);
+        new_tensor_data._op = Some(Op::Interpolate { scale_factor, mode });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_images(out.grad.as_ref().unwrap());
+            let Some(Op::Interpolate { mode, .. }) = out._op else {
+                unreachable!()
+            };
+            let input_shape = out._children[0].borrow().data.raw_dim();
+            let (height, width) = (input_shape[2], input_shape[3]);
+            let rows = taps(height, grad.shape()[2], mode);
+            let cols = taps(width, grad.shape()[3], mode);
+
+            // Every output pixel hands its gradient back to the input pixels it was mixed from
+            let mut grad_input = ArrayD::<f32>::zeros(input_shape);
+            {
+                let mut grad_input = grad_input.view_mut().into_dimensionality::<Ix4>().unwrap();
+                for ((n, c, y, x), &g) in grad.indexed_iter() {
+                    for &(iy, wy) in &rows[y] {
+                        for &(ix, wx) in &cols[x] {
+                            grad_input[[n, c, iy, ix]] += wy * wx * g;
+                        }
+                    }
+                }
+            }
+            accumulate_grad(&out._children[0], grad_input);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Tensor::new(new_tensor_data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvOptions {
+    pub stride: usize,
+    // Zeros added on both sides of every spatial axis
+    pub padding: usize,
+    // Spacing between kernel taps, > 1 widens the receptive field without more weights
+    pub dilation: usize,
+    // Channels are split into this many groups convolved separately, the weight is
+    // [C_out, C_in / groups, ...]. groups == C_in is a depthwise convolution.
+    pub groups: usize,
+}
+
+impl Default for ConvOptions {
+    fn default() -> Self {
+        ConvOptions {
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            groups: 1,
+        }
+    }
+}
+
+impl ConvOptions {
+    // Output length along an axis of `input` with a `kernel`-wide kernel, None if the kernel
+    // doesn't fit even once
+    pub(crate) fn output_len(&self, input: usize, kernel: usize) -> Option<usize> {
+        let span = self.dilation * (kernel.max(1) - 1) + 1;
+        (input + 2 * self.padding)
+            .checked_sub(span)
+            .map(|rest| rest / self.stride + 1)
+    }
+
+    // Input position read by kernel tap `k` at output position `o`, None in the padding
+    fn source(&self, o: usize, k: usize, input: usize) -> Option<usize> {
+        (o * self.stride + k * self.dilation)
+            .checked_sub(self.padding)
+            .filter(|&i| i < input)
+    }
+}
+
+#[track_caller]
+fn shape_error(op: &'static str, lhs: Vec<usize>, rhs: Vec<usize>) -> TensorError {
+    TensorError::ShapeMismatch {
+        op,
+        lhs,
+        rhs,
+        operands: None,
+        location: if cfg!(debug_assertions) {
+            Some(Location::caller())
+        } else {
+            None
+        },
+    }
+}
+
+// Where a kernel sliding over one sample's spatial axes reads from, for any number of axes.
+// Spatial positions are flattened row-major.
+struct Geometry {
+    input: usize,
+    kernel: usize,
+    output: Vec<usize>,
+    // Flat input position of kernel tap k at output position o at [k * outputs + o], None in
+    // the padding
+    taps: Vec<Option<usize>>,
+}
+
+impl Geometry {
+    fn new(input: &[usize], kernel: &[usize], options: ConvOptions) -> Option<Geometry> {
+        let output = input
+            .iter()
+            .zip(kernel)
+            .map(|(&i, &k)| options.output_len(i, k))
+            .collect::<Option<Vec<usize>>>()?;
+        let outputs: usize = output.iter().product();
+        let kernels: usize = kernel.iter().product();
+        let mut taps = Vec::with_capacity(kernels * outputs);
+        for k in 0..kernels {
+            for o in 0..outputs {
+                // Peel the axes off the flat indices from the last one
+                let (mut k_rest, mut o_rest, mut position, mut stride) = (k, o, 0, 1);
+                let mut inside = true;
+                for axis in (0..input.len()).rev() {
+                    let (ka, oa) = (k_rest % kernel[axis], o_rest % output[axis]);
+                    k_rest /= kernel[axis];
+                    o_rest /= output[axis];
+                    match options.source(oa, ka, input[axis]) {
+                        Some(i) => position += i * stride,
+                        None => inside = false,
+                    }
+                    stride *= input[axis];
+                }
+                taps.push(inside.then_some(position));
+            }
+        }
+        Some(Geometry {
+            input: input.iter().product(),
+            kernel: kernels,
+            output,
+            taps,
+        })
+    }
+
+    fn outputs(&self) -> usize {
+        self.output.iter().product()
+    }
+
+    // [C, inputs] -> [C * kernels, outputs]: column o holds what the kernel reads at output
+    // position o, channel-major
+    fn unfold(&self, input: ArrayView2<f32>) -> Array2<f32> {
+        let outputs = self.outputs();
+        Array2::from_shape_fn((input.nrows() * self.kernel, outputs), |(row, o)| {
+            let (c, k) = (row / self.kernel, row % self.kernel);
+            self.taps[k * outputs + o].map_or(0.0, |i| input[[c, i]])
+        })
+    }
+
+    // Adjoint of unfold: columns summed back into the [C, inputs] positions they came from
+    fn fold(&self, columns: ArrayView2<f32>) -> Array2<f32> {
+        let outputs = self.outputs();
+        let mut folded = Array2::zeros((columns.nrows() / self.kernel, self.input));
+        for ((row, o), &value) in columns.indexed_iter() {
+            let (c, k) = (row / self.kernel, row % self.kernel);
+            if let Some(i) = self.taps[k * outputs + o] {
+                folded[[c, i]] += value;
+            }
+        }
+        folded
+    }
+}
+
+// [N, C, ...spatial] as [N, C, flattened spatial]
+fn flatten_spatial(data: &ArrayD<f32>) -> Array3<f32> {
+    let shape = data.shape();
+    let spatial = shape[2..].iter().product();
+    data.to_owned()
+        .into_shape((shape[0], shape[1], spatial))
+        .unwrap()
+}
+
+// Convolution over the trailing spatial axes, shared by conv1d and conv2d
+#[track_caller]
+fn convolve(
+    op: &'static str,
+    input: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    options: ConvOptions,
+    spatial_axes: usize,
+) -> Result<Tensor, TensorError> {
+    let _timer = profiler::forward(op);
+    assert!(
+        options.stride > 0 && options.dilation > 0 && options.groups > 0,
+        "{} stride, dilation and groups must be at least 1",
+        op
+    );
+    let operands = [input, weight].into_iter().chain(bias).collect::<Vec<_>>();
+    device::check_same(op, &operands)?;
+    let (input_shape, weight_shape) = (input.shape(), weight.shape());
+    let groups = options.groups;
+    if input_shape.len() != spatial_axes + 2
+        || weight_shape.len() != spatial_axes + 2
+        || input_shape[1] != weight_shape[1] * groups
+        || weight_shape[0] % groups != 0
+    {
+        return Err(shape_error(op, input_shape, weight_shape).with_operands(input, weight));
+    }
+    let (batch, out_channels) = (input_shape[0], weight_shape[0]);
+    if let Some(bias) = bias {
+        if bias.shape() != [out_channels] {
+            return Err(shape_error(op, weight_shape, bias.shape()).with_operands(weight, bias));
+        }
+    }
+    let Some(geometry) = Geometry::new(&input_shape[2..], &weight_shape[2..], options) else {
+        return Err(shape_error(op, input_shape, weight_shape).with_operands(input, weight));
+    };
+
+    let output = {
+        let input = flatten_spatial(&input.borrow().data);
+        let weight = weight.borrow();
+        let weight = weight
+            .data
+            .view()
+            .into_shape((out_channels, weight_shape[1] * geometry.kernel))
+            .unwrap();
+        let mut output = Array3::<f32>::zeros((batch, out_channels, geometry.outputs()));
+        for (n, mut out) in output.outer_iter_mut().enumerate() {
+            let columns = geometry.unfold(input.index_axis(Axis(0), n));
+            grouped_matmul(weight, columns.view(), groups, out.view_mut());
+            if let Some(bias) = bias {
+                out += &bias.borrow().data.view().insert_axis(Axis(1));
+            }
+        }
+        let shape = [&[batch, out_channels], geometry.output.as_slice()].concat();
+        output.into_shape(IxDyn(&shape)).unwrap()
+    };
+
+    if inference::is_enabled() {
+        return Ok(Tensor::from(output).on_device_of(input));
+    }
+    let mut new_tensor_data = TensorData::new(output);
+    new_tensor_data._op = Some(if spatial_axes == 1 {
+        Op::Conv1d(options)
+    } else {
+        Op::Conv2d(options)
+    });
+    new_tensor_data._children = vec![input.clone(), weight.clone()];
+    new_tensor_data._children.extend(bias.cloned());
+
+    fn backward(out: &TensorData) {
+        let grad = flatten_spatial(out.grad.as_ref().unwrap());
+        let Some(Op::Conv1d(options) | Op::Conv2d(options)) = out._op else {
+            unreachable!()
+        };
+        let input = out._children[0].borrow().data.clone();
+        let weight = out._children[1].borrow().data.clone();
+        let geometry = Geometry::new(&input.shape()[2..], &weight.shape()[2..], options).unwrap();
+        let input_shape = input.raw_dim();
+        let input = flatten_spatial(&input);
+        let weight_shape = weight.raw_dim();
+        let weight = weight
+            .into_shape((weight_shape[0], weight_shape[1] * geometry.kernel))
+            .unwrap();
+
+        // Per group out[n] = W cols[n], so dW = sum_n G[n] cols[n]^T and dcols[n] = W^T G[n]
+        let (out_per_group, rows_per_group) = (weight.nrows() / options.groups, weight.ncols());
+        let mut grad_weight = Array2::<f32>::zeros(weight.raw_dim());
+        let mut grad_input = Array3::<f32>::zeros(input.raw_dim());
+        for (n, g) in grad.outer_iter().enumerate() {
+            let columns = geometry.unfold(input.index_axis(Axis(0), n));
+            let mut grad_columns = Array2::<f32>::zeros(columns.raw_dim());
+            for group in 0..options.groups {
+                let outs = s![group * out_per_group..(group + 1) * out_per_group, ..];
+                let rows = s![group * rows_per_group..(group + 1) * rows_per_group, ..];
+                let g = g.slice(outs);
+                grad_weight
+                    .slice_mut(outs)
+                    .scaled_add(1.0, &g.dot(&columns.slice(rows).t()));
+                grad_columns
+                    .slice_mut(rows)
+                    .assign(&weight.slice(outs).t().dot(&g));
+            }
+            grad_input
+                .index_axis_mut(Axis(0), n)
+                .assign(&geometry.fold(grad_columns.view()));
+        }
+        accumulate_grad(
+            &out._children[0],
+            grad_input.into_shape(input_shape).unwrap(),
+        );
+        accumulate_grad(
+            &out._children[1],
+            grad_weight.into_shape(weight_shape).unwrap(),
+        );
+        if let Some(bias) = out._children.get(2) {
+            accumulate_grad(bias, grad.sum_axis(Axis(2)).sum_axis(Axis(0)).into_dyn());
+        }
+    }
+    new_tensor_data._backward = Some(backward);
+
+    Ok(Tensor::new(new_tensor_data))
+}
+
+// out = W cols with both split into `groups` blocks: output rows of group g only see the
+// column rows of the input channels in g
+fn grouped_matmul(
+    weight: ArrayView2<f32>,
+    columns: ArrayView2<f32>,
+    groups: usize,
+    mut out: ndarray::ArrayViewMut2<f32>,
+) {
+    let (out_per_group, rows_per_group) = (weight.nrows() / groups, weight.ncols());
+    for group in 0..groups {
+        let outs = s![group * out_per_group..(group + 1) * out_per_group, ..];
+        let rows = s![group * rows_per_group..(group + 1) * rows_per_group, ..];
+        out.slice_mut(outs)
+            .assign(&weight.slice(outs).dot(&columns.slice(rows)));
+    }
+}
+
+impl Tensor {
+    // 1-D convolution (cross-correlation, like PyTorch) of [N, C_in, L] sequences with a
+    // [C_out, C_in / groups, K] weight and optional [C_out] bias, [N, C_out, L_out] out
+    #[track_caller]
+    pub fn conv1d(&self, weight: &Tensor, bias: Option<&Tensor>, options: ConvOptions) -> Tensor {
+        self.try_conv1d(weight, bias, options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_conv1d(
+        &self,
+        weight: &Tensor,
+        bias: Option<&Tensor>,
+        options: ConvOptions,
+    ) -> Result<Tensor, TensorError> {
+        convolve("conv1d", self, weight, bias, options, 1)
+    }
+
+    // 2-D convolution of [N, C_in, H, W] images with a [C_out, C_in / groups, KH, KW] weight
+    // and optional [C_out] bias, [N, C_out, H_out, W_out] out. Options apply to both axes.
+    #[track_caller]
+    pub fn conv2d(&self, weight: &Tensor, bias: Option<&Tensor>, options: ConvOptions) -> Tensor {
+        self.try_conv2d(weight, bias, options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_conv2d(
+        &self,
+        weight: &Tensor,
+        bias: Option<&Tensor>,
+        options: ConvOptions,
+    ) -> Result<Tensor, TensorError> {
+        convolve("conv2d", self, weight, bias, options, 2)
+    }
+
+    // The convolution's unfolding step (PyTorch's unfold) as an op of its own, for custom
+    // convolution variants: [N, C, H, W] -> [N, C * KH * KW, L] where column l holds the
+    // KH x KW patch of every channel that output position l of a conv2d with these options
+    // would read, padding as zeros. `options.groups` isn't used.
+    #[track_caller]
+    pub fn im2col(&self, kernel: [usize; 2], options: ConvOptions) -> Tensor {
+        self.try_im2col(kernel, options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_im2col(
+        &self,
+        kernel: [usize; 2],
+        options: ConvOptions,
+    ) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("im2col");
+        let shape = self.shape();
+        let geometry = (shape.len() == 4)
+            .then(|| Geometry::new(&shape[2..], &kernel, options))
+            .flatten()
+            .ok_or_else(|| shape_error("im2col", shape.clone(), kernel.to_vec()))?;
+        let columns = {
+            let input = flatten_spatial(&self.borrow().data);
+            let mut columns =
+                Array3::<f32>::zeros((shape[0], shape[1] * geometry.kernel, geometry.outputs()));
+            for (n, mut out) in columns.outer_iter_mut().enumerate() {
+                out.assign(&geometry.unfold(input.index_axis(Axis(0), n)));
+            }
+            columns.into_dyn()
+        };
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(columns).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(columns);
+        new_tensor_data._op = Some(Op::Im2col { kernel, options });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out
+                .grad
+                .as_ref()
+                .unwrap()
+                .view()
+                .into_dimensionality::<Ix3>()
+                .unwrap();
+            let Some(Op::Im2col { kernel, options }) = out._op else {
+                unreachable!()
+            };
+            let input_shape = out._children[0].borrow().data.raw_dim();
+            let geometry = Geometry::new(
+                &out._children[0].borrow().data.shape()[2..],
+                &kernel,
+                options,
+            )
+            .unwrap();
+
+            let mut grad_input =
+                Array3::<f32>::zeros((input_shape[0], input_shape[1], geometry.input));
+            for (n, g) in grad.outer_iter().enumerate() {
+                grad_input
+                    .index_axis_mut(Axis(0), n)
+                    .assign(&geometry.fold(g));
+            }
+            accumulate_grad(
+                &out._children[0],
+                grad_input.into_shape(input_shape).unwrap(),
+            );
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Inverse layout of im2col (PyTorch's fold): [N, C * KH * KW, L] columns back to
+    // [N, C, H, W] images of `output_size`, summing the values patches put on the same pixel.
+    // With overlapping patches that's the adjoint of im2col rather than its inverse.
+    #[track_caller]
+    pub fn col2im(
+        &self,
+        output_size: [usize; 2],
+        kernel: [usize; 2],
+        options: ConvOptions,
+    ) -> Tensor {
+        self.try_col2im(output_size, kernel, options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_col2im(
+        &self,
+        output_size: [usize; 2],
+        kernel: [usize; 2],
+        options: ConvOptions,
+    ) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("col2im");
+        let shape = self.shape();
+        let geometry = Geometry::new(&output_size, &kernel, options).filter(|geometry| {
+            shape.len() == 3
+                && shape[1].is_multiple_of(geometry.kernel)
+                && shape[2] == geometry.outputs()
+        });
+        let Some(geometry) = geometry else {
+            return Err(shape_error("col2im", shape, [output_size, kernel].concat()));
+        };
+        let channels = shape[1] / geometry.kernel;
+        let images = {
+            let columns = self.borrow();
+            let columns = columns.data.view().into_dimensionality::<Ix3>().unwrap();
+            let mut images = Array3::<f32>::zeros((shape[0], channels, geometry.input));
+            for (n, mut out) in images.outer_iter_mut().enumerate() {
+                out.assign(&geometry.fold(columns.index_axis(Axis(0), n)));
+            }
+            images
+                .into_shape(IxDyn(&[shape[0], channels, output_size[0], output_size[1]]))
+                .unwrap()
+        };
+
+        if inference::is_enabled() {
+            return Ok(Tensor::from(images).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(images);
+        new_tensor_data._op = Some(Op::Col2im {
+            output_size,
+            kernel,
+            options,
+        });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = flatten_spatial(out.grad.as_ref().unwrap());
+            let Some(Op::Col2im {
+                output_size,
+                kernel,
+                options,
+            }) = out._op
+            else {
+                unreachable!()
+            };
+            let geometry = Geometry::new(&output_size, &kernel, options).unwrap();
+
+            // The adjoint of a fold is the unfold
+            let columns_shape = out._children[0].borrow().data.raw_dim();
+            let mut grad_columns =
+                Array3::<f32>::zeros((columns_shape[0], columns_shape[1], columns_shape[2]));
+            for (n, g) in grad.outer_iter().enumerate() {
+                grad_columns
+                    .index_axis_mut(Axis(0), n)
+                    .assign(&geometry.unfold(g));
+            }
+            accumulate_grad(&out._children[0], grad_columns.into_dyn());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Average pooling of [N, C, H, W] feature maps down to [N, C, OH, OW] whatever H and W
+    // are: output cell i covers input rows floor(i H / OH) to ceil((i + 1) H / OH), like
+    // PyTorch, and the same for columns
+    #[track_caller]
+    pub fn adaptive_avg_pool2d(&self, output_size: [usize; 2]) -> Tensor {
+        let _timer = profiler::forward("adaptive_avg_pool2d");
+        let shape = self.shape();
+        assert!(
+            shape.len() == 4,
+            "adaptive_avg_pool2d expects [N, C, H, W] images, got shape {:?}",
+            shape
+        );
+        assert!(
+            output_size[0] > 0 && output_size[1] > 0,
+            "adaptive_avg_pool2d output size must be positive, got {:?}",
+            output_size
+        );
+        let rows = adaptive_bins(shape[2], output_size[0]);
+        let cols = adaptive_bins(shape[3], output_size[1]);
+        let pooled = {
+            let input = &self.borrow().data;
+            let input = as_images(input);
+            Array4::from_shape_fn(
+                (shape[0], shape[1], output_size[0], output_size[1]),
+                |(n, c, y, x)| {
+                    let window = input.slice(s![n, c, rows[y].clone(), cols[x].clone()]);
+                    window.sum() / window.len() as f32
+                },
+            )
+            .into_dyn()
+        };
+
+        if inference::is_enabled() {
+            return Tensor::from(pooled).on_device_of(self);
+        }
+        let mut new_tensor_data = TensorData::new(pooled);
+        new_tensor_data._op = Some(Op::AdaptiveAvgPool2d { output_size });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_images(out.grad.as_ref().unwrap());
+            let input_shape = out._children[0].borrow().data.raw_dim();
+            let rows = adaptive_bins(input_shape[2], grad.shape()[2]);
+            let cols = adaptive_bins(input_shape[3], grad.shape()[3]);
+
+            // Each cell spreads its gradient evenly over the pixels it averaged, bins can
+            // overlap when the output is larger than the input
+            let mut grad_input = Array4::<f32>::zeros((
+                input_shape[0],
+                input_shape[1],
+                input_shape[2],
+                input_shape[3],
+            ));
+            for ((n, c, y, x), &g) in grad.indexed_iter() {
+                let mut window = grad_input.slice_mut(s![n, c, rows[y].clone(), cols[x].clone()]);
+                let share = g / window.len() as f32;
+                window.mapv_inplace(|v| v + share);
+            }
+            accumulate_grad(&out._children[0], grad_input.into_dyn());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Tensor::new(new_tensor_data)
+    }
+
+    // Mean of every channel of [N, C, H, W] feature maps as [N, C], ready for a Linear
+    // classifier head
+    #[track_caller]
+    pub fn global_avg_pool2d(&self) -> Tensor {
+        let shape = self.shape();
+        self.adaptive_avg_pool2d([1, 1]).reshape(&shape[..2])
+    }
+
+    // Maximum over windows of `kernel` steps of [N, C, L] sequences, moving by `stride`
+    #[track_caller]
+    pub fn max_pool1d(&self, kernel: usize, stride: usize) -> Tensor {
+        self.pool1d("max_pool1d", kernel, stride)
+    }
+
+    // Mean over windows of `kernel` steps of [N, C, L] sequences, moving by `stride`
+    #[track_caller]
+    pub fn avg_pool1d(&self, kernel: usize, stride: usize) -> Tensor {
+        self.pool1d("avg_pool1d", kernel, stride)
+    }
+
+    #[track_caller]
+    fn pool1d(&self, op: &'static str, kernel: usize, stride: usize) -> Tensor {
+        let _timer = profiler::forward(op);
+        let shape = self.shape();
+        assert!(
+            shape.len() == 3,
+            "{} expects [N, C, L] sequences, got shape {:?}",
+            op,
+            shape
+        );
+        assert!(
+            kernel > 0 && stride > 0 && kernel <= shape[2],
+            "{} needs 0 < kernel <= {} and a positive stride, got kernel {} and stride {}",
+            op,
+            shape[2],
+            kernel,
+            stride
+        );
+        let len = (shape[2] - kernel) / stride + 1;
+        let pooled = {
+            let input = self.borrow();
+            let input = input.data.view().into_dimensionality::<Ix3>().unwrap();
+            Array3::from_shape_fn((shape[0], shape[1], len), |(n, c, o)| {
+                let window = input.slice(s![n, c, o * stride..o * stride + kernel]);
+                if op == "max_pool1d" {
+                    window.fold(f32::NEG_INFINITY, |m, &x| m.max(x))
+                } else {
+                    window.sum() / kernel as f32
+                }
+            })
+            .into_dyn()
+        };
+
+        if inference::is_enabled() {
+            return Tensor::from(pooled).on_device_of(self);
+        }
+        let mut new_tensor_data = TensorData::new(pooled);
+        new_tensor_data._op = Some(if op == "max_pool1d" {
+            Op::MaxPool1d { kernel, stride }
+        } else {
+            Op::AvgPool1d { kernel, stride }
+        });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out
+                .grad
+                .as_ref()
+                .unwrap()
+                .view()
+                .into_dimensionality::<Ix3>()
+                .unwrap();
+            let (kernel, stride, is_max) = match out._op {
+                Some(Op::MaxPool1d { kernel, stride }) => (kernel, stride, true),
+                Some(Op::AvgPool1d { kernel, stride }) => (kernel, stride, false),
+                _ => unreachable!(),
+            };
+            let input = out._children[0].borrow().data.clone();
+            let input = input.into_dimensionality::<Ix3>().unwrap();
+
+            // Max pooling routes each window's gradient to its (first) maximum, average pooling
+            // spreads it evenly
+            let mut grad_input = Array3::<f32>::zeros(input.raw_dim());
+            for ((n, c, o), &g) in grad.indexed_iter() {
+                let start = o * stride;
+                if is_max {
+                    let window = input.slice(s![n, c, start..start + kernel]);
+                    let argmax = window
+                        .iter()
+                        .enumerate()
+                        .fold((0, f32::NEG_INFINITY), |best, (i, &x)| {
+                            if x > best.1 {
+                                (i, x)
+                            } else {
+                                best
+                            }
+                        })
+                        .0;
+                    grad_input[[n, c, start + argmax]] += g;
+                } else {
+                    grad_input
+                        .slice_mut(s![n, c, start..start + kernel])
+                        .mapv_inplace(|x| x + g / kernel as f32);
+                }
+            }
+            accumulate_grad(&out._children[0], grad_input.into_dyn());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Tensor::new(new_tensor_data)
+    }
+}
@@ -2,21 +2,100 @@
 // This causes some serious bugs when using .borrow() for interior mutabililty
 // because bringing it into scope overwrites correct borrow() function
 
-use ndarray::{arr0, ArrayD};
+use crate::anomaly;
+use crate::device::{self, Binary, Device, Unary};
+use crate::error::{Operand, TensorError};
+use crate::inference;
+use crate::memory;
+use crate::named;
+use crate::op::Op;
+use crate::profiler;
+use ndarray::{ArrayD, Axis, Ix2, IxDyn};
 use std::cell::RefCell;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
+use std::panic::Location;
 use std::rc::Rc;
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct TensorData {
+    // Name to tell the tensor apart when debugging, see `Tensor::named`
+    pub label: Option<String>,
+    // One name per axis, "_" for unnamed ones, see the named module
+    pub dim_names: Option<Vec<&'static str>>,
+    // Where `data` lives, see the device module
+    pub device: Device,
     pub data: ArrayD<f32>,
     pub grad: Option<ArrayD<f32>>,
-    pub _op: Option<String>,
+    // Whether backward fills in `grad`, only meaningful for leaves. A parameter with it off is
+    // frozen, optimizers skip it since it never gets a gradient.
+    pub requires_grad: bool,
+    // Whether row lookups (gather_rows) leave their gradient in `sparse_grad` instead of
+    // scattering it into a dense `grad`, for large embedding tables
+    pub sparse: bool,
+    pub sparse_grad: Option<SparseGrad>,
+    pub _op: Option<Op>,
     pub _children: Vec<Tensor>,
     pub _backward: Option<fn(out: &TensorData)>,
+    // Extra values the backward pass needs besides the children (indices, masks, ...)
+    pub _saved: Vec<ArrayD<f32>>,
+    // Integer values the backward pass needs, the rows a gather_rows picked
+    pub _indices: Rc<[usize]>,
     pub _uuid: Uuid,
+    // Where the tensor was created, only captured in debug builds. Ops are #[track_caller] so
+    // this is the user's expression, or the library line for ops a layer runs internally.
+    pub _location: Option<&'static Location<'static>>,
+    // What memory::stats() currently counts for this tensor
+    _tracked_bytes: usize,
+}
+
+// Gradient of a tensor that only a few rows (along the first axis) received, e.g. from an
+// embedding lookup: `values` holds one row per entry of `indices`. An index can appear more
+// than once until the gradient is coalesced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseGrad {
+    pub indices: Vec<usize>,
+    // [indices.len(), ...row shape]
+    pub values: ArrayD<f32>,
+}
+
+impl SparseGrad {
+    // Sorted unique indices with the rows of duplicates summed
+    pub fn coalesce(&self) -> SparseGrad {
+        let mut order: Vec<usize> = (0..self.indices.len()).collect();
+        order.sort_by_key(|&i| self.indices[i]);
+        let mut indices: Vec<usize> = Vec::new();
+        let mut rows: Vec<ArrayD<f32>> = Vec::new();
+        for i in order {
+            let row = self.values.index_axis(Axis(0), i);
+            if indices.last() == Some(&self.indices[i]) {
+                *rows.last_mut().unwrap() += &row;
+            } else {
+                indices.push(self.indices[i]);
+                rows.push(row.to_owned());
+            }
+        }
+        let mut shape = self.values.shape().to_vec();
+        shape[0] = indices.len();
+        let views: Vec<_> = rows.iter().map(|r| r.view()).collect();
+        let values = if views.is_empty() {
+            ArrayD::zeros(IxDyn(&shape))
+        } else {
+            ndarray::stack(Axis(0), &views).unwrap()
+        };
+        SparseGrad { indices, values }
+    }
+
+    // Dense gradient of a tensor of `shape`, zero outside the stored rows
+    pub fn to_dense(&self, shape: &[usize]) -> ArrayD<f32> {
+        let mut dense = ArrayD::zeros(IxDyn(shape));
+        for (i, &index) in self.indices.iter().enumerate() {
+            let mut row = dense.index_axis_mut(Axis(0), index);
+            row += &self.values.index_axis(Axis(0), i);
+        }
+        dense
+    }
 }
 
 // Wrapper around TensorData, access Tensordata content: tensor.0.borrow()
@@ -24,30 +103,136 @@ pub struct TensorData {
 pub struct Tensor(Rc<RefCell<TensorData>>);
 
 impl TensorData {
+    // #[track_caller] to record the creation site, see `_location`
+    #[track_caller]
     pub fn new(data: ArrayD<f32>) -> TensorData {
+        let bytes = data.len() * size_of::<f32>();
+        memory::track_new(bytes);
         TensorData {
+            label: None,
+            dim_names: None,
+            device: Device::Cpu,
             data,
             grad: None,
+            requires_grad: true,
+            sparse: false,
+            sparse_grad: None,
             _op: None,
             _children: Vec::new(),
             _backward: None,
-            _uuid: Uuid::new_v4(),
+            _saved: Vec::new(),
+            _indices: Rc::from([]),
+            _uuid: if inference::is_enabled() {
+                inference::next_id()
+            } else {
+                Uuid::new_v4()
+            },
+            _location: if cfg!(debug_assertions) {
+                Some(Location::caller())
+            } else {
+                None
+            },
+            _tracked_bytes: bytes,
         }
     }
+
+    // Bring memory::stats() up to date after the data, gradient or saved values changed size
+    pub(crate) fn retrack(&mut self) {
+        let values = self.data.len()
+            + self.grad.as_ref().map_or(0, |g| g.len())
+            + self._saved.iter().map(|s| s.len()).sum::<usize>();
+        let bytes = values * size_of::<f32>()
+            + self._indices.len() * size_of::<usize>()
+            + self.sparse_grad.as_ref().map_or(0, |g| {
+                g.values.len() * size_of::<f32>() + g.indices.len() * size_of::<usize>()
+            });
+        memory::track_resize(self._tracked_bytes, bytes);
+        self._tracked_bytes = bytes;
+    }
+}
+
+impl Drop for TensorData {
+    fn drop(&mut self) {
+        memory::track_drop(self._tracked_bytes);
+    }
 }
 
 impl Tensor {
-    pub fn new(data: TensorData) -> Tensor {
+    pub fn new(mut data: TensorData) -> Tensor {
+        // Ops fill in their saved values after TensorData::new
+        data.retrack();
+        // Op outputs are on the device of their inputs
+        if let Some(child) = data._children.first() {
+            data.device = child.borrow().device;
+        }
+        profiler::allocated(&data);
         Tensor(Rc::new(RefCell::new(data)))
     }
 
+    pub fn shape(&self) -> Vec<usize> {
+        self.borrow().data.shape().to_vec()
+    }
+
+    pub fn ndim(&self) -> usize {
+        self.borrow().data.ndim()
+    }
+
+    pub fn numel(&self) -> usize {
+        self.borrow().data.len()
+    }
+
+    // 0-dimensional tensor, as produced by e.g. `arr0(1.0)`
+    pub fn is_scalar(&self) -> bool {
+        self.ndim() == 0
+    }
+
+    // Value of a single-element tensor, any shape as long as it holds exactly one value
+    pub fn item(&self) -> f32 {
+        self.try_item().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_item(&self) -> Result<f32, TensorError> {
+        let data = &self.borrow().data;
+        if data.len() != 1 {
+            return Err(TensorError::NonScalarItem {
+                shape: data.shape().to_vec(),
+            });
+        }
+        Ok(*data.iter().next().unwrap())
+    }
+
+    // Flattened copy of the data in logical (row-major) order
+    pub fn to_vec(&self) -> Vec<f32> {
+        self.borrow().data.iter().copied().collect()
+    }
+
+    pub fn to_vec2(&self) -> Vec<Vec<f32>> {
+        let data = &self.borrow().data;
+        assert!(
+            data.ndim() == 2,
+            "to_vec2() requires a 2-D tensor, got shape {:?}",
+            data.shape()
+        );
+        data.outer_iter()
+            .map(|row| row.iter().copied().collect())
+            .collect()
+    }
+
+    #[track_caller]
     pub fn tanh(&self) -> Tensor {
-        let data = self.borrow().data.clone();
+        let _timer = profiler::forward("tanh");
         // Tanh forward
-        let tanh_data = data.mapv(|x| x.tanh());
+        let tanh_data = device::map(self.device(), Unary::Tanh, &self.borrow().data);
 
+        let dim_names = self.dim_names();
+        if inference::is_enabled() {
+            return Tensor::from(tanh_data)
+                .with_dims(dim_names)
+                .on_device_of(self);
+        }
         let mut new_tensor_data = TensorData::new(tanh_data);
-        new_tensor_data._op = Some(String::from("tanh"));
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Tanh);
         new_tensor_data._children = vec![self.clone()];
 
         fn backward(out: &TensorData) {
@@ -56,20 +241,54 @@ impl Tensor {
 
             // Tanh derivative: (1 - tanh^2) * grad
             let grad_input = grad * (1.0 - &tanh_out * &tanh_out);
-            out._children[0].borrow_mut().grad = Some(grad_input);
+            accumulate_grad(&out._children[0], grad_input);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Tensor::new(new_tensor_data)
+    }
+
+    #[track_caller]
+    pub fn exp(&self) -> Tensor {
+        let _timer = profiler::forward("exp");
+        let exp_data = device::map(self.device(), Unary::Exp, &self.borrow().data);
+
+        let dim_names = self.dim_names();
+        if inference::is_enabled() {
+            return Tensor::from(exp_data)
+                .with_dims(dim_names)
+                .on_device_of(self);
+        }
+        let mut new_tensor_data = TensorData::new(exp_data);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Exp);
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            // exp is its own derivative
+            let grad_input = out.grad.clone().unwrap() * &out.data;
+            accumulate_grad(&out._children[0], grad_input);
         }
         new_tensor_data._backward = Some(backward);
 
         Tensor::new(new_tensor_data)
     }
 
+    #[track_caller]
     pub fn relu(&self) -> Tensor {
-        let data = self.borrow().data.clone();
+        let _timer = profiler::forward("relu");
         // ReLU forward: max(0, x)
-        let relu_data = data.mapv(|x| if x > 0.0 { x } else { 0.0 });
+        let relu_data = device::map(self.device(), Unary::Relu, &self.borrow().data);
 
+        let dim_names = self.dim_names();
+        if inference::is_enabled() {
+            return Tensor::from(relu_data)
+                .with_dims(dim_names)
+                .on_device_of(self);
+        }
         let mut new_tensor_data = TensorData::new(relu_data);
-        new_tensor_data._op = Some(String::from("relu"));
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Relu);
         new_tensor_data._children = vec![self.clone()];
 
         fn backward(out: &TensorData) {
@@ -78,29 +297,507 @@ impl Tensor {
 
             // ReLU derivative: 1 if x > 0, 0 otherwise
             let grad_input = grad * relu_out.mapv(|x| if x > 0.0 { 1.0 } else { 0.0 });
-            out._children[0].borrow_mut().grad = Some(grad_input);
+            accumulate_grad(&out._children[0], grad_input);
         }
         new_tensor_data._backward = Some(backward);
 
         Tensor::new(new_tensor_data)
     }
 
+    // exp(x) / sum(exp(x)) along `axis`, shifted by the max for stability
+    #[track_caller]
+    pub fn softmax(&self, axis: usize) -> Tensor {
+        self.try_softmax(axis).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_softmax(&self, axis: usize) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("softmax");
+        let softmax_data = {
+            let data = &self.borrow().data;
+            if axis >= data.ndim() {
+                return Err(TensorError::InvalidAxis {
+                    axis,
+                    ndim: data.ndim(),
+                });
+            }
+            let max = data
+                .fold_axis(Axis(axis), f32::NEG_INFINITY, |&m, &x| m.max(x))
+                .insert_axis(Axis(axis));
+            let exp = (data - &max).mapv(f32::exp);
+            let sum = exp.sum_axis(Axis(axis)).insert_axis(Axis(axis));
+            exp / sum
+        };
+
+        let dim_names = self.dim_names();
+        if inference::is_enabled() {
+            return Ok(Tensor::from(softmax_data)
+                .with_dims(dim_names)
+                .on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(softmax_data);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Softmax { axis });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.as_ref().unwrap();
+            let Some(Op::Softmax { axis }) = out._op else {
+                unreachable!()
+            };
+            let axis = Axis(axis);
+
+            // Softmax derivative: y * (g - sum(g * y))
+            let dot = (grad * &out.data).sum_axis(axis).insert_axis(axis);
+            let grad_input = &out.data * &(grad - &dot);
+            accumulate_grad(&out._children[0], grad_input);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Sum along `axis`, which is dropped from the shape
+    #[track_caller]
+    pub fn sum_axis(&self, axis: usize) -> Tensor {
+        self.try_sum_axis(axis).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_sum_axis(&self, axis: usize) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("sum");
+        let summed = {
+            let data = &self.borrow().data;
+            if axis >= data.ndim() {
+                return Err(TensorError::InvalidAxis {
+                    axis,
+                    ndim: data.ndim(),
+                });
+            }
+            device::sum_axis(self.device(), data, axis)
+        };
+
+        let dim_names = self.dim_names().map(|mut names| {
+            names.remove(axis);
+            names
+        });
+        if inference::is_enabled() {
+            return Ok(Tensor::from(summed).with_dims(dim_names).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(summed);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Sum { axis });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let Some(Op::Sum { axis }) = out._op else {
+                unreachable!()
+            };
+            // Every summed element gets the gradient of its sum
+            let input_shape = out._children[0].borrow().data.raw_dim();
+            let grad = out.grad.as_ref().unwrap().view().insert_axis(Axis(axis));
+            let grad_input = grad.broadcast(input_shape).unwrap().to_owned();
+            accumulate_grad(&out._children[0], grad_input);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Matrix product of two 2-D tensors, [n, k] x [k, m] -> [n, m]
+    #[track_caller]
+    pub fn matmul(&self, other: &Tensor) -> Tensor {
+        match self.try_matmul(other) {
+            Ok(out) => out,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_matmul(&self, other: &Tensor) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("matmul");
+        device::check_same("matmul", &[self, other])?;
+        let (lhs, rhs) = (self.shape(), other.shape());
+        if lhs.len() != 2 || rhs.len() != 2 || lhs[1] != rhs[0] {
+            return Err(TensorError::ShapeMismatch {
+                op: "matmul",
+                lhs,
+                rhs,
+                operands: Some(Box::new([Operand::of(self), Operand::of(other)])),
+                location: if cfg!(debug_assertions) {
+                    Some(Location::caller())
+                } else {
+                    None
+                },
+            });
+        }
+        let dim_names = named::matmul(self, other)?;
+        let product = device::matmul(
+            self.device(),
+            as_matrix(&self.borrow().data).view(),
+            as_matrix(&other.borrow().data).view(),
+        );
+
+        let product = product.into_dyn();
+        if inference::is_enabled() {
+            return Ok(Tensor::from(product)
+                .with_dims(dim_names)
+                .on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(product);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Matmul);
+        new_tensor_data._children = vec![self.clone(), other.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = as_matrix(out.grad.as_ref().unwrap());
+            let left = as_matrix(&out._children[0].borrow().data);
+            let right = as_matrix(&out._children[1].borrow().data);
+
+            // d(A B)/dA = G B^T, d(A B)/dB = A^T G
+            let device = out.device;
+            let grad_left = device::matmul(device, grad.view(), right.t()).into_dyn();
+            let grad_right = device::matmul(device, left.t(), grad.view()).into_dyn();
+            accumulate_grad(&out._children[0], grad_left);
+            accumulate_grad(&out._children[1], grad_right);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Same values in a new shape with the same number of elements, row-major order
+    #[track_caller]
+    pub fn reshape(&self, shape: &[usize]) -> Tensor {
+        self.try_reshape(shape).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_reshape(&self, shape: &[usize]) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("reshape");
+        let reshaped = {
+            let data = &self.borrow().data;
+            if data.len() != shape.iter().product::<usize>() {
+                return Err(TensorError::ShapeMismatch {
+                    op: "reshape",
+                    lhs: data.shape().to_vec(),
+                    rhs: shape.to_vec(),
+                    operands: Some(Box::new([Operand::of(self), Operand::default()])),
+                    location: if cfg!(debug_assertions) {
+                        Some(Location::caller())
+                    } else {
+                        None
+                    },
+                });
+            }
+            data.as_standard_layout()
+                .into_owned()
+                .into_shape(IxDyn(shape))
+                .unwrap()
+        };
+
+        let dim_names = self.dim_names().filter(|_| self.shape() == shape);
+        if inference::is_enabled() {
+            return Ok(Tensor::from(reshaped)
+                .with_dims(dim_names)
+                .on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(reshaped);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Reshape);
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.as_ref().unwrap().as_standard_layout().into_owned();
+            let shape = out._children[0].borrow().data.raw_dim();
+            accumulate_grad(&out._children[0], grad.into_shape(shape).unwrap());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Transpose of a 2-D tensor
+    #[track_caller]
+    pub fn t(&self) -> Tensor {
+        let _timer = profiler::forward("t");
+        let data = self.borrow().data.clone();
+        assert!(
+            data.ndim() == 2,
+            "t() expects a 2-D tensor, got shape {:?}",
+            data.shape()
+        );
+        let transposed = data.reversed_axes().as_standard_layout().into_owned();
+
+        let dim_names = self
+            .dim_names()
+            .map(|names| names.into_iter().rev().collect());
+        if inference::is_enabled() {
+            return Tensor::from(transposed)
+                .with_dims(dim_names)
+                .on_device_of(self);
+        }
+        let mut new_tensor_data = TensorData::new(transposed);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Transpose);
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.clone().unwrap();
+            accumulate_grad(&out._children[0], grad.reversed_axes());
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Tensor::new(new_tensor_data)
+    }
+
+    // Elementwise |self - other| <= atol + rtol * |other|, same semantics as numpy.allclose
+    // but without broadcasting: tensors of different shapes are never close
+    pub fn allclose(&self, other: &Tensor, rtol: f32, atol: f32) -> bool {
+        let a = &self.borrow().data;
+        let b = &other.borrow().data;
+        a.shape() == b.shape()
+            && a.iter()
+                .zip(b.iter())
+                .all(|(x, y)| (x - y).abs() <= atol + rtol * y.abs())
+    }
+
+    // Single element by full index, e.g. `t.get(&[1, 0])`
+    pub fn get(&self, index: &[usize]) -> f32 {
+        self.try_get(index).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_get(&self, index: &[usize]) -> Result<f32, TensorError> {
+        let data = &self.borrow().data;
+        data.get(index)
+            .copied()
+            .ok_or_else(|| TensorError::IndexOutOfBounds {
+                index: index.to_vec(),
+                shape: data.shape().to_vec(),
+            })
+    }
+
+    // Slice along the first axis, differentiable: the gradient flows back into row `i`
+    #[track_caller]
+    pub fn row(&self, i: usize) -> Tensor {
+        self.try_row(i).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_row(&self, i: usize) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("row");
+        let row_data = {
+            let data = &self.borrow().data;
+            if data.ndim() == 0 {
+                return Err(TensorError::InvalidAxis { axis: 0, ndim: 0 });
+            }
+            if i >= data.len_of(Axis(0)) {
+                return Err(TensorError::IndexOutOfBounds {
+                    index: vec![i],
+                    shape: data.shape().to_vec(),
+                });
+            }
+            data.index_axis(Axis(0), i).to_owned()
+        };
+
+        let dim_names = self.dim_names().map(|names| names[1..].to_vec());
+        if inference::is_enabled() {
+            return Ok(Tensor::from(row_data)
+                .with_dims(dim_names)
+                .on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(row_data);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Row { index: i });
+        new_tensor_data._children = vec![self.clone()];
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.clone().unwrap();
+            let Some(Op::Row { index: i }) = out._op else {
+                unreachable!()
+            };
+
+            // Scatter the row gradient into an otherwise zero gradient of the input shape
+            let mut grad_input = ArrayD::<f32>::zeros(out._children[0].borrow().data.raw_dim());
+            grad_input.index_axis_mut(Axis(0), i).assign(&grad);
+            accumulate_grad(&out._children[0], grad_input);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Rows `indices` along the first axis, stacked: [V, ...] -> [indices.len(), ...]. The
+    // lookup behind embeddings, the gradient stays sparse when the input is `set_sparse`.
+    #[track_caller]
+    pub fn gather_rows(&self, indices: &[usize]) -> Tensor {
+        self.try_gather_rows(indices)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_gather_rows(&self, indices: &[usize]) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("gather_rows");
+        let rows = {
+            let data = &self.borrow().data;
+            if data.ndim() == 0 {
+                return Err(TensorError::InvalidAxis { axis: 0, ndim: 0 });
+            }
+            if let Some(&i) = indices.iter().find(|&&i| i >= data.len_of(Axis(0))) {
+                return Err(TensorError::IndexOutOfBounds {
+                    index: vec![i],
+                    shape: data.shape().to_vec(),
+                });
+            }
+            data.select(Axis(0), indices)
+        };
+
+        let dim_names = self.dim_names();
+        if inference::is_enabled() {
+            return Ok(Tensor::from(rows).with_dims(dim_names).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(rows);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::GatherRows);
+        new_tensor_data._children = vec![self.clone()];
+        new_tensor_data._indices = Rc::from(indices);
+
+        fn backward(out: &TensorData) {
+            let grad = out.grad.clone().unwrap();
+            accumulate_sparse_grad(&out._children[0], gathered_indices(out), grad);
+        }
+        new_tensor_data._backward = Some(backward);
+
+        Ok(Tensor::new(new_tensor_data))
+    }
+
+    // Float one-hot encoding of integer labels stored as f32: [...] -> [..., num_classes].
+    // Labels aren't differentiable, the result is a constant leaf without gradient.
+    #[track_caller]
+    pub fn one_hot(&self, num_classes: usize) -> Tensor {
+        self.try_one_hot(num_classes)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[track_caller]
+    pub fn try_one_hot(&self, num_classes: usize) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("one_hot");
+        let data = &self.borrow().data;
+        let mut shape = data.shape().to_vec();
+        shape.push(num_classes);
+        let mut encoded = ArrayD::<f32>::zeros(IxDyn(&shape));
+        for (i, &label) in data.iter().enumerate() {
+            if label < 0.0 || label.fract() != 0.0 || label >= num_classes as f32 {
+                return Err(TensorError::InvalidClass {
+                    value: label,
+                    num_classes,
+                });
+            }
+            encoded.as_slice_mut().unwrap()[i * num_classes + label as usize] = 1.0;
+        }
+        Ok(constant(encoded))
+    }
+
+    // Gradient as a fresh leaf tensor, detached from the graph that produced it
+    pub fn grad(&self) -> Option<Tensor> {
+        self.grad_array().map(Tensor::from)
+    }
+
+    // Dense gradient, rows from a sparse gradient included
+    pub fn grad_array(&self) -> Option<ArrayD<f32>> {
+        let inner = self.borrow();
+        match (&inner.grad, &inner.sparse_grad) {
+            (grad, None) => grad.clone(),
+            (None, Some(sparse)) => Some(sparse.to_dense(inner.data.shape())),
+            (Some(grad), Some(sparse)) => Some(grad + &sparse.to_dense(inner.data.shape())),
+        }
+    }
+
+    // Coalesced sparse part of the gradient, see `set_sparse`
+    pub fn sparse_grad(&self) -> Option<SparseGrad> {
+        self.borrow().sparse_grad.as_ref().map(SparseGrad::coalesce)
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.borrow().sparse
+    }
+
+    // Keep the gradient of row lookups (gather_rows) sparse, so optimizers only update the
+    // rows that were used
+    pub fn set_sparse(&self, sparse: bool) {
+        self.borrow_mut().sparse = sparse;
+    }
+
+    pub fn requires_grad(&self) -> bool {
+        self.borrow().requires_grad
+    }
+
+    // Name shown by Display, in shape errors, graph exports and profiles instead of just the
+    // shape and op: `let w1 = Tensor::from(init).named("w1");`
+    pub fn named(self, label: &str) -> Tensor {
+        self.set_label(label);
+        self
+    }
+
+    pub fn set_label(&self, label: &str) {
+        self.borrow_mut().label = Some(label.to_string());
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self.borrow().label.clone()
+    }
+
+    // Where the tensor was created, None in release builds
+    pub fn created_at(&self) -> Option<&'static Location<'static>> {
+        self.borrow()._location
+    }
+
+    // Turning it off also drops the current gradient
+    pub fn set_requires_grad(&self, requires_grad: bool) {
+        let mut inner = self.borrow_mut();
+        inner.requires_grad = requires_grad;
+        if !requires_grad {
+            inner.grad = None;
+            inner.sparse_grad = None;
+            inner.retrack();
+        }
+    }
+
+    // Forget the accumulated gradient, call before every backward pass of a training step
+    pub fn zero_grad(&self) {
+        let mut inner = self.borrow_mut();
+        inner.grad = None;
+        inner.sparse_grad = None;
+        inner.retrack();
+    }
+
+    // Tensors hash on their uuid, so interior mutability doesn't affect the key
+    #[allow(clippy::mutable_key_type)]
     pub fn backward(&self) {
         let mut topo: Vec<Tensor> = vec![];
         let mut visited: HashSet<Tensor> = HashSet::new();
         self._build_topo(&mut topo, &mut visited);
         topo.reverse();
 
-        // Should this aray not be just ones with shape of self.data
-        self.borrow_mut().grad = Some(arr0(1.0).into_dyn());
+        // Seed with ones of the output's shape, for a non-scalar output this is the gradient
+        // of its sum, and every op can rely on out.grad having the shape of out.data
+        let seed = ArrayD::ones(self.borrow().data.raw_dim());
+        self.borrow_mut().grad = Some(seed);
+        self.borrow_mut().retrack();
         for v in topo {
             // Check if v has a backward function, if so invoke it
             if let Some(backprop) = v.borrow()._backward {
+                let timer = profiler::backward(&v.borrow());
                 backprop(&v.borrow());
+                drop(timer);
+                if anomaly::is_enabled() {
+                    anomaly::check_backward(&v.borrow());
+                }
             }
         }
     }
 
+    #[allow(clippy::mutable_key_type)]
     fn _build_topo(&self, topo: &mut Vec<Tensor>, visited: &mut HashSet<Tensor>) {
         if visited.insert(self.clone()) {
             self.borrow()._children.iter().for_each(|child| {
@@ -110,6 +807,82 @@ impl Tensor {
         }
     }
 }
+
+// Add `grad` onto the child's existing gradient (None counts as 0), so tensors used
+// several times in the graph receive the sum of all contributions
+pub(crate) fn accumulate_grad(child: &Tensor, grad: ArrayD<f32>) {
+    let mut child_mut = child.borrow_mut();
+    if !child_mut.requires_grad {
+        return;
+    }
+    let grad = grad_for_shape(grad, child_mut.data.shape());
+    child_mut.grad = Some(match child_mut.grad.take() {
+        Some(existing) => &existing + &grad,
+        None => grad,
+    });
+    child_mut.retrack();
+}
+
+// Row indices of a gather_rows node
+pub(crate) fn gathered_indices(node: &TensorData) -> Vec<usize> {
+    node._indices.to_vec()
+}
+
+// Add gradient rows for the given first-axis indices of a sparse child, dense children get
+// them scattered into a full gradient
+pub(crate) fn accumulate_sparse_grad(child: &Tensor, indices: Vec<usize>, values: ArrayD<f32>) {
+    if !child.borrow().sparse {
+        let grad = SparseGrad { indices, values }.to_dense(child.borrow().data.shape());
+        accumulate_grad(child, grad);
+        return;
+    }
+    let mut child_mut = child.borrow_mut();
+    if !child_mut.requires_grad {
+        return;
+    }
+    child_mut.sparse_grad = Some(match child_mut.sparse_grad.take() {
+        Some(mut existing) => {
+            existing.indices.extend(indices);
+            existing.values =
+                ndarray::concatenate(Axis(0), &[existing.values.view(), values.view()]).unwrap();
+            existing
+        }
+        None => SparseGrad { indices, values },
+    });
+    child_mut.retrack();
+}
+
+fn as_matrix(data: &ArrayD<f32>) -> ndarray::Array2<f32> {
+    data.clone().into_dimensionality::<Ix2>().unwrap()
+}
+
+// Bring a gradient to the shape of the tensor it belongs to. A child that was broadcast in
+// the forward pass gets the gradient summed over the broadcast axes, a gradient with fewer
+// dimensions is broadcast up.
+fn grad_for_shape(mut grad: ArrayD<f32>, shape: &[usize]) -> ArrayD<f32> {
+    if grad.shape() == shape {
+        return grad;
+    }
+    while grad.ndim() > shape.len() {
+        grad = grad.sum_axis(Axis(0));
+    }
+    let offset = shape.len() - grad.ndim();
+    for axis in 0..grad.ndim() {
+        if shape[axis + offset] == 1 && grad.len_of(Axis(axis)) != 1 {
+            grad = grad.sum_axis(Axis(axis)).insert_axis(Axis(axis));
+        }
+    }
+    grad.broadcast(shape)
+        .unwrap_or_else(|| {
+            panic!(
+                "gradient of shape {:?} doesn't fit a tensor of shape {:?}",
+                grad.shape(),
+                shape
+            )
+        })
+        .to_owned()
+}
+
 // Lets us do `tensor.borrow().data` instead of `tensor.0.borrow().data`
 impl std::ops::Deref for Tensor {
     type Target = Rc<RefCell<TensorData>>;
@@ -132,17 +905,129 @@ impl PartialEq for Tensor {
 
 impl Eq for Tensor {}
 
+// Compact one-line summary, unlike Debug this doesn't walk the child graph
+// e.g. Tensor(name=h1, shape=[2, 4], op=+, data=[1.0, 2.0, 3.0, ..., 6.0, 7.0, 8.0], grad=true),
+// named dims show as shape=[batch=2, dim=4]. Only the values shown are formatted.
+impl std::fmt::Display for Tensor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const PREVIEW: usize = 3;
+        let inner = self.borrow();
+        let join = |values: &mut dyn Iterator<Item = &f32>| {
+            values
+                .map(|x| format!("{:?}", x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let len = inner.data.len();
+        let preview = if len > 2 * PREVIEW {
+            format!(
+                "{}, ..., {}",
+                join(&mut inner.data.iter().take(PREVIEW)),
+                join(&mut inner.data.iter().skip(len - PREVIEW))
+            )
+        } else {
+            join(&mut inner.data.iter())
+        };
+        write!(f, "Tensor(")?;
+        if let Some(label) = &inner.label {
+            write!(f, "name={}, ", label)?;
+        }
+        let shape = match &inner.dim_names {
+            Some(names) => {
+                let dims: Vec<String> = names
+                    .iter()
+                    .zip(inner.data.shape())
+                    .map(|(&name, size)| match name {
+                        named::UNNAMED => size.to_string(),
+                        name => format!("{}={}", name, size),
+                    })
+                    .collect();
+                format!("[{}]", dims.join(", "))
+            }
+            None => format!("{:?}", inner.data.shape()),
+        };
+        write!(
+            f,
+            "shape={}, op={}, data=[{}], grad={})",
+            shape,
+            inner._op.map_or("None", |op| op.name()),
+            preview,
+            inner.grad.is_some()
+        )
+    }
+}
+
 impl From<ArrayD<f32>> for Tensor {
+    #[track_caller]
     fn from(item: ArrayD<f32>) -> Self {
         Tensor::new(TensorData::new(item))
     }
 }
 
-impl std::ops::Add<&Tensor> for &Tensor {
-    type Output = Tensor;
-    fn add(self, other: &Tensor) -> Tensor {
-        let mut new_tensor_data = TensorData::new(&self.borrow().data + &other.borrow().data);
-        new_tensor_data._op = Some(String::from("+"));
+// A leaf that doesn't collect gradients, for masks, labels, samples and the like
+#[track_caller]
+pub(crate) fn constant(data: ArrayD<f32>) -> Tensor {
+    let tensor = Tensor::from(data);
+    tensor.set_requires_grad(false);
+    tensor
+}
+
+// Shape of `a` and `b` broadcast together following numpy rules, None if incompatible
+pub(crate) fn broadcast_shape(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    // Align from the trailing dimension, missing leading dims count as 1
+    let dim = |s: &[usize], i: usize| if i < s.len() { s[s.len() - 1 - i] } else { 1 };
+    let mut shape = (0..a.len().max(b.len()))
+        .map(|i| match (dim(a, i), dim(b, i)) {
+            (x, y) if x == y => Some(x),
+            (1, y) => Some(y),
+            (x, 1) => Some(x),
+            _ => None,
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    shape.reverse();
+    Some(shape)
+}
+
+// #[track_caller] all the way up so the error points at the user's expression, not this file
+#[track_caller]
+fn check_broadcast(op: &'static str, lhs: &Tensor, rhs: &Tensor) -> Result<(), TensorError> {
+    device::check_same(op, &[lhs, rhs])?;
+    let (lhs_shape, rhs_shape) = (lhs.shape(), rhs.shape());
+    match broadcast_shape(&lhs_shape, &rhs_shape) {
+        Some(_) => Ok(()),
+        None => Err(TensorError::ShapeMismatch {
+            op,
+            lhs: lhs_shape,
+            rhs: rhs_shape,
+            operands: Some(Box::new([Operand::of(lhs), Operand::of(rhs)])),
+            location: if cfg!(debug_assertions) {
+                Some(Location::caller())
+            } else {
+                None
+            },
+        }),
+    }
+}
+
+// Fallible versions of the arithmetic operators, `&a + &b` panics with the same error
+impl Tensor {
+    #[track_caller]
+    pub fn try_add(&self, other: &Tensor) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("+");
+        check_broadcast("+", self, other)?;
+        let dim_names = named::broadcast("+", self, other)?;
+        let sum = device::zip(
+            self.device(),
+            Binary::Add,
+            &self.borrow().data,
+            &other.borrow().data,
+        );
+        if inference::is_enabled() {
+            return Ok(Tensor::from(sum).with_dims(dim_names).on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(sum);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Add);
         // Clone not that expensive because it is a data location/address that we are copying
         new_tensor_data._children = vec![self.clone(), other.clone()];
 
@@ -151,71 +1036,138 @@ impl std::ops::Add<&Tensor> for &Tensor {
             // 1 * out.grad because we want to propagate the gradients from end to beginning
             let grad = out.grad.clone().unwrap();
 
-            // Update gradients of the children
+            // Update gradients of the children, accumulate_grad sums in case that the same
+            // variable is in the equation multiple times and undoes any broadcasting
             for child in out._children.iter() {
-                // A child with a None for gradient should be set to 0
-                let mut child_mut = child.borrow_mut();
-
-                let child_grad = child_mut
-                    .grad
-                    .clone()
-                    .unwrap_or_else(|| arr0(0.0).into_dyn());
-
-                // "&child_grad +" bcs we have to accumulate gradients in case that the same variable is in the equation multiple times
-                child_mut.grad = Some(&child_grad + &grad);
+                accumulate_grad(child, grad.clone());
             }
         }
         new_tensor_data._backward = Some(backward);
 
-        Tensor::new(new_tensor_data)
+        Ok(Tensor::new(new_tensor_data))
     }
-}
 
-impl std::ops::Mul<&Tensor> for &Tensor {
-    type Output = Tensor;
-    fn mul(self, other: &Tensor) -> Self::Output {
-        let mut new_tensor_data = TensorData::new(&self.borrow().data * &other.borrow().data);
-        new_tensor_data._op = Some(String::from("*"));
+    #[track_caller]
+    pub fn try_mul(&self, other: &Tensor) -> Result<Tensor, TensorError> {
+        let _timer = profiler::forward("*");
+        check_broadcast("*", self, other)?;
+        let dim_names = named::broadcast("*", self, other)?;
+        let product = device::zip(
+            self.device(),
+            Binary::Mul,
+            &self.borrow().data,
+            &other.borrow().data,
+        );
+        if inference::is_enabled() {
+            return Ok(Tensor::from(product)
+                .with_dims(dim_names)
+                .on_device_of(self));
+        }
+        let mut new_tensor_data = TensorData::new(product);
+        new_tensor_data.dim_names = dim_names;
+        new_tensor_data._op = Some(Op::Mul);
         new_tensor_data._children = vec![self.clone(), other.clone()];
 
         fn backward(out: &TensorData) {
             let grad = out.grad.clone().unwrap();
 
             // Clone data outside the mutable borrow phase to avoid conflicts
-            let (left_data, right_data, left_grad, right_grad, children_are_same) = {
-                let left_child = out._children[0].borrow();
-                let right_child = out._children[1].borrow();
-
-                (
-                    left_child.data.clone(),
-                    right_child.data.clone(),
-                    left_child
-                        .grad
-                        .clone()
-                        .unwrap_or_else(|| arr0(0.0).into_dyn()),
-                    right_child
-                        .grad
-                        .clone()
-                        .unwrap_or_else(|| arr0(0.0).into_dyn()),
-                    Rc::ptr_eq(&out._children[0], &out._children[1]),
-                )
-            };
-
-            // If children are the same, mutable borrows of both will cause program to panic
-            if children_are_same {
-                let mut child_mut = out._children[0].borrow_mut();
-                child_mut.grad = Some(&left_grad + &(grad.clone() * (&left_data + &right_data)));
-            } else {
-                let mut left_child_mut = out._children[0].borrow_mut();
-                let mut right_child_mut = out._children[1].borrow_mut();
+            let left_data = out._children[0].borrow().data.clone();
+            let right_data = out._children[1].borrow().data.clone();
 
-                left_child_mut.grad = Some(&left_grad + &(grad.clone() * &right_data));
-                right_child_mut.grad = Some(&right_grad + &(grad * &left_data));
-            }
+            // accumulate_grad borrows one child at a time, so `x * x` (same child twice)
+            // correctly ends up with grad * 2x
+            accumulate_grad(&out._children[0], &grad * &right_data);
+            accumulate_grad(&out._children[1], grad * &left_data);
         }
 
         new_tensor_data._backward = Some(backward);
 
-        Tensor::new(new_tensor_data)
+        Ok(Tensor::new(new_tensor_data))
     }
 }
+
+impl std::ops::Add<&Tensor> for &Tensor {
+    type Output = Tensor;
+    #[track_caller]
+    fn add(self, other: &Tensor) -> Tensor {
+        match self.try_add(other) {
+            Ok(out) => out,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+impl std::ops::Mul<&Tensor> for &Tensor {
+    type Output = Tensor;
+    #[track_caller]
+    fn mul(self, other: &Tensor) -> Tensor {
+        match self.try_mul(other) {
+            Ok(out) => out,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+// `a + b`, `a + &b` and `&a + b` all forward to the `&a + &b` implementation,
+// Tensor is a cheap Rc handle so taking it by value costs nothing
+macro_rules! forward_binop {
+    ($imp:ident, $method:ident) => {
+        impl std::ops::$imp<Tensor> for Tensor {
+            type Output = Tensor;
+            #[track_caller]
+            fn $method(self, other: Tensor) -> Tensor {
+                std::ops::$imp::$method(&self, &other)
+            }
+        }
+
+        impl std::ops::$imp<&Tensor> for Tensor {
+            type Output = Tensor;
+            #[track_caller]
+            fn $method(self, other: &Tensor) -> Tensor {
+                std::ops::$imp::$method(&self, other)
+            }
+        }
+
+        impl std::ops::$imp<Tensor> for &Tensor {
+            type Output = Tensor;
+            #[track_caller]
+            fn $method(self, other: Tensor) -> Tensor {
+                std::ops::$imp::$method(self, &other)
+            }
+        }
+    };
+}
+
+forward_binop!(Add, add);
+forward_binop!(Mul, mul);
+
+// In-place value updates for parameter updates (e.g. `w -= &step`). These mutate the data
+// directly and are not recorded in the graph, gradients and children are left untouched.
+macro_rules! assign_op {
+    ($imp:ident, $method:ident, $op:tt) => {
+        impl std::ops::$imp<&Tensor> for Tensor {
+            fn $method(&mut self, other: &Tensor) {
+                // Copy first, `other` may be `self` and can't be borrowed while we mutate
+                let other_data = other.borrow().data.clone();
+                self.borrow_mut().data $op &other_data;
+            }
+        }
+
+        impl std::ops::$imp<Tensor> for Tensor {
+            fn $method(&mut self, other: Tensor) {
+                std::ops::$imp::$method(self, &other)
+            }
+        }
+
+        impl std::ops::$imp<f32> for Tensor {
+            fn $method(&mut self, other: f32) {
+                self.borrow_mut().data $op other;
+            }
+        }
+    };
+}
+
+assign_op!(AddAssign, add_assign, +=);
+assign_op!(SubAssign, sub_assign, -=);
+assign_op!(MulAssign, mul_assign, *=);
@@ -0,0 +1,234 @@
+// Tokenizers turning text into token ids for language modelling experiments
+
+use crate::tensor::Tensor;
+use ndarray::Array1;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub trait Tokenizer {
+    fn encode(&self, text: &str) -> Vec<usize>;
+    fn decode(&self, ids: &[usize]) -> String;
+    fn vocab_size(&self) -> usize;
+
+    // Token ids as a 1-D float tensor, Tensor only stores f32
+    fn encode_tensor(&self, text: &str) -> Tensor {
+        let ids = self.encode(text);
+        Tensor::from(Array1::from_iter(ids.into_iter().map(|id| id as f32)).into_dyn())
+    }
+}
+
+fn invalid(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), msg),
+    )
+}
+
+// One token per distinct character of the training text, ids in sorted character order
+pub struct CharTokenizer {
+    chars: Vec<char>,
+    ids: HashMap<char, usize>,
+}
+
+impl CharTokenizer {
+    pub fn fit(text: &str) -> CharTokenizer {
+        let mut chars: Vec<char> = text.chars().collect();
+        chars.sort();
+        chars.dedup();
+        CharTokenizer::from_chars(chars)
+    }
+
+    fn from_chars(chars: Vec<char>) -> CharTokenizer {
+        let ids = chars.iter().enumerate().map(|(i, &c)| (c, i)).collect();
+        CharTokenizer { chars, ids }
+    }
+
+    // "char v1" followed by one unicode code point per line
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = String::from("char v1\n");
+        for c in &self.chars {
+            out.push_str(&format!("{}\n", *c as u32));
+        }
+        fs::write(path, out)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<CharTokenizer> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        if lines.next() != Some("char v1") {
+            return Err(invalid(path, "not a char tokenizer vocab"));
+        }
+        let chars = lines
+            .map(|line| {
+                line.parse::<u32>()
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| invalid(path, &format!("invalid code point {:?}", line)))
+            })
+            .collect::<io::Result<Vec<char>>>()?;
+        Ok(CharTokenizer::from_chars(chars))
+    }
+}
+
+impl Tokenizer for CharTokenizer {
+    // Panics on characters that weren't in the training text
+    fn encode(&self, text: &str) -> Vec<usize> {
+        text.chars()
+            .map(|c| {
+                *self
+                    .ids
+                    .get(&c)
+                    .unwrap_or_else(|| panic!("character {:?} is not in the vocabulary", c))
+            })
+            .collect()
+    }
+
+    fn decode(&self, ids: &[usize]) -> String {
+        ids.iter().map(|&id| self.chars[id]).collect()
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.chars.len()
+    }
+}
+
+// Byte-level byte-pair encoding: ids 0..256 are raw bytes, every merge learned in training
+// adds one token for a pair of existing tokens, so any string can be encoded
+pub struct BpeTokenizer {
+    // merges[i] is the pair that token 256 + i replaces
+    merges: Vec<(usize, usize)>,
+    ranks: HashMap<(usize, usize), usize>,
+    vocab: Vec<Vec<u8>>,
+}
+
+fn merge(ids: &[usize], pair: (usize, usize), new_id: usize) -> Vec<usize> {
+    let mut merged = Vec::with_capacity(ids.len());
+    let mut i = 0;
+    while i < ids.len() {
+        if i + 1 < ids.len() && (ids[i], ids[i + 1]) == pair {
+            merged.push(new_id);
+            i += 2;
+        } else {
+            merged.push(ids[i]);
+            i += 1;
+        }
+    }
+    merged
+}
+
+impl BpeTokenizer {
+    // Learn merges of the most frequent adjacent pair until there are `vocab_size` tokens,
+    // or no pair occurs more than once
+    pub fn train(text: &str, vocab_size: usize) -> BpeTokenizer {
+        assert!(
+            vocab_size >= 256,
+            "BPE vocab_size must be at least 256 bytes"
+        );
+        let mut ids: Vec<usize> = text.bytes().map(usize::from).collect();
+        let mut merges = Vec::new();
+        while 256 + merges.len() < vocab_size {
+            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+            for pair in ids.windows(2) {
+                *counts.entry((pair[0], pair[1])).or_default() += 1;
+            }
+            // Ties broken on the pair itself so training is deterministic
+            let Some((&pair, &count)) = counts
+                .iter()
+                .max_by_key(|(&pair, &count)| (count, std::cmp::Reverse(pair)))
+            else {
+                break;
+            };
+            if count < 2 {
+                break;
+            }
+            ids = merge(&ids, pair, 256 + merges.len());
+            merges.push(pair);
+        }
+        BpeTokenizer::from_merges(merges)
+    }
+
+    fn from_merges(merges: Vec<(usize, usize)>) -> BpeTokenizer {
+        let mut vocab: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        for &(a, b) in &merges {
+            let token = [vocab[a].as_slice(), vocab[b].as_slice()].concat();
+            vocab.push(token);
+        }
+        let ranks = merges
+            .iter()
+            .enumerate()
+            .map(|(i, &pair)| (pair, i))
+            .collect();
+        BpeTokenizer {
+            merges,
+            ranks,
+            vocab,
+        }
+    }
+
+    // "bpe v1" followed by one merged pair of token ids per line, in merge order
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = String::from("bpe v1\n");
+        for (a, b) in &self.merges {
+            out.push_str(&format!("{} {}\n", a, b));
+        }
+        fs::write(path, out)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<BpeTokenizer> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        if lines.next() != Some("bpe v1") {
+            return Err(invalid(path, "not a BPE tokenizer vocab"));
+        }
+        let mut merges = Vec::new();
+        for line in lines {
+            let pair: Vec<usize> = line
+                .split(' ')
+                .map(|id| id.parse::<usize>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(path, &format!("invalid merge {:?}", line)))?;
+            // Merges can only refer to bytes or earlier merges
+            let next_id = 256 + merges.len();
+            if pair.len() != 2 || pair[0] >= next_id || pair[1] >= next_id {
+                return Err(invalid(path, &format!("invalid merge {:?}", line)));
+            }
+            merges.push((pair[0], pair[1]));
+        }
+        Ok(BpeTokenizer::from_merges(merges))
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn encode(&self, text: &str) -> Vec<usize> {
+        let mut ids: Vec<usize> = text.bytes().map(usize::from).collect();
+        // Apply the earliest learned merge present until none applies, same order as training
+        while let Some((pair, rank)) = ids
+            .windows(2)
+            .filter_map(|pair| {
+                let pair = (pair[0], pair[1]);
+                self.ranks.get(&pair).map(|&rank| (pair, rank))
+            })
+            .min_by_key(|&(_, rank)| rank)
+        {
+            ids = merge(&ids, pair, 256 + rank);
+        }
+        ids
+    }
+
+    // Invalid UTF-8 (a sequence split mid character) decodes to U+FFFD
+    fn decode(&self, ids: &[usize]) -> String {
+        let bytes: Vec<u8> = ids
+            .iter()
+            .flat_map(|&id| self.vocab[id].iter().copied())
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.vocab.len()
+    }
+}
@@ -0,0 +1,341 @@
+// Ready-made callbacks for the Trainer
+
+use crate::checkpoint;
+use crate::memory::{self, MemoryStats};
+use crate::random::rng_state;
+use crate::train::{Callback, Context, EpochMetrics};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Minimum time between two redraws of a progress bar
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+// Metric a callback watches, lower is better for every one of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Monitor {
+    TrainLoss,
+    // Needs fit_with_validation
+    ValLoss,
+}
+
+impl Monitor {
+    fn value(self, metrics: &EpochMetrics) -> f32 {
+        match self {
+            Monitor::TrainLoss => metrics.train_loss,
+            Monitor::ValLoss => metrics.val_loss.expect(
+                "Monitor::ValLoss needs validation metrics, train with fit_with_validation",
+            ),
+        }
+    }
+}
+
+// Lowest value seen so far, counting as improved only when beating it by more than min_delta
+#[derive(Debug, Clone)]
+struct Best {
+    monitor: Monitor,
+    min_delta: f32,
+    value: Option<f32>,
+    epoch: Option<usize>,
+}
+
+impl Best {
+    fn new(monitor: Monitor) -> Best {
+        Best {
+            monitor,
+            min_delta: 0.0,
+            value: None,
+            epoch: None,
+        }
+    }
+
+    // Record the epoch's value, true if it's the new best. NaN never improves.
+    fn update(&mut self, metrics: &EpochMetrics) -> bool {
+        let value = self.monitor.value(metrics);
+        let improved = match self.value {
+            None => !value.is_nan(),
+            Some(best) => value < best - self.min_delta,
+        };
+        if improved {
+            self.value = Some(value);
+            self.epoch = Some(metrics.epoch);
+        }
+        improved
+    }
+}
+
+// Stop training once the monitored metric hasn't improved for `patience` epochs:
+// `Trainer::new(..).callback(EarlyStopping::new(5).min_delta(1e-4))`
+#[derive(Debug, Clone)]
+pub struct EarlyStopping {
+    patience: usize,
+    best: Best,
+    // Epochs since the last improvement
+    wait: usize,
+    stopped_epoch: Option<usize>,
+}
+
+impl EarlyStopping {
+    // Monitors the validation loss by default
+    pub fn new(patience: usize) -> EarlyStopping {
+        EarlyStopping {
+            patience,
+            best: Best::new(Monitor::ValLoss),
+            wait: 0,
+            stopped_epoch: None,
+        }
+    }
+
+    pub fn monitor(mut self, monitor: Monitor) -> Self {
+        self.best.monitor = monitor;
+        self
+    }
+
+    // Smallest decrease that counts as an improvement
+    pub fn min_delta(mut self, min_delta: f32) -> Self {
+        self.best.min_delta = min_delta;
+        self
+    }
+
+    pub fn best(&self) -> Option<f32> {
+        self.best.value
+    }
+
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best.epoch
+    }
+
+    // Epoch after which training was stopped, None if it ran to the end
+    pub fn stopped_epoch(&self) -> Option<usize> {
+        self.stopped_epoch
+    }
+}
+
+impl Callback for EarlyStopping {
+    fn on_epoch_end(&mut self, ctx: &mut Context, metrics: &EpochMetrics) {
+        if self.best.update(metrics) {
+            self.wait = 0;
+            return;
+        }
+        self.wait += 1;
+        if self.wait >= self.patience {
+            ctx.stop_training = true;
+            self.stopped_epoch = Some(metrics.epoch);
+        }
+    }
+}
+
+// Save a checkpoint (see checkpoint::save) every time the monitored metric reaches a new best,
+// so `path` always holds the best weights seen. The saved epoch is the number of epochs
+// completed.
+#[derive(Debug, Clone)]
+pub struct ModelCheckpoint {
+    path: PathBuf,
+    best: Best,
+}
+
+impl ModelCheckpoint {
+    // Monitors the validation loss by default
+    pub fn new(path: impl AsRef<Path>) -> ModelCheckpoint {
+        ModelCheckpoint {
+            path: path.as_ref().to_path_buf(),
+            best: Best::new(Monitor::ValLoss),
+        }
+    }
+
+    pub fn monitor(mut self, monitor: Monitor) -> Self {
+        self.best.monitor = monitor;
+        self
+    }
+
+    pub fn min_delta(mut self, min_delta: f32) -> Self {
+        self.best.min_delta = min_delta;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn best(&self) -> Option<f32> {
+        self.best.value
+    }
+
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best.epoch
+    }
+}
+
+impl Callback for ModelCheckpoint {
+    // Panics when the checkpoint can't be written, rather than training on without the
+    // weights it was asked to keep
+    fn on_epoch_end(&mut self, ctx: &mut Context, metrics: &EpochMetrics) {
+        if !self.best.update(metrics) {
+            return;
+        }
+        checkpoint::save(
+            &self.path,
+            ctx.model,
+            &*ctx.optimizer,
+            metrics.epoch as u64 + 1,
+            &rng_state(),
+        )
+        .unwrap_or_else(|e| panic!("failed to save {}: {}", self.path.display(), e));
+    }
+}
+
+// Live progress on stderr, redrawn in place on a terminal:
+//
+//     epoch 3/10 [==========>         ] 45/100 loss 0.1234
+//
+// followed by a summary line per epoch. When stderr isn't a terminal (CI logs, redirected
+// output) only the summary lines are written.
+#[derive(Debug, Clone)]
+pub struct ProgressBar {
+    width: usize,
+    interactive: bool,
+    // Running mean of the epoch's batch losses
+    loss_sum: f64,
+    batches: usize,
+    started: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        ProgressBar {
+            width: 30,
+            interactive: io::stderr().is_terminal(),
+            loss_sum: 0.0,
+            batches: 0,
+            started: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    // Characters between the brackets
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    fn draw(&mut self, ctx: &Context) {
+        let loss = self.loss_sum / self.batches as f64;
+        let progress = match ctx.num_batches {
+            Some(total) if total > 0 => {
+                let filled = (self.width * self.batches / total).min(self.width);
+                let head = if filled < self.width { ">" } else { "" };
+                format!(
+                    "[{}{}{}] {}/{}",
+                    "=".repeat(filled),
+                    head,
+                    " ".repeat(self.width - filled - head.len()),
+                    self.batches,
+                    total
+                )
+            }
+            _ => format!("{} batches", self.batches),
+        };
+        let mut stderr = io::stderr().lock();
+        // Carriage return and clear the line, so a shorter line doesn't leave stale text
+        let _ = write!(
+            stderr,
+            "\r\x1b[2Kepoch {}/{} {} loss {:.4}",
+            ctx.epoch + 1,
+            ctx.epochs,
+            progress,
+            loss
+        );
+        let _ = stderr.flush();
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        ProgressBar::new()
+    }
+}
+
+impl Callback for ProgressBar {
+    fn on_epoch_start(&mut self, _ctx: &mut Context) {
+        self.loss_sum = 0.0;
+        self.batches = 0;
+        self.started = Instant::now();
+        self.last_draw = None;
+    }
+
+    fn on_batch_end(&mut self, ctx: &mut Context, batch: usize, loss: f32) {
+        self.loss_sum += loss as f64;
+        self.batches += 1;
+        let last = ctx.num_batches == Some(batch + 1);
+        let due = self
+            .last_draw
+            .is_none_or(|t| t.elapsed() >= REDRAW_INTERVAL);
+        if self.interactive && (due || last) {
+            self.draw(ctx);
+        }
+    }
+
+    fn on_epoch_end(&mut self, ctx: &mut Context, metrics: &EpochMetrics) {
+        let mut stderr = io::stderr().lock();
+        if self.interactive {
+            let _ = write!(stderr, "\r\x1b[2K");
+        }
+        let val = metrics
+            .val_loss
+            .map_or(String::new(), |v| format!(" val loss {:.4}", v));
+        let _ = writeln!(
+            stderr,
+            "epoch {}/{} train loss {:.4}{} ({:.1}s)",
+            ctx.epoch + 1,
+            ctx.epochs,
+            metrics.train_loss,
+            val,
+            self.started.elapsed().as_secs_f32()
+        );
+    }
+}
+
+// Tensor memory after every training step (see the memory module), with the peak reached
+// during the step. The graph of the step is already dropped, so `live_tensors` and `bytes`
+// should stay flat from one step to the next; growth points at a retained graph.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTracker {
+    steps: Vec<MemoryStats>,
+}
+
+impl MemoryTracker {
+    pub fn new() -> MemoryTracker {
+        MemoryTracker::default()
+    }
+
+    // One entry per batch trained so far
+    pub fn steps(&self) -> &[MemoryStats] {
+        &self.steps
+    }
+
+    pub fn peak_bytes(&self) -> usize {
+        self.steps.iter().map(|s| s.peak_bytes).max().unwrap_or(0)
+    }
+}
+
+impl Callback for MemoryTracker {
+    fn on_epoch_start(&mut self, _ctx: &mut Context) {
+        memory::reset_peak();
+    }
+
+    fn on_batch_end(&mut self, ctx: &mut Context, batch: usize, _loss: f32) {
+        let stats = memory::stats();
+        log::debug!(
+            epoch = ctx.epoch,
+            batch,
+            live_tensors = stats.live_tensors,
+            bytes = stats.bytes,
+            peak_bytes = stats.peak_bytes;
+            "memory after step"
+        );
+        self.steps.push(stats);
+        memory::reset_peak();
+    }
+}
@@ -0,0 +1,100 @@
+// Knowledge distillation with the Trainer: a frozen teacher supplies soft targets for a
+// trainable student.
+//
+//     let distiller = Distiller::new(&student, &teacher, DistillationOptions::default());
+//     let mut optimizer = Adam::new(student.parameters(), 1e-3);
+//     Trainer::new(&distiller, &mut optimizer, |pred, target| distiller.loss(pred, target))
+//         .epochs(10)
+//         .fit(&loader);
+//
+// Run as a Module, the distiller returns the student's logits and keeps the teacher's for the
+// same inputs, which `loss` then reads. Only the student's parameters are exposed.
+
+use crate::error::TensorError;
+use crate::inference::inference_mode;
+use crate::loss::{self, DistillationOptions};
+use crate::nn::{Module, TracedLayer};
+use crate::shape::ShapedLayer;
+use crate::tensor::Tensor;
+use std::cell::RefCell;
+
+pub struct Distiller<'a> {
+    student: &'a dyn Module,
+    teacher: &'a dyn Module,
+    pub options: DistillationOptions,
+    // Teacher logits for the last forward
+    teacher_logits: RefCell<Option<Tensor>>,
+}
+
+impl<'a> Distiller<'a> {
+    // Freezes the teacher, unfreeze it afterwards to train it again
+    pub fn new(
+        student: &'a dyn Module,
+        teacher: &'a dyn Module,
+        options: DistillationOptions,
+    ) -> Distiller<'a> {
+        teacher.freeze();
+        Distiller {
+            student,
+            teacher,
+            options,
+            teacher_logits: RefCell::new(None),
+        }
+    }
+
+    pub fn student(&self) -> &'a dyn Module {
+        self.student
+    }
+
+    pub fn teacher(&self) -> &'a dyn Module {
+        self.teacher
+    }
+
+    // loss::distillation of the student logits of the last forward against the teacher's,
+    // `targets` holds the class indices as f32 in any shape with one entry per sample
+    #[track_caller]
+    pub fn loss(&self, student_logits: &Tensor, targets: &Tensor) -> Tensor {
+        let teacher_logits = self
+            .teacher_logits
+            .borrow()
+            .clone()
+            .expect("Distiller::loss needs a forward first, for the teacher's logits");
+        let targets = targets.reshape(&[targets.numel()]);
+        loss::distillation(student_logits, &teacher_logits, &targets, self.options)
+    }
+}
+
+impl Module for Distiller<'_> {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        // The teacher only provides targets, no graph is needed through it
+        let teacher_logits = inference_mode(|| self.teacher.forward(input));
+        *self.teacher_logits.borrow_mut() = Some(teacher_logits);
+        self.student.forward(input)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        self.student.named_parameters()
+    }
+
+    fn forward_traced(
+        &self,
+        input: &Tensor,
+        name: &str,
+        visit: &mut dyn FnMut(TracedLayer),
+    ) -> Tensor {
+        self.student.forward_traced(input, name, visit)
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, TensorError> {
+        self.student.output_shape(input_shape)
+    }
+
+    fn output_shape_traced(
+        &self,
+        input_shape: &[usize],
+        name: &str,
+        visit: &mut dyn FnMut(ShapedLayer),
+    ) -> Result<Vec<usize>, TensorError> {
+        self.student.output_shape_traced(input_shape, name, visit)
+    }
+}
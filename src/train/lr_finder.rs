@@ -0,0 +1,196 @@
+// Learning-rate range test (Smith, "Cyclical Learning Rates for Training Neural Networks"):
+// train for a few hundred batches while raising the learning rate exponentially and watch
+// where the loss falls fastest and where it blows up.
+//
+//     let sweep = trainer.find_lr(&train_loader, &LrFinder::new());
+//     println!("{}", sweep);
+//     let (low, high) = sweep.suggested_range().unwrap();
+//
+// The model and optimizer are put back as they were afterwards.
+
+use crate::tensor::Tensor;
+use crate::train::Trainer;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LrFinder {
+    pub start_lr: f32,
+    pub end_lr: f32,
+    // Batches to sweep over, the loader is restarted when it runs out
+    pub steps: usize,
+    // Weight of the previous value in the exponential moving average of the loss
+    pub smoothing: f32,
+    // Stop once the smoothed loss exceeds this multiple of the best one
+    pub divergence: f32,
+}
+
+impl LrFinder {
+    pub fn new() -> LrFinder {
+        LrFinder {
+            start_lr: 1e-7,
+            end_lr: 10.0,
+            steps: 100,
+            smoothing: 0.98,
+            divergence: 4.0,
+        }
+    }
+
+    pub fn range(mut self, start_lr: f32, end_lr: f32) -> Self {
+        assert!(
+            0.0 < start_lr && start_lr < end_lr,
+            "LrFinder needs 0 < start_lr < end_lr, got {} and {}",
+            start_lr,
+            end_lr
+        );
+        self.start_lr = start_lr;
+        self.end_lr = end_lr;
+        self
+    }
+
+    pub fn steps(mut self, steps: usize) -> Self {
+        assert!(steps >= 2, "LrFinder needs at least 2 steps");
+        self.steps = steps;
+        self
+    }
+
+    pub fn smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn divergence(mut self, divergence: f32) -> Self {
+        self.divergence = divergence;
+        self
+    }
+
+    fn lr(&self, step: usize) -> f32 {
+        let t = step as f32 / (self.steps - 1) as f32;
+        self.start_lr * (self.end_lr / self.start_lr).powf(t)
+    }
+}
+
+impl Default for LrFinder {
+    fn default() -> Self {
+        LrFinder::new()
+    }
+}
+
+// Losses recorded during a sweep, one entry per batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrSweep {
+    pub lrs: Vec<f32>,
+    pub losses: Vec<f32>,
+    // Bias-corrected moving average of `losses`, what the suggestions are based on
+    pub smoothed: Vec<f32>,
+}
+
+impl LrSweep {
+    // Where the smoothed loss falls fastest against log(lr). None if the sweep was too short
+    // or the loss never fell.
+    pub fn suggested_lr(&self) -> Option<f32> {
+        let mut steepest: Option<(usize, f32)> = None;
+        for i in 1..self.smoothed.len() {
+            let slope = (self.smoothed[i] - self.smoothed[i - 1])
+                / (self.lrs[i].ln() - self.lrs[i - 1].ln());
+            if slope < 0.0 && steepest.is_none_or(|(_, s)| slope < s) {
+                steepest = Some((i, slope));
+            }
+        }
+        steepest.map(|(i, _)| self.lrs[i])
+    }
+
+    // From the steepest descent up to a tenth of the learning rate with the lowest loss, the
+    // usual bounds for a one-cycle or cyclical schedule
+    pub fn suggested_range(&self) -> Option<(f32, f32)> {
+        let low = self.suggested_lr()?;
+        let (best, _) = self
+            .smoothed
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| !l.is_nan())
+            .min_by(|a, b| a.1.total_cmp(b.1))?;
+        Some((low, (self.lrs[best] / 10.0).max(low)))
+    }
+}
+
+impl fmt::Display for LrSweep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>12}  {:>12}", "lr", "loss")?;
+        // Around 20 rows whatever the number of steps
+        let every = self.lrs.len().div_ceil(20).max(1);
+        for i in (0..self.lrs.len()).step_by(every) {
+            writeln!(f, "{:>12.3e}  {:>12.6}", self.lrs[i], self.smoothed[i])?;
+        }
+        match self.suggested_range() {
+            Some((low, high)) => write!(
+                f,
+                "suggested lr {:.3e}, range {:.3e} to {:.3e}",
+                self.suggested_lr().unwrap(),
+                low,
+                high
+            ),
+            None => write!(f, "no suggestion, the loss never decreased"),
+        }
+    }
+}
+
+impl Trainer<'_> {
+    // Run the range test on batches from `train`, callbacks aren't called. Stops early when
+    // the loss diverges.
+    pub fn find_lr<T>(&mut self, train: &T, finder: &LrFinder) -> LrSweep
+    where
+        for<'b> &'b T: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        let model_state = self.model.state_dict();
+        let optimizer_state = self.optimizer.state_dict();
+        let lr = self.optimizer.lr();
+
+        let mut sweep = LrSweep {
+            lrs: Vec::with_capacity(finder.steps),
+            losses: Vec::with_capacity(finder.steps),
+            smoothed: Vec::with_capacity(finder.steps),
+        };
+        let mut average = 0.0;
+        let mut best = f32::INFINITY;
+        let mut batches = train.into_iter();
+        'sweep: for step in 0..finder.steps {
+            let (inputs, targets) = match batches.next() {
+                Some(batch) => batch,
+                None => {
+                    batches = train.into_iter();
+                    match batches.next() {
+                        Some(batch) => batch,
+                        // An empty loader, nothing to sweep over
+                        None => break 'sweep,
+                    }
+                }
+            };
+            let lr = finder.lr(step);
+            self.optimizer.set_lr(lr);
+            self.optimizer.zero_grad();
+            let loss = (self.loss_fn)(&self.model.forward(&inputs), &targets);
+            loss.backward();
+            self.optimizer.step();
+
+            let loss = loss.item();
+            average = finder.smoothing * average + (1.0 - finder.smoothing) * loss;
+            let smoothed = average / (1.0 - finder.smoothing.powi(step as i32 + 1));
+            sweep.lrs.push(lr);
+            sweep.losses.push(loss);
+            sweep.smoothed.push(smoothed);
+            log::debug!(step, lr, loss, smoothed; "lr finder step");
+            if smoothed.is_nan() || smoothed > finder.divergence * best {
+                break;
+            }
+            best = best.min(smoothed);
+        }
+
+        self.model
+            .load_state_dict(&model_state)
+            .expect("restoring the model's own state dict");
+        self.optimizer.load_state_dict(&optimizer_state);
+        self.optimizer.set_lr(lr);
+        self.model.zero_grad();
+        sweep
+    }
+}
@@ -0,0 +1,283 @@
+// The standard training loop, so it doesn't have to be rewritten (with a forgotten zero_grad
+// or a validation pass that steps the optimizer) for every model:
+//
+//     let mut trainer = Trainer::new(&model, &mut optimizer, |pred, target| {
+//         loss::mse(pred, target, Reduction::Mean)
+//     })
+//     .epochs(20);
+//     let history = trainer.fit_with_validation(&train_loader, &val_loader);
+//
+// Works with anything that batches by reference into (inputs, targets), i.e. DataLoader and
+// StreamLoader. Callbacks hook into the loop for logging, schedules and the like, see Callback.
+//
+// Every epoch is also logged through the `log` crate, at info level with `epoch`, `train_loss`
+// and `val_loss` as key-values, and every batch at debug level. Add a ProgressBar callback for
+// a live display.
+
+pub mod callbacks;
+pub mod distill;
+pub mod lr_finder;
+pub mod regression;
+
+pub use callbacks::{EarlyStopping, MemoryTracker, ModelCheckpoint, Monitor, ProgressBar};
+pub use distill::Distiller;
+pub use lr_finder::{LrFinder, LrSweep};
+pub use regression::{fit_linear_regression, polynomial_features, RegressionFit};
+
+use crate::inference::inference_mode;
+use crate::nn::Module;
+use crate::optim::Optimizer;
+use crate::tensor::Tensor;
+
+// Loss of a batch of predictions against its targets, reduced to a scalar
+pub type LossFn<'a> = Box<dyn Fn(&Tensor, &Tensor) -> Tensor + 'a>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpochMetrics {
+    // Counted from 0
+    pub epoch: usize,
+    // Mean per-sample loss over the epoch, weighting every batch by its size
+    pub train_loss: f32,
+    pub val_loss: Option<f32>,
+}
+
+// What a callback can see and change, e.g. `ctx.optimizer.set_lr(..)` for a schedule
+pub struct Context<'c> {
+    pub model: &'c dyn Module,
+    pub optimizer: &'c mut dyn Optimizer,
+    // Counted from 0
+    pub epoch: usize,
+    pub epochs: usize,
+    // Batches in the current epoch, None when the loader can't tell (e.g. a StreamLoader) or
+    // before the epoch's first batch
+    pub num_batches: Option<usize>,
+    // Set to end training after the current epoch
+    pub stop_training: bool,
+}
+
+// Hooks called by the Trainer, every one defaults to doing nothing. Callbacks run in the order
+// they were added.
+pub trait Callback {
+    fn on_epoch_start(&mut self, _ctx: &mut Context) {}
+
+    // After the gradients of a batch are computed and before the optimizer uses them, e.g. to
+    // clip or inspect them
+    fn on_backward(&mut self, _ctx: &mut Context) {}
+
+    // `batch` counts from 0 within the epoch
+    fn on_batch_end(&mut self, _ctx: &mut Context, _batch: usize, _loss: f32) {}
+
+    // After validation and before anything sees the epoch's metrics, which this may replace,
+    // e.g. with their mean over all ranks (see distributed::GradSync)
+    fn on_metrics(&mut self, _ctx: &mut Context, _metrics: &mut EpochMetrics) {}
+
+    // After validation, with the epoch's metrics
+    fn on_epoch_end(&mut self, _ctx: &mut Context, _metrics: &EpochMetrics) {}
+}
+
+// So a callback can be lent to the trainer and inspected after fit
+impl<C: Callback + ?Sized> Callback for &mut C {
+    fn on_epoch_start(&mut self, ctx: &mut Context) {
+        (**self).on_epoch_start(ctx)
+    }
+
+    fn on_backward(&mut self, ctx: &mut Context) {
+        (**self).on_backward(ctx)
+    }
+
+    fn on_batch_end(&mut self, ctx: &mut Context, batch: usize, loss: f32) {
+        (**self).on_batch_end(ctx, batch, loss)
+    }
+
+    fn on_metrics(&mut self, ctx: &mut Context, metrics: &mut EpochMetrics) {
+        (**self).on_metrics(ctx, metrics)
+    }
+
+    fn on_epoch_end(&mut self, ctx: &mut Context, metrics: &EpochMetrics) {
+        (**self).on_epoch_end(ctx, metrics)
+    }
+}
+
+pub struct Trainer<'a> {
+    model: &'a dyn Module,
+    optimizer: &'a mut dyn Optimizer,
+    loss_fn: LossFn<'a>,
+    epochs: usize,
+    callbacks: Vec<Box<dyn Callback + 'a>>,
+    // Epoch the callbacks are told about
+    epoch: usize,
+    num_batches: Option<usize>,
+    stop_training: bool,
+}
+
+impl<'a> Trainer<'a> {
+    pub fn new(
+        model: &'a dyn Module,
+        optimizer: &'a mut dyn Optimizer,
+        loss_fn: impl Fn(&Tensor, &Tensor) -> Tensor + 'a,
+    ) -> Trainer<'a> {
+        Trainer {
+            model,
+            optimizer,
+            loss_fn: Box::new(loss_fn),
+            epochs: 1,
+            callbacks: Vec::new(),
+            epoch: 0,
+            num_batches: None,
+            stop_training: false,
+        }
+    }
+
+    pub fn epochs(mut self, epochs: usize) -> Self {
+        self.epochs = epochs;
+        self
+    }
+
+    pub fn callback(mut self, callback: impl Callback + 'a) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    pub fn fit<T>(&mut self, train: &T) -> Vec<EpochMetrics>
+    where
+        for<'b> &'b T: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        self.run(|trainer| (trainer.train_epoch(train), None))
+    }
+
+    // Like fit, with a pass over `val` after every epoch
+    pub fn fit_with_validation<T, V>(&mut self, train: &T, val: &V) -> Vec<EpochMetrics>
+    where
+        for<'b> &'b T: IntoIterator<Item = (Tensor, Tensor)>,
+        for<'b> &'b V: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        self.run(|trainer| (trainer.train_epoch(train), Some(trainer.evaluate(val))))
+    }
+
+    // One pass over `train` updating the model, returns the mean loss. NaN if there were no
+    // batches.
+    pub fn train_epoch<T>(&mut self, train: &T) -> f32
+    where
+        for<'b> &'b T: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        let mut mean = RunningMean::default();
+        let batches = train.into_iter();
+        self.num_batches = match batches.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None,
+        };
+        for (batch, (inputs, targets)) in batches.enumerate() {
+            // Gradients accumulate across backward passes, clear the previous batch's first
+            self.optimizer.zero_grad();
+            let loss = (self.loss_fn)(&self.model.forward(&inputs), &targets);
+            loss.backward();
+            self.notify(|callback, ctx| callback.on_backward(ctx));
+            self.optimizer.step();
+            // Drop the graph before on_batch_end, so callbacks see what outlives the step
+            let loss = {
+                let value = loss.item();
+                drop(loss);
+                value
+            };
+            mean.add(loss, batch_size(&inputs));
+            log::debug!(epoch = self.epoch, batch, loss; "batch finished");
+            self.notify(|callback, ctx| callback.on_batch_end(ctx, batch, loss));
+        }
+        mean.value()
+    }
+
+    // Mean loss over `data` without updating the model, NaN if there were no batches. Runs in
+    // inference mode, no graph is recorded.
+    pub fn evaluate<T>(&self, data: &T) -> f32
+    where
+        for<'b> &'b T: IntoIterator<Item = (Tensor, Tensor)>,
+    {
+        let mut mean = RunningMean::default();
+        for (inputs, targets) in data {
+            let loss = inference_mode(|| (self.loss_fn)(&self.model.forward(&inputs), &targets));
+            mean.add(loss.item(), batch_size(&inputs));
+        }
+        mean.value()
+    }
+
+    // The epoch loop around `epoch`, which returns the train and validation loss. Stops early
+    // when a callback sets `stop_training`, the history then has fewer than `epochs` entries.
+    fn run(&mut self, mut epoch: impl FnMut(&mut Self) -> (f32, Option<f32>)) -> Vec<EpochMetrics> {
+        let mut history = Vec::with_capacity(self.epochs);
+        self.stop_training = false;
+        for i in 0..self.epochs {
+            self.epoch = i;
+            self.num_batches = None;
+            self.notify(|callback, ctx| callback.on_epoch_start(ctx));
+            let (train_loss, val_loss) = epoch(self);
+            let mut metrics = EpochMetrics {
+                epoch: i,
+                train_loss,
+                val_loss,
+            };
+            self.notify(|callback, ctx| callback.on_metrics(ctx, &mut metrics));
+            let EpochMetrics {
+                train_loss,
+                val_loss,
+                ..
+            } = metrics;
+            log::info!(
+                epoch = i,
+                train_loss,
+                val_loss:? = val_loss;
+                "epoch {}/{}: train loss {}{}",
+                i + 1,
+                self.epochs,
+                train_loss,
+                val_loss.map_or(String::new(), |v| format!(", val loss {}", v))
+            );
+            self.notify(|callback, ctx| callback.on_epoch_end(ctx, &metrics));
+            history.push(metrics);
+            if self.stop_training {
+                log::info!(epoch = i; "stopping early after epoch {}", i + 1);
+                break;
+            }
+        }
+        history
+    }
+
+    fn notify(&mut self, mut hook: impl FnMut(&mut dyn Callback, &mut Context)) {
+        let mut ctx = Context {
+            model: self.model,
+            optimizer: &mut *self.optimizer,
+            epoch: self.epoch,
+            epochs: self.epochs,
+            num_batches: self.num_batches,
+            stop_training: self.stop_training,
+        };
+        for callback in &mut self.callbacks {
+            hook(callback.as_mut(), &mut ctx);
+        }
+        self.stop_training = ctx.stop_training;
+    }
+}
+
+fn batch_size(inputs: &Tensor) -> usize {
+    inputs.shape().first().copied().unwrap_or(1)
+}
+
+#[derive(Default)]
+struct RunningMean {
+    sum: f64,
+    count: usize,
+}
+
+impl RunningMean {
+    fn add(&mut self, value: f32, weight: usize) {
+        self.sum += value as f64 * weight as f64;
+        self.count += weight;
+    }
+
+    fn value(&self) -> f32 {
+        if self.count == 0 {
+            f32::NAN
+        } else {
+            (self.sum / self.count as f64) as f32
+        }
+    }
+}
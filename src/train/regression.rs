@@ -0,0 +1,82 @@
+// Least-squares fits by gradient descent, the whole Tensor -> loss -> optimizer pipeline in
+// a few lines (see examples/regression.rs):
+//
+//     let fit = fit_linear_regression(&polynomial_features(&x, 3), &y, 2000, 0.05);
+//     println!("{:?} + {}", fit.weights(), fit.bias());
+//
+// For real models use the Trainer, this is full-batch and has no validation.
+
+use crate::loss::{self, Reduction};
+use crate::nn::{Linear, Module};
+use crate::optim::{Optimizer, Sgd};
+use crate::tensor::Tensor;
+use ndarray::Array2;
+
+pub struct RegressionFit {
+    // [1, features] weight and [1] bias
+    pub model: Linear,
+    // Mean squared error before every step
+    pub losses: Vec<f32>,
+}
+
+impl RegressionFit {
+    pub fn weights(&self) -> Vec<f32> {
+        self.model.weight.to_vec()
+    }
+
+    pub fn bias(&self) -> f32 {
+        self.model.bias.as_ref().unwrap().to_vec()[0]
+    }
+
+    // [N, 1] predictions for [N, features] inputs
+    pub fn predict(&self, x: &Tensor) -> Tensor {
+        self.model.predict(x)
+    }
+}
+
+// Fit y = x w + b by `epochs` steps of plain gradient descent on the mean squared error over
+// the whole of `x` ([N, features]) and `y` ([N] or [N, 1]). Standardize large inputs first,
+// the learning rate has to suit the scale of every feature.
+pub fn fit_linear_regression(x: &Tensor, y: &Tensor, epochs: usize, lr: f32) -> RegressionFit {
+    let shape = x.shape();
+    assert!(
+        shape.len() == 2,
+        "fit_linear_regression expects [N, features] inputs, got shape {:?}",
+        shape
+    );
+    let n = shape[0];
+    assert!(
+        y.shape() == [n] || y.shape() == [n, 1],
+        "fit_linear_regression expects [{}] or [{}, 1] targets, got shape {:?}",
+        n,
+        n,
+        y.shape()
+    );
+    let y = y.reshape(&[n, 1]);
+    let model = Linear::new(shape[1], 1);
+    let mut optimizer = Sgd::new(model.parameters(), lr);
+    let mut losses = Vec::with_capacity(epochs);
+    for _ in 0..epochs {
+        optimizer.zero_grad();
+        let loss = loss::mse(&model.forward(x), &y, Reduction::Mean);
+        loss.backward();
+        optimizer.step();
+        losses.push(loss.item());
+    }
+    RegressionFit { model, losses }
+}
+
+// [x, x^2, ..., x^degree] for [N] or [N, 1] inputs, as [N, degree], so polynomial regression
+// is linear regression on these
+pub fn polynomial_features(x: &Tensor, degree: usize) -> Tensor {
+    let values = x.to_vec();
+    assert!(
+        x.shape() == [values.len()] || x.shape() == [values.len(), 1],
+        "polynomial_features expects [N] or [N, 1] inputs, got shape {:?}",
+        x.shape()
+    );
+    let features = Array2::from_shape_fn((values.len(), degree), |(i, d)| {
+        values[i].powi(d as i32 + 1)
+    });
+    Tensor::from(features.into_dyn())
+}
@@ -0,0 +1,472 @@
+// Hyperparameter search: every trial gets a set of values from a search space, trains a model
+// with them and is scored by its training history, the lowest score wins.
+//
+//     let space = SearchSpace::new()
+//         .log_uniform("lr", 1e-4, 1e-1)
+//         .choice("hidden", [16, 32, 64]);
+//     let result = Tuner::random(&space, 20).threads(4).run(|trial| {
+//         let model = Sequential::new()
+//             .add(Linear::new(2, trial.usize("hidden")))
+//             .add(ReLU)
+//             .add(Linear::new(trial.usize("hidden"), 2));
+//         let mut optimizer = Adam::new(model.parameters(), trial.f32("lr"));
+//         let (train, val) = loaders();
+//         let mut trainer = Trainer::new(&model, &mut optimizer, loss_fn).epochs(10);
+//         trainer.fit_with_validation(&train, &val)
+//     });
+//     println!("{}", result);
+//
+// A trial scores the lowest validation loss of its epochs, or the final training loss without
+// validation. Tensors can't cross threads, so with several threads the objective has to build
+// everything it trains on itself, from plain arrays or files. Each trial seeds the global RNG
+// with the tuner's seed plus its index first, results don't depend on the thread count.
+
+use crate::random::{manual_seed, Generator};
+use crate::train::EpochMetrics;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Value {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    // One of a list, in order for grid search
+    Choice(Vec<Value>),
+    // Integers from low to high inclusive
+    IntRange { low: i64, high: i64 },
+    // Random search only
+    Uniform { low: f32, high: f32 },
+    // Uniform in log space, for learning rates and weight decays. Random search only.
+    LogUniform { low: f32, high: f32 },
+}
+
+impl Param {
+    fn sample(&self, rng: &mut Generator) -> Value {
+        match *self {
+            Param::Choice(ref values) => values[rng.gen_range(0..values.len())].clone(),
+            Param::IntRange { low, high } => Value::Int(rng.gen_range(low..=high)),
+            Param::Uniform { low, high } => Value::Float(rng.gen_range(low..=high)),
+            Param::LogUniform { low, high } => {
+                Value::Float(rng.gen_range(low.ln()..=high.ln()).exp())
+            }
+        }
+    }
+
+    fn grid_values(&self) -> Option<Vec<Value>> {
+        match *self {
+            Param::Choice(ref values) => Some(values.clone()),
+            Param::IntRange { low, high } => Some((low..=high).map(Value::Int).collect()),
+            Param::Uniform { .. } | Param::LogUniform { .. } => None,
+        }
+    }
+}
+
+// Named hyperparameters and the values each can take, in the order they were added
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchSpace {
+    pub params: Vec<(String, Param)>,
+}
+
+impl SearchSpace {
+    pub fn new() -> SearchSpace {
+        SearchSpace::default()
+    }
+
+    pub fn param(mut self, name: &str, param: Param) -> Self {
+        assert!(
+            self.params.iter().all(|(existing, _)| existing != name),
+            "SearchSpace already has a parameter named {:?}",
+            name
+        );
+        self.params.push((name.to_string(), param));
+        self
+    }
+
+    pub fn choice<V: Into<Value>>(self, name: &str, values: impl IntoIterator<Item = V>) -> Self {
+        let values: Vec<Value> = values.into_iter().map(Into::into).collect();
+        assert!(
+            !values.is_empty(),
+            "SearchSpace::choice needs at least one value for {:?}",
+            name
+        );
+        self.param(name, Param::Choice(values))
+    }
+
+    pub fn int_range(self, name: &str, low: i64, high: i64) -> Self {
+        assert!(
+            low <= high,
+            "SearchSpace::int_range needs low <= high for {:?}, got {} and {}",
+            name,
+            low,
+            high
+        );
+        self.param(name, Param::IntRange { low, high })
+    }
+
+    pub fn uniform(self, name: &str, low: f32, high: f32) -> Self {
+        assert!(
+            low <= high,
+            "SearchSpace::uniform needs low <= high for {:?}, got {} and {}",
+            name,
+            low,
+            high
+        );
+        self.param(name, Param::Uniform { low, high })
+    }
+
+    pub fn log_uniform(self, name: &str, low: f32, high: f32) -> Self {
+        assert!(
+            0.0 < low && low <= high,
+            "SearchSpace::log_uniform needs 0 < low <= high for {:?}, got {} and {}",
+            name,
+            low,
+            high
+        );
+        self.param(name, Param::LogUniform { low, high })
+    }
+
+    // Every combination of the parameters' values, the last parameter varying fastest. Panics
+    // for continuous parameters, sample those with `random`.
+    pub fn grid(&self) -> Vec<Trial> {
+        let mut combinations = vec![BTreeMap::new()];
+        for (name, param) in &self.params {
+            let values = param.grid_values().unwrap_or_else(|| {
+                panic!(
+                    "grid search needs discrete parameters, {:?} is continuous (use choice)",
+                    name
+                )
+            });
+            combinations = combinations
+                .into_iter()
+                .flat_map(|params: BTreeMap<String, Value>| {
+                    values.iter().map(move |value| {
+                        let mut params = params.clone();
+                        params.insert(name.clone(), value.clone());
+                        params
+                    })
+                })
+                .collect();
+        }
+        numbered(combinations)
+    }
+
+    // `trials` independent draws from the space
+    pub fn random(&self, trials: usize, seed: u64) -> Vec<Trial> {
+        let mut rng = Generator::seed_from_u64(seed);
+        let combinations = (0..trials)
+            .map(|_| {
+                self.params
+                    .iter()
+                    .map(|(name, param)| (name.clone(), param.sample(&mut rng)))
+                    .collect()
+            })
+            .collect();
+        numbered(combinations)
+    }
+}
+
+fn numbered(combinations: Vec<BTreeMap<String, Value>>) -> Vec<Trial> {
+    combinations
+        .into_iter()
+        .enumerate()
+        .map(|(index, params)| Trial { index, params })
+        .collect()
+}
+
+// One set of hyperparameter values. The getters panic when the name is missing or holds
+// another type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    // Counted from 0 in the order the trials were generated
+    pub index: usize,
+    pub params: BTreeMap<String, Value>,
+}
+
+impl Trial {
+    #[track_caller]
+    pub fn get(&self, name: &str) -> &Value {
+        self.params.get(name).unwrap_or_else(|| {
+            panic!(
+                "trial has no parameter {:?}, only {:?}",
+                name,
+                self.params.keys().collect::<Vec<_>>()
+            )
+        })
+    }
+
+    // Integer values are converted
+    #[track_caller]
+    pub fn f32(&self, name: &str) -> f32 {
+        match *self.get(name) {
+            Value::Float(value) => value,
+            Value::Int(value) => value as f32,
+            ref other => panic!("trial parameter {:?} is {:?}, not a number", name, other),
+        }
+    }
+
+    #[track_caller]
+    pub fn i64(&self, name: &str) -> i64 {
+        match *self.get(name) {
+            Value::Int(value) => value,
+            ref other => panic!("trial parameter {:?} is {:?}, not an integer", name, other),
+        }
+    }
+
+    #[track_caller]
+    pub fn usize(&self, name: &str) -> usize {
+        let value = self.i64(name);
+        usize::try_from(value)
+            .unwrap_or_else(|_| panic!("trial parameter {:?} is negative: {}", name, value))
+    }
+
+    #[track_caller]
+    pub fn bool(&self, name: &str) -> bool {
+        match *self.get(name) {
+            Value::Bool(value) => value,
+            ref other => panic!("trial parameter {:?} is {:?}, not a bool", name, other),
+        }
+    }
+
+    #[track_caller]
+    pub fn str(&self, name: &str) -> &str {
+        match self.get(name) {
+            Value::Str(value) => value,
+            other => panic!("trial parameter {:?} is {:?}, not a string", name, other),
+        }
+    }
+}
+
+impl fmt::Display for Trial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "{}", params.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialResult {
+    pub trial: Trial,
+    pub history: Vec<EpochMetrics>,
+    // Lowest validation loss, or the last training loss. NaN without any epochs.
+    pub score: f32,
+}
+
+impl TrialResult {
+    fn new(trial: Trial, history: Vec<EpochMetrics>) -> TrialResult {
+        let val_losses = history.iter().filter_map(|epoch| epoch.val_loss);
+        let score = match val_losses.reduce(f32::min) {
+            Some(best) => best,
+            None => history.last().map_or(f32::NAN, |epoch| epoch.train_loss),
+        };
+        TrialResult {
+            trial,
+            history,
+            score,
+        }
+    }
+}
+
+// All trials in generation order
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuneResult {
+    pub trials: Vec<TrialResult>,
+}
+
+impl TuneResult {
+    // The lowest score, ignoring NaN ones. None when no trial has a score.
+    pub fn best(&self) -> Option<&TrialResult> {
+        self.trials
+            .iter()
+            .filter(|result| !result.score.is_nan())
+            .min_by(|a, b| a.score.total_cmp(&b.score))
+    }
+
+    // Trials from best to worst, NaN scores last
+    pub fn ranked(&self) -> Vec<&TrialResult> {
+        let mut ranked: Vec<&TrialResult> = self.trials.iter().collect();
+        ranked.sort_by(|a, b| match (a.score.is_nan(), b.score.is_nan()) {
+            (false, false) => a.score.total_cmp(&b.score),
+            (nan_a, nan_b) => nan_a.cmp(&nan_b),
+        });
+        ranked
+    }
+}
+
+impl fmt::Display for TuneResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>6}  {:>12}  params", "trial", "score")?;
+        for result in self.ranked() {
+            writeln!(
+                f,
+                "{:>6}  {:>12.6}  {}",
+                result.trial.index, result.score, result.trial
+            )?;
+        }
+        match self.best() {
+            Some(best) => write!(f, "best: trial {} ({})", best.trial.index, best.trial),
+            None => write!(f, "no trial has a score"),
+        }
+    }
+}
+
+enum Strategy {
+    Grid,
+    Random(usize),
+}
+
+pub struct Tuner {
+    space: SearchSpace,
+    strategy: Strategy,
+    threads: usize,
+    seed: u64,
+}
+
+impl Tuner {
+    // Every combination, see SearchSpace::grid
+    pub fn grid(space: &SearchSpace) -> Tuner {
+        Tuner::with_strategy(space, Strategy::Grid)
+    }
+
+    // `trials` random draws, see SearchSpace::random
+    pub fn random(space: &SearchSpace, trials: usize) -> Tuner {
+        Tuner::with_strategy(space, Strategy::Random(trials))
+    }
+
+    fn with_strategy(space: &SearchSpace, strategy: Strategy) -> Tuner {
+        Tuner {
+            space: space.clone(),
+            strategy,
+            threads: 1,
+            seed: 0,
+        }
+    }
+
+    // Trials running at once, 1 (on the calling thread) by default
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "Tuner needs at least 1 thread");
+        self.threads = threads;
+        self
+    }
+
+    // For drawing random trials and for the RNG of each trial
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // The trials `run` would train
+    pub fn trials(&self) -> Vec<Trial> {
+        match self.strategy {
+            Strategy::Grid => self.space.grid(),
+            Strategy::Random(trials) => self.space.random(trials, self.seed),
+        }
+    }
+
+    // Train every trial with `objective`, which returns the trial's training history (what
+    // Trainer::fit and fit_with_validation return)
+    pub fn run<F>(&self, objective: F) -> TuneResult
+    where
+        F: Fn(&Trial) -> Vec<EpochMetrics> + Sync,
+    {
+        let trials = self.trials();
+        let run_trial = |trial: &Trial| {
+            manual_seed(self.seed.wrapping_add(trial.index as u64));
+            let result = TrialResult::new(trial.clone(), objective(trial));
+            log::info!(
+                trial = trial.index,
+                score = result.score;
+                "trial {}/{}: score {} ({})",
+                trial.index + 1,
+                trials.len(),
+                result.score,
+                trial
+            );
+            result
+        };
+        if self.threads == 1 {
+            return TuneResult {
+                trials: trials.iter().map(run_trial).collect(),
+            };
+        }
+
+        // Workers take the next trial as they become free
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(trials.len()));
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(trials.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(trial) = trials.get(i) else {
+                        break;
+                    };
+                    let result = run_trial(trial);
+                    results.lock().unwrap().push(result);
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|result| result.trial.index);
+        TuneResult { trials: results }
+    }
+}
@@ -0,0 +1,1108 @@
+// Line plots of training runs, behind the "viz" feature, saved as SVG or PNG to review runs
+// without exporting them:
+//
+//     let history = trainer.fit_with_validation(&train_loader, &val_loader);
+//     viz::loss_curves(&history).log_y(true).save("runs/loss.png")?;
+//     viz::metric_curves("Validation", &[("accuracy", &accuracies)]).save("runs/acc.svg")?;
+//
+// Both formats are drawn from the same layout. SVGs use the viewer's sans-serif font, PNGs a
+// built-in 5x7 pixel font that writes letters in upper case.
+//
+// BarChart draws labelled values the same way. For a quick look at a tensor in the terminal
+// there's `Tensor::show`, see Heatmap.
+
+use crate::tensor::Tensor;
+use crate::train::EpochMetrics;
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+use std::fmt::{self, Write as _};
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+type Color = [u8; 3];
+
+// Matplotlib's default cycle, series take these in order
+const PALETTE: [Color; 6] = [
+    [31, 119, 180],
+    [255, 127, 14],
+    [44, 160, 44],
+    [214, 39, 40],
+    [148, 103, 189],
+    [140, 86, 75],
+];
+const BLACK: Color = [0, 0, 0];
+const WHITE: Color = [255, 255, 255];
+const GRID: Color = [225, 225, 225];
+const FRAME: Color = [120, 120, 120];
+
+// Space around the plot area for the title, tick labels and axis labels
+const MARGIN_LEFT: f32 = 80.0;
+const MARGIN_RIGHT: f32 = 25.0;
+const MARGIN_TOP: f32 = 45.0;
+const MARGIN_BOTTOM: f32 = 55.0;
+
+// PNGs are drawn at this multiple of their size and scaled down, for smooth lines
+const SUPERSAMPLE: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f32, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plot {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub series: Vec<Series>,
+    // In pixels, 800 by 500 by default
+    pub width: u32,
+    pub height: u32,
+    // Logarithmic y axis, points at or below 0 are left out
+    pub log_y: bool,
+}
+
+impl Plot {
+    pub fn new(title: &str) -> Plot {
+        Plot {
+            title: title.to_string(),
+            x_label: String::new(),
+            y_label: String::new(),
+            series: Vec::new(),
+            width: 800,
+            height: 500,
+            log_y: false,
+        }
+    }
+
+    pub fn x_label(mut self, label: &str) -> Self {
+        self.x_label = label.to_string();
+        self
+    }
+
+    pub fn y_label(mut self, label: &str) -> Self {
+        self.y_label = label.to_string();
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        assert!(
+            width as f32 > MARGIN_LEFT + MARGIN_RIGHT && height as f32 > MARGIN_TOP + MARGIN_BOTTOM,
+            "plot of {}x{} pixels is too small for its margins",
+            width,
+            height
+        );
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn log_y(mut self, log_y: bool) -> Self {
+        self.log_y = log_y;
+        self
+    }
+
+    // Add a curve through `points` in order, non-finite points are left out
+    pub fn line(mut self, name: &str, points: &[(f32, f32)]) -> Self {
+        self.series.push(Series {
+            name: name.to_string(),
+            points: points.to_vec(),
+        });
+        self
+    }
+
+    pub fn to_svg(&self) -> String {
+        render_svg(self.width, self.height, self.layout())
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        render_image(self.width, self.height, self.layout())
+    }
+
+    // SVG or PNG by the extension of `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save(path.as_ref(), || self.to_svg(), || self.to_image())
+    }
+
+    // The shapes of the plot in pixel coordinates, y pointing down
+    fn layout(&self) -> Vec<Shape> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let area = Area {
+            left: MARGIN_LEFT,
+            right: width - MARGIN_RIGHT,
+            top: MARGIN_TOP,
+            bottom: height - MARGIN_BOTTOM,
+        };
+        let series: Vec<Vec<(f32, f32)>> = self
+            .series
+            .iter()
+            .map(|series| {
+                series
+                    .points
+                    .iter()
+                    .filter(|(x, y)| x.is_finite() && y.is_finite() && (!self.log_y || *y > 0.0))
+                    .map(|&(x, y)| (x, if self.log_y { y.log10() } else { y }))
+                    .collect()
+            })
+            .collect();
+        let all = series.iter().flatten();
+        let (x_min, x_max) = padded_range(all.clone().map(|p| p.0));
+        let (y_min, y_max) = padded_range(all.map(|p| p.1));
+        let x_ticks = nice_ticks(x_min, x_max);
+        let (y_min, y_max, y_ticks) = if self.log_y {
+            (y_min, y_max, log_ticks(y_min, y_max))
+        } else {
+            // Round the y range out to ticks, the frame starts and ends on one
+            let step = nice_ticks(y_min, y_max).step;
+            let (y_min, y_max) = ((y_min / step).floor() * step, (y_max / step).ceil() * step);
+            (y_min, y_max, nice_ticks_with_step(y_min, y_max, step))
+        };
+        let to_x = |x: f32| area.left + (x - x_min) / (x_max - x_min) * (area.right - area.left);
+        let to_y = |y: f32| area.bottom - (y - y_min) / (y_max - y_min) * (area.bottom - area.top);
+
+        let mut shapes = vec![Shape::Rect {
+            x: 0.0,
+            y: 0.0,
+            width,
+            height,
+            fill: Some(WHITE),
+            stroke: None,
+        }];
+        for &tick in &x_ticks.values {
+            let x = to_x(tick);
+            shapes.push(Shape::line(&[(x, area.top), (x, area.bottom)], GRID, 1.0));
+            shapes.push(Shape::text(
+                x,
+                area.bottom + 14.0,
+                &format_tick(tick, x_ticks.step),
+                12.0,
+                Anchor::Middle,
+            ));
+        }
+        for &tick in &y_ticks.values {
+            let y = to_y(tick);
+            let label = if self.log_y {
+                let value = 10f32.powf(tick);
+                format_tick(value, value)
+            } else {
+                format_tick(tick, y_ticks.step)
+            };
+            shapes.push(Shape::line(&[(area.left, y), (area.right, y)], GRID, 1.0));
+            shapes.push(Shape::text(area.left - 8.0, y, &label, 12.0, Anchor::End));
+        }
+        shapes.push(Shape::Rect {
+            x: area.left,
+            y: area.top,
+            width: area.right - area.left,
+            height: area.bottom - area.top,
+            fill: None,
+            stroke: Some(FRAME),
+        });
+        for (i, points) in series.iter().enumerate() {
+            let points: Vec<(f32, f32)> = points.iter().map(|&(x, y)| (to_x(x), to_y(y))).collect();
+            shapes.push(Shape::line(&points, PALETTE[i % PALETTE.len()], 2.0));
+        }
+
+        shapes.push(Shape::text(
+            width / 2.0,
+            MARGIN_TOP / 2.0,
+            &self.title,
+            16.0,
+            Anchor::Middle,
+        ));
+        shapes.push(Shape::text(
+            (area.left + area.right) / 2.0,
+            height - 18.0,
+            &self.x_label,
+            13.0,
+            Anchor::Middle,
+        ));
+        shapes.push(Shape::Text {
+            x: 18.0,
+            y: (area.top + area.bottom) / 2.0,
+            text: self.y_label.clone(),
+            size: 13.0,
+            anchor: Anchor::Middle,
+            vertical: true,
+        });
+        shapes.extend(self.legend(&area));
+        shapes
+    }
+
+    // Swatches and names in the top right corner of the plot area
+    fn legend(&self, area: &Area) -> Vec<Shape> {
+        if self.series.is_empty() {
+            return Vec::new();
+        }
+        let longest = self.series.iter().map(|s| s.name.len()).max().unwrap_or(0);
+        // Rough text width, wide enough for both backends
+        let box_width = 40.0 + longest as f32 * 7.5;
+        let box_height = 8.0 + 18.0 * self.series.len() as f32;
+        let (x, y) = (area.right - box_width - 10.0, area.top + 10.0);
+        let mut shapes = vec![Shape::Rect {
+            x,
+            y,
+            width: box_width,
+            height: box_height,
+            fill: Some(WHITE),
+            stroke: Some(GRID),
+        }];
+        for (i, series) in self.series.iter().enumerate() {
+            let row = y + 13.0 + 18.0 * i as f32;
+            let color = PALETTE[i % PALETTE.len()];
+            shapes.push(Shape::line(&[(x + 8.0, row), (x + 28.0, row)], color, 2.0));
+            shapes.push(Shape::text(
+                x + 34.0,
+                row,
+                &series.name,
+                12.0,
+                Anchor::Start,
+            ));
+        }
+        shapes
+    }
+}
+
+// Training and, when there was a validation set, validation loss per epoch (counted from 1)
+pub fn loss_curves(history: &[EpochMetrics]) -> Plot {
+    let train: Vec<(f32, f32)> = history
+        .iter()
+        .map(|epoch| ((epoch.epoch + 1) as f32, epoch.train_loss))
+        .collect();
+    let val: Vec<(f32, f32)> = history
+        .iter()
+        .filter_map(|epoch| Some(((epoch.epoch + 1) as f32, epoch.val_loss?)))
+        .collect();
+    let plot = Plot::new("Loss")
+        .x_label("epoch")
+        .y_label("loss")
+        .line("train", &train);
+    if val.is_empty() {
+        plot
+    } else {
+        plot.line("validation", &val)
+    }
+}
+
+// One curve per named metric, the values being per epoch (counted from 1), e.g. accuracies
+// collected after every epoch
+pub fn metric_curves(title: &str, metrics: &[(&str, &[f32])]) -> Plot {
+    metrics
+        .iter()
+        .fold(Plot::new(title).x_label("epoch"), |plot, (name, values)| {
+            let points: Vec<(f32, f32)> = values
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| ((i + 1) as f32, value))
+                .collect();
+            plot.line(name, &points)
+        })
+}
+
+// Horizontal bars, one per labelled value top to bottom, e.g. the gradient norm of every
+// parameter (see gradients::GradFlow). Highlighted bars are drawn in red.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarChart {
+    pub title: String,
+    pub x_label: String,
+    pub bars: Vec<Bar>,
+    // In pixels, the height defaults to fit the bars
+    pub width: u32,
+    pub height: Option<u32>,
+    // Logarithmic value axis, values at or below 0 get no bar
+    pub log_x: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    pub label: String,
+    pub value: f32,
+    pub highlight: bool,
+}
+
+const BAR_HEIGHT: f32 = 22.0;
+
+impl BarChart {
+    pub fn new(title: &str) -> BarChart {
+        BarChart {
+            title: title.to_string(),
+            x_label: String::new(),
+            bars: Vec::new(),
+            width: 800,
+            height: None,
+            log_x: false,
+        }
+    }
+
+    pub fn x_label(mut self, label: &str) -> Self {
+        self.x_label = label.to_string();
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        assert!(
+            width as f32 > MARGIN_LEFT + MARGIN_RIGHT && height as f32 > MARGIN_TOP + MARGIN_BOTTOM,
+            "chart of {}x{} pixels is too small for its margins",
+            width,
+            height
+        );
+        self.width = width;
+        self.height = Some(height);
+        self
+    }
+
+    pub fn log_x(mut self, log_x: bool) -> Self {
+        self.log_x = log_x;
+        self
+    }
+
+    pub fn bar(mut self, label: &str, value: f32, highlight: bool) -> Self {
+        self.bars.push(Bar {
+            label: label.to_string(),
+            value,
+            highlight,
+        });
+        self
+    }
+
+    pub fn to_svg(&self) -> String {
+        render_svg(self.width, self.pixel_height(), self.layout())
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        render_image(self.width, self.pixel_height(), self.layout())
+    }
+
+    // SVG or PNG by the extension of `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save(path.as_ref(), || self.to_svg(), || self.to_image())
+    }
+
+    fn pixel_height(&self) -> u32 {
+        self.height.unwrap_or_else(|| {
+            (MARGIN_TOP + MARGIN_BOTTOM + BAR_HEIGHT * self.bars.len().max(1) as f32) as u32
+        })
+    }
+
+    fn layout(&self) -> Vec<Shape> {
+        let (width, height) = (self.width as f32, self.pixel_height() as f32);
+        // Labels go left of the bars, as wide as the longest one needs
+        let longest = self
+            .bars
+            .iter()
+            .map(|bar| bar.label.len())
+            .max()
+            .unwrap_or(0);
+        let area = Area {
+            left: (20.0 + longest as f32 * 9.0).max(MARGIN_LEFT),
+            right: width - MARGIN_RIGHT - 60.0,
+            top: MARGIN_TOP,
+            bottom: height - MARGIN_BOTTOM,
+        };
+        let value = |v: f32| if self.log_x { v.log10() } else { v };
+        let shown = self
+            .bars
+            .iter()
+            .map(|bar| bar.value)
+            .filter(|v| v.is_finite() && (!self.log_x || *v > 0.0));
+        let (min, max, ticks) = if self.log_x {
+            let (min, max) = padded_range(shown.map(f32::log10));
+            let (min, max) = (min.floor(), max.ceil());
+            (min, max, log_ticks(min, max))
+        } else {
+            // Bars start at 0
+            let (_, max) = padded_range(shown.chain([0.0]));
+            let step = nice_ticks(0.0, max).step;
+            let max = (max / step).ceil() * step;
+            (0.0, max, nice_ticks_with_step(0.0, max, step))
+        };
+        let to_x = |v: f32| area.left + (v - min) / (max - min) * (area.right - area.left);
+
+        let mut shapes = vec![Shape::Rect {
+            x: 0.0,
+            y: 0.0,
+            width,
+            height,
+            fill: Some(WHITE),
+            stroke: None,
+        }];
+        for &tick in &ticks.values {
+            let x = to_x(tick);
+            let label = if self.log_x {
+                let value = 10f32.powf(tick);
+                format_tick(value, value)
+            } else {
+                format_tick(tick, ticks.step)
+            };
+            shapes.push(Shape::line(&[(x, area.top), (x, area.bottom)], GRID, 1.0));
+            shapes.push(Shape::text(
+                x,
+                area.bottom + 14.0,
+                &label,
+                12.0,
+                Anchor::Middle,
+            ));
+        }
+        let row_height = (area.bottom - area.top) / self.bars.len().max(1) as f32;
+        for (i, bar) in self.bars.iter().enumerate() {
+            let y = area.top + row_height * (i as f32 + 0.5);
+            shapes.push(Shape::text(
+                area.left - 8.0,
+                y,
+                &bar.label,
+                12.0,
+                Anchor::End,
+            ));
+            let end = if bar.value.is_finite() && (!self.log_x || bar.value > 0.0) {
+                to_x(value(bar.value)).clamp(area.left, area.right)
+            } else if bar.value.is_nan() || bar.value <= 0.0 {
+                // A stub, so highlighting still shows
+                area.left + 3.0
+            } else {
+                area.right
+            };
+            shapes.push(Shape::Rect {
+                x: area.left,
+                y: y - row_height * 0.35,
+                width: end - area.left,
+                height: row_height * 0.7,
+                fill: Some(if bar.highlight {
+                    PALETTE[3]
+                } else {
+                    PALETTE[0]
+                }),
+                stroke: None,
+            });
+            shapes.push(Shape::text(
+                end + 6.0,
+                y,
+                &format!("{:.2e}", bar.value),
+                11.0,
+                Anchor::Start,
+            ));
+        }
+        shapes.push(Shape::Rect {
+            x: area.left,
+            y: area.top,
+            width: area.right - area.left,
+            height: area.bottom - area.top,
+            fill: None,
+            stroke: Some(FRAME),
+        });
+        shapes.push(Shape::text(
+            width / 2.0,
+            MARGIN_TOP / 2.0,
+            &self.title,
+            16.0,
+            Anchor::Middle,
+        ));
+        shapes.push(Shape::text(
+            (area.left + area.right) / 2.0,
+            height - 18.0,
+            &self.x_label,
+            13.0,
+            Anchor::Middle,
+        ));
+        shapes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+// What the SVG and PNG backends draw, in pixels of the final plot
+enum Shape {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        fill: Option<Color>,
+        stroke: Option<Color>,
+    },
+    Line {
+        points: Vec<(f32, f32)>,
+        color: Color,
+        width: f32,
+    },
+    // Vertically centered on `y`, vertical text reads bottom to top and is centered on (x, y)
+    Text {
+        x: f32,
+        y: f32,
+        text: String,
+        size: f32,
+        anchor: Anchor,
+        vertical: bool,
+    },
+}
+
+impl Shape {
+    fn line(points: &[(f32, f32)], color: Color, width: f32) -> Shape {
+        Shape::Line {
+            points: points.to_vec(),
+            color,
+            width,
+        }
+    }
+
+    fn text(x: f32, y: f32, text: &str, size: f32, anchor: Anchor) -> Shape {
+        Shape::Text {
+            x,
+            y,
+            text: text.to_string(),
+            size,
+            anchor,
+            vertical: false,
+        }
+    }
+}
+
+struct Area {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+// Data range with room when all values are equal, (0, 1) without any
+fn padded_range(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    let (min, max) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        let pad = if min == 0.0 { 1.0 } else { min.abs() * 0.1 };
+        (min - pad, max + pad)
+    } else {
+        (min, max)
+    }
+}
+
+struct Ticks {
+    values: Vec<f32>,
+    step: f32,
+}
+
+// Around 6 ticks at multiples of 1, 2 or 5 times a power of 10
+fn nice_ticks(min: f32, max: f32) -> Ticks {
+    let raw = (max - min) / 6.0;
+    let magnitude = 10f32.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= raw)
+        .unwrap_or(10.0 * magnitude);
+    nice_ticks_with_step(min, max, step)
+}
+
+fn nice_ticks_with_step(min: f32, max: f32, step: f32) -> Ticks {
+    // With some slack, range ends rounded to a tick may be a rounding error off it
+    let first = (min / step - 1e-3).ceil() as i64;
+    let last = (max / step + 1e-3).floor() as i64;
+    Ticks {
+        values: (first..=last).map(|i| i as f32 * step).collect(),
+        step,
+    }
+}
+
+// Ticks on log10 values: every power of 10 in range, or nice ticks of the plain values when
+// the range spans less than two of them
+fn log_ticks(min: f32, max: f32) -> Ticks {
+    let (first, last) = (min.ceil() as i32, max.floor() as i32);
+    if last > first {
+        return Ticks {
+            values: (first..=last).map(|p| p as f32).collect(),
+            step: 1.0,
+        };
+    }
+    let plain = nice_ticks(10f32.powf(min), 10f32.powf(max));
+    Ticks {
+        values: plain
+            .values
+            .into_iter()
+            .filter(|&v| v > 0.0)
+            .map(f32::log10)
+            .collect(),
+        step: plain.step,
+    }
+}
+
+// Enough decimals to tell ticks `step` apart, scientific notation for very large or small values
+fn format_tick(value: f32, step: f32) -> String {
+    let abs = value.abs();
+    if abs != 0.0 && !(1e-3..1e5).contains(&abs) {
+        return format!("{:.0e}", value);
+    }
+    let decimals = (-step.abs().log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, value)
+}
+
+fn render_svg(width: u32, height: u32, shapes: Vec<Shape>) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\">\n",
+        w = width,
+        h = height
+    );
+    for shape in shapes {
+        match shape {
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                fill,
+                stroke,
+            } => {
+                let _ = writeln!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"{}\"/>",
+                    x,
+                    y,
+                    width,
+                    height,
+                    fill.map_or(String::from("none"), svg_color),
+                    stroke.map_or(String::from("none"), svg_color)
+                );
+            }
+            Shape::Line {
+                points,
+                color,
+                width,
+            } => {
+                let points: Vec<String> =
+                    points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                let _ = writeln!(
+                    svg,
+                    "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" \
+                     stroke-linejoin=\"round\"/>",
+                    points.join(" "),
+                    svg_color(color),
+                    width
+                );
+            }
+            Shape::Text {
+                x,
+                y,
+                text,
+                size,
+                anchor,
+                vertical,
+            } => {
+                let anchor = match anchor {
+                    Anchor::Start => "start",
+                    Anchor::Middle => "middle",
+                    Anchor::End => "end",
+                };
+                let rotate = if vertical {
+                    format!(" transform=\"rotate(-90 {} {})\"", x, y)
+                } else {
+                    String::new()
+                };
+                let _ = writeln!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"{}\" \
+                     dominant-baseline=\"middle\"{}>{}</text>",
+                    x,
+                    y,
+                    size,
+                    anchor,
+                    rotate,
+                    escape(&text)
+                );
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn render_image(width: u32, height: u32, shapes: Vec<Shape>) -> RgbImage {
+    let mut canvas = Canvas {
+        image: RgbImage::from_pixel(width * SUPERSAMPLE, height * SUPERSAMPLE, Rgb(WHITE)),
+    };
+    let s = SUPERSAMPLE as f32;
+    for shape in shapes {
+        match shape {
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                fill,
+                stroke,
+            } => {
+                let (x, y, width, height) = (x * s, y * s, width * s, height * s);
+                if let Some(fill) = fill {
+                    canvas.fill_rect(x, y, width, height, fill);
+                }
+                if let Some(stroke) = stroke {
+                    let corners = [
+                        (x, y),
+                        (x + width, y),
+                        (x + width, y + height),
+                        (x, y + height),
+                        (x, y),
+                    ];
+                    canvas.polyline(&corners, stroke, s);
+                }
+            }
+            Shape::Line {
+                points,
+                color,
+                width,
+            } => {
+                let points: Vec<(f32, f32)> = points.iter().map(|&(x, y)| (x * s, y * s)).collect();
+                canvas.polyline(&points, color, width * s);
+            }
+            Shape::Text {
+                x,
+                y,
+                text,
+                size,
+                anchor,
+                vertical,
+            } => canvas.text(x * s, y * s, &text, size * s, anchor, vertical),
+        }
+    }
+    imageops::resize(&canvas.image, width, height, FilterType::Triangle)
+}
+
+fn save(
+    path: &Path,
+    svg: impl FnOnce() -> String,
+    image: impl FnOnce() -> RgbImage,
+) -> io::Result<()> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("svg") => std::fs::write(path, svg()),
+        Some("png") => image().save(path).map_err(io::Error::other),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't plot to {}, use .svg or .png", path.display()),
+        )),
+    }
+}
+
+fn svg_color([r, g, b]: Color) -> String {
+    format!("rgb({},{},{})", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+struct Canvas {
+    image: RgbImage,
+}
+
+impl Canvas {
+    fn put(&mut self, x: i64, y: i64, color: Color) {
+        if x >= 0 && y >= 0 && (x as u32) < self.image.width() && (y as u32) < self.image.height() {
+            self.image.put_pixel(x as u32, y as u32, Rgb(color));
+        }
+    }
+
+    fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        for py in y.round() as i64..(y + height).round() as i64 {
+            for px in x.round() as i64..(x + width).round() as i64 {
+                self.put(px, py, color);
+            }
+        }
+    }
+
+    // Stamps a square of `width` pixels every pixel along each segment
+    fn polyline(&mut self, points: &[(f32, f32)], color: Color, width: f32) {
+        let half = (width / 2.0).max(0.5);
+        let stamp = |canvas: &mut Canvas, x: f32, y: f32| {
+            canvas.fill_rect(x - half, y - half, 2.0 * half, 2.0 * half, color)
+        };
+        if let [(x, y)] = *points {
+            stamp(self, x, y);
+        }
+        for pair in points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+            for i in 0..=steps {
+                let t = i as f32 / steps as f32;
+                stamp(self, x0 + t * (x1 - x0), y0 + t * (y1 - y0));
+            }
+        }
+    }
+
+    fn text(&mut self, x: f32, y: f32, text: &str, size: f32, anchor: Anchor, vertical: bool) {
+        // Glyph pixels as canvas pixels, 7 of them a bit under the font size
+        let scale = (size / 9.0).round().max(1.0) as i64;
+        let chars: Vec<char> = text.chars().collect();
+        let length = (chars.len() as i64 * 6 - 1).max(0) * scale;
+        let height = 7 * scale;
+        let start = match anchor {
+            Anchor::Start => 0,
+            Anchor::Middle => -length / 2,
+            Anchor::End => -length,
+        };
+        let (x, y) = (x.round() as i64, y.round() as i64);
+        for (i, &c) in chars.iter().enumerate() {
+            let rows = glyph(c);
+            for (gy, row) in rows.iter().enumerate() {
+                for gx in 0..5 {
+                    if row & (0x10 >> gx) == 0 {
+                        continue;
+                    }
+                    // Offsets along the text and down from its top
+                    let u = start + (i as i64 * 6 + gx) * scale;
+                    let v = gy as i64 * scale - height / 2;
+                    for du in 0..scale {
+                        for dv in 0..scale {
+                            let (along, down) = (u + du, v + dv);
+                            if vertical {
+                                self.put(x + down, y - along, BLACK);
+                            } else {
+                                self.put(x + along, y + down, BLACK);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 5x7 bitmaps, a row per byte with the leftmost pixel in bit 4. Lower case letters use the
+// upper case glyphs, characters without one show as '?'.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '<' => [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        '[' => [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E],
+        ']' => [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '*' => [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// Terminal heatmap of a 2-D tensor, e.g. an attention map or an MNIST digit while debugging:
+//
+//     attention_weights.show();
+//     println!("{}", Heatmap::new(&image).width(40).color(false));
+//
+// With color every character shows two vertically stacked cells as an upper half block
+// colored on the viridis scale (dark blue for the minimum, yellow for the maximum, magenta
+// for NaN), so cells come out square. Without color each cell is two characters of a shade
+// block. Tensors with more than 2 axes are accepted when the others have size 1, [N] shows as
+// one row, and wide ones are averaged down to the width.
+pub struct Heatmap {
+    shape: Vec<usize>,
+    values: ndarray::Array2<f32>,
+    width: usize,
+    color: bool,
+}
+
+impl Heatmap {
+    #[track_caller]
+    pub fn new(tensor: &Tensor) -> Heatmap {
+        let data = tensor.borrow().data.clone();
+        let shape = data.shape().to_vec();
+        let axes: Vec<usize> = shape.iter().copied().filter(|&n| n != 1).collect();
+        let (rows, cols) = match *axes.as_slice() {
+            [] => (1, 1),
+            [n] => (1, n),
+            [rows, cols] => (rows, cols),
+            _ => panic!(
+                "heatmap needs a 2-D tensor (other axes of size 1), got shape {:?}",
+                shape
+            ),
+        };
+        let values = data
+            .into_shape((rows, cols))
+            .expect("same number of elements");
+        Heatmap {
+            shape,
+            values,
+            width: 80,
+            color: true,
+        }
+    }
+
+    // Most terminal columns to use, 80 by default
+    pub fn width(mut self, width: usize) -> Self {
+        assert!(width >= 2, "heatmap width must be at least 2 columns");
+        self.width = width;
+        self
+    }
+
+    // 24-bit ANSI colors, on by default
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // Average blocks of cells until the columns fit
+    fn cells(&self) -> ndarray::Array2<f32> {
+        let (rows, cols) = self.values.dim();
+        let per_cell = if self.color { 1 } else { 2 };
+        let factor = (cols * per_cell).div_ceil(self.width).max(1);
+        if factor == 1 {
+            return self.values.clone();
+        }
+        ndarray::Array2::from_shape_fn((rows.div_ceil(factor), cols.div_ceil(factor)), |(r, c)| {
+            let block = self.values.slice(ndarray::s![
+                r * factor..((r + 1) * factor).min(rows),
+                c * factor..((c + 1) * factor).min(cols)
+            ]);
+            block.sum() / block.len() as f32
+        })
+    }
+}
+
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+const NAN_COLOR: Color = [255, 0, 255];
+
+impl fmt::Display for Heatmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells = self.cells();
+        let finite = self.values.iter().copied().filter(|v| v.is_finite());
+        let (min, max) = finite.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+        // 0 to 1 along the scale, None for NaN
+        let level = |v: f32| -> Option<f32> {
+            if v.is_nan() {
+                None
+            } else if max > min {
+                Some(((v - min) / (max - min)).clamp(0.0, 1.0))
+            } else {
+                Some(0.5)
+            }
+        };
+        let rgb = |v: f32| level(v).map_or(NAN_COLOR, viridis);
+        writeln!(f, "{:?} min {} max {}", self.shape, min, max)?;
+        if self.color {
+            for pair in cells.axis_chunks_iter(ndarray::Axis(0), 2) {
+                for c in 0..pair.ncols() {
+                    let [r, g, b] = rgb(pair[[0, c]]);
+                    write!(f, "\x1b[38;2;{};{};{}m", r, g, b)?;
+                    match pair.get([1, c]) {
+                        Some(&below) => {
+                            let [r, g, b] = rgb(below);
+                            write!(f, "\x1b[48;2;{};{};{}m▀", r, g, b)?;
+                        }
+                        None => write!(f, "\x1b[49m▀")?,
+                    }
+                }
+                writeln!(f, "\x1b[0m")?;
+            }
+            // The scale from min to max
+            for i in 0..16 {
+                let [r, g, b] = viridis(i as f32 / 15.0);
+                write!(f, "\x1b[38;2;{};{};{}m█", r, g, b)?;
+            }
+            write!(f, "\x1b[0m {} to {}", min, max)
+        } else {
+            for row in cells.rows() {
+                let line: String = row
+                    .iter()
+                    .map(|&v| match level(v) {
+                        Some(level) => SHADES[(level * 4.0).round() as usize],
+                        None => '?',
+                    })
+                    .flat_map(|c| [c, c])
+                    .collect();
+                writeln!(f, "{}", line.trim_end())?;
+            }
+            write!(
+                f,
+                "{} {} to {}",
+                SHADES.iter().collect::<String>(),
+                min,
+                max
+            )
+        }
+    }
+}
+
+// Matplotlib's viridis colormap at `t` in [0, 1], interpolated between 5 of its colors
+fn viridis(t: f32) -> Color {
+    const STOPS: [Color; 5] = [
+        [68, 1, 84],
+        [59, 82, 139],
+        [33, 145, 140],
+        [94, 201, 98],
+        [253, 231, 37],
+    ];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (x.floor() as usize).min(STOPS.len() - 2);
+    let frac = x - i as f32;
+    let mut color = [0; 3];
+    for (k, channel) in color.iter_mut().enumerate() {
+        let (a, b) = (STOPS[i][k] as f32, STOPS[i + 1][k] as f32);
+        *channel = (a + frac * (b - a)).round() as u8;
+    }
+    color
+}
+
+impl Tensor {
+    // Print the tensor as a Heatmap, in color when stdout is a terminal
+    #[track_caller]
+    pub fn show(&self) {
+        let color = io::stdout().is_terminal();
+        println!("{}", Heatmap::new(self).color(color));
+    }
+}
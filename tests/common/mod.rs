@@ -0,0 +1,40 @@
+// Shared by the integration tests, each of which uses only some of it
+#![allow(dead_code)]
+
+use rust_ml::loss::{self, Reduction};
+use rust_ml::ndarray::{ArrayD, Dimension};
+use rust_ml::tensor::Tensor;
+
+// Compare the gradient backward gives for the scalar f(x) with central differences, entry by
+// entry. Steps are large for f32, so `tol` is relative to the size of the numeric gradient.
+#[track_caller]
+pub fn check_gradient(f: impl Fn(&Tensor) -> Tensor, x: ArrayD<f32>, tol: f32) {
+    let input = Tensor::from(x.clone());
+    f(&input).backward();
+    let analytic = input.grad_array().expect("f(x) doesn't depend on x");
+    let eps = 1e-2;
+    for (index, &grad) in analytic.indexed_iter() {
+        let (mut plus, mut minus) = (x.clone(), x.clone());
+        plus[&index] += eps;
+        minus[&index] -= eps;
+        let numeric =
+            (f(&Tensor::from(plus)).item() - f(&Tensor::from(minus)).item()) / (2.0 * eps);
+        assert!(
+            (numeric - grad).abs() <= tol * (1.0 + numeric.abs()),
+            "gradient at {:?} is {}, central differences give {}",
+            index.slice(),
+            grad,
+            numeric
+        );
+    }
+}
+
+// Sum of the squared entries of x times fixed weights, a scalar loss that gives every entry a
+// different gradient
+pub fn weighted_squares(x: &Tensor) -> Tensor {
+    let len = x.shape().iter().product::<usize>();
+    let weights = (0..len).map(|i| (i as f32 * 0.9 + 0.3).sin()).collect();
+    let weights = ArrayD::from_shape_vec(x.shape(), weights).unwrap();
+    let zeros = Tensor::from(ArrayD::zeros(x.shape()));
+    loss::mse(&(x * &Tensor::from(weights)), &zeros, Reduction::Sum)
+}
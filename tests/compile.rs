@@ -0,0 +1,111 @@
+use rust_ml::assert_tensor_close;
+use rust_ml::compile::{compile, compile_with, CompiledModule, Pass};
+use rust_ml::memory;
+use rust_ml::ndarray::{Array2, ArrayD, IxDyn};
+use rust_ml::nn::Module;
+use rust_ml::tensor;
+use rust_ml::tensor::Tensor;
+
+// A forward pass with something for every pass: a constant made during the trace that only
+// meets other constants, an activation computed twice and a parameter
+struct Probe {
+    weight: Tensor,
+}
+
+impl Module for Probe {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let scale = tensor![[0.5, -1.0, 2.0]];
+        // tanh and * only read constants
+        let scale = &scale.tanh() * &scale;
+        let (a, b) = (input.relu(), input.relu());
+        (&(&a * &scale) + &b).tanh().matmul(&self.weight)
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        vec![(String::from("weight"), self.weight.clone())]
+    }
+}
+
+fn probe() -> Probe {
+    Probe {
+        weight: Tensor::from(ArrayD::from_shape_fn(IxDyn(&[3, 2]), |index| {
+            (index[0] * 2 + index[1]) as f32 * 0.3 - 0.7
+        })),
+    }
+}
+
+fn input(phase: f32) -> Tensor {
+    Tensor::from(
+        Array2::from_shape_fn((4, 3), |(i, j)| ((i * 3 + j) as f32 + phase).sin()).into_dyn(),
+    )
+}
+
+fn compiled(model: &Probe, passes: &[Pass]) -> CompiledModule {
+    compile_with(model, &input(0.0), passes).unwrap()
+}
+
+#[test]
+fn passes_keep_the_outputs() {
+    let model = probe();
+    let x = input(1.0);
+    let expected = model.forward(&x);
+    let all = compile(&model, &input(0.0)).unwrap();
+    assert_tensor_close!(all.forward(&x), expected);
+    for passes in [
+        &[][..],
+        &[Pass::FoldConstants],
+        &[Pass::EliminateCommonSubexpressions],
+    ] {
+        assert_tensor_close!(compiled(&model, passes).forward(&x), expected);
+    }
+    // Parameters are read at every call, not folded
+    model.weight.borrow_mut().data *= -2.0;
+    assert_tensor_close!(all.forward(&x), model.forward(&x));
+}
+
+#[test]
+fn each_pass_removes_its_steps() {
+    let model = probe();
+    // tanh(scale), * scale, relu, relu, *, +, tanh, matmul
+    assert_eq!(compiled(&model, &[]).len(), 8);
+    assert_eq!(compiled(&model, &[Pass::FoldConstants]).len(), 6);
+    assert_eq!(
+        compiled(&model, &[Pass::EliminateCommonSubexpressions]).len(),
+        7
+    );
+    // Everything in a fresh trace feeds the output
+    assert_eq!(compiled(&model, &[Pass::EliminateDeadSteps]).len(), 8);
+    assert_eq!(compile(&model, &input(0.0)).unwrap().len(), 5);
+}
+
+#[test]
+fn dead_step_elimination_drops_folded_constants() {
+    let model = probe();
+    let live = |passes: &[Pass]| {
+        let before = memory::stats().live_tensors;
+        let compiled = compiled(&model, passes);
+        let held = memory::stats().live_tensors - before;
+        drop(compiled);
+        held
+    };
+    // The scale and its tanh are only read by steps folding replaced
+    assert_eq!(
+        live(&[Pass::FoldConstants]) - live(&[Pass::FoldConstants, Pass::EliminateDeadSteps]),
+        2
+    );
+}
+
+#[test]
+fn common_subexpressions_match_commuted_inputs() {
+    struct Commuted;
+    impl Module for Commuted {
+        fn forward(&self, input: &Tensor) -> Tensor {
+            let relu = input.relu();
+            &(input * &relu) + &(&relu * input)
+        }
+    }
+    let x = input(0.0);
+    let compiled = compile_with(&Commuted, &x, &[Pass::EliminateCommonSubexpressions]).unwrap();
+    assert_eq!(compiled.len(), 3);
+    assert_tensor_close!(compiled.forward(&x), Commuted.forward(&x));
+}
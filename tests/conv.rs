@@ -0,0 +1,230 @@
+mod common;
+
+use common::{check_gradient, weighted_squares};
+use rust_ml::ndarray::{Array3, Array4, ArrayD, Axis, IxDyn};
+use rust_ml::nn::{AvgPool1d, Conv1d, Conv2d, MaxPool1d, Module, Sequential};
+use rust_ml::spatial::ConvOptions;
+use rust_ml::tensor::Tensor;
+use rust_ml::{assert_tensor_close, tensor};
+
+fn input(shape: &[usize]) -> ArrayD<f32> {
+    let len = shape.iter().product::<usize>();
+    let data = (0..len).map(|i| (i as f32 * 0.37).sin()).collect();
+    ArrayD::from_shape_vec(IxDyn(shape), data).unwrap()
+}
+
+fn weight(shape: &[usize]) -> ArrayD<f32> {
+    let len = shape.iter().product::<usize>();
+    let data = (0..len).map(|i| (i as f32 * 0.7).cos()).collect();
+    ArrayD::from_shape_vec(IxDyn(shape), data).unwrap()
+}
+
+const STRIDED: ConvOptions = ConvOptions {
+    stride: 2,
+    padding: 2,
+    dilation: 2,
+    groups: 1,
+};
+
+// out[n, o, l] = bias[o] + sum over c, k of w[o, c, k] x[n, c, l * stride + k * dilation - padding]
+fn naive_conv1d(x: &ArrayD<f32>, w: &ArrayD<f32>, bias: &[f32], options: ConvOptions) -> Tensor {
+    let ConvOptions {
+        stride,
+        padding,
+        dilation,
+        ..
+    } = options;
+    let (n, c, len) = (x.shape()[0], x.shape()[1], x.shape()[2]);
+    let (out_channels, k) = (w.shape()[0], w.shape()[2]);
+    let out_len = (len + 2 * padding - dilation * (k - 1) - 1) / stride + 1;
+    let out = Array3::from_shape_fn((n, out_channels, out_len), |(b, o, l)| {
+        let mut sum = bias[o];
+        for ci in 0..c {
+            for ki in 0..k {
+                let i = (l * stride + ki * dilation) as isize - padding as isize;
+                if (0..len as isize).contains(&i) {
+                    sum += w[[o, ci, ki]] * x[[b, ci, i as usize]];
+                }
+            }
+        }
+        sum
+    });
+    Tensor::from(out.into_dyn())
+}
+
+#[test]
+fn conv1d_matches_the_definition() {
+    let (x, w) = (input(&[2, 3, 9]), weight(&[4, 3, 3]));
+    let bias = tensor![0.1, -0.2, 0.3, 0.0];
+    for options in [ConvOptions::default(), STRIDED] {
+        let out = Tensor::from(x.clone()).conv1d(&Tensor::from(w.clone()), Some(&bias), options);
+        let expected = naive_conv1d(&x, &w, &bias.to_vec(), options);
+        assert_tensor_close!(out, expected, 1e-5, 1e-5);
+    }
+}
+
+#[test]
+fn conv1d_gradients() {
+    let (x, w) = (input(&[2, 3, 9]), weight(&[4, 3, 3]));
+    let bias = Tensor::from(ArrayD::from_elem(IxDyn(&[4]), 0.1));
+    let weight_tensor = Tensor::from(w.clone());
+    check_gradient(
+        |x| weighted_squares(&x.conv1d(&weight_tensor, Some(&bias), STRIDED)),
+        x.clone(),
+        1e-2,
+    );
+    let input_tensor = Tensor::from(x);
+    check_gradient(
+        |w| weighted_squares(&input_tensor.conv1d(w, Some(&bias), STRIDED)),
+        w,
+        1e-2,
+    );
+    check_gradient(
+        |b| weighted_squares(&input_tensor.conv1d(&weight_tensor, Some(b), STRIDED)),
+        bias.borrow().data.clone(),
+        1e-2,
+    );
+}
+
+#[test]
+fn pool1d_values_and_gradients() {
+    let x = Tensor::from(
+        ArrayD::from_shape_vec(IxDyn(&[1, 1, 5]), vec![1.0, 3.0, 2.0, 5.0, 4.0]).unwrap(),
+    );
+    assert_eq!(x.max_pool1d(2, 2).to_vec(), vec![3.0, 5.0]);
+    assert_eq!(
+        x.avg_pool1d(3, 1).to_vec(),
+        vec![2.0, 10.0 / 3.0, 11.0 / 3.0]
+    );
+    // Overlapping windows send the gradient of each to its maximum
+    check_gradient(
+        |x| weighted_squares(&x.max_pool1d(2, 1)),
+        input(&[2, 3, 7]),
+        1e-2,
+    );
+    check_gradient(
+        |x| weighted_squares(&x.avg_pool1d(3, 2)),
+        input(&[2, 3, 7]),
+        1e-2,
+    );
+}
+
+#[test]
+fn conv1d_layers_stack() {
+    let model = Sequential::new()
+        .add(Conv1d::new(3, 5, 3).padding(1))
+        .add(MaxPool1d::new(2))
+        .add(AvgPool1d::new(2).stride(1));
+    let out = model.forward(&Tensor::from(input(&[2, 3, 9])));
+    assert_eq!(out.shape(), vec![2, 5, 3]);
+    assert_eq!(model.output_shape(&[2, 3, 9]).unwrap(), vec![2, 5, 3]);
+}
+
+#[test]
+fn conv1d_checks_channels() {
+    let x = Tensor::from(input(&[2, 3, 9]));
+    let w = Tensor::from(weight(&[4, 2, 3]));
+    assert!(x.try_conv1d(&w, None, ConvOptions::default()).is_err());
+}
+
+const GROUPED: ConvOptions = ConvOptions {
+    stride: 2,
+    padding: 1,
+    dilation: 1,
+    groups: 2,
+};
+
+// Each group of output channels only sees its own group of input channels
+fn naive_conv2d(x: &ArrayD<f32>, w: &ArrayD<f32>, options: ConvOptions) -> Tensor {
+    let ConvOptions {
+        stride,
+        padding,
+        groups,
+        ..
+    } = options;
+    let (n, height, width) = (x.shape()[0], x.shape()[2], x.shape()[3]);
+    let (out_channels, group_channels, kh, kw) =
+        (w.shape()[0], w.shape()[1], w.shape()[2], w.shape()[3]);
+    let out_per_group = out_channels / groups;
+    let out_height = (height + 2 * padding - kh) / stride + 1;
+    let out_width = (width + 2 * padding - kw) / stride + 1;
+    let out = Array4::from_shape_fn(
+        (n, out_channels, out_height, out_width),
+        |(b, o, row, col)| {
+            let group = o / out_per_group;
+            let mut sum = 0.0;
+            for ci in 0..group_channels {
+                for ky in 0..kh {
+                    for kx in 0..kw {
+                        let iy = (row * stride + ky) as isize - padding as isize;
+                        let ix = (col * stride + kx) as isize - padding as isize;
+                        if (0..height as isize).contains(&iy) && (0..width as isize).contains(&ix) {
+                            let c = group * group_channels + ci;
+                            sum += w[[o, ci, ky, kx]] * x[[b, c, iy as usize, ix as usize]];
+                        }
+                    }
+                }
+            }
+            sum
+        },
+    );
+    Tensor::from(out.into_dyn())
+}
+
+#[test]
+fn grouped_conv2d_matches_the_definition() {
+    let (x, w) = (input(&[2, 4, 5, 6]), weight(&[6, 2, 3, 2]));
+    let out = Tensor::from(x.clone()).conv2d(&Tensor::from(w.clone()), None, GROUPED);
+    assert_eq!(out.shape(), vec![2, 6, 3, 4]);
+    assert_tensor_close!(out, naive_conv2d(&x, &w, GROUPED), 1e-5, 1e-5);
+}
+
+#[test]
+fn grouped_conv2d_gradients() {
+    let (x, w) = (input(&[2, 4, 5, 6]), weight(&[6, 2, 3, 2]));
+    let weight_tensor = Tensor::from(w.clone());
+    check_gradient(
+        |x| weighted_squares(&x.conv2d(&weight_tensor, None, GROUPED)),
+        x.clone(),
+        1e-2,
+    );
+    let input_tensor = Tensor::from(x);
+    check_gradient(
+        |w| weighted_squares(&input_tensor.conv2d(w, None, GROUPED)),
+        w,
+        1e-2,
+    );
+}
+
+#[test]
+fn depthwise_conv_filters_each_channel_alone() {
+    let conv = Conv2d::depthwise(4, 3).padding(1);
+    assert_eq!(conv.weight.shape(), vec![4, 1, 3, 3]);
+    let x = input(&[2, 4, 5, 6]);
+    let out = conv.forward(&Tensor::from(x.clone()));
+    assert_eq!(out.shape(), vec![2, 4, 5, 6]);
+    // Zeroing one input channel only changes its own output channel
+    let mut masked = x;
+    masked.index_axis_mut(Axis(1), 2).fill(0.0);
+    let masked_out = conv.forward(&Tensor::from(masked)).borrow().data.clone();
+    let out = out.borrow().data.clone();
+    for channel in [0, 1, 3] {
+        assert_eq!(
+            out.index_axis(Axis(1), channel),
+            masked_out.index_axis(Axis(1), channel)
+        );
+    }
+    assert_ne!(
+        out.index_axis(Axis(1), 2),
+        masked_out.index_axis(Axis(1), 2)
+    );
+}
+
+#[test]
+fn grouped_layers_check_channels() {
+    assert_eq!(Conv1d::new(4, 8, 3).groups(4).weight.shape(), vec![8, 1, 3]);
+    let x = Tensor::from(input(&[2, 4, 5, 6]));
+    let w = Tensor::from(weight(&[6, 2, 3, 2]));
+    // Two input channels per kernel only fit four inputs with two groups
+    assert!(x.try_conv2d(&w, None, ConvOptions::default()).is_err());
+}
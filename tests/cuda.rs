@@ -0,0 +1,53 @@
+#![cfg(feature = "cuda")]
+
+use rust_ml::assert_tensor_close;
+use rust_ml::device::Device;
+use rust_ml::ndarray::{ArrayD, Dimension, IxDyn};
+use rust_ml::tensor::Tensor;
+
+fn input(shape: &[usize], seed: f32) -> Tensor {
+    Tensor::from(ArrayD::from_shape_fn(IxDyn(shape), |index| {
+        (index.slice().iter().sum::<usize>() as f32 * 0.37 + seed).sin() * 2.0
+    }))
+}
+
+fn gpu(tensor: &Tensor) -> Tensor {
+    tensor.to(Device::Gpu(0))
+}
+
+// Needs a GPU, run with `cargo test --features cuda -- --ignored`
+#[test]
+#[ignore]
+fn gpu_ops_match_the_cpu() {
+    rust_ml::cuda::init().expect("no GPU to test on");
+    let (a, b) = (input(&[17, 33], 0.0), input(&[33, 9], 1.0));
+    let c = input(&[17, 33], 2.0);
+    let (ga, gb, gc) = (gpu(&a), gpu(&b), gpu(&c));
+
+    assert_tensor_close!(ga.matmul(&gb), a.matmul(&b), 1e-4, 1e-5);
+    assert_tensor_close!(ga.tanh(), a.tanh());
+    assert_tensor_close!(ga.exp(), a.exp());
+    assert_tensor_close!(ga.relu(), a.relu());
+    assert_tensor_close!(&ga + &gc, &a + &c);
+    assert_tensor_close!(&ga * &gc, &a * &c);
+    for axis in 0..2 {
+        assert_tensor_close!(ga.sum_axis(axis), a.sum_axis(axis), 1e-4, 1e-5);
+    }
+}
+
+#[test]
+#[ignore]
+fn gpu_matmul_gradients_match_the_cpu() {
+    rust_ml::cuda::init().expect("no GPU to test on");
+    let (a, b) = (input(&[5, 7], 0.0), input(&[7, 3], 1.0));
+    let (ga, gb) = (input(&[5, 7], 0.0), input(&[7, 3], 1.0));
+    a.matmul(&b).sum_axis(1).sum_axis(0).backward();
+    gpu(&ga)
+        .matmul(&gpu(&gb))
+        .sum_axis(1)
+        .sum_axis(0)
+        .backward();
+    // The gradient goes back to the CPU leaves
+    assert_tensor_close!(ga.grad().unwrap(), a.grad().unwrap(), 1e-4, 1e-5);
+    assert_tensor_close!(gb.grad().unwrap(), b.grad().unwrap(), 1e-4, 1e-5);
+}
@@ -0,0 +1,80 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rust_ml::data::mnist;
+use rust_ml::data::transforms::GaussianNoise;
+use rust_ml::data::{DataLoader, Dataset, TensorDataset};
+use rust_ml::ndarray::{ArrayD, IxDyn};
+use rust_ml::random::manual_seed;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+
+// Every batch of a shuffled, noise-augmented loader with `workers` threads
+fn noisy_batches(seed: u64, workers: usize) -> Vec<Vec<f32>> {
+    manual_seed(seed);
+    let dataset = TensorDataset::new(
+        ArrayD::zeros(IxDyn(&[64, 3, 4, 4])),
+        ArrayD::zeros(IxDyn(&[64])),
+    )
+    .transform(GaussianNoise::new(1.0));
+    let loader = DataLoader::new(dataset, 4)
+        .shuffle(true)
+        .num_workers(workers);
+    loader.iter().map(|(x, _)| x.to_vec()).collect()
+}
+
+#[test]
+fn worker_augmentations_follow_manual_seed() {
+    assert_eq!(noisy_batches(7, 4), noisy_batches(7, 4));
+    assert_ne!(noisy_batches(7, 4), noisy_batches(8, 4));
+}
+
+#[test]
+fn workers_augment_each_batch_differently() {
+    let batches = noisy_batches(7, 4);
+    assert_eq!(batches.len(), 16);
+    for (i, batch) in batches.iter().enumerate() {
+        assert!(batches[..i].iter().all(|earlier| earlier != batch));
+    }
+}
+
+// An unsigned byte IDX file holding 0, 1, 2, ... in the given shape
+fn idx_gz(path: &std::path::Path, shape: &[u32]) {
+    let mut bytes = vec![0, 0, 0x08, shape.len() as u8];
+    for dim in shape {
+        bytes.extend(dim.to_be_bytes());
+    }
+    let len: u32 = shape.iter().product();
+    bytes.extend((0..len).map(|i| i as u8));
+    let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+    encoder.write_all(&bytes).unwrap();
+    encoder.finish().unwrap();
+}
+
+#[test]
+fn mnist_decompresses_the_idx_files_it_finds() {
+    let dir = std::env::temp_dir().join(format!("rust_ml_mnist_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    // Nothing to load yet, the error says where to get the files
+    let error = mnist::load(&dir).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert!(error.to_string().contains("train-images-idx3-ubyte.gz"));
+
+    for (name, shape) in [
+        ("train-images-idx3-ubyte", &[3, 2, 2][..]),
+        ("train-labels-idx1-ubyte", &[3]),
+        ("t10k-images-idx3-ubyte", &[2, 2, 2]),
+        ("t10k-labels-idx1-ubyte", &[2]),
+    ] {
+        idx_gz(&dir.join(format!("{}.gz", name)), shape);
+    }
+    let data = mnist::load(&dir).unwrap();
+    assert_eq!((data.train.len(), data.test.len()), (3, 2));
+    let (image, label) = data.train.get(1);
+    assert_eq!(image.to_vec(), [4.0, 5.0, 6.0, 7.0].map(|v| v / 255.0));
+    assert_eq!(label.to_vec(), [1.0]);
+    // The decompressed files replace the archives and load again as they are
+    assert!(!dir.join("t10k-labels-idx1-ubyte.gz").exists());
+    assert_eq!(mnist::load(&dir).unwrap().test.len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,24 @@
+use rust_ml::ndarray::{ArrayD, IxDyn};
+use rust_ml::tensor;
+use rust_ml::tensor::Tensor;
+
+#[test]
+fn display_shows_small_tensors_whole() {
+    let x = tensor![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+    assert_eq!(
+        x.to_string(),
+        "Tensor(shape=[2, 3], op=None, data=[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], grad=false)"
+    );
+}
+
+#[test]
+fn display_previews_the_ends_of_larger_tensors() {
+    let x = Tensor::from(ArrayD::from_shape_fn(IxDyn(&[2, 4]), |index| {
+        (index[0] * 4 + index[1] + 1) as f32
+    }));
+    let h1 = (&x + &x).named("h1");
+    assert_eq!(
+        h1.to_string(),
+        "Tensor(name=h1, shape=[2, 4], op=+, data=[2.0, 4.0, 6.0, ..., 12.0, 14.0, 16.0], grad=false)"
+    );
+}
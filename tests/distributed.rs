@@ -0,0 +1,120 @@
+use rust_ml::data::{DistributedSampler, Sampler};
+use rust_ml::distributed::{DistributedDataParallel, ProcessGroup};
+use rust_ml::loss::{self, Reduction};
+use rust_ml::ndarray::{ArrayD, Axis, IxDyn};
+use rust_ml::nn::{Linear, Module};
+use rust_ml::random::manual_seed;
+use rust_ml::tensor::Tensor;
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::thread;
+
+// Run `f` on every rank of a localhost group, one thread per rank, results in rank order
+fn run_ranks<T: Send>(world_size: usize, f: impl Fn(ProcessGroup) -> T + Sync) -> Vec<T> {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    thread::scope(|scope| {
+        let ranks: Vec<_> = (0..world_size)
+            .map(|rank| {
+                let f = &f;
+                scope.spawn(move || {
+                    f(ProcessGroup::init(("127.0.0.1", port), rank, world_size).unwrap())
+                })
+            })
+            .collect();
+        ranks.into_iter().map(|rank| rank.join().unwrap()).collect()
+    })
+}
+
+#[test]
+fn all_reduce_sums_over_the_ring() {
+    let results = run_ranks(3, |group| {
+        // Seven values don't split evenly into three segments
+        let rank = group.rank() as f32;
+        let mut values: Vec<f32> = (0..7).map(|i| i as f32 + 10.0 * rank).collect();
+        group.all_reduce(&mut values).unwrap();
+        let mut broadcast = vec![rank; 4];
+        group.broadcast(&mut broadcast, 1).unwrap();
+        group.barrier().unwrap();
+        (values, broadcast)
+    });
+    let sums: Vec<f32> = (0..7).map(|i| 3.0 * i as f32 + 30.0).collect();
+    for (values, broadcast) in results {
+        assert_eq!(values, sums);
+        assert_eq!(broadcast, [1.0; 4]);
+    }
+}
+
+fn dataset() -> (ArrayD<f32>, ArrayD<f32>) {
+    let x = ArrayD::from_shape_fn(IxDyn(&[7, 3]), |index| {
+        ((index[0] * 3 + index[1]) as f32 * 0.7).sin()
+    });
+    let y = ArrayD::from_shape_fn(IxDyn(&[7, 2]), |index| (index[0] + index[1]) as f32 * 0.1);
+    (x, y)
+}
+
+fn flat_grads(model: &dyn Module) -> Vec<f32> {
+    model
+        .parameters()
+        .iter()
+        .flat_map(|p| p.grad_array().unwrap())
+        .collect()
+}
+
+#[test]
+fn data_parallel_averages_the_gradients() {
+    let (x, y) = dataset();
+    let results = run_ranks(3, |group| {
+        // Each rank starts from different weights until construction copies rank 0's
+        manual_seed(group.rank() as u64);
+        let sampler = DistributedSampler::new(group.rank(), group.world_size());
+        let model = DistributedDataParallel::new(Linear::new(3, 2), group).unwrap();
+        let weights: Vec<f32> = model
+            .parameters()
+            .iter()
+            .flat_map(|p| p.borrow().data.clone())
+            .collect();
+        let shard = sampler.indices(7);
+        let rows = |data: &ArrayD<f32>| Tensor::from(data.select(Axis(0), &shard));
+        loss::mse(&model.forward(&rows(&x)), &rows(&y), Reduction::Mean).backward();
+        let local = flat_grads(&model);
+        model.sync_grads().unwrap();
+        (shard, weights, local, flat_grads(&model))
+    });
+
+    let (shards, weights): (Vec<_>, Vec<_>) = results.iter().map(|r| (&r.0, &r.1)).unzip();
+    // Seven samples over three ranks, padded with the first two
+    assert_eq!(shards, [&vec![0, 3, 6], &vec![1, 4, 0], &vec![2, 5, 1]]);
+    assert!(weights.iter().all(|w| *w == weights[0]));
+    let mean: Vec<f32> = (0..results[0].2.len())
+        .map(|i| results.iter().map(|r| r.2[i]).sum::<f32>() / 3.0)
+        .collect();
+    for (_, _, local, synced) in &results {
+        assert_ne!(local, synced);
+        for (synced, mean) in synced.iter().zip(&mean) {
+            assert!((synced - mean).abs() < 1e-6, "{:?} vs {:?}", synced, mean);
+        }
+    }
+}
+
+#[test]
+fn distributed_sampler_shards_a_shared_shuffle() {
+    let samplers: Vec<_> = (0..3)
+        .map(|rank| DistributedSampler::new(rank, 3).shuffle(true).seed(7))
+        .collect();
+    for _epoch in 0..2 {
+        let shards: Vec<Vec<usize>> = samplers.iter().map(|s| s.indices(10)).collect();
+        assert!(shards.iter().all(|shard| shard.len() == 4));
+        let seen: HashSet<usize> = shards.iter().flatten().copied().collect();
+        assert_eq!(seen.len(), 10);
+        // Two of the twelve slots repeat the start of the shared order
+        let order: Vec<usize> = (0..12).map(|i| shards[i % 3][i / 3]).collect();
+        assert_eq!(order[10..], order[..2]);
+    }
+    // Every epoch draws a new order
+    let first = DistributedSampler::new(0, 3).shuffle(true).seed(7);
+    assert_ne!(first.indices(10), first.indices(10));
+}
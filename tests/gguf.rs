@@ -0,0 +1,243 @@
+use rust_ml::io::gguf::{self, MetadataValue};
+use std::io;
+
+const F32: u32 = 0;
+const F16: u32 = 1;
+const Q8_0: u32 = 8;
+const BF16: u32 = 30;
+
+// A GGUF v3 file with u32 or string metadata and tensors given by their row-major shape and
+// raw data, laid out back to back at `alignment`
+struct Writer {
+    alignment: usize,
+    metadata: Vec<(String, u32, Vec<u8>)>,
+    tensors: Vec<(String, Vec<u64>, u32, Vec<u8>)>,
+}
+
+impl Writer {
+    fn new() -> Writer {
+        Writer {
+            alignment: 32,
+            metadata: Vec::new(),
+            tensors: Vec::new(),
+        }
+    }
+
+    fn u32(mut self, key: &str, value: u32) -> Self {
+        self.metadata
+            .push((key.to_string(), 4, value.to_le_bytes().to_vec()));
+        self
+    }
+
+    fn string(mut self, key: &str, value: &str) -> Self {
+        self.metadata.push((key.to_string(), 8, string(value)));
+        self
+    }
+
+    fn alignment(self, alignment: u32) -> Self {
+        Writer {
+            alignment: alignment.max(1) as usize,
+            ..self.u32("general.alignment", alignment)
+        }
+    }
+
+    fn tensor(mut self, name: &str, shape: &[u64], ggml_type: u32, data: Vec<u8>) -> Self {
+        self.tensors
+            .push((name.to_string(), shape.to_vec(), ggml_type, data));
+        self
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        let mut out = b"GGUF".to_vec();
+        out.extend(3u32.to_le_bytes());
+        out.extend((self.tensors.len() as u64).to_le_bytes());
+        out.extend((self.metadata.len() as u64).to_le_bytes());
+        for (key, value_type, value) in &self.metadata {
+            out.extend(string(key));
+            out.extend(value_type.to_le_bytes());
+            out.extend(value);
+        }
+        let mut offset = 0;
+        for (name, shape, ggml_type, data) in &self.tensors {
+            out.extend(string(name));
+            out.extend((shape.len() as u32).to_le_bytes());
+            // Innermost dimension first
+            for dim in shape.iter().rev() {
+                out.extend(dim.to_le_bytes());
+            }
+            out.extend(ggml_type.to_le_bytes());
+            out.extend((offset as u64).to_le_bytes());
+            offset = (offset + data.len()).next_multiple_of(self.alignment);
+        }
+        for (_, _, _, data) in &self.tensors {
+            out.resize(out.len().next_multiple_of(self.alignment), 0);
+            out.extend(data);
+        }
+        out
+    }
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut out = (value.len() as u64).to_le_bytes().to_vec();
+    out.extend(value.as_bytes());
+    out
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn u16_bytes(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn error_kind(bytes: &[u8]) -> io::ErrorKind {
+    match gguf::parse(bytes) {
+        Ok(_) => panic!("parsed an invalid file"),
+        Err(e) => e.kind(),
+    }
+}
+
+#[test]
+fn reads_f32_tensors_and_metadata() {
+    let values = [1.0, -2.0, 0.5, 3.25, 0.0, -0.125];
+    let file = Writer::new()
+        .string("general.name", "tiny")
+        .u32("llama.block_count", 2)
+        .tensor("weight", &[2, 3], F32, f32_bytes(&values))
+        .tensor("bias", &[2], F32, f32_bytes(&[0.5, -0.5]))
+        .bytes();
+    let model = gguf::parse(&file).unwrap();
+    assert_eq!(model.version, 3);
+    assert_eq!(model.metadata["general.name"].as_str(), Some("tiny"));
+    assert_eq!(model.metadata["llama.block_count"], MetadataValue::U32(2));
+    let weight = &model.tensors["weight"];
+    assert_eq!(weight.shape(), [2, 3]);
+    assert_eq!(weight.iter().copied().collect::<Vec<_>>(), values);
+    assert_eq!(model.tensors["bias"].shape(), [2]);
+}
+
+#[test]
+fn reads_half_precision_tensors() {
+    // 1.0, -2.0, 0.5 and 0.0 as f16
+    let halves = u16_bytes(&[0x3c00, 0xc000, 0x3800, 0x0000]);
+    // bf16 is the top half of an f32
+    let values: [f32; 4] = [1.5, -3.0, 0.25, 256.0];
+    let brains = u16_bytes(&values.map(|v| (v.to_bits() >> 16) as u16));
+    let file = Writer::new()
+        .tensor("half", &[4], F16, halves)
+        .tensor("brain", &[2, 2], BF16, brains)
+        .bytes();
+    let model = gguf::parse(&file).unwrap();
+    let half: Vec<f32> = model.tensors["half"].iter().copied().collect();
+    assert_eq!(half, [1.0, -2.0, 0.5, 0.0]);
+    let brain: Vec<f32> = model.tensors["brain"].iter().copied().collect();
+    assert_eq!(brain, values);
+}
+
+#[test]
+fn dequantizes_q8_0_blocks() {
+    // Two blocks, with scales 0.5 and -2.0
+    let mut data = Vec::new();
+    for scale in [0x3800u16, 0xc000] {
+        data.extend(scale.to_le_bytes());
+        data.extend((0..32).map(|i| (i - 16) as i8 as u8));
+    }
+    let file = Writer::new().tensor("q", &[2, 32], Q8_0, data).bytes();
+    let q = &gguf::parse(&file).unwrap().tensors["q"];
+    assert_eq!(q.shape(), [2, 32]);
+    assert_eq!(q[[0, 0]], -8.0);
+    assert_eq!(q[[0, 31]], 7.5);
+    assert_eq!(q[[1, 0]], 32.0);
+    assert_eq!(q[[1, 17]], -2.0);
+}
+
+#[test]
+fn honors_a_custom_alignment() {
+    let file = Writer::new()
+        .alignment(64)
+        .tensor("a", &[3], F32, f32_bytes(&[1.0, 2.0, 3.0]))
+        .tensor("b", &[1], F32, f32_bytes(&[4.0]))
+        .bytes();
+    let model = gguf::parse(&file).unwrap();
+    assert_eq!(
+        model.tensors["b"].iter().copied().collect::<Vec<_>>(),
+        [4.0]
+    );
+}
+
+#[test]
+fn state_dict_renames_and_drops() {
+    let file = Writer::new()
+        .tensor("blk.0.ffn.weight", &[1], F32, f32_bytes(&[1.0]))
+        .tensor("token_embd.weight", &[1], F32, f32_bytes(&[2.0]))
+        .bytes();
+    let model = gguf::parse(&file).unwrap();
+    let state = model.state_dict(|name| {
+        name.strip_prefix("blk.")
+            .map(|rest| format!("layers.{}", rest))
+    });
+    assert_eq!(state.keys().collect::<Vec<_>>(), ["layers.0.ffn.weight"]);
+}
+
+#[test]
+fn rejects_bad_headers() {
+    let file = Writer::new()
+        .tensor("w", &[2], F32, f32_bytes(&[1.0, 2.0]))
+        .bytes();
+    let mut bad_magic = file.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(error_kind(&bad_magic), io::ErrorKind::InvalidData);
+    let mut old_version = file.clone();
+    old_version[4] = 1;
+    assert_eq!(error_kind(&old_version), io::ErrorKind::Unsupported);
+    let unknown_type = Writer::new().tensor("w", &[2], 99, vec![0; 8]).bytes();
+    assert_eq!(error_kind(&unknown_type), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let file = Writer::new()
+        .string("general.name", "tiny")
+        .tensor("w", &[4], F32, f32_bytes(&[1.0, 2.0, 3.0, 4.0]))
+        .bytes();
+    // Every cut either ends the header early or the data of the tensor
+    for len in 0..file.len() {
+        assert_eq!(
+            error_kind(&file[..len]),
+            io::ErrorKind::InvalidData,
+            "{}",
+            len
+        );
+    }
+}
+
+#[test]
+fn rejects_zero_alignment() {
+    let file = Writer::new()
+        .alignment(0)
+        .tensor("w", &[1], F32, f32_bytes(&[1.0]))
+        .bytes();
+    assert_eq!(error_kind(&file), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_sizes_that_overflow() {
+    let huge_shape = Writer::new()
+        .tensor("w", &[u64::MAX / 2, 4], F32, Vec::new())
+        .bytes();
+    assert_eq!(error_kind(&huge_shape), io::ErrorKind::InvalidData);
+    let huge_bytes = Writer::new()
+        .tensor("w", &[u64::MAX / 4 + 1], F32, Vec::new())
+        .bytes();
+    assert_eq!(error_kind(&huge_bytes), io::ErrorKind::InvalidData);
+    let mut huge_offset = Writer::new()
+        .tensor("w", &[1], F32, f32_bytes(&[1.0]))
+        .bytes();
+    // The offset is the last field of the only tensor info, before the padding
+    let info_end = 4 + 4 + 8 + 8 + (8 + 1) + 4 + 8 + 4 + 8;
+    huge_offset[info_end - 8..info_end].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(error_kind(&huge_offset), io::ErrorKind::InvalidData);
+    let partial_block = Writer::new().tensor("q", &[16], Q8_0, vec![0; 34]).bytes();
+    assert_eq!(error_kind(&partial_block), io::ErrorKind::InvalidData);
+}
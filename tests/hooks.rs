@@ -0,0 +1,177 @@
+use rust_ml::ensemble::Ensemble;
+use rust_ml::ndarray::{Array2, ArrayD};
+use rust_ml::nn::{
+    CausalSelfAttention, FeatureExtractor, ForwardHook, ForwardHooks, HookHandle, Hooked, Linear,
+    Module, ReLU, Sequential,
+};
+use rust_ml::tensor::Tensor;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+fn ones(rows: usize, columns: usize) -> Tensor {
+    Tensor::from(Array2::<f32>::ones((rows, columns)).into_dyn())
+}
+
+// A hook that counts its calls
+fn counter() -> (Rc<RefCell<usize>>, ForwardHook) {
+    let count = Rc::new(RefCell::new(0));
+    let hook_count = count.clone();
+    (count, Box::new(move |_, _| *hook_count.borrow_mut() += 1))
+}
+
+#[test]
+fn hooks_see_inputs_and_outputs() {
+    let model = Sequential::new()
+        .add(Linear::new(3, 4))
+        .add(ReLU)
+        .add(Linear::new(4, 2));
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let layer_seen = seen.clone();
+    let layer = model.layers[0].register_forward_hook(Box::new(move |input, output| {
+        layer_seen
+            .borrow_mut()
+            .push((input.shape(), output.shape()))
+    }));
+    let model_seen = seen.clone();
+    let whole = model.register_forward_hook(Box::new(move |input, output| {
+        model_seen
+            .borrow_mut()
+            .push((input.shape(), output.shape()))
+    }));
+    model.forward(&ones(5, 3));
+    model.predict(&ones(5, 3));
+    let layer_call = (vec![5, 3], vec![5, 4]);
+    let model_call = (vec![5, 3], vec![5, 2]);
+    assert_eq!(
+        *seen.borrow(),
+        [
+            layer_call.clone(),
+            model_call.clone(),
+            layer_call,
+            model_call
+        ]
+    );
+    layer.remove();
+    model.forward(&ones(5, 3));
+    assert_eq!(seen.borrow().len(), 5);
+    whole.remove();
+    model.forward(&ones(5, 3));
+    assert_eq!(seen.borrow().len(), 5);
+}
+
+#[test]
+fn a_module_and_its_first_field_have_separate_hooks() {
+    let attention = CausalSelfAttention::new(4);
+    let (query_calls, hook) = counter();
+    attention.query.register_forward_hook(hook);
+    let (attention_calls, hook) = counter();
+    attention.register_forward_hook(hook);
+    attention.forward(&ones(2, 4));
+    assert_eq!((*query_calls.borrow(), *attention_calls.borrow()), (1, 1));
+}
+
+#[test]
+fn hooks_move_with_the_module() {
+    let layer = Linear::new(3, 4);
+    let (calls, hook) = counter();
+    layer.register_forward_hook(hook);
+    let moved = Box::new(layer);
+    moved.forward(&ones(1, 3));
+    assert_eq!(*calls.borrow(), 1);
+}
+
+#[test]
+fn hooks_drop_with_the_module() {
+    let layer = Linear::new(3, 4);
+    let handle = layer.register_forward_hook(Box::new(|_, _| panic!("hook of a dropped layer")));
+    drop(layer);
+    // Other layers, wherever they end up in memory, don't run it
+    Linear::new(3, 4).forward(&ones(1, 3));
+    handle.remove();
+}
+
+#[test]
+fn stateless_layers_need_hooked() {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        ReLU.register_forward_hook(Box::new(|_, _| {}))
+    }));
+    assert!(result.is_err());
+    let relu = Hooked::new(ReLU);
+    let (calls, hook) = counter();
+    relu.register_forward_hook(hook);
+    let output = relu.forward(&ones(2, 3));
+    assert_eq!(output.to_vec(), vec![1.0; 6]);
+    assert_eq!(*calls.borrow(), 1);
+    assert_eq!(relu.type_name(), "ReLU");
+}
+
+// A custom module that runs its own hooks
+struct Doubler {
+    hooks: ForwardHooks,
+}
+
+impl Module for Doubler {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        self.hooks
+            .run(input, input * &Tensor::from(ArrayD::from_elem(vec![], 2.0)))
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Tensor)> {
+        Vec::new()
+    }
+
+    fn forward_hooks(&self) -> Option<&ForwardHooks> {
+        Some(&self.hooks)
+    }
+}
+
+#[test]
+fn custom_modules_run_their_hooks() {
+    let doubler = Doubler {
+        hooks: ForwardHooks::new(),
+    };
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let hook_seen = seen.clone();
+    doubler.register_forward_hook(Box::new(move |_, output| {
+        hook_seen.borrow_mut().extend(output.to_vec())
+    }));
+    doubler.forward(&ones(1, 2));
+    assert_eq!(*seen.borrow(), [2.0, 2.0]);
+}
+
+#[test]
+fn a_hook_can_remove_itself() {
+    let layer = Linear::new(3, 4);
+    let calls = Rc::new(RefCell::new(0));
+    let handle: Rc<RefCell<Option<HookHandle>>> = Rc::default();
+    let (hook_calls, hook_handle) = (calls.clone(), handle.clone());
+    let registered = layer.register_forward_hook(Box::new(move |_, _| {
+        *hook_calls.borrow_mut() += 1;
+        hook_handle.borrow_mut().take().unwrap().remove();
+    }));
+    *handle.borrow_mut() = Some(registered);
+    layer.forward(&ones(1, 3));
+    layer.forward(&ones(1, 3));
+    assert_eq!(*calls.borrow(), 1);
+    assert!(layer.forward_hooks().unwrap().is_empty());
+}
+
+#[test]
+fn container_hooks_run_when_traced() {
+    let model = Sequential::new().add(Linear::new(3, 4)).add(ReLU);
+    let (calls, hook) = counter();
+    model.register_forward_hook(hook);
+    let extractor = FeatureExtractor::new(model, &["0"]);
+    extractor.forward(&ones(2, 3));
+    extractor.extract(&ones(2, 3));
+    assert_eq!(*calls.borrow(), 2);
+
+    let ensemble = Ensemble::mean()
+        .add(Linear::new(3, 2))
+        .add(Linear::new(3, 2));
+    let (calls, hook) = counter();
+    ensemble.register_forward_hook(hook);
+    FeatureExtractor::new(ensemble, &["1"]).extract(&ones(2, 3));
+    assert_eq!(*calls.borrow(), 1);
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{check_gradient, weighted_squares};
+use rust_ml::error::TensorError;
+use rust_ml::ndarray::{Array2, ArrayD};
+use rust_ml::tensor::Tensor;
+use rust_ml::{assert_tensor_close, tensor};
+
+fn a() -> ArrayD<f32> {
+    rust_ml::ndarray::arr2(&[[2.0, 1.0, 0.5], [0.3, 3.0, -1.0], [1.0, 0.0, 1.5]]).into_dyn()
+}
+
+fn eye(n: usize) -> Tensor {
+    Tensor::from(Array2::<f32>::eye(n).into_dyn())
+}
+
+#[test]
+fn inverse_times_matrix_is_identity() {
+    let a = Tensor::from(a());
+    assert_tensor_close!(a.matmul(&a.inverse()), eye(3), 1e-5, 1e-5);
+    assert_tensor_close!(a.inverse().matmul(&a), eye(3), 1e-5, 1e-5);
+}
+
+#[test]
+fn solve_vector_and_matrix_right_hand_sides() {
+    let a = Tensor::from(a());
+    let b = tensor![1.0, -2.0, 0.5];
+    let x = a.solve(&b);
+    assert_eq!(x.shape(), vec![3]);
+    assert_tensor_close!(a.matmul(&x.reshape(&[3, 1])).reshape(&[3]), b, 1e-5, 1e-5);
+    let b = tensor![[1.0, 0.0], [-2.0, 1.0], [0.5, 3.0]];
+    let x = a.solve(&b);
+    assert_eq!(x.shape(), vec![3, 2]);
+    assert_tensor_close!(a.matmul(&x), b, 1e-5, 1e-5);
+}
+
+#[test]
+fn inverse_gradient() {
+    check_gradient(|a| weighted_squares(&a.inverse()), a(), 2e-2);
+}
+
+#[test]
+fn solve_gradient() {
+    let b = tensor![[1.0, 0.0], [-2.0, 1.0], [0.5, 3.0]];
+    check_gradient(|a| weighted_squares(&a.solve(&b)), a(), 2e-2);
+    let a = Tensor::from(a());
+    check_gradient(
+        |b| weighted_squares(&a.solve(b)),
+        b.borrow().data.clone(),
+        2e-2,
+    );
+}
+
+#[test]
+fn singular_and_non_square_matrices_are_errors() {
+    let singular = tensor![[1.0, 2.0], [2.0, 4.0]];
+    assert_eq!(
+        singular.try_inverse().unwrap_err(),
+        TensorError::SingularMatrix
+    );
+    assert!(singular.try_solve(&tensor![1.0, 1.0]).is_err());
+    assert!(Tensor::from(ArrayD::zeros(vec![2, 3]))
+        .try_inverse()
+        .is_err());
+}
+
+// B B^T + I is symmetric positive definite for any B
+fn spd(b: &Tensor) -> Tensor {
+    &b.matmul(&b.t()) + &eye(b.shape()[0])
+}
+
+#[test]
+fn cholesky_reconstructs_the_matrix() {
+    let a = spd(&Tensor::from(a()));
+    let l = a.cholesky();
+    assert_tensor_close!(l.matmul(&l.t()), a, 1e-5, 1e-5);
+    assert_eq!(
+        (l.get(&[0, 1]), l.get(&[0, 2]), l.get(&[1, 2])),
+        (0.0, 0.0, 0.0)
+    );
+    assert!((0..3).all(|i| l.get(&[i, i]) > 0.0));
+}
+
+#[test]
+fn logdet_matches_the_determinant() {
+    assert!((tensor![[2.0, 0.0], [0.0, 3.0]].logdet().item() - 6.0f32.ln()).abs() < 1e-6);
+    assert!((tensor![[1.0, 2.0], [3.0, 4.0]].logdet().item()).is_nan());
+    let a = spd(&Tensor::from(a()));
+    let l = a.cholesky();
+    let from_diagonal = (0..3).map(|i| 2.0 * l.get(&[i, i]).ln()).sum::<f32>();
+    assert!((a.logdet().item() - from_diagonal).abs() < 1e-4);
+}
+
+#[test]
+fn cholesky_and_logdet_gradients() {
+    check_gradient(|b| weighted_squares(&spd(b).cholesky()), a(), 1e-2);
+    check_gradient(|b| spd(b).logdet(), a(), 1e-2);
+}
+
+#[test]
+fn cholesky_of_an_indefinite_matrix_is_an_error() {
+    let indefinite = tensor![[0.0, 1.0], [1.0, 0.0]];
+    assert_eq!(
+        indefinite.try_cholesky().unwrap_err(),
+        TensorError::NotPositiveDefinite
+    );
+}
+
+fn tall() -> ArrayD<f32> {
+    rust_ml::ndarray::arr2(&[
+        [1.0, 0.3, -0.2],
+        [0.5, 1.7, 0.1],
+        [-0.4, 0.2, 0.9],
+        [0.3, -0.8, 0.4],
+    ])
+    .into_dyn()
+}
+
+fn wide() -> ArrayD<f32> {
+    tall().reversed_axes()
+}
+
+#[test]
+fn qr_factors() {
+    let a = Tensor::from(tall());
+    let (q, r) = a.qr();
+    assert_eq!((q.shape(), r.shape()), (vec![4, 3], vec![3, 3]));
+    assert_tensor_close!(q.matmul(&r), a, 1e-5, 1e-5);
+    assert_tensor_close!(q.t().matmul(&q), eye(3), 1e-5, 1e-5);
+    assert_eq!(
+        (r.get(&[1, 0]), r.get(&[2, 0]), r.get(&[2, 1])),
+        (0.0, 0.0, 0.0)
+    );
+    assert!((0..3).all(|i| r.get(&[i, i]) >= 0.0));
+}
+
+#[test]
+fn svd_factors() {
+    for data in [tall(), wide()] {
+        let a = Tensor::from(data);
+        let (m, n) = (a.shape()[0], a.shape()[1]);
+        let k = m.min(n);
+        let (u, s, vt) = a.svd();
+        assert_eq!(
+            (u.shape(), s.shape(), vt.shape()),
+            (vec![m, k], vec![k], vec![k, n])
+        );
+        let values = s.to_vec();
+        assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_tensor_close!((&u * &s).matmul(&vt), a, 1e-5, 1e-5);
+        assert_tensor_close!(u.t().matmul(&u), eye(k), 1e-5, 1e-5);
+        assert_tensor_close!(vt.matmul(&vt.t()), eye(k), 1e-5, 1e-5);
+    }
+    // A and A^T have the same singular values
+    let (_, tall_values, _) = Tensor::from(tall()).svd();
+    let (_, wide_values, _) = Tensor::from(wide()).svd();
+    assert_tensor_close!(tall_values, wide_values, 1e-5, 1e-5);
+}
+
+#[test]
+fn qr_gradient() {
+    check_gradient(
+        |a| {
+            let (q, r) = a.qr();
+            &weighted_squares(&q) + &weighted_squares(&r)
+        },
+        tall(),
+        2e-2,
+    );
+}
+
+#[test]
+fn svd_gradient() {
+    // Squared entries of the singular vectors, so their signs don't matter
+    let svd_loss = |a: &Tensor| {
+        let (u, s, vt) = a.svd();
+        &(&weighted_squares(&(&u * &u)) + &weighted_squares(&(&vt * &vt))) + &weighted_squares(&s)
+    };
+    check_gradient(svd_loss, tall(), 2e-2);
+    check_gradient(svd_loss, wide(), 2e-2);
+}
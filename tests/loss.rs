@@ -0,0 +1,253 @@
+mod common;
+
+use common::check_gradient;
+use rust_ml::loss::{self, CrossEntropyOptions, DistillationOptions, Reduction};
+use rust_ml::ndarray::{Array3, ArrayD, Axis, IxDyn};
+use rust_ml::tensor;
+use rust_ml::tensor::Tensor;
+
+// [T, 1, C] log-probabilities from per-step probabilities
+fn log_probs(steps: &[&[f32]]) -> Tensor {
+    let (t, c) = (steps.len(), steps[0].len());
+    let data = Array3::from_shape_fn((t, 1, c), |(i, _, j)| steps[i][j].ln());
+    Tensor::from(data.into_dyn())
+}
+
+#[test]
+fn ctc_sums_over_alignments() {
+    // With blank 0 the target [1] aligns to "1 1", "_ 1" and "1 _" over two steps
+    let p = [[0.2, 0.8], [0.6, 0.4]];
+    let input = log_probs(&[&p[0], &p[1]]);
+    let loss = loss::ctc(&input, &[vec![1]], &[2], 0, Reduction::Sum);
+    let likelihood = p[0][1] * p[1][1] + p[0][0] * p[1][1] + p[0][1] * p[1][0];
+    assert!((loss.item() + likelihood.ln()).abs() < 1e-5);
+}
+
+#[test]
+fn ctc_repeated_labels_need_a_blank_between() {
+    let step: &[f32] = &[0.5, 0.5];
+    let targets = [vec![1, 1]];
+    let too_short = loss::ctc(&log_probs(&[step, step]), &targets, &[2], 0, Reduction::Sum);
+    assert_eq!(too_short.item(), f32::INFINITY);
+    // "1 _ 1" is the only alignment over three steps
+    let loss = loss::ctc(&log_probs(&[step; 3]), &targets, &[3], 0, Reduction::Sum);
+    assert!((loss.item() + 0.125f32.ln()).abs() < 1e-5);
+}
+
+#[test]
+fn ctc_mean_divides_by_target_length() {
+    let step: &[f32] = &[0.3, 0.4, 0.3];
+    let input = log_probs(&[step; 4]);
+    let targets = [vec![1, 2]];
+    let sum = loss::ctc(&input, &targets, &[4], 0, Reduction::Sum).item();
+    let mean = loss::ctc(&input, &targets, &[4], 0, Reduction::Mean).item();
+    assert!((mean - sum / 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn ctc_gradient() {
+    let data = ArrayD::from_shape_fn(IxDyn(&[5, 2, 4]), |index| {
+        -1.0 - ((index[0] * 8 + index[1] * 4 + index[2]) as f32 * 0.7)
+            .sin()
+            .abs()
+    });
+    let ctc = |x: &Tensor| loss::ctc(x, &[vec![1, 2], vec![3, 3]], &[5, 4], 0, Reduction::Mean);
+    check_gradient(ctc, data, 1e-2);
+}
+
+#[test]
+fn ctc_leaves_impossible_samples_without_gradient() {
+    let input = Tensor::from(ArrayD::from_elem(IxDyn(&[2, 2, 3]), (1.0f32 / 3.0).ln()));
+    // Three labels can't fit in two steps
+    let targets = [vec![1, 2, 1], vec![1]];
+    let losses = loss::ctc(&input, &targets, &[2, 2], 0, Reduction::None).to_vec();
+    assert_eq!(losses[0], f32::INFINITY);
+    assert!(losses[1].is_finite());
+    loss::ctc(&input, &targets, &[2, 2], 0, Reduction::Sum).backward();
+    let grad = input.grad_array().unwrap();
+    assert!(grad.index_axis(Axis(1), 0).iter().all(|&g| g == 0.0));
+    assert!(grad.index_axis(Axis(1), 1).iter().any(|&g| g != 0.0));
+}
+
+fn ignoring(reduction: Reduction) -> CrossEntropyOptions<'static> {
+    CrossEntropyOptions {
+        ignore_index: Some(-100),
+        reduction,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn cross_entropy_matches_log_softmax() {
+    let logits = tensor![[1.0, 2.0, 0.5], [0.1, 0.2, 0.3]];
+    let loss = loss::cross_entropy(&logits, &tensor![1.0, 2.0]);
+    let nll =
+        |row: [f32; 3], target: usize| row.iter().map(|x| x.exp()).sum::<f32>().ln() - row[target];
+    let expected = (nll([1.0, 2.0, 0.5], 1) + nll([0.1, 0.2, 0.3], 2)) / 2.0;
+    assert!((loss.item() - expected).abs() < 1e-6);
+}
+
+#[test]
+fn cross_entropy_ignore_index_drops_rows() {
+    let logits = tensor![[1.0, 2.0, 0.5], [0.1, 0.2, 0.3], [2.0, -1.0, 0.0]];
+    let kept = tensor![[1.0, 2.0, 0.5], [2.0, -1.0, 0.0]];
+    let loss = loss::cross_entropy_with(
+        &logits,
+        &tensor![1.0, -100.0, 0.0],
+        ignoring(Reduction::Mean),
+    );
+    let expected = loss::cross_entropy(&kept, &tensor![1.0, 0.0]);
+    // The mean is over the two rows that count
+    assert!((loss.item() - expected.item()).abs() < 1e-6);
+    loss.backward();
+    expected.backward();
+    let (grad, kept_grad) = (logits.grad_array().unwrap(), kept.grad_array().unwrap());
+    assert!(grad.index_axis(Axis(0), 1).iter().all(|&g| g == 0.0));
+    assert_eq!(
+        grad.index_axis(Axis(0), 0),
+        kept_grad.index_axis(Axis(0), 0)
+    );
+    assert_eq!(
+        grad.index_axis(Axis(0), 2),
+        kept_grad.index_axis(Axis(0), 1)
+    );
+}
+
+#[test]
+fn cross_entropy_ignore_index_without_reduction() {
+    let logits = tensor![[1.0, 2.0], [0.5, 0.5]];
+    let targets = tensor![-100.0, 1.0];
+    let losses = loss::cross_entropy_with(&logits, &targets, ignoring(Reduction::None));
+    assert_eq!(losses.shape(), vec![2]);
+    assert_eq!(losses.to_vec()[0], 0.0);
+    assert!((losses.to_vec()[1] - 2.0f32.ln()).abs() < 1e-6);
+}
+
+#[test]
+fn cross_entropy_all_ignored_is_zero() {
+    let logits = tensor![[1.0, 2.0], [0.5, 0.5]];
+    let targets = tensor![-100.0, -100.0];
+    let loss = loss::cross_entropy_with(&logits, &targets, ignoring(Reduction::Mean));
+    assert_eq!(loss.item(), 0.0);
+    loss.backward();
+    assert!(logits.grad_array().unwrap().iter().all(|&g| g == 0.0));
+}
+
+#[test]
+fn cross_entropy_gradient() {
+    let data = ArrayD::from_shape_fn(IxDyn(&[4, 3]), |index| {
+        (index[0] * 3 + index[1]) as f32 * 0.4 - 2.0
+    });
+    let targets = tensor![2.0, -100.0, 0.0, 1.0];
+    let options = CrossEntropyOptions {
+        label_smoothing: 0.1,
+        ..ignoring(Reduction::Mean)
+    };
+    check_gradient(
+        |x| loss::cross_entropy_with(x, &targets, options),
+        data,
+        1e-2,
+    );
+}
+
+#[test]
+fn smooth_l1_with_zero_beta_is_l1() {
+    let (pred, target) = (tensor![1.0, -2.0, 0.5], tensor![0.0, 0.0, 0.5]);
+    for reduction in [Reduction::Mean, Reduction::Sum] {
+        assert_eq!(
+            loss::smooth_l1(&pred, &target, 0.0, reduction).item(),
+            loss::l1(&pred, &target, reduction).item()
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "huber delta must be positive")]
+fn huber_needs_a_positive_delta() {
+    loss::huber(&tensor![1.0], &tensor![0.0], 0.0, Reduction::Mean);
+}
+
+fn distillation_options(alpha: f32) -> DistillationOptions {
+    DistillationOptions {
+        temperature: 3.0,
+        alpha,
+        reduction: Reduction::Mean,
+    }
+}
+
+#[test]
+fn distillation_without_the_teacher_is_cross_entropy() {
+    let student = tensor![[1.0, 2.0, 0.5], [0.1, 0.2, 0.3]];
+    let teacher = tensor![[0.0, 3.0, 1.0], [2.0, 0.0, 0.0]];
+    let targets = tensor![1.0, 2.0];
+    let loss = loss::distillation(&student, &teacher, &targets, distillation_options(0.0));
+    assert!((loss.item() - loss::cross_entropy(&student, &targets).item()).abs() < 1e-6);
+}
+
+#[test]
+fn distillation_gradient() {
+    let data = ArrayD::from_shape_fn(IxDyn(&[3, 4]), |index| {
+        ((index[0] * 7 + index[1] * 3) % 5) as f32 * 0.3 - 0.5
+    });
+    let teacher = Tensor::from(ArrayD::from_shape_fn(IxDyn(&[3, 4]), |index| {
+        ((index[0] + 2 * index[1]) % 4) as f32 * 0.7
+    }));
+    let targets = tensor![1.0, 3.0, 0.0];
+    check_gradient(
+        |x| loss::distillation(x, &teacher, &targets, distillation_options(0.7)),
+        data,
+        1e-2,
+    );
+}
+
+#[test]
+#[should_panic(expected = "distillation targets must be class indices, got 0.5")]
+fn distillation_targets_are_class_indices() {
+    let logits = tensor![[1.0, 2.0], [0.5, 0.5]];
+    loss::distillation(
+        &logits,
+        &logits,
+        &tensor![0.5, 1.0],
+        distillation_options(0.5),
+    );
+}
+
+#[test]
+fn cosine_embedding_pulls_and_pushes() {
+    let x1 = tensor![[1.0, 0.0], [1.0, 1.0], [0.0, 2.0]];
+    let x2 = tensor![[0.0, 3.0], [2.0, 2.0], [1.0, 1.0]];
+    let y = tensor![1.0, -1.0, -1.0];
+    let losses = loss::cosine_embedding(&x1, &x2, &y, 0.5, Reduction::None).to_vec();
+    // Orthogonal but similar, identical directions but dissimilar, 45 degrees apart
+    let expected = [1.0, 0.5, 0.5f32.sqrt() - 0.5];
+    for (loss, expected) in losses.iter().zip(expected) {
+        assert!((loss - expected).abs() < 1e-6, "{:?}", losses);
+    }
+    // Below the margin a dissimilar pair costs nothing
+    let far = loss::cosine_embedding(&x1, &x2, &y, 0.9, Reduction::None).to_vec();
+    assert!((far[1] - 0.1).abs() < 1e-6);
+    assert_eq!(far[2], 0.0);
+}
+
+#[test]
+fn cosine_embedding_gradient() {
+    let data = ArrayD::from_shape_fn(IxDyn(&[3, 4]), |index| {
+        ((index[0] * 4 + index[1]) as f32 * 1.3).sin()
+    });
+    let other = Tensor::from(ArrayD::from_shape_fn(IxDyn(&[3, 4]), |index| {
+        ((index[0] * 4 + index[1]) as f32 * 0.7).cos()
+    }));
+    let y = tensor![1.0, -1.0, 1.0];
+    check_gradient(
+        |x| loss::cosine_embedding(x, &other, &y, -0.5, Reduction::Mean),
+        data,
+        1e-2,
+    );
+}
+
+#[test]
+#[should_panic(expected = "cosine_embedding labels must be 1 or -1, got 0")]
+fn cosine_embedding_labels_are_signs() {
+    let x = tensor![[1.0, 2.0]];
+    loss::cosine_embedding(&x, &x, &tensor![0.0], 0.0, Reduction::Mean);
+}
@@ -0,0 +1,204 @@
+use rust_ml::assert_tensor_close;
+use rust_ml::io::onnx;
+use rust_ml::nn::{Linear, Module, ReLU, Sequential, Tanh};
+use rust_ml::random::manual_seed;
+use rust_ml::tensor;
+use std::io;
+
+// Just enough protobuf to write ONNX graphs by hand
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn int(mut self, field: u64, value: i64) -> Self {
+        self.varint(field << 3);
+        self.varint(value as u64);
+        self
+    }
+
+    fn bytes(mut self, field: u64, value: &[u8]) -> Self {
+        self.varint(field << 3 | 2);
+        self.varint(value.len() as u64);
+        self.0.extend(value);
+        self
+    }
+
+    fn string(self, field: u64, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(self, field: u64, value: Proto) -> Self {
+        self.bytes(field, &value.0)
+    }
+}
+
+// A NodeProto reading `inputs` and writing `output`
+fn node(op_type: &str, inputs: &[&str], output: &str) -> Proto {
+    inputs
+        .iter()
+        .fold(Proto::default(), |n, input| n.string(1, input))
+        .string(2, output)
+        .string(4, op_type)
+}
+
+fn ints_attribute(name: &str, values: &[i64]) -> Proto {
+    let packed = values.iter().fold(Proto::default(), |mut p, &v| {
+        p.varint(v as u64);
+        p
+    });
+    Proto::default()
+        .string(1, name)
+        .bytes(8, &packed.0)
+        .int(20, 7)
+}
+
+fn int_attribute(name: &str, value: i64) -> Proto {
+    Proto::default().string(1, name).int(3, value).int(20, 2)
+}
+
+fn float_attribute(name: &str, value: f32) -> Proto {
+    let mut attribute = Proto::default().string(1, name);
+    attribute.varint(2 << 3 | 5);
+    attribute.0.extend(value.to_le_bytes());
+    attribute.int(20, 1)
+}
+
+fn float_initializer(name: &str, shape: &[i64], values: &[f32]) -> Proto {
+    let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    shape
+        .iter()
+        .fold(Proto::default(), |t, &d| t.int(1, d))
+        .int(2, 1)
+        .string(8, name)
+        .bytes(9, &raw)
+}
+
+fn int64_initializer(name: &str, shape: &[i64], values: &[i64]) -> Proto {
+    let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    shape
+        .iter()
+        .fold(Proto::default(), |t, &d| t.int(1, d))
+        .int(2, 7)
+        .string(8, name)
+        .bytes(9, &raw)
+}
+
+// A ModelProto with graph input `x` and output `y`
+fn model(nodes: Vec<Proto>, initializers: Vec<Proto>) -> Vec<u8> {
+    let graph = nodes
+        .into_iter()
+        .fold(Proto::default(), |g, n| g.message(1, n));
+    let graph = initializers
+        .into_iter()
+        .fold(graph, |g, t| g.message(5, t))
+        .message(11, Proto::default().string(1, "x"))
+        .message(12, Proto::default().string(1, "y"));
+    Proto::default()
+        .int(1, 8)
+        .message(7, graph)
+        .message(8, Proto::default().string(1, "").int(2, 17))
+        .0
+}
+
+fn load_error(bytes: &[u8]) -> io::Error {
+    match onnx::load_bytes(bytes) {
+        Ok(_) => panic!("loaded an invalid model"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn exported_models_load_back() {
+    manual_seed(0);
+    let model = Sequential::new()
+        .add(Linear::new(3, 4))
+        .add(ReLU)
+        .add(Linear::new(4, 2))
+        .add(Tanh);
+    let x = tensor![[1.0, 2.0, 3.0], [0.5, -0.1, 0.2]];
+    let loaded = onnx::load_bytes(&onnx::export_bytes(&model, &x).unwrap()).unwrap();
+    assert_eq!(loaded.state_dict(), model.state_dict());
+    let other = tensor![[-1.0, 0.0, 2.0], [0.3, 0.3, 0.3], [4.0, -2.0, 1.0]];
+    assert_tensor_close!(loaded.forward(&x), model.forward(&x));
+    assert_tensor_close!(loaded.forward(&other), model.forward(&other));
+    // The initializers train like the original parameters
+    loaded.forward(&x).sum_axis(1).sum_axis(0).backward();
+    assert!(loaded.parameters().iter().all(|p| p.grad().is_some()));
+}
+
+#[test]
+fn gemm_scales_and_transposes() {
+    let gemm = node("Gemm", &["x", "w", "b"], "y")
+        .message(5, float_attribute("alpha", 2.0))
+        .message(5, float_attribute("beta", 0.5))
+        .message(5, int_attribute("transB", 1));
+    let bytes = model(
+        vec![gemm],
+        vec![
+            float_initializer("w", &[1, 2], &[1.0, -1.0]),
+            float_initializer("b", &[1], &[4.0]),
+        ],
+    );
+    let loaded = onnx::load_bytes(&bytes).unwrap();
+    let y = loaded.forward(&tensor![[3.0, 1.0], [0.0, 2.0]]);
+    assert_eq!(y.to_vec(), [6.0, -2.0]);
+}
+
+#[test]
+fn rejects_unsupported_ops() {
+    let bytes = model(vec![node("Conv", &["x"], "y")], vec![]);
+    let error = load_error(&bytes);
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    assert!(error.to_string().contains("`Conv`"), "{}", error);
+}
+
+#[test]
+fn rejects_transposes_other_than_2d() {
+    let swap = node("Transpose", &["x"], "y").message(5, ints_attribute("perm", &[1, 0]));
+    assert!(onnx::load_bytes(&model(vec![swap], vec![])).is_ok());
+    let identity = node("Transpose", &["x"], "y").message(5, ints_attribute("perm", &[0, 1]));
+    let error = load_error(&model(vec![identity], vec![]));
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    let three_d = node("Transpose", &["x"], "y").message(5, ints_attribute("perm", &[0, 2, 1]));
+    assert_eq!(
+        load_error(&model(vec![three_d], vec![])).kind(),
+        io::ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn gathers_need_a_scalar_index() {
+    let gather = || node("Gather", &["x", "i"], "y");
+    let scalar = model(vec![gather()], vec![int64_initializer("i", &[], &[-1])]);
+    let loaded = onnx::load_bytes(&scalar).unwrap();
+    assert_eq!(
+        loaded.forward(&tensor![[1.0, 2.0], [3.0, 4.0]]).to_vec(),
+        [3.0, 4.0]
+    );
+    let vector = model(vec![gather()], vec![int64_initializer("i", &[2], &[0, 1])]);
+    assert_eq!(load_error(&vector).kind(), io::ErrorKind::Unsupported);
+    let float_index = model(vec![gather()], vec![float_initializer("i", &[], &[0.0])]);
+    assert_eq!(load_error(&float_index).kind(), io::ErrorKind::Unsupported);
+    let axis_1 = gather().message(5, int_attribute("axis", 1));
+    let axis_1 = model(vec![axis_1], vec![int64_initializer("i", &[], &[0])]);
+    assert_eq!(load_error(&axis_1).kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn rejects_graphs_that_read_undefined_values() {
+    let bytes = model(vec![node("Add", &["x", "missing"], "y")], vec![]);
+    assert_eq!(load_error(&bytes).kind(), io::ErrorKind::InvalidData);
+    let never_written = model(vec![node("Relu", &["x"], "z")], vec![]);
+    assert_eq!(
+        load_error(&never_written).kind(),
+        io::ErrorKind::InvalidData
+    );
+}
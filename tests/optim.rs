@@ -0,0 +1,105 @@
+mod common;
+
+use common::weighted_squares;
+use rust_ml::ndarray::{ArrayD, Axis, IxDyn};
+use rust_ml::optim::{Adam, Optimizer, Sgd};
+use rust_ml::tensor::Tensor;
+
+// A [5, 3] embedding table, sparse or not
+fn table(sparse: bool) -> Tensor {
+    let table = Tensor::from(ArrayD::from_shape_fn(IxDyn(&[5, 3]), |index| {
+        (index[0] * 3 + index[1]) as f32 * 0.25 - 1.5
+    }));
+    table.set_sparse(sparse);
+    table
+}
+
+// One optimizer step on the loss of the rows at `indices`
+fn step(optimizer: &mut dyn Optimizer, indices: &[usize]) {
+    optimizer.zero_grad();
+    weighted_squares(&optimizer.parameters()[0].gather_rows(indices)).backward();
+    optimizer.step();
+}
+
+fn row(tensor: &Tensor, index: usize) -> ArrayD<f32> {
+    tensor.borrow().data.index_axis(Axis(0), index).to_owned()
+}
+
+#[test]
+fn sparse_sgd_leaves_other_rows_and_their_velocity() {
+    let mut sparse = Sgd::new(vec![table(true)], 0.1).momentum(0.9);
+    let mut dense = Sgd::new(vec![table(false)], 0.1).momentum(0.9);
+    // Row 1 is looked up twice, its gradients are summed before the velocity decays once
+    step(&mut sparse, &[1, 3, 1]);
+    step(&mut dense, &[1, 3, 1]);
+    let (sparse_table, dense_table) = (
+        sparse.parameters()[0].clone(),
+        dense.parameters()[0].clone(),
+    );
+    assert!(sparse_table.allclose(&dense_table, 1e-6, 1e-7));
+    for untouched in [0, 2, 4] {
+        assert_eq!(row(&sparse_table, untouched), row(&table(true), untouched));
+    }
+
+    let (rows, velocity) = (sparse_table.borrow().data.clone(), sparse.state_dict());
+    step(&mut sparse, &[0]);
+    step(&mut dense, &[0]);
+    // Momentum keeps moving every row of the dense table, the sparse one only moves row 0
+    let new_velocity = sparse.state_dict();
+    for index in 1..5 {
+        assert_eq!(row(&sparse_table, index), rows.index_axis(Axis(0), index));
+        assert_eq!(
+            new_velocity["0.velocity"].index_axis(Axis(0), index),
+            velocity["0.velocity"].index_axis(Axis(0), index)
+        );
+    }
+    assert_ne!(row(&sparse_table, 0), rows.index_axis(Axis(0), 0));
+    assert_ne!(row(&dense_table, 1), rows.index_axis(Axis(0), 1));
+}
+
+#[test]
+fn sparse_adam_leaves_other_rows_and_their_moments() {
+    let mut sparse = Adam::new(vec![table(true)], 0.1);
+    let mut dense = Adam::new(vec![table(false)], 0.1);
+    // With zero moments the first step only moves rows with a gradient, so both agree as long
+    // as the duplicate row 2 gets one update with the summed gradient
+    step(&mut sparse, &[2, 4, 2]);
+    step(&mut dense, &[2, 4, 2]);
+    let (sparse_table, dense_table) = (
+        sparse.parameters()[0].clone(),
+        dense.parameters()[0].clone(),
+    );
+    assert!(sparse_table.allclose(&dense_table, 1e-6, 1e-7));
+
+    let (rows, moments) = (sparse_table.borrow().data.clone(), sparse.state_dict());
+    step(&mut sparse, &[0, 1]);
+    let new_moments = sparse.state_dict();
+    for index in 2..5 {
+        assert_eq!(row(&sparse_table, index), rows.index_axis(Axis(0), index));
+        for key in ["0.exp_avg", "0.exp_avg_sq"] {
+            assert_eq!(
+                new_moments[key].index_axis(Axis(0), index),
+                moments[key].index_axis(Axis(0), index)
+            );
+        }
+    }
+    for index in 0..2 {
+        assert_ne!(row(&sparse_table, index), rows.index_axis(Axis(0), index));
+    }
+}
+
+#[test]
+fn sparse_steps_apply_weight_decay_to_looked_up_rows() {
+    let mut sparse = Sgd::new(vec![table(true)], 0.1).weight_decay(0.5);
+    let mut dense = Sgd::new(vec![table(false)], 0.1).weight_decay(0.5);
+    step(&mut sparse, &[3, 3]);
+    step(&mut dense, &[3, 3]);
+    let (sparse_table, dense_table) = (
+        sparse.parameters()[0].clone(),
+        dense.parameters()[0].clone(),
+    );
+    assert_eq!(row(&sparse_table, 3), row(&dense_table, 3));
+    // Dense weight decay shrinks every row, sparse only the one that was used
+    assert_eq!(row(&sparse_table, 0), row(&table(true), 0));
+    assert_ne!(row(&dense_table, 0), row(&table(false), 0));
+}
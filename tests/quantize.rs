@@ -0,0 +1,99 @@
+use rust_ml::ndarray::{Array2, ArrayD};
+use rust_ml::nn::{Linear, Module, ReLU, Sequential};
+use rust_ml::quantize::{self, QuantParams, QuantizedLayer, QuantizedLinear, RangeObserver};
+use rust_ml::random::manual_seed;
+use rust_ml::tensor;
+use rust_ml::tensor::Tensor;
+
+fn batch(rows: usize, columns: usize, phase: f32) -> Tensor {
+    Tensor::from(
+        Array2::from_shape_fn((rows, columns), |(i, j)| {
+            ((i * columns + j) as f32 * 0.37 + phase).sin() * 2.0
+        })
+        .into_dyn(),
+    )
+}
+
+fn max_abs(x: &ArrayD<f32>) -> f32 {
+    x.iter().fold(0.0f32, |m, v| m.max(v.abs()))
+}
+
+#[test]
+fn quant_params_round_trip() {
+    for (min, max) in [(-1.0, 3.0), (0.0, 6.0), (-5.0, -1.0), (-0.25, 0.25)] {
+        let params = QuantParams::from_range(min, max);
+        // Every int8 value is the image of its dequantized value
+        for q in -128..=127 {
+            assert_eq!(params.quantize(params.dequantize(q)), q, "{:?}", params);
+        }
+        // Zero is exact and the rest of the range is off by at most half a step
+        assert_eq!(params.dequantize(params.quantize(0.0)), 0.0);
+        for i in 0..=100 {
+            let x = min.min(0.0) + (max.max(0.0) - min.min(0.0)) * i as f32 / 100.0;
+            let error = (params.dequantize(params.quantize(x)) - x).abs();
+            assert!(error <= params.scale * 0.5 + 1e-6, "{} in {:?}", x, params);
+        }
+    }
+    // An empty range still gets a usable scale
+    assert_eq!(
+        QuantParams::from_range(0.0, 0.0),
+        QuantParams {
+            scale: 1.0,
+            zero_point: -128
+        }
+    );
+}
+
+#[test]
+fn range_observer_calibrates_on_finite_values() {
+    let mut observer = RangeObserver::default();
+    observer.observe(&tensor![1.0, f32::NAN, -3.0].borrow().data);
+    observer.observe(&tensor![f32::INFINITY, 2.0].borrow().data);
+    assert_eq!((observer.min, observer.max), (-3.0, 2.0));
+    assert_eq!(observer.params(), QuantParams::from_range(-3.0, 2.0));
+}
+
+#[test]
+fn weights_are_scaled_per_row() {
+    manual_seed(0);
+    let linear = Linear::new(6, 3);
+    let quantized = QuantizedLinear::new(&linear, QuantParams::from_range(-1.0, 1.0));
+    let weight = linear.weight.borrow().data.clone();
+    let rounded = quantized.dequantize().weight.borrow().data.clone();
+    for o in 0..3 {
+        let row_max = (0..6).fold(0.0f32, |m, i| m.max(weight[[o, i]].abs()));
+        assert!((quantized.weight_scales[o] - row_max / 127.0).abs() < 1e-9);
+        // The largest weight of each row maps to ±127
+        assert!(quantized.weight.row(o).iter().any(|q| q.abs() == 127));
+        for i in 0..6 {
+            let error = (rounded[[o, i]] - weight[[o, i]]).abs();
+            assert!(error <= quantized.weight_scales[o] * 0.5 + 1e-7);
+        }
+    }
+}
+
+#[test]
+fn quantized_sequential_stays_close_to_the_float_model() {
+    manual_seed(0);
+    let model = Sequential::new()
+        .add(Linear::new(8, 16))
+        .add(ReLU)
+        .add(Linear::new(16, 4));
+    let calibration = [batch(32, 8, 0.0), batch(32, 8, 1.0)];
+    let x = batch(16, 8, 0.5);
+    let reference = model.forward(&x).borrow().data.clone();
+    let float_bytes = 4 * (8 * 16 + 16 + 16 * 4 + 4);
+
+    let quantized = quantize::quantize(model, &calibration);
+    assert!(matches!(quantized.layers[0], QuantizedLayer::Linear(_)));
+    assert!(matches!(quantized.layers[1], QuantizedLayer::Float(_)));
+    assert!(quantized.weight_bytes() < float_bytes / 2);
+    let out = quantized.forward(&x).borrow().data.clone();
+    assert_eq!(out.shape(), [16, 4]);
+    let tolerance = 0.05 * max_abs(&reference);
+    assert!(max_abs(&(&out - &reference)) < tolerance);
+
+    // So is the float model with the rounded weights
+    let dequantized = quantized.dequantize().forward(&x).borrow().data.clone();
+    assert!(max_abs(&(&dequantized - &reference)) < tolerance);
+}
@@ -0,0 +1,59 @@
+use rust_ml::data::synthetic::make_regression;
+use rust_ml::random::manual_seed;
+use rust_ml::tensor;
+use rust_ml::tensor::Tensor;
+use rust_ml::train::{fit_linear_regression, polynomial_features};
+
+#[test]
+fn linear_regression_recovers_the_coefficients() {
+    manual_seed(0);
+    let (x, y) = make_regression(200, &[2.0, -3.0, 0.5], 1.5, 0.0);
+    let fit = fit_linear_regression(&x, &y, 500, 0.1);
+    for (fitted, expected) in fit.weights().iter().zip([2.0, -3.0, 0.5]) {
+        assert!((fitted - expected).abs() < 1e-3, "{:?}", fit.weights());
+    }
+    assert!((fit.bias() - 1.5).abs() < 1e-3, "{}", fit.bias());
+    assert_eq!(fit.losses.len(), 500);
+    // Gradient descent on a convex loss, up to rounding once it has converged
+    assert!(fit.losses.windows(2).all(|pair| pair[1] <= pair[0] + 1e-6));
+    assert!(*fit.losses.last().unwrap() < 1e-6);
+    assert_eq!(fit.predict(&x).shape(), vec![200, 1]);
+}
+
+#[test]
+fn linear_regression_with_noise_gets_close() {
+    manual_seed(1);
+    let (x, y) = make_regression(500, &[1.0, 4.0], -2.0, 0.1);
+    let fit = fit_linear_regression(&x, &y.reshape(&[500]), 300, 0.1);
+    let weights = fit.weights();
+    assert!((weights[0] - 1.0).abs() < 0.05 && (weights[1] - 4.0).abs() < 0.05);
+    assert!((fit.bias() + 2.0).abs() < 0.05);
+    // The noise variance is all that's left
+    assert!(*fit.losses.last().unwrap() < 0.02);
+}
+
+#[test]
+fn polynomial_regression_fits_a_parabola() {
+    let points: Vec<f32> = (0..41).map(|i| i as f32 / 20.0 - 1.0).collect();
+    let targets: Vec<f32> = points.iter().map(|x| 1.0 + 2.0 * x - x * x).collect();
+    let x = Tensor::from(rust_ml::ndarray::Array1::from(points).into_dyn());
+    let y = Tensor::from(rust_ml::ndarray::Array1::from(targets).into_dyn());
+    manual_seed(0);
+    let fit = fit_linear_regression(&polynomial_features(&x, 2), &y, 3000, 0.3);
+    let weights = fit.weights();
+    assert!((weights[0] - 2.0).abs() < 1e-2 && (weights[1] + 1.0).abs() < 1e-2);
+    assert!((fit.bias() - 1.0).abs() < 1e-2);
+}
+
+#[test]
+fn polynomial_features_are_powers() {
+    let features = polynomial_features(&tensor![2.0, -1.0], 3);
+    assert_eq!(features.shape(), vec![2, 3]);
+    assert_eq!(features.to_vec(), vec![2.0, 4.0, 8.0, -1.0, 1.0, -1.0]);
+}
+
+#[test]
+#[should_panic(expected = "fit_linear_regression expects [3] or [3, 1] targets")]
+fn linear_regression_checks_the_targets() {
+    fit_linear_regression(&tensor![[1.0], [2.0], [3.0]], &tensor![1.0, 2.0], 10, 0.1);
+}
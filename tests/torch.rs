@@ -0,0 +1,263 @@
+use rust_ml::io::torch;
+use rust_ml::ndarray::{arr1, arr2};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Protocol 2 pickle opcodes, written the way torch.save lays out a state dict
+#[derive(Default)]
+struct Pickle(Vec<u8>);
+
+impl Pickle {
+    fn op(mut self, opcode: u8) -> Self {
+        self.0.push(opcode);
+        self
+    }
+
+    fn global(mut self, module: &str, name: &str) -> Self {
+        self.0.push(b'c');
+        self.0.extend(format!("{}\n{}\n", module, name).as_bytes());
+        self
+    }
+
+    fn string(mut self, value: &str) -> Self {
+        self.0.push(0x8c);
+        self.0.push(value.len() as u8);
+        self.0.extend(value.as_bytes());
+        self
+    }
+
+    fn int(mut self, value: i32) -> Self {
+        self.0.push(b'J');
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn ints(self, values: &[i32]) -> Self {
+        values.iter().fold(self.op(b'('), |p, &v| p.int(v)).op(b't')
+    }
+
+    fn ordered_dict(self) -> Self {
+        self.global("collections", "OrderedDict").op(b')').op(b'R')
+    }
+
+    // _rebuild_tensor_v2((storage, dtype, key, location, numel), offset, size, stride,
+    // requires_grad, backward_hooks)
+    fn tensor(self, storage: &str, key: &str, offset: i32, size: &[i32], stride: &[i32]) -> Self {
+        self.global("torch._utils", "_rebuild_tensor_v2")
+            .op(b'(')
+            .op(b'(')
+            .string("storage")
+            .global("torch", storage)
+            .string(key)
+            .string("cpu")
+            .int(0)
+            .op(b't')
+            .op(b'Q')
+            .int(offset)
+            .ints(size)
+            .ints(stride)
+            .op(0x89)
+            .ordered_dict()
+            .op(b't')
+            .op(b'R')
+    }
+
+    // An OrderedDict of `entries`, each pushing its own value
+    fn dict(self, entries: Vec<(&str, Pickle)>) -> Self {
+        let pickle = self.ordered_dict().op(b'(');
+        entries
+            .into_iter()
+            .fold(pickle, |mut p, (key, value)| {
+                p = p.string(key);
+                p.0.extend(value.0);
+                p
+            })
+            .op(b'u')
+    }
+
+    fn state_dict(entries: Vec<(&str, Pickle)>) -> Vec<u8> {
+        Pickle::default().op(0x80).op(2).dict(entries).op(b'.').0
+    }
+}
+
+fn le_bytes<const N: usize, T: Copy>(values: &[T], to_bytes: fn(T) -> [u8; N]) -> Vec<u8> {
+    values.iter().flat_map(|&v| to_bytes(v)).collect()
+}
+
+// A torch.save archive holding `pickle` and the storages by key, returns its path
+fn archive(name: &str, pickle: &[u8], storages: &[(&str, Vec<u8>)]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rust_ml_torch_{}.pt", name));
+    let mut zip = ZipWriter::new(File::create(&path).unwrap());
+    let options = SimpleFileOptions::default();
+    zip.start_file("model/data.pkl", options).unwrap();
+    zip.write_all(pickle).unwrap();
+    zip.start_file("model/byteorder", options).unwrap();
+    zip.write_all(b"little").unwrap();
+    for (key, bytes) in storages {
+        zip.start_file(format!("model/data/{}", key), options)
+            .unwrap();
+        zip.write_all(bytes).unwrap();
+    }
+    zip.finish().unwrap();
+    path
+}
+
+fn load_error(name: &str, pickle: &[u8], storages: &[(&str, Vec<u8>)]) -> io::Error {
+    match torch::load(archive(name, pickle, storages)) {
+        Ok(_) => panic!("loaded an invalid state dict"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn loads_storages_of_every_common_dtype() {
+    let pickle = Pickle::state_dict(vec![
+        (
+            "weight",
+            Pickle::default().tensor("FloatStorage", "0", 0, &[2, 2], &[2, 1]),
+        ),
+        (
+            "half",
+            Pickle::default().tensor("HalfStorage", "1", 0, &[3], &[1]),
+        ),
+        (
+            "steps",
+            Pickle::default().tensor("LongStorage", "2", 0, &[2], &[1]),
+        ),
+        ("epoch", Pickle::default().int(3)),
+    ]);
+    let floats = le_bytes(&[1.0f32, -2.0, 0.5, 4.0], f32::to_le_bytes);
+    // 1.0, -2.0 and 0.5 as f16
+    let halves = le_bytes(&[0x3c00u16, 0xc000, 0x3800], u16::to_le_bytes);
+    let longs = le_bytes(&[3i64, -7], i64::to_le_bytes);
+    let path = archive(
+        "dtypes",
+        &pickle,
+        &[("0", floats), ("1", halves), ("2", longs)],
+    );
+    let state = torch::load(path).unwrap();
+    assert_eq!(state.len(), 3);
+    assert_eq!(state["weight"], arr2(&[[1.0, -2.0], [0.5, 4.0]]).into_dyn());
+    assert_eq!(state["half"], arr1(&[1.0, -2.0, 0.5]).into_dyn());
+    assert_eq!(state["steps"], arr1(&[3.0, -7.0]).into_dyn());
+}
+
+#[test]
+fn follows_offsets_and_strides() {
+    // A transposed [3, 2] tensor and a strided slice share one storage
+    let pickle = Pickle::state_dict(vec![
+        (
+            "transposed",
+            Pickle::default().tensor("FloatStorage", "0", 0, &[2, 3], &[1, 2]),
+        ),
+        (
+            "every_third",
+            Pickle::default().tensor("FloatStorage", "0", 1, &[2], &[3]),
+        ),
+        (
+            "broadcast",
+            Pickle::default().tensor("FloatStorage", "0", 5, &[2, 2], &[0, 0]),
+        ),
+    ]);
+    let storage = le_bytes(&[0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0], f32::to_le_bytes);
+    let state = torch::load(archive("strides", &pickle, &[("0", storage)])).unwrap();
+    assert_eq!(
+        state["transposed"],
+        arr2(&[[0.0, 2.0, 4.0], [1.0, 3.0, 5.0]]).into_dyn()
+    );
+    assert_eq!(state["every_third"], arr1(&[1.0, 4.0]).into_dyn());
+    assert_eq!(
+        state["broadcast"],
+        arr2(&[[5.0, 5.0], [5.0, 5.0]]).into_dyn()
+    );
+}
+
+#[test]
+fn flattens_nested_dicts() {
+    let bias = Pickle::default()
+        .global("torch._utils", "_rebuild_parameter")
+        .op(b'(')
+        .tensor("FloatStorage", "0", 0, &[1], &[1])
+        .op(0x88)
+        .ordered_dict()
+        .op(b't')
+        .op(b'R');
+    let model = Pickle::default().dict(vec![("bias", bias)]);
+    let pickle = Pickle::state_dict(vec![("model", model), ("epoch", Pickle::default().int(3))]);
+    let storage = le_bytes(&[0.25f32], f32::to_le_bytes);
+    let state = torch::load(archive("nested", &pickle, &[("0", storage)])).unwrap();
+    assert_eq!(state.keys().collect::<Vec<_>>(), ["model.bias"]);
+    assert_eq!(state["model.bias"], arr1(&[0.25]).into_dyn());
+}
+
+#[test]
+fn refuses_globals_outside_a_state_dict() {
+    let pickle = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default()
+            .global("os", "system")
+            .string("echo hi")
+            .op(0x85)
+            .op(b'R'),
+    )]);
+    let error = load_error("globals", &pickle, &[]);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("os.system"), "{}", error);
+    // STACK_GLOBAL goes through the same check
+    let pickle = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default().string("builtins").string("eval").op(0x93),
+    )]);
+    let error = load_error("stack_global", &pickle, &[]);
+    assert!(error.to_string().contains("builtins.eval"), "{}", error);
+}
+
+#[test]
+fn refuses_unknown_opcodes() {
+    // INST builds an object by name without going through GLOBAL
+    let mut pickle = Pickle::default().op(0x80).op(2).op(b'(');
+    pickle.0.extend(b"ios\nsystem\n");
+    let error = load_error("opcodes", &pickle.op(b'.').0, &[]);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("0x69"), "{}", error);
+}
+
+#[test]
+fn rejects_strides_past_the_storage() {
+    let storage = || le_bytes(&[0.0f32; 4], f32::to_le_bytes);
+    let too_wide = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default().tensor("FloatStorage", "0", 0, &[2, 2], &[3, 1]),
+    )]);
+    let error = load_error("too_wide", &too_wide, &[("0", storage())]);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("past its storage"), "{}", error);
+    let offset = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default().tensor("FloatStorage", "0", 3, &[2], &[1]),
+    )]);
+    let error = load_error("offset", &offset, &[("0", storage())]);
+    assert!(error.to_string().contains("past its storage"), "{}", error);
+    // An empty tensor reads nothing, wherever it points
+    let empty = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default().tensor("FloatStorage", "0", 9, &[0, 2], &[2, 1]),
+    )]);
+    let state = torch::load(archive("empty", &empty, &[("0", storage())])).unwrap();
+    assert_eq!(state["x"].shape(), [0, 2]);
+}
+
+#[test]
+fn rejects_missing_storages() {
+    let pickle = Pickle::state_dict(vec![(
+        "x",
+        Pickle::default().tensor("FloatStorage", "7", 0, &[1], &[1]),
+    )]);
+    assert_eq!(
+        load_error("missing", &pickle, &[]).kind(),
+        io::ErrorKind::InvalidData
+    );
+}